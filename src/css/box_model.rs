@@ -1,4 +1,5 @@
 #[allow(dead_code)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Box {
 	pub content: Rectangle,
 	pub padding: Edges,
@@ -9,25 +10,27 @@ pub struct Box {
 impl Box {
 	pub fn default() -> Box {
 		Box {
-			content: Rectangle {height: 0, width: 0},
-			padding: Edges {top: 0, bottom: 0, right: 0, left: 0},
-			border: Edges {top: 0, bottom: 0, right: 0, left: 0},
-			margin: Edges {top: 0, bottom: 0, right: 0, left: 0},
+			content: Rectangle {height: 0.0, width: 0.0},
+			padding: Edges {top: 0.0, bottom: 0.0, right: 0.0, left: 0.0},
+			border: Edges {top: 0.0, bottom: 0.0, right: 0.0, left: 0.0},
+			margin: Edges {top: 0.0, bottom: 0.0, right: 0.0, left: 0.0},
 		}
 	}
 
 }
 
 #[allow(dead_code)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Edges {
-	pub top: u32,
-	pub bottom: u32,
-	pub right: u32,
-	pub left: u32,
+	pub top: f32,
+	pub bottom: f32,
+	pub right: f32,
+	pub left: f32,
 }
 
 #[allow(dead_code)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Rectangle {
-	height: u32,
-	width: u32,
+	height: f32,
+	width: f32,
 }
\ No newline at end of file
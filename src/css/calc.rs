@@ -0,0 +1,336 @@
+use super::parser::ComponentValue;
+use super::tokenizer::Token;
+use super::stylesheet::{Unit, LengthContext};
+
+/// A math expression from calc(), min(), max() or clamp(). It is kept as a
+/// tree until the percentages and relative lengths in it can be resolved
+/// www.w3.org/TR/css-values-4/#math
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum CalcNode {
+	Number(f32),
+	Percentage(f32),
+	Length(f32, Unit),
+	Add(Box<CalcNode>, Box<CalcNode>),
+	Subtract(Box<CalcNode>, Box<CalcNode>),
+	Multiply(Box<CalcNode>, Box<CalcNode>),
+	Divide(Box<CalcNode>, Box<CalcNode>),
+	Min(Vec<CalcNode>),
+	Max(Vec<CalcNode>),
+	/// 'clamp(min, value, max)'
+	Clamp(Box<CalcNode>, Box<CalcNode>, Box<CalcNode>),
+}
+
+/// What a math expression resolves to. Percentages and lengths can be
+/// added together, as percentages of a length
+/// www.w3.org/TR/css-values-4/#calc-type-checking
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CalcType {
+	Number,
+	Length,
+	Percentage,
+	LengthPercentage,
+}
+
+impl CalcType {
+	/// The type of the sum of two values, None if they cannot be added
+	fn add(self, other: CalcType) -> Option<CalcType> {
+		match (self, other) {
+			(a, b) if a == b => Some(a),
+			(CalcType::Number, _) | (_, CalcType::Number) => None,
+			_ => Some(CalcType::LengthPercentage),
+		}
+	}
+}
+
+impl CalcNode {
+	/// The type of the expression, None if it mixes types that cannot be
+	/// combined, such as a length times a length or a number plus a length
+	pub fn calc_type(&self) -> Option<CalcType> {
+		match *self {
+			CalcNode::Number(_) => Some(CalcType::Number),
+			CalcNode::Percentage(_) => Some(CalcType::Percentage),
+			CalcNode::Length(_, _) => Some(CalcType::Length),
+			CalcNode::Add(ref a, ref b) | CalcNode::Subtract(ref a, ref b) => {
+				match (a.calc_type(), b.calc_type()) {
+					(Some(a), Some(b)) => a.add(b),
+					_ => None,
+				}
+			}
+			CalcNode::Multiply(ref a, ref b) => {
+				match (a.calc_type(), b.calc_type()) {
+					(Some(CalcType::Number), t) | (t, Some(CalcType::Number)) => t,
+					_ => None,
+				}
+			}
+			CalcNode::Divide(ref a, ref b) => {
+				match b.calc_type() {
+					Some(CalcType::Number) => a.calc_type(),
+					_ => None,
+				}
+			}
+			CalcNode::Min(ref args) | CalcNode::Max(ref args) => {
+				let mut result = match args.first() {
+					Some(a) => a.calc_type(),
+					None => return None,
+				};
+				for arg in args[1..].iter() {
+					result = match (result, arg.calc_type()) {
+						(Some(a), Some(b)) => a.add(b),
+						_ => None,
+					};
+				}
+				result
+			}
+			CalcNode::Clamp(ref min, ref value, ref max) => {
+				match (min.calc_type(), value.calc_type(), max.calc_type()) {
+					(Some(a), Some(b), Some(c)) => a.add(b).and_then(|t| t.add(c)),
+					_ => None,
+				}
+			}
+		}
+	}
+
+	/// Resolve the expression to a number, or to a length in px. Relative
+	/// lengths are resolved against 'context' and percentages against
+	/// 'basis'; None if there are percentages and no basis. A NaN result
+	/// becomes 0 and an infinite one the largest finite value
+	pub fn resolve(&self, context: &LengthContext, basis: Option<f32>) -> Option<f32> {
+		self.evaluate(context, basis).map(|n| {
+			if n.is_nan() {
+				0.0
+			} else {
+				n.max(::std::f32::MIN).min(::std::f32::MAX)
+			}
+		})
+	}
+
+	fn evaluate(&self, context: &LengthContext, basis: Option<f32>) -> Option<f32> {
+		let both = |a: &CalcNode, b: &CalcNode| {
+			match (a.evaluate(context, basis), b.evaluate(context, basis)) {
+				(Some(a), Some(b)) => Some((a, b)),
+				_ => None,
+			}
+		};
+		let all = |args: &Vec<CalcNode>| {
+			let values: Vec<f32> = args.iter().filter_map(|a| a.evaluate(context, basis)).collect();
+			if values.len() == args.len() { Some(values) } else { None }
+		};
+
+		match *self {
+			CalcNode::Number(n) => Some(n),
+			CalcNode::Percentage(p) => basis.map(|b| b * p / 100.0),
+			CalcNode::Length(n, unit) => Some(n * unit.to_px(context)),
+			CalcNode::Add(ref a, ref b) => both(a, b).map(|(a, b)| a + b),
+			CalcNode::Subtract(ref a, ref b) => both(a, b).map(|(a, b)| a - b),
+			CalcNode::Multiply(ref a, ref b) => both(a, b).map(|(a, b)| a * b),
+			CalcNode::Divide(ref a, ref b) => both(a, b).map(|(a, b)| a / b),
+			CalcNode::Min(ref args) => all(args).map(|v| v.iter().fold(::std::f32::INFINITY, |m, &n| m.min(n))),
+			CalcNode::Max(ref args) => all(args).map(|v| v.iter().fold(::std::f32::NEG_INFINITY, |m, &n| m.max(n))),
+			CalcNode::Clamp(ref min, ref value, ref max) => {
+				match (min.evaluate(context, basis), value.evaluate(context, basis), max.evaluate(context, basis)) {
+					// The minimum wins over the maximum
+					(Some(min), Some(value), Some(max)) => Some(value.min(max).max(min)),
+					_ => None,
+				}
+			}
+		}
+	}
+}
+
+/// Parse a calc(), min(), max() or clamp() function. Return None if
+/// 'value' is not one of them, does not parse or does not type check
+pub fn parse_math_function(value: &ComponentValue) -> Option<CalcNode> {
+	let node = match *value {
+		ComponentValue::Function(ref name, ref arguments) => {
+			math_function(&name.to_ascii_lowercase()[..], &arguments[..])
+		}
+		_ => None,
+	};
+
+	match node {
+		Some(ref n) if n.calc_type().is_none() => None,
+		n => n,
+	}
+}
+
+fn math_function(name: &str, arguments: &[ComponentValue]) -> Option<CalcNode> {
+	if name == "calc" {
+		return parse_sum(arguments);
+	}
+
+	let mut args = Vec::new();
+	for argument in arguments.split(|v| *v == ComponentValue::Token(Token::Comma)) {
+		match parse_sum(argument) {
+			Some(a) => args.push(a),
+			None => return None,
+		}
+	}
+
+	match name {
+		"min" => Some(CalcNode::Min(args)),
+		"max" => Some(CalcNode::Max(args)),
+		"clamp" if args.len() == 3 => {
+			let max = Box::new(args.pop().unwrap());
+			let value = Box::new(args.pop().unwrap());
+			let min = Box::new(args.pop().unwrap());
+			Some(CalcNode::Clamp(min, value, max))
+		}
+		_ => None,
+	}
+}
+
+fn is_whitespace(value: &ComponentValue) -> bool {
+	*value == ComponentValue::Token(Token::Whitespace)
+}
+
+/// Leave out the white space at either end of 'values'
+fn trim(values: &[ComponentValue]) -> &[ComponentValue] {
+	let start = values.iter().position(|v| !is_whitespace(v)).unwrap_or(values.len());
+	let end = values.iter().rposition(|v| !is_whitespace(v)).map_or(start, |e| e + 1);
+
+	&values[start..end]
+}
+
+/// 'product [+|- product]*'. The '+' and '-' have to have white space on
+/// both sides, so they are not read as the sign of a number
+/// www.w3.org/TR/css-values-4/#calc-syntax
+fn parse_sum(values: &[ComponentValue]) -> Option<CalcNode> {
+	let values = trim(values);
+	let mut result: Option<CalcNode> = None;
+	let mut operator = '+';
+	let mut start = 0;
+
+	for i in 0..values.len() + 1 {
+		let delim = match values.get(i) {
+			Some(&ComponentValue::Token(Token::Delim(c))) if (c == '+' || c == '-') && i > 0 &&
+				is_whitespace(&values[i - 1]) && values.get(i + 1).map_or(false, is_whitespace) => c,
+			None => ' ',
+			_ => continue,
+		};
+
+		let term = match parse_product(&values[start..i]) {
+			Some(t) => t,
+			None => return None,
+		};
+
+		result = Some(match result {
+			None => term,
+			Some(r) if operator == '+' => CalcNode::Add(Box::new(r), Box::new(term)),
+			Some(r) => CalcNode::Subtract(Box::new(r), Box::new(term)),
+		});
+		operator = delim;
+		start = i + 1;
+	}
+
+	result
+}
+
+/// 'value [*|/ value]*'
+fn parse_product(values: &[ComponentValue]) -> Option<CalcNode> {
+	let values: Vec<&ComponentValue> = values.iter().filter(|v| !is_whitespace(v)).collect();
+	if values.len() % 2 == 0 {
+		return None;
+	}
+
+	let mut result = match parse_value(values[0]) {
+		Some(v) => v,
+		None => return None,
+	};
+
+	for i in 0..values.len() / 2 {
+		let value = match parse_value(values[2 * i + 2]) {
+			Some(v) => Box::new(v),
+			None => return None,
+		};
+
+		result = match *values[2 * i + 1] {
+			ComponentValue::Token(Token::Delim('*')) => CalcNode::Multiply(Box::new(result), value),
+			ComponentValue::Token(Token::Delim('/')) => CalcNode::Divide(Box::new(result), value),
+			_ => return None,
+		};
+	}
+
+	Some(result)
+}
+
+/// A number, percentage, length, '(sum)' or nested math function
+fn parse_value(value: &ComponentValue) -> Option<CalcNode> {
+	match *value {
+		ComponentValue::Token(Token::Number(n)) => Some(CalcNode::Number(n.value as f32)),
+		ComponentValue::Token(Token::Percentage(n)) => Some(CalcNode::Percentage(n.value as f32)),
+		ComponentValue::Token(Token::Dimension(n, ref unit)) => {
+			Unit::from_name(&unit[..]).map(|u| CalcNode::Length(n.value as f32, u))
+		}
+		ComponentValue::Block(Token::OpenParen, ref inner) => parse_sum(&inner[..]),
+		ComponentValue::Function(ref name, ref arguments) => {
+			math_function(&name.to_ascii_lowercase()[..], &arguments[..])
+		}
+		_ => None,
+	}
+}
+
+#[cfg(test)]
+mod test_calc {
+	use super::{parse_math_function, CalcNode, CalcType};
+	use super::super::parser::CssParser;
+	use super::super::stylesheet::{Unit, LengthContext};
+
+	fn parse(text: &str) -> Option<CalcNode> {
+		let values = CssParser::new(text.to_string()).parse_component_values();
+		parse_math_function(&values[0])
+	}
+
+	fn resolve(text: &str, basis: Option<f32>) -> Option<f32> {
+		parse(text).and_then(|c| c.resolve(&LengthContext::default(), basis))
+	}
+
+	#[test]
+	fn parse_expressions() {
+		assert_eq!(parse("calc(100% - 2em)"),
+				   Some(CalcNode::Subtract(Box::new(CalcNode::Percentage(100.0)),
+										   Box::new(CalcNode::Length(2.0, Unit::Em)))));
+		assert_eq!(parse("calc(1px + 2px * 3)"),
+				   Some(CalcNode::Add(Box::new(CalcNode::Length(1.0, Unit::Px)),
+									  Box::new(CalcNode::Multiply(Box::new(CalcNode::Length(2.0, Unit::Px)),
+																  Box::new(CalcNode::Number(3.0)))))));
+		assert!(parse("CALC( (1px + 2px)/2 )").is_some());
+		assert!(parse("clamp(1rem, 2vw, 2rem)").is_some());
+		assert!(parse("min(10px, max(5%, 1em), calc(3px))").is_some());
+
+		// '+' and '-' need white space around them
+		assert_eq!(parse("calc(1px -2px)"), None);
+		assert_eq!(parse("calc(1px+2px)"), None);
+		assert_eq!(parse("calc()"), None);
+		assert_eq!(parse("clamp(1px, 2px)"), None);
+		assert_eq!(parse("calc(1px + 2foo)"), None);
+		assert_eq!(parse("var(--x)"), None);
+	}
+
+	#[test]
+	fn type_checking() {
+		let calc_type = |text: &str| parse(text).and_then(|c| c.calc_type());
+
+		assert_eq!(calc_type("calc(2 * 3)"), Some(CalcType::Number));
+		assert_eq!(calc_type("calc(2px * 3)"), Some(CalcType::Length));
+		assert_eq!(calc_type("calc(100% - 2em)"), Some(CalcType::LengthPercentage));
+		assert_eq!(calc_type("max(10%, 20%)"), Some(CalcType::Percentage));
+		assert_eq!(calc_type("calc(2px * 3px)"), None);
+		assert_eq!(calc_type("calc(2px / 3px)"), None);
+		assert_eq!(calc_type("calc(2px + 3)"), None);
+		assert_eq!(calc_type("min(1px, 2)"), None);
+	}
+
+	#[test]
+	fn resolution() {
+		assert_eq!(resolve("calc(100% - 2em)", Some(200.0)), Some(168.0));
+		assert_eq!(resolve("calc(100% - 2em)", None), None);
+		assert_eq!(resolve("calc((1in + 4px) / 2)", None), Some(50.0));
+		assert_eq!(resolve("min(10px, 1em, 2in)", None), Some(10.0));
+		assert_eq!(resolve("max(10px, 1em)", None), Some(16.0));
+		assert_eq!(resolve("clamp(1rem, 1vw, 2rem)", None), Some(16.0));
+		assert_eq!(resolve("clamp(1rem, 10vw, 2rem)", None), Some(32.0));
+		assert_eq!(resolve("clamp(2rem, 10vw, 1rem)", None), Some(32.0));
+		assert_eq!(resolve("calc(1px / 0)", None), Some(::std::f32::MAX));
+	}
+}
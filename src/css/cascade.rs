@@ -0,0 +1,420 @@
+use super::selector::Specificity;
+use super::stylesheet::{Declaration, Value};
+
+/// Where a stylesheet comes from. Normal declarations of a later origin
+/// win over those of an earlier one; for important declarations the order
+/// is reversed
+/// www.w3.org/TR/css-cascade-5/#cascading-origins
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Origin {
+	UserAgent,
+	User,
+	Author,
+}
+
+impl Default for Origin {
+	fn default() -> Origin {
+		Origin::Author
+	}
+}
+
+/// The keywords every property accepts
+/// www.w3.org/TR/css-cascade-5/#defaulting-keywords
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum CssWideKeyword {
+	Initial,
+	Inherit,
+	Unset,
+	Revert,
+	RevertLayer,
+}
+
+impl CssWideKeyword {
+	pub fn from_name(name: &str) -> Option<CssWideKeyword> {
+		match &name.to_ascii_lowercase()[..] {
+			"initial" => Some(CssWideKeyword::Initial),
+			"inherit" => Some(CssWideKeyword::Inherit),
+			"unset" => Some(CssWideKeyword::Unset),
+			"revert" => Some(CssWideKeyword::Revert),
+			"revert-layer" => Some(CssWideKeyword::RevertLayer),
+			_ => None,
+		}
+	}
+
+	pub fn name(&self) -> &'static str {
+		match *self {
+			CssWideKeyword::Initial => "initial",
+			CssWideKeyword::Inherit => "inherit",
+			CssWideKeyword::Unset => "unset",
+			CssWideKeyword::Revert => "revert",
+			CssWideKeyword::RevertLayer => "revert-layer",
+		}
+	}
+}
+
+/// The cascade layers of a stylesheet and the layer each of its style
+/// rules is in. Layers form a tree of names, a sublayer 'b' of 'a' being
+/// 'a.b'; node 0 holds the rules outside any layer. Anonymous layers have
+/// an empty name, so no later rule can name them again
+/// www.w3.org/TR/css-cascade-5/#layering
+#[derive(Clone)]
+pub struct CascadeLayers {
+	names: Vec<String>,
+	children: Vec<Vec<usize>>,
+	ranks: Vec<usize>,
+	rule_layers: Vec<usize>,
+}
+
+impl CascadeLayers {
+	pub fn new() -> CascadeLayers {
+		CascadeLayers {
+			names: vec![String::new()],
+			children: vec![Vec::new()],
+			ranks: vec![0],
+			rule_layers: Vec::new(),
+		}
+	}
+
+	/// The layer 'name', which may be a dotted path, inside layer 'parent'.
+	/// Layers not seen before are added after their siblings
+	pub fn declare(&mut self, parent: usize, name: &str) -> usize {
+		let mut layer = parent;
+
+		for part in name.split('.') {
+			let found = self.children[layer].iter().cloned().find(|&c| self.names[c] == part);
+			layer = match found {
+				Some(c) => c,
+				None => self.add(layer, part.to_string()),
+			};
+		}
+
+		layer
+	}
+
+	/// A new anonymous layer inside layer 'parent'
+	pub fn anonymous(&mut self, parent: usize) -> usize {
+		self.add(parent, String::new())
+	}
+
+	fn add(&mut self, parent: usize, name: String) -> usize {
+		let layer = self.names.len();
+		self.names.push(name);
+		self.children.push(Vec::new());
+		self.children[parent].push(layer);
+
+		// a layer's own rules come after those of its sublayers, so
+		// unlayered rules come after every layer
+		let mut ranks = vec![0; self.names.len()];
+		let mut next = 0;
+		self.rank(0, &mut ranks, &mut next);
+		self.ranks = ranks;
+
+		layer
+	}
+
+	fn rank(&self, layer: usize, ranks: &mut Vec<usize>, next: &mut usize) {
+		for &child in self.children[layer].iter() {
+			self.rank(child, ranks, next);
+		}
+		ranks[layer] = *next;
+		*next += 1;
+	}
+
+	/// Record that the next style rule in source order is in 'layer'
+	pub fn add_rule(&mut self, layer: usize) {
+		self.rule_layers.push(layer);
+	}
+
+	/// The position in layer order of the layer style rule 'n' is in
+	pub fn rule_rank(&self, n: usize) -> usize {
+		self.ranks[self.rule_layers[n]]
+	}
+
+	/// The layer style rule 'n' is in
+	pub fn rule_layer(&self, n: usize) -> usize {
+		self.rule_layers[n]
+	}
+
+	/// The position of 'layer' in layer order
+	pub fn layer_rank(&self, layer: usize) -> usize {
+		self.ranks[layer]
+	}
+
+	/// Add the layers of 'other', a later stylesheet of the same origin,
+	/// sharing those with the same name. Returns the layer here each of
+	/// its layers became
+	/// www.w3.org/TR/css-cascade-5/#layer-ordering
+	pub fn merge(&mut self, other: &CascadeLayers) -> Vec<usize> {
+		let mut layers = vec![0; other.names.len()];
+		self.merge_children(other, 0, &mut layers);
+		layers
+	}
+
+	fn merge_children(&mut self, other: &CascadeLayers, layer: usize, layers: &mut Vec<usize>) {
+		for &child in other.children[layer].iter() {
+			layers[child] = if other.names[child].is_empty() {
+				self.anonymous(layers[layer])
+			} else {
+				self.declare(layers[layer], &other.names[child])
+			};
+			self.merge_children(other, child, layers);
+		}
+	}
+}
+
+/// A declaration that applies to an element and what the cascade sorts it
+/// by. 'layer' is its layer's position in layer order and 'order' its
+/// stylesheet, its rule and its position in the rule, in source order
+pub struct CascadeEntry<'a> {
+	pub declaration: &'a Declaration,
+	pub origin: Origin,
+	pub layer: usize,
+	pub specificity: Specificity,
+	pub order: (usize, usize, usize),
+}
+
+impl<'a> CascadeEntry<'a> {
+	/// Normal user agent, user and author declarations, then important
+	/// author, user and user agent ones. Important declarations in earlier
+	/// layers win over those in later layers and unlayered ones
+	fn key(&self) -> (usize, usize, Specificity, (usize, usize, usize)) {
+		let origin = self.origin as usize;
+
+		if self.declaration.important {
+			(5 - origin, ::std::usize::MAX - self.layer, self.specificity, self.order)
+		} else {
+			(origin, self.layer, self.specificity, self.order)
+		}
+	}
+}
+
+/// Sort 'entries' from the lowest to the highest precedence and return the
+/// declaration that wins for each property, in that order. 'revert' rolls
+/// a property back to what the earlier origins give it and 'revert-layer'
+/// to what the earlier layers give it; with nothing to roll back to they
+/// act like 'unset'. Declarations with missing values take no part
+/// www.w3.org/TR/css-cascade-5/#cascade-sort
+pub fn cascade(mut entries: Vec<CascadeEntry>) -> Vec<Declaration> {
+	// declarations whose value did not parse are ignored
+	entries.retain(|e| e.declaration.property_value != Value::Missing);
+	entries.sort_by(|a, b| a.key().cmp(&b.key()));
+
+	let mut seen: Vec<&Declaration> = Vec::new();
+	let mut winners: Vec<(usize, Declaration)> = Vec::new();
+
+	for i in (0..entries.len()).rev() {
+		let declaration = entries[i].declaration;
+		if seen.iter().any(|d| d.same_property(declaration)) {
+			continue;
+		}
+		seen.push(declaration);
+
+		let value = match rolled_back(&entries[..], i) {
+			Some(j) => entries[j].declaration.property_value.clone(),
+			None => match declaration.custom_name() {
+				Some(name) => Value::CustomCssWide(name.to_string(), CssWideKeyword::Unset),
+				None => Value::CssWide(CssWideKeyword::Unset),
+			},
+		};
+
+		winners.push((i, Declaration {
+			property_name: declaration.property_name,
+			property_value: value,
+			important: entries[i].declaration.important,
+		}));
+	}
+
+	winners.sort_by(|a, b| a.0.cmp(&b.0));
+	winners.into_iter().map(|(_, d)| d).collect()
+}
+
+/// The entry whose value entry 'i' ends up with once 'revert' and
+/// 'revert-layer' are followed, None if they roll back past every entry
+fn rolled_back(entries: &[CascadeEntry], i: usize) -> Option<usize> {
+	let mut i = i;
+
+	loop {
+		let entry = &entries[i];
+		let earlier = (0..i).rev().filter(|&j| entries[j].declaration.same_property(entry.declaration));
+
+		let fallback = match entry.declaration.property_value.css_wide_keyword() {
+			Some(CssWideKeyword::Revert) => {
+				earlier.filter(|&j| entries[j].origin < entry.origin).next()
+			}
+			Some(CssWideKeyword::RevertLayer) => {
+				earlier.filter(|&j| entries[j].origin != entry.origin || entries[j].layer != entry.layer).next()
+			}
+			_ => return Some(i),
+		};
+
+		match fallback {
+			Some(j) => i = j,
+			None => return None,
+		}
+	}
+}
+
+#[cfg(test)]
+mod test_cascade {
+	use super::{cascade, CascadeEntry, CascadeLayers, CssWideKeyword, Origin};
+	use super::super::selector::Specificity;
+	use super::super::stylesheet::{string_to_declarations, Declaration, Property, Value, Unit};
+
+	fn declaration(property: Property, value: Value, important: bool) -> Declaration {
+		Declaration {
+			property_name: property,
+			property_value: value,
+			important: important,
+		}
+	}
+
+	fn entry<'a>(declaration: &'a Declaration, origin: Origin, layer: usize, order: usize) -> CascadeEntry<'a> {
+		CascadeEntry {
+			declaration: declaration,
+			origin: origin,
+			layer: layer,
+			specificity: Specificity::default(),
+			order: (0, order, 0),
+		}
+	}
+
+	fn px(n: f32) -> Value {
+		Value::Size(n, Unit::Px)
+	}
+
+	#[test]
+	fn cascade_origin_and_importance() {
+		let ua = declaration(Property::FontSize, px(1.0), false);
+		let author = declaration(Property::FontSize, px(2.0), false);
+		let ua_important = declaration(Property::FontSize, px(3.0), true);
+		let user_important = declaration(Property::FontSize, px(4.0), true);
+
+		let winner = cascade(vec![entry(&author, Origin::Author, 0, 1), entry(&ua, Origin::UserAgent, 0, 0)]);
+		assert_eq!(winner[0].property_value, px(2.0));
+
+		let winner = cascade(vec![entry(&author, Origin::Author, 0, 1),
+								  entry(&user_important, Origin::User, 0, 0)]);
+		assert_eq!(winner[0].property_value, px(4.0));
+		assert!(winner[0].important);
+
+		let winner = cascade(vec![entry(&user_important, Origin::User, 0, 1),
+								  entry(&ua_important, Origin::UserAgent, 0, 0)]);
+		assert_eq!(winner[0].property_value, px(3.0));
+	}
+
+	#[test]
+	fn cascade_layers_and_importance() {
+		let early = declaration(Property::FontSize, px(1.0), false);
+		let late = declaration(Property::FontSize, px(2.0), false);
+		let winner = cascade(vec![entry(&late, Origin::Author, 1, 0), entry(&early, Origin::Author, 0, 1)]);
+		assert_eq!(winner[0].property_value, px(2.0));
+
+		let early = declaration(Property::FontSize, px(1.0), true);
+		let late = declaration(Property::FontSize, px(2.0), true);
+		let winner = cascade(vec![entry(&late, Origin::Author, 1, 0), entry(&early, Origin::Author, 0, 1)]);
+		assert_eq!(winner[0].property_value, px(1.0));
+	}
+
+	#[test]
+	fn cascade_revert() {
+		let ua = declaration(Property::FontSize, px(1.0), false);
+		let layered = declaration(Property::FontSize, px(2.0), false);
+		let revert = declaration(Property::FontSize, Value::CssWide(CssWideKeyword::Revert), false);
+		let revert_layer = declaration(Property::FontSize, Value::CssWide(CssWideKeyword::RevertLayer), false);
+
+		let winner = cascade(vec![entry(&ua, Origin::UserAgent, 0, 0), entry(&layered, Origin::Author, 0, 1),
+								  entry(&revert, Origin::Author, 1, 2)]);
+		assert_eq!(winner[0].property_value, px(1.0));
+
+		let winner = cascade(vec![entry(&ua, Origin::UserAgent, 0, 0), entry(&layered, Origin::Author, 0, 1),
+								  entry(&revert_layer, Origin::Author, 1, 2)]);
+		assert_eq!(winner[0].property_value, px(2.0));
+
+		let winner = cascade(vec![entry(&revert, Origin::UserAgent, 0, 0)]);
+		assert_eq!(winner[0].property_value, Value::CssWide(CssWideKeyword::Unset));
+
+		// a custom property keeps its name when it is unset
+		let custom = string_to_declarations("--gap", " revert ").unwrap().remove(0);
+		assert_eq!(custom.property_value, Value::CustomCssWide("--gap".to_string(), CssWideKeyword::Revert));
+		let winner = cascade(vec![entry(&custom, Origin::Author, 0, 0)]);
+		assert_eq!(winner[0].property_value, Value::CustomCssWide("--gap".to_string(), CssWideKeyword::Unset));
+	}
+
+	#[test]
+	fn cascade_ignores_missing_values() {
+		let valid = declaration(Property::FontSize, px(1.0), false);
+		let missing = declaration(Property::FontSize, Value::Missing, false);
+		let missing_important = declaration(Property::FontSize, Value::Missing, true);
+
+		let winner = cascade(vec![entry(&valid, Origin::Author, 0, 0), entry(&missing, Origin::Author, 0, 1),
+								  entry(&missing_important, Origin::Author, 1, 2)]);
+		assert_eq!(winner.len(), 1);
+		assert_eq!(winner[0].property_value, px(1.0));
+
+		assert!(cascade(vec![entry(&missing, Origin::Author, 0, 0)]).is_empty());
+	}
+
+	#[test]
+	fn cascade_winners_in_order() {
+		let size = declaration(Property::FontSize, px(1.0), false);
+		let height = declaration(Property::LineHeight, px(2.0), false);
+		let size_again = declaration(Property::FontSize, px(3.0), false);
+
+		let winners = cascade(vec![entry(&size, Origin::Author, 0, 0), entry(&height, Origin::Author, 0, 1),
+								   entry(&size_again, Origin::Author, 0, 2)]);
+		assert_eq!(winners.len(), 2);
+		assert_eq!(winners[0].property_name, Property::LineHeight);
+		assert_eq!(winners[1].property_value, px(3.0));
+	}
+
+	#[test]
+	fn cascade_layer_order() {
+		let mut layers = CascadeLayers::new();
+		let a = layers.declare(0, "a");
+		let b = layers.declare(0, "b");
+		let a_x = layers.declare(0, "a.x");
+		assert_eq!(layers.declare(a, "x"), a_x);
+
+		for &layer in [0, a, b, a_x].iter() {
+			layers.add_rule(layer);
+		}
+
+		// a.x, a, b, then unlayered
+		assert_eq!(layers.rule_rank(3), 0);
+		assert_eq!(layers.rule_rank(1), 1);
+		assert_eq!(layers.rule_rank(2), 2);
+		assert_eq!(layers.rule_rank(0), 3);
+
+		let anonymous = layers.anonymous(0);
+		layers.add_rule(anonymous);
+		assert_eq!(layers.rule_rank(4), 3);
+		assert_eq!(layers.rule_rank(0), 4);
+	}
+
+	#[test]
+	fn cascade_merged_layers() {
+		let mut first = CascadeLayers::new();
+		first.declare(0, "b.x");
+		first.anonymous(0);
+
+		let mut second = CascadeLayers::new();
+		let a = second.declare(0, "a");
+		let b = second.declare(0, "b");
+		let b_y = second.declare(b, "y");
+
+		let mut merged = CascadeLayers::new();
+		let first_layers = merged.merge(&first);
+		let second_layers = merged.merge(&second);
+
+		// b.x, b.y, b, the anonymous layer, a, then unlayered
+		assert_eq!(second_layers[b], first_layers[1]);
+		assert_eq!(merged.layer_rank(first_layers[2]), 0);
+		assert_eq!(merged.layer_rank(second_layers[b_y]), 1);
+		assert_eq!(merged.layer_rank(second_layers[b]), 2);
+		assert_eq!(merged.layer_rank(first_layers[3]), 3);
+		assert_eq!(merged.layer_rank(second_layers[a]), 4);
+		assert_eq!(merged.layer_rank(0), 5);
+	}
+}
@@ -0,0 +1,522 @@
+use super::parser::{CssParser, ComponentValue};
+use super::tokenizer::Token;
+
+/// An sRGB color with 8 bits per channel, as it is painted. Colors given in
+/// other color spaces are converted when they are parsed
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Color {
+	pub red: u8,
+	pub green: u8,
+	pub blue: u8,
+	pub alpha: u8,
+}
+
+impl Color {
+	pub fn rgba(red: u8, green: u8, blue: u8, alpha: u8) -> Color {
+		Color {
+			red: red,
+			green: green,
+			blue: blue,
+			alpha: alpha,
+		}
+	}
+
+	/// An opaque color
+	pub fn rgb(red: u8, green: u8, blue: u8) -> Color {
+		Color::rgba(red, green, blue, 255)
+	}
+
+	pub fn transparent() -> Color {
+		Color::rgba(0, 0, 0, 0)
+	}
+
+	/// Build a color from sRGB channels between 0 and 1. Channels outside
+	/// of that range, from colors outside of the sRGB gamut, are clipped
+	fn from_srgb(red: f64, green: f64, blue: f64, alpha: f64) -> Color {
+		let channel = |c: f64| (c.max(0.0).min(1.0) * 255.0).round() as u8;
+
+		Color::rgba(channel(red), channel(green), channel(blue), channel(alpha))
+	}
+
+	/// Look up one of the named colors, ignoring case
+	/// www.w3.org/TR/css-color-4/#named-colors
+	pub fn from_name(name: &str) -> Option<Color> {
+		let name = name.to_ascii_lowercase();
+
+		if name == "transparent" {
+			return Some(Color::transparent());
+		}
+
+		NAMED_COLORS.iter().find(|&&(n, _)| n == &name[..]).map(|&(_, rgb)| {
+			Color::rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)
+		})
+	}
+
+	/// Parse the digits of a hex color: 3, 4, 6 or 8 of them, the last one
+	/// or two giving the alpha
+	/// www.w3.org/TR/css-color-4/#hex-notation
+	pub fn from_hex(digits: &str) -> Option<Color> {
+		let values: Vec<u8> = digits.chars().filter_map(|c| c.to_digit(16).map(|d| d as u8)).collect();
+		if values.len() != digits.len() {
+			return None;
+		}
+
+		let pair = |i: usize| values[i] * 16 + values[i + 1];
+
+		match values.len() {
+			3 => Some(Color::rgb(values[0] * 17, values[1] * 17, values[2] * 17)),
+			4 => Some(Color::rgba(values[0] * 17, values[1] * 17, values[2] * 17, values[3] * 17)),
+			6 => Some(Color::rgb(pair(0), pair(2), pair(4))),
+			8 => Some(Color::rgba(pair(0), pair(2), pair(4), pair(6))),
+			_ => None,
+		}
+	}
+}
+
+/// Parse a <color> other than 'currentColor', which depends on the element
+/// it is used on. Return None if 'text' is not a color
+/// www.w3.org/TR/css-color-4/#color-syntax
+pub fn parse_color(text: &str) -> Option<Color> {
+	let values = CssParser::new(text.to_string()).parse_component_values();
+	let values: Vec<&ComponentValue> = values.iter().filter(|v| !is_whitespace(v)).collect();
+
+	if values.len() != 1 {
+		return None;
+	}
+
+	from_component_value(values[0])
+}
+
+/// Parse a <color> from a single component value
+pub fn from_component_value(value: &ComponentValue) -> Option<Color> {
+	match *value {
+		ComponentValue::Token(Token::Ident(ref name)) => Color::from_name(&name[..]),
+		ComponentValue::Token(Token::Hash(ref digits, _)) => Color::from_hex(&digits[..]),
+		ComponentValue::Function(ref name, ref arguments) => {
+			parse_function(&name.to_ascii_lowercase()[..], &arguments[..])
+		}
+		_ => None,
+	}
+}
+
+fn is_whitespace(value: &ComponentValue) -> bool {
+	match *value {
+		ComponentValue::Token(Token::Whitespace) => true,
+		_ => false,
+	}
+}
+
+/// An argument of a color function
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Channel {
+	Number(f64),
+	/// The percentage as written, 50 for '50%'
+	Percentage(f64),
+	/// An angle in degrees
+	Angle(f64),
+	/// 'none', a missing component. It is treated as zero
+	None,
+}
+
+impl Channel {
+	fn from_component_value(value: &ComponentValue) -> Option<Channel> {
+		match *value {
+			ComponentValue::Token(Token::Number(n)) => Some(Channel::Number(n.value)),
+			ComponentValue::Token(Token::Percentage(n)) => Some(Channel::Percentage(n.value)),
+			ComponentValue::Token(Token::Dimension(n, ref unit)) => {
+				let degrees = match &unit.to_ascii_lowercase()[..] {
+					"deg" => n.value,
+					"grad" => n.value * 0.9,
+					"rad" => n.value.to_degrees(),
+					"turn" => n.value * 360.0,
+					_ => return None,
+				};
+				Some(Channel::Angle(degrees))
+			}
+			ComponentValue::Token(Token::Ident(ref i)) if i.eq_ignore_ascii_case("none") => Some(Channel::None),
+			_ => None,
+		}
+	}
+
+	/// A number, or a percentage of 'full'
+	fn number(&self, full: f64) -> Option<f64> {
+		match *self {
+			Channel::Number(n) => Some(n),
+			Channel::Percentage(p) => Some(p / 100.0 * full),
+			Channel::None => Some(0.0),
+			Channel::Angle(_) => None,
+		}
+	}
+
+	/// A hue in degrees, from a number or an angle
+	fn hue(&self) -> Option<f64> {
+		match *self {
+			Channel::Number(n) | Channel::Angle(n) => Some(n),
+			Channel::None => Some(0.0),
+			Channel::Percentage(_) => None,
+		}
+	}
+
+	fn is_percentage(&self) -> bool {
+		match *self {
+			Channel::Percentage(_) => true,
+			_ => false,
+		}
+	}
+}
+
+/// Parse the arguments of a color function. The modern syntax separates
+/// the channels with spaces and the alpha with a '/'. rgb() and hsl() also
+/// take the legacy syntax, all arguments separated by commas
+/// www.w3.org/TR/css-color-4/#rgb-functions
+fn parse_function(name: &str, arguments: &[ComponentValue]) -> Option<Color> {
+	let arguments: Vec<&ComponentValue> = arguments.iter().filter(|v| !is_whitespace(v)).collect();
+	let is_comma = |v: &&ComponentValue| **v == ComponentValue::Token(Token::Comma);
+	let is_slash = |v: &&ComponentValue| **v == ComponentValue::Token(Token::Delim('/'));
+	let legacy = arguments.iter().any(|v| is_comma(v));
+
+	let (channels, alpha) = if legacy {
+		// 'a, b, c[, alpha]'
+		let mut values = Vec::new();
+		for (i, value) in arguments.iter().enumerate() {
+			if (i % 2 == 1) != is_comma(value) {
+				return None;
+			}
+			if i % 2 == 0 {
+				values.push(*value);
+			}
+		}
+
+		if arguments.len() % 2 == 0 || (values.len() != 3 && values.len() != 4) {
+			return None;
+		}
+		let alpha = values.get(3).map(|v| *v);
+		values.truncate(3);
+		(values, alpha)
+	} else {
+		// 'a b c[ / alpha]'
+		let slash = arguments.iter().position(|v| is_slash(v));
+		match slash {
+			Some(i) if i + 2 == arguments.len() => (arguments[..i].to_vec(), Some(arguments[i + 1])),
+			Some(_) => return None,
+			None => (arguments.clone(), None),
+		}
+	};
+
+	if channels.len() != 3 {
+		return None;
+	}
+
+	let mut c = Vec::new();
+	for value in channels.iter() {
+		match Channel::from_component_value(value) {
+			Some(Channel::None) if legacy => return None,
+			Some(channel) => c.push(channel),
+			None => return None,
+		}
+	}
+
+	let alpha = match alpha.map(|a| Channel::from_component_value(a)) {
+		None => 1.0,
+		Some(Some(Channel::None)) if legacy => return None,
+		Some(Some(a)) => match a.number(1.0) {
+			Some(a) => a,
+			None => return None,
+		},
+		Some(None) => return None,
+	};
+
+	match name {
+		"rgb" | "rgba" => {
+			// The legacy syntax does not mix numbers and percentages
+			if legacy && (c[0].is_percentage() != c[1].is_percentage() ||
+						  c[0].is_percentage() != c[2].is_percentage()) {
+				return None;
+			}
+
+			match (c[0].number(255.0), c[1].number(255.0), c[2].number(255.0)) {
+				(Some(r), Some(g), Some(b)) => Some(Color::from_srgb(r / 255.0, g / 255.0, b / 255.0, alpha)),
+				_ => None,
+			}
+		}
+		"hsl" | "hsla" => {
+			if legacy && (!c[1].is_percentage() || !c[2].is_percentage()) {
+				return None;
+			}
+
+			match (c[0].hue(), c[1].number(100.0), c[2].number(100.0)) {
+				(Some(h), Some(s), Some(l)) => {
+					let (r, g, b) = hsl_to_srgb(h, s / 100.0, l / 100.0);
+					Some(Color::from_srgb(r, g, b, alpha))
+				}
+				_ => None,
+			}
+		}
+		"hwb" if !legacy => {
+			match (c[0].hue(), c[1].number(100.0), c[2].number(100.0)) {
+				(Some(h), Some(w), Some(b)) => {
+					let (r, g, b) = hwb_to_srgb(h, w / 100.0, b / 100.0);
+					Some(Color::from_srgb(r, g, b, alpha))
+				}
+				_ => None,
+			}
+		}
+		"lab" if !legacy => {
+			match (c[0].number(100.0), c[1].number(125.0), c[2].number(125.0)) {
+				(Some(l), Some(a), Some(b)) => {
+					let (r, g, b) = lab_to_srgb(l.max(0.0).min(100.0), a, b);
+					Some(Color::from_srgb(r, g, b, alpha))
+				}
+				_ => None,
+			}
+		}
+		"lch" if !legacy => {
+			match (c[0].number(100.0), c[1].number(150.0), c[2].hue()) {
+				(Some(l), Some(chroma), Some(h)) => {
+					let (a, b) = polar_to_rectangular(chroma.max(0.0), h);
+					let (r, g, b) = lab_to_srgb(l.max(0.0).min(100.0), a, b);
+					Some(Color::from_srgb(r, g, b, alpha))
+				}
+				_ => None,
+			}
+		}
+		"oklab" if !legacy => {
+			match (c[0].number(1.0), c[1].number(0.4), c[2].number(0.4)) {
+				(Some(l), Some(a), Some(b)) => {
+					let (r, g, b) = oklab_to_srgb(l.max(0.0).min(1.0), a, b);
+					Some(Color::from_srgb(r, g, b, alpha))
+				}
+				_ => None,
+			}
+		}
+		"oklch" if !legacy => {
+			match (c[0].number(1.0), c[1].number(0.4), c[2].hue()) {
+				(Some(l), Some(chroma), Some(h)) => {
+					let (a, b) = polar_to_rectangular(chroma.max(0.0), h);
+					let (r, g, b) = oklab_to_srgb(l.max(0.0).min(1.0), a, b);
+					Some(Color::from_srgb(r, g, b, alpha))
+				}
+				_ => None,
+			}
+		}
+		_ => None,
+	}
+}
+
+/// www.w3.org/TR/css-color-4/#hsl-to-rgb
+fn hsl_to_srgb(hue: f64, saturation: f64, lightness: f64) -> (f64, f64, f64) {
+	let hue = ((hue % 360.0) + 360.0) % 360.0;
+	let saturation = saturation.max(0.0).min(1.0);
+	let lightness = lightness.max(0.0).min(1.0);
+
+	let f = |n: f64| {
+		let k = (n + hue / 30.0) % 12.0;
+		let a = saturation * lightness.min(1.0 - lightness);
+		lightness - a * (k - 3.0).min(9.0 - k).min(1.0).max(-1.0)
+	};
+
+	(f(0.0), f(8.0), f(4.0))
+}
+
+/// www.w3.org/TR/css-color-4/#hwb-to-rgb
+fn hwb_to_srgb(hue: f64, white: f64, black: f64) -> (f64, f64, f64) {
+	let white = white.max(0.0).min(1.0);
+	let black = black.max(0.0).min(1.0);
+
+	if white + black >= 1.0 {
+		let gray = white / (white + black);
+		return (gray, gray, gray);
+	}
+
+	let (r, g, b) = hsl_to_srgb(hue, 1.0, 0.5);
+	let scale = |c: f64| c * (1.0 - white - black) + white;
+
+	(scale(r), scale(g), scale(b))
+}
+
+fn polar_to_rectangular(chroma: f64, hue: f64) -> (f64, f64) {
+	let hue = hue.to_radians();
+
+	(chroma * hue.cos(), chroma * hue.sin())
+}
+
+/// CIE Lab, relative to the D50 white point, to sRGB
+/// www.w3.org/TR/css-color-4/#color-conversion-code
+fn lab_to_srgb(l: f64, a: f64, b: f64) -> (f64, f64, f64) {
+	let kappa = 24389.0 / 27.0;
+	let epsilon = 216.0 / 24389.0;
+
+	let fy = (l + 16.0) / 116.0;
+	let fx = a / 500.0 + fy;
+	let fz = fy - b / 200.0;
+
+	let inverse = |f: f64| if f * f * f > epsilon { f * f * f } else { (116.0 * f - 16.0) / kappa };
+	let y = if l > kappa * epsilon { fy * fy * fy } else { l / kappa };
+
+	// Scale by the D50 white point
+	let xyz_d50 = [inverse(fx) * 0.3457 / 0.3585, y, inverse(fz) * (1.0 - 0.3457 - 0.3585) / 0.3585];
+
+	// Bradford chromatic adaptation from D50 to D65
+	let xyz_d65 = multiply(&[[0.955473421488075, -0.02309845494876471, 0.06325924320057072],
+							 [-0.0283697093338637, 1.0099953980813041, 0.021041441191917323],
+							 [0.012314014864481998, -0.020507649298898964, 1.330365926242124]], &xyz_d50);
+
+	let linear = multiply(&[[3.2409699419045226, -1.537383177570094, -0.4986107602930034],
+							[-0.9692436362808796, 1.8759675015077202, 0.04155505740717559],
+							[0.05563007969699366, -0.20397695888897652, 1.0569715142428786]], &xyz_d65);
+
+	(gamma(linear[0]), gamma(linear[1]), gamma(linear[2]))
+}
+
+/// Oklab to sRGB
+/// bottosson.github.io/posts/oklab
+fn oklab_to_srgb(l: f64, a: f64, b: f64) -> (f64, f64, f64) {
+	let lms = multiply(&[[1.0, 0.3963377774, 0.2158037573],
+						 [1.0, -0.1055613458, -0.0638541728],
+						 [1.0, -0.0894841775, -1.2914855480]], &[l, a, b]);
+	let lms = [lms[0] * lms[0] * lms[0], lms[1] * lms[1] * lms[1], lms[2] * lms[2] * lms[2]];
+
+	let linear = multiply(&[[4.0767416621, -3.3077115913, 0.2309699292],
+							[-1.2684380046, 2.6097574011, -0.3413193965],
+							[-0.0041960863, -0.7034186147, 1.7076147010]], &lms);
+
+	(gamma(linear[0]), gamma(linear[1]), gamma(linear[2]))
+}
+
+fn multiply(m: &[[f64; 3]; 3], v: &[f64; 3]) -> [f64; 3] {
+	let row = |r: &[f64; 3]| r[0] * v[0] + r[1] * v[1] + r[2] * v[2];
+
+	[row(&m[0]), row(&m[1]), row(&m[2])]
+}
+
+/// The sRGB transfer function, from linear light to the encoded channel
+fn gamma(c: f64) -> f64 {
+	let c = c.max(0.0).min(1.0);
+
+	if c <= 0.0031308 {
+		12.92 * c
+	} else {
+		1.055 * c.powf(1.0 / 2.4) - 0.055
+	}
+}
+
+/// The named colors and their 0xRRGGBB values
+static NAMED_COLORS: [(&'static str, u32); 148] = [
+	("aliceblue", 0xf0f8ff), ("antiquewhite", 0xfaebd7), ("aqua", 0x00ffff),
+	("aquamarine", 0x7fffd4), ("azure", 0xf0ffff), ("beige", 0xf5f5dc),
+	("bisque", 0xffe4c4), ("black", 0x000000), ("blanchedalmond", 0xffebcd),
+	("blue", 0x0000ff), ("blueviolet", 0x8a2be2), ("brown", 0xa52a2a),
+	("burlywood", 0xdeb887), ("cadetblue", 0x5f9ea0), ("chartreuse", 0x7fff00),
+	("chocolate", 0xd2691e), ("coral", 0xff7f50), ("cornflowerblue", 0x6495ed),
+	("cornsilk", 0xfff8dc), ("crimson", 0xdc143c), ("cyan", 0x00ffff),
+	("darkblue", 0x00008b), ("darkcyan", 0x008b8b), ("darkgoldenrod", 0xb8860b),
+	("darkgray", 0xa9a9a9), ("darkgreen", 0x006400), ("darkgrey", 0xa9a9a9),
+	("darkkhaki", 0xbdb76b), ("darkmagenta", 0x8b008b), ("darkolivegreen", 0x556b2f),
+	("darkorange", 0xff8c00), ("darkorchid", 0x9932cc), ("darkred", 0x8b0000),
+	("darksalmon", 0xe9967a), ("darkseagreen", 0x8fbc8f), ("darkslateblue", 0x483d8b),
+	("darkslategray", 0x2f4f4f), ("darkslategrey", 0x2f4f4f), ("darkturquoise", 0x00ced1),
+	("darkviolet", 0x9400d3), ("deeppink", 0xff1493), ("deepskyblue", 0x00bfff),
+	("dimgray", 0x696969), ("dimgrey", 0x696969), ("dodgerblue", 0x1e90ff),
+	("firebrick", 0xb22222), ("floralwhite", 0xfffaf0), ("forestgreen", 0x228b22),
+	("fuchsia", 0xff00ff), ("gainsboro", 0xdcdcdc), ("ghostwhite", 0xf8f8ff),
+	("gold", 0xffd700), ("goldenrod", 0xdaa520), ("gray", 0x808080),
+	("green", 0x008000), ("greenyellow", 0xadff2f), ("grey", 0x808080),
+	("honeydew", 0xf0fff0), ("hotpink", 0xff69b4), ("indianred", 0xcd5c5c),
+	("indigo", 0x4b0082), ("ivory", 0xfffff0), ("khaki", 0xf0e68c),
+	("lavender", 0xe6e6fa), ("lavenderblush", 0xfff0f5), ("lawngreen", 0x7cfc00),
+	("lemonchiffon", 0xfffacd), ("lightblue", 0xadd8e6), ("lightcoral", 0xf08080),
+	("lightcyan", 0xe0ffff), ("lightgoldenrodyellow", 0xfafad2), ("lightgray", 0xd3d3d3),
+	("lightgreen", 0x90ee90), ("lightgrey", 0xd3d3d3), ("lightpink", 0xffb6c1),
+	("lightsalmon", 0xffa07a), ("lightseagreen", 0x20b2aa), ("lightskyblue", 0x87cefa),
+	("lightslategray", 0x778899), ("lightslategrey", 0x778899), ("lightsteelblue", 0xb0c4de),
+	("lightyellow", 0xffffe0), ("lime", 0x00ff00), ("limegreen", 0x32cd32),
+	("linen", 0xfaf0e6), ("magenta", 0xff00ff), ("maroon", 0x800000),
+	("mediumaquamarine", 0x66cdaa), ("mediumblue", 0x0000cd), ("mediumorchid", 0xba55d3),
+	("mediumpurple", 0x9370db), ("mediumseagreen", 0x3cb371), ("mediumslateblue", 0x7b68ee),
+	("mediumspringgreen", 0x00fa9a), ("mediumturquoise", 0x48d1cc), ("mediumvioletred", 0xc71585),
+	("midnightblue", 0x191970), ("mintcream", 0xf5fffa), ("mistyrose", 0xffe4e1),
+	("moccasin", 0xffe4b5), ("navajowhite", 0xffdead), ("navy", 0x000080),
+	("oldlace", 0xfdf5e6), ("olive", 0x808000), ("olivedrab", 0x6b8e23),
+	("orange", 0xffa500), ("orangered", 0xff4500), ("orchid", 0xda70d6),
+	("palegoldenrod", 0xeee8aa), ("palegreen", 0x98fb98), ("paleturquoise", 0xafeeee),
+	("palevioletred", 0xdb7093), ("papayawhip", 0xffefd5), ("peachpuff", 0xffdab9),
+	("peru", 0xcd853f), ("pink", 0xffc0cb), ("plum", 0xdda0dd),
+	("powderblue", 0xb0e0e6), ("purple", 0x800080), ("rebeccapurple", 0x663399),
+	("red", 0xff0000), ("rosybrown", 0xbc8f8f), ("royalblue", 0x4169e1),
+	("saddlebrown", 0x8b4513), ("salmon", 0xfa8072), ("sandybrown", 0xf4a460),
+	("seagreen", 0x2e8b57), ("seashell", 0xfff5ee), ("sienna", 0xa0522d),
+	("silver", 0xc0c0c0), ("skyblue", 0x87ceeb), ("slateblue", 0x6a5acd),
+	("slategray", 0x708090), ("slategrey", 0x708090), ("snow", 0xfffafa),
+	("springgreen", 0x00ff7f), ("steelblue", 0x4682b4), ("tan", 0xd2b48c),
+	("teal", 0x008080), ("thistle", 0xd8bfd8), ("tomato", 0xff6347),
+	("turquoise", 0x40e0d0), ("violet", 0xee82ee), ("wheat", 0xf5deb3),
+	("white", 0xffffff), ("whitesmoke", 0xf5f5f5), ("yellow", 0xffff00),
+	("yellowgreen", 0x9acd32),
+];
+
+#[cfg(test)]
+mod test_color {
+	use super::{parse_color, Color};
+
+	/// Colors converted from other color spaces may be off by one
+	fn close_to(text: &str, expected: Color) -> bool {
+		let near = |a: u8, b: u8| (a as i32 - b as i32).abs() <= 1;
+
+		match parse_color(text) {
+			Some(c) => near(c.red, expected.red) && near(c.green, expected.green) &&
+					   near(c.blue, expected.blue) && c.alpha == expected.alpha,
+			None => false,
+		}
+	}
+
+	#[test]
+	fn named_and_hex_colors() {
+		assert_eq!(parse_color("red"), Some(Color::rgb(255, 0, 0)));
+		assert_eq!(parse_color(" RebeccaPurple "), Some(Color::rgb(0x66, 0x33, 0x99)));
+		assert_eq!(parse_color("transparent"), Some(Color::rgba(0, 0, 0, 0)));
+		assert_eq!(parse_color("#0f8"), Some(Color::rgb(0, 255, 0x88)));
+		assert_eq!(parse_color("#0f88"), Some(Color::rgba(0, 255, 0x88, 0x88)));
+		assert_eq!(parse_color("#102030"), Some(Color::rgb(0x10, 0x20, 0x30)));
+		assert_eq!(parse_color("#10203040"), Some(Color::rgba(0x10, 0x20, 0x30, 0x40)));
+
+		assert_eq!(parse_color("currentColor"), None);
+		assert_eq!(parse_color("nonsense"), None);
+		assert_eq!(parse_color("#12345"), None);
+		assert_eq!(parse_color("#ggg"), None);
+		assert_eq!(parse_color("red blue"), None);
+	}
+
+	#[test]
+	fn rgb_and_hsl_functions() {
+		assert_eq!(parse_color("rgb(255, 128, 0)"), Some(Color::rgb(255, 128, 0)));
+		assert_eq!(parse_color("rgba(100%, 50%, 0%, 0.5)"), Some(Color::rgba(255, 128, 0, 128)));
+		assert_eq!(parse_color("rgb(255 50% 0 / 25%)"), Some(Color::rgba(255, 128, 0, 64)));
+		assert_eq!(parse_color("rgb(none 300 -5)"), Some(Color::rgb(0, 255, 0)));
+		assert_eq!(parse_color("hsl(120, 100%, 25%)"), Some(Color::rgb(0, 128, 0)));
+		assert_eq!(parse_color("hsl(0.5turn 100 50 / 1)"), Some(Color::rgb(0, 255, 255)));
+		assert_eq!(parse_color("hwb(0 0% 0%)"), Some(Color::rgb(255, 0, 0)));
+		assert_eq!(parse_color("hwb(90deg 60% 60%)"), Some(Color::rgb(128, 128, 128)));
+
+		// The legacy syntax takes neither 'none' nor mixed numbers and percentages
+		assert_eq!(parse_color("rgb(255, 50%, 0)"), None);
+		assert_eq!(parse_color("rgb(none, 0, 0)"), None);
+		assert_eq!(parse_color("hsl(120, 100, 25)"), None);
+		assert_eq!(parse_color("hwb(0, 0%, 0%)"), None);
+		assert_eq!(parse_color("rgb(1, 2 3)"), None);
+		assert_eq!(parse_color("rgb(1 2 3 / )"), None);
+	}
+
+	#[test]
+	fn lab_and_oklab_functions() {
+		assert!(close_to("lab(54.29 80.8 69.89)", Color::rgb(255, 0, 0)));
+		assert!(close_to("lab(100% 0 0)", Color::rgb(255, 255, 255)));
+		assert!(close_to("lch(32.39% 61.24 308.9 / 50%)", Color::rgba(102, 51, 153, 128)));
+		assert!(close_to("oklab(0.628 0.2249 0.1258)", Color::rgb(255, 0, 0)));
+		assert!(close_to("oklch(62.8% 0.2577 29.23)", Color::rgb(255, 0, 0)));
+		assert!(close_to("oklch(0 0 0)", Color::rgb(0, 0, 0)));
+
+		// Out of gamut colors are clipped
+		assert!(close_to("oklch(0.9 0.4 145)", Color::rgb(0, 255, 0)));
+	}
+}
@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+
+use super::tokenizer::{tokenize, Token};
+use super::stylesheet::{string_to_value, string_to_property_value, LengthContext, Property, PropertyRule, Value};
+use super::calc::CalcType;
+use super::cascade::CssWideKeyword;
+
+/// Whether 'name' is the name of a custom property, '--' and at least one
+/// more character
+/// www.w3.org/TR/css-variables-1/#defining-variables
+pub fn is_custom_property_name(name: &str) -> bool {
+	name.starts_with("--") && name.len() > 2
+}
+
+/// Whether 'text' uses var() anywhere, so its value can only be known once
+/// the custom properties it refers to are
+pub fn contains_var(text: &str) -> bool {
+	let (_, tokens) = tokenize(text);
+	tokens.iter().any(|t| match t.token {
+		Token::Function(ref name) => name.eq_ignore_ascii_case("var"),
+		_ => false,
+	})
+}
+
+/// Replace every 'var(--name)' and 'var(--name, fallback)' in 'text' with
+/// what 'lookup' gives for the custom property, or else with the fallback,
+/// itself substituted. None if a var() is malformed or its property has
+/// no value and there is no fallback
+/// www.w3.org/TR/css-variables-1/#substitute-a-var
+pub fn substitute(text: &str, lookup: &mut FnMut(&str) -> Option<String>) -> Option<String> {
+	let (source, tokens) = tokenize(text);
+	let source_text = |start: usize, end: usize| -> String { source[start..end].iter().cloned().collect() };
+	let mut result = String::new();
+	let mut copied = 0;
+	let mut i = 0;
+
+	while i < tokens.len() {
+		let is_var = match tokens[i].token {
+			Token::Function(ref name) => name.eq_ignore_ascii_case("var"),
+			_ => false,
+		};
+		if !is_var {
+			i += 1;
+			continue;
+		}
+
+		// the var()'s arguments run to its matching ')'
+		let mut depth = 1;
+		let mut close = i + 1;
+		while close < tokens.len() {
+			match tokens[close].token {
+				Token::Function(_) | Token::OpenParen | Token::OpenSquare | Token::OpenCurly => depth += 1,
+				Token::CloseParen | Token::CloseSquare | Token::CloseCurly => depth -= 1,
+				_ => {}
+			}
+			if depth == 0 {
+				break;
+			}
+			close += 1;
+		}
+
+		let arguments: Vec<usize> = (i + 1..close).filter(|&j| tokens[j].token != Token::Whitespace).collect();
+		let name = match arguments.first().map(|&j| &tokens[j].token) {
+			Some(&Token::Ident(ref name)) if is_custom_property_name(name) => name.clone(),
+			_ => return None,
+		};
+		let fallback = match arguments.get(1).map(|&j| (j, &tokens[j].token)) {
+			None => None,
+			Some((j, &Token::Comma)) => {
+				let end = if close < tokens.len() { tokens[close].start } else { source.len() };
+				Some(source_text(tokens[j].end, end))
+			}
+			Some(_) => return None,
+		};
+
+		let value = match lookup(&name[..]) {
+			Some(value) => value,
+			None => match fallback {
+				Some(fallback) => match substitute(fallback.trim(), lookup) {
+					Some(value) => value,
+					None => return None,
+				},
+				None => return None,
+			},
+		};
+
+		result.push_str(&source_text(copied, tokens[i].start));
+		result.push_str(&value[..]);
+		copied = if close < tokens.len() { tokens[close].end } else { source.len() };
+		i = close + 1;
+	}
+
+	result.push_str(&source_text(copied, source.len()));
+	Some(result.trim().to_string())
+}
+
+/// The custom properties registered by @property rules, by name. A later
+/// registration of a name replaces an earlier one
+/// www.w3.org/TR/css-properties-values-api-1/#determining-registration
+#[derive(Debug, Clone, Default)]
+pub struct PropertyRegistry {
+	properties: HashMap<String, PropertyRule>,
+}
+
+impl PropertyRegistry {
+	pub fn new() -> PropertyRegistry {
+		PropertyRegistry::default()
+	}
+
+	pub fn register(&mut self, rule: &PropertyRule) {
+		self.properties.insert(rule.name.clone(), rule.clone());
+	}
+
+	pub fn get(&self, name: &str) -> Option<&PropertyRule> {
+		self.properties.get(name)
+	}
+
+	pub fn rules(&self) -> Vec<&PropertyRule> {
+		self.properties.values().collect()
+	}
+
+	/// Whether the custom property 'name' is inherited, as every
+	/// unregistered one is
+	pub fn inherits(&self, name: &str) -> bool {
+		self.get(name).map_or(true, |r| r.inherits)
+	}
+
+	/// The initial value of the custom property 'name', None for the
+	/// guaranteed-invalid value unregistered ones start with
+	pub fn initial_value(&self, name: &str) -> Option<String> {
+		self.get(name).and_then(|r| r.initial_value.clone())
+	}
+
+	/// The computed value of 'value' given for the custom property 'name'.
+	/// Registered properties whose syntax does not match are invalid at
+	/// computed-value time, giving None; lengths they take are made
+	/// absolute against 'context'
+	pub fn compute(&self, name: &str, value: &str, context: &LengthContext) -> Option<String> {
+		match self.get(name) {
+			Some(rule) => syntax_value(&rule.syntax[..], value, context),
+			None => Some(value.to_string()),
+		}
+	}
+}
+
+/// Whether 'syntax' is a syntax string this engine supports: '*' or
+/// alternatives separated by '|', each a data type of <length>, <number>,
+/// <percentage>, <length-percentage>, <integer>, <color> or <custom-ident>
+/// or a literal keyword
+/// www.w3.org/TR/css-properties-values-api-1/#syntax-strings
+pub fn is_valid_syntax(syntax: &str) -> bool {
+	if syntax.trim() == "*" {
+		return true;
+	}
+
+	syntax.split('|').map(|s| s.trim()).all(|component| {
+		match component {
+			"<length>" | "<number>" | "<percentage>" | "<length-percentage>" |
+			"<integer>" | "<color>" | "<custom-ident>" => true,
+			_ => {
+				!component.is_empty() && CssWideKeyword::from_name(component).is_none() &&
+				component.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+			}
+		}
+	})
+}
+
+/// Whether 'value' matches the syntax string 'syntax'
+pub fn matches_syntax(syntax: &str, value: &str) -> bool {
+	syntax_value(syntax, value, &LengthContext::default()).is_some()
+}
+
+/// The computed value of 'value' for the first alternative of 'syntax' it
+/// matches, None if it matches none
+fn syntax_value(syntax: &str, value: &str, context: &LengthContext) -> Option<String> {
+	let value = value.trim();
+	if syntax.trim() == "*" {
+		return Some(value.to_string());
+	}
+
+	let parsed = string_to_value(value);
+	let px = |v: &Value| v.to_px(context).map(|n| format!("{}px", n));
+
+	for component in syntax.split('|').map(|s| s.trim()) {
+		let computed = match (component, &parsed) {
+			("<length>", _) | ("<length-percentage>", &Value::Size(_, _)) => px(&parsed),
+			("<length-percentage>", &Value::Percentage(_)) => Some(value.to_string()),
+			("<length-percentage>", &Value::Calc(ref c)) if c.calc_type() != Some(CalcType::Number) => {
+				px(&parsed).or(Some(value.to_string()))
+			}
+			("<number>", &Value::Number(n)) => Some(n.to_string()),
+			("<integer>", &Value::Number(n)) if n.fract() == 0.0 => Some(n.to_string()),
+			("<percentage>", &Value::Percentage(_)) => Some(value.to_string()),
+			("<color>", _) => match string_to_property_value(Property::Color, value) {
+				Value::ColorValue(_) | Value::CurrentColor => Some(value.to_string()),
+				_ => None,
+			},
+			("<custom-ident>", _) => {
+				let (_, tokens) = tokenize(value);
+				match (tokens.len(), tokens.first().map(|t| &t.token)) {
+					(1, Some(&Token::Ident(ref i))) if CssWideKeyword::from_name(i).is_none() => Some(i.clone()),
+					_ => None,
+				}
+			}
+			(keyword, _) if !keyword.starts_with('<') && keyword == value => Some(value.to_string()),
+			_ => None,
+		};
+
+		if computed.is_some() {
+			return computed;
+		}
+	}
+
+	None
+}
+
+#[cfg(test)]
+mod test_custom {
+	use std::collections::HashMap;
+	use super::{contains_var, is_valid_syntax, substitute, PropertyRegistry};
+	use super::super::stylesheet::{LengthContext, PropertyRule};
+
+	fn substituted(text: &str, values: &HashMap<&str, &str>) -> Option<String> {
+		substitute(text, &mut |name: &str| values.get(name).map(|v| v.to_string()))
+	}
+
+	#[test]
+	fn custom_var_substitution() {
+		let mut values = HashMap::new();
+		values.insert("--a", "1px");
+		values.insert("--b", "red");
+
+		assert!(contains_var("calc(VAR(--a) * 2)"));
+		assert!(!contains_var("--a"));
+		assert_eq!(substituted("var(--a) solid var(--b)", &values), Some("1px solid red".to_string()));
+		assert_eq!(substituted("calc(var(--a) + var(--c, 2px))", &values), Some("calc(1px + 2px)".to_string()));
+		assert_eq!(substituted("var(--c, var(--d, var(--b)))", &values), Some("red".to_string()));
+		assert_eq!(substituted("var(--c,)", &values), Some("".to_string()));
+		assert_eq!(substituted("var(--c)", &values), None);
+		assert_eq!(substituted("var(a)", &values), None);
+		assert_eq!(substituted("var(--a 1px)", &values), None);
+	}
+
+	#[test]
+	fn custom_registered_syntax() {
+		assert!(is_valid_syntax("*"));
+		assert!(is_valid_syntax("<length> | auto"));
+		assert!(!is_valid_syntax("<size>"));
+		assert!(!is_valid_syntax("<length> | inherit"));
+
+		let mut registry = PropertyRegistry::new();
+		registry.register(&PropertyRule {
+			name: "--size".to_string(),
+			syntax: "<length> | auto".to_string(),
+			inherits: false,
+			initial_value: Some("0px".to_string()),
+		});
+
+		let context = LengthContext::default();
+		assert_eq!(registry.compute("--size", "2em", &context), Some("32px".to_string()));
+		assert_eq!(registry.compute("--size", "auto", &context), Some("auto".to_string()));
+		assert_eq!(registry.compute("--size", "red", &context), None);
+		assert_eq!(registry.compute("--other", "red", &context), Some("red".to_string()));
+		assert!(!registry.inherits("--size"));
+		assert_eq!(registry.initial_value("--other"), None);
+	}
+}
@@ -0,0 +1,666 @@
+use super::parser::{CssParser, ComponentValue};
+use super::tokenizer::Token;
+use super::serializer::serialize_component_value;
+
+/// The size of the viewport in css pixels
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Viewport {
+	pub width: f32,
+	pub height: f32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ColorScheme {
+	Light,
+	Dark,
+}
+
+/// What media queries are evaluated against: the kind of device, its
+/// viewport, its resolution in dots per css pixel and the color scheme
+/// the user prefers
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaEnvironment {
+	pub media_type: MediaType,
+	pub viewport: Viewport,
+	pub resolution: f32,
+	pub color_scheme: ColorScheme,
+}
+
+impl MediaEnvironment {
+	/// A screen with a viewport of 'width' by 'height' css pixels
+	pub fn screen(width: f32, height: f32) -> MediaEnvironment {
+		MediaEnvironment {
+			media_type: MediaType::Screen,
+			viewport: Viewport { width: width, height: height },
+			resolution: 1.0,
+			color_scheme: ColorScheme::Light,
+		}
+	}
+}
+
+impl Default for MediaEnvironment {
+	/// An 800 by 600 screen
+	fn default() -> MediaEnvironment {
+		MediaEnvironment::screen(800.0, 600.0)
+	}
+}
+
+/// A comma separated list of media queries. It matches if any of its
+/// queries does, or if it is empty
+/// www.w3.org/TR/mediaqueries-4/#mq-list
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MediaQueryList {
+	pub queries: Vec<MediaQuery>,
+}
+
+/// '[not | only] <media type> [and <condition>]' or just a condition, whose
+/// media type is 'all'. A query that does not parse becomes 'not all'
+/// www.w3.org/TR/mediaqueries-4/#media
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MediaQuery {
+	pub negated: bool,
+	pub media_type: MediaType,
+	pub condition: Option<MediaCondition>,
+}
+
+/// Media types that are not known match nothing
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum MediaType {
+	All,
+	Screen,
+	Print,
+	Unknown(String),
+}
+
+/// 'Unknown' is anything in parentheses that is not a condition or a
+/// feature this engine knows, with a valid value. It keeps its source
+/// text, parentheses included, so it can be written back
+/// www.w3.org/TR/mediaqueries-4/#typedef-media-condition
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum MediaCondition {
+	Not(Box<MediaCondition>),
+	And(Vec<MediaCondition>),
+	Or(Vec<MediaCondition>),
+	Feature(MediaFeature),
+	Unknown(String),
+}
+
+/// A media feature test. 'min-' and 'max-' features and the range syntax
+/// are all turned into comparisons of the feature with values, as in
+/// 'width >= 600px'. Without comparisons the feature is tested in a
+/// boolean context
+/// www.w3.org/TR/mediaqueries-4/#mq-features
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MediaFeature {
+	pub name: String,
+	pub comparisons: Vec<(Comparison, MediaValue)>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Comparison {
+	Lt,
+	Le,
+	Eq,
+	Ge,
+	Gt,
+}
+
+/// Lengths are in css pixels and resolutions in dots per css pixel
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum MediaValue {
+	Length(f32),
+	Ratio(f32, f32),
+	Resolution(f32),
+	Keyword(String),
+}
+
+impl MediaQueryList {
+	/// Parse a comma separated list of media queries. A list that is only
+	/// white space is empty
+	pub fn from_component_values(values: &[ComponentValue]) -> MediaQueryList {
+		let whitespace = ComponentValue::Token(Token::Whitespace);
+
+		if values.iter().all(|v| *v == whitespace) {
+			return MediaQueryList { queries: Vec::new() };
+		}
+
+		let queries = values.split(|v| *v == ComponentValue::Token(Token::Comma)).map(|query| {
+			let query: Vec<&ComponentValue> = query.iter().filter(|&v| *v != whitespace).collect();
+			parse_media_query(&query[..]).unwrap_or(MediaQuery::not_all())
+		}).collect();
+
+		MediaQueryList {
+			queries: queries,
+		}
+	}
+
+	pub fn matches(&self, environment: &MediaEnvironment) -> bool {
+		self.queries.is_empty() || self.queries.iter().any(|q| q.matches(environment))
+	}
+}
+
+/// Parse the media query list in 'text'
+pub fn parse_media_query_list(text: &str) -> MediaQueryList {
+	let values = CssParser::new(text.to_string()).parse_component_values();
+	MediaQueryList::from_component_values(&values[..])
+}
+
+impl MediaQuery {
+	fn not_all() -> MediaQuery {
+		MediaQuery {
+			negated: true,
+			media_type: MediaType::All,
+			condition: None,
+		}
+	}
+
+	/// An unknown condition makes the query false, negated or not
+	pub fn matches(&self, environment: &MediaEnvironment) -> bool {
+		let type_matches = match self.media_type {
+			MediaType::All => true,
+			MediaType::Unknown(_) => false,
+			ref t => *t == environment.media_type,
+		};
+
+		let result = match self.condition {
+			Some(ref c) if type_matches => c.evaluate(environment),
+			_ => Some(type_matches),
+		};
+
+		match result {
+			Some(r) => r != self.negated,
+			None => false,
+		}
+	}
+}
+
+impl MediaCondition {
+	/// Evaluate with three-valued logic; None is unknown
+	/// www.w3.org/TR/mediaqueries-4/#evaluating
+	pub fn evaluate(&self, environment: &MediaEnvironment) -> Option<bool> {
+		match *self {
+			MediaCondition::Not(ref c) => c.evaluate(environment).map(|r| !r),
+			MediaCondition::And(ref conditions) => {
+				let results: Vec<Option<bool>> = conditions.iter().map(|c| c.evaluate(environment)).collect();
+				if results.contains(&Some(false)) {
+					Some(false)
+				} else if results.contains(&None) {
+					None
+				} else {
+					Some(true)
+				}
+			}
+			MediaCondition::Or(ref conditions) => {
+				let results: Vec<Option<bool>> = conditions.iter().map(|c| c.evaluate(environment)).collect();
+				if results.contains(&Some(true)) {
+					Some(true)
+				} else if results.contains(&None) {
+					None
+				} else {
+					Some(false)
+				}
+			}
+			MediaCondition::Feature(ref f) => Some(f.evaluate(environment)),
+			MediaCondition::Unknown(_) => None,
+		}
+	}
+}
+
+impl MediaFeature {
+	pub fn evaluate(&self, environment: &MediaEnvironment) -> bool {
+		let viewport = environment.viewport;
+		let scheme = match environment.color_scheme {
+			ColorScheme::Light => "light",
+			ColorScheme::Dark => "dark",
+		};
+		let orientation = if viewport.height >= viewport.width { "portrait" } else { "landscape" };
+
+		let actual = match &self.name[..] {
+			"width" => MediaValue::Length(viewport.width),
+			"height" => MediaValue::Length(viewport.height),
+			"aspect-ratio" => MediaValue::Ratio(viewport.width, viewport.height),
+			"resolution" => MediaValue::Resolution(environment.resolution),
+			"orientation" => MediaValue::Keyword(orientation.to_string()),
+			"prefers-color-scheme" => MediaValue::Keyword(scheme.to_string()),
+			_ => return false,
+		};
+
+		// in a boolean context, a feature is true unless it is zero
+		if self.comparisons.is_empty() {
+			return match actual {
+				MediaValue::Keyword(_) => true,
+				ref v => v.number() != 0.0,
+			};
+		}
+
+		self.comparisons.iter().all(|&(comparison, ref value)| {
+			match (&actual, value) {
+				(&MediaValue::Keyword(ref a), &MediaValue::Keyword(ref b)) => comparison == Comparison::Eq && a == b,
+				_ => {
+					let (a, b) = (actual.number(), value.number());
+					match comparison {
+						Comparison::Lt => a < b,
+						Comparison::Le => a <= b,
+						Comparison::Eq => a == b,
+						Comparison::Ge => a >= b,
+						Comparison::Gt => a > b,
+					}
+				}
+			}
+		})
+	}
+}
+
+impl MediaValue {
+	fn number(&self) -> f32 {
+		match *self {
+			MediaValue::Length(n) | MediaValue::Resolution(n) => n,
+			MediaValue::Ratio(a, b) => if b == 0.0 { ::std::f32::INFINITY } else { a / b },
+			MediaValue::Keyword(_) => 0.0,
+		}
+	}
+}
+
+fn ident(value: &ComponentValue) -> Option<String> {
+	match *value {
+		ComponentValue::Token(Token::Ident(ref i)) => Some(i.to_ascii_lowercase()),
+		_ => None,
+	}
+}
+
+/// Parse a media query from its component values, white space left out
+fn parse_media_query(values: &[&ComponentValue]) -> Option<MediaQuery> {
+	let first = values.first().and_then(|v| ident(v));
+	let second = values.get(1).and_then(|v| ident(v));
+
+	// '[not | only] <media type> ...'
+	let (negated, type_at) = match (first.as_ref().map(|f| &f[..]), second.is_some()) {
+		(Some("not"), true) => (true, 1),
+		(Some("only"), true) => (false, 1),
+		(Some("not"), false) | (Some("only"), false) | (None, _) => {
+			return parse_condition(values, true).map(|c| MediaQuery {
+				negated: false,
+				media_type: MediaType::All,
+				condition: Some(c),
+			});
+		}
+		(Some(_), _) => (false, 0),
+	};
+
+	let media_type = match &ident(values[type_at]).unwrap()[..] {
+		"all" => MediaType::All,
+		"screen" => MediaType::Screen,
+		"print" => MediaType::Print,
+		"only" | "not" | "and" | "or" | "layer" => return None,
+		other => MediaType::Unknown(other.to_string()),
+	};
+
+	let rest = &values[type_at + 1..];
+	let condition = if rest.is_empty() {
+		None
+	} else if ident(rest[0]).as_ref().map(|a| &a[..]) == Some("and") {
+		match parse_condition(&rest[1..], false) {
+			Some(c) => Some(c),
+			None => return None,
+		}
+	} else {
+		return None;
+	};
+
+	Some(MediaQuery {
+		negated: negated,
+		media_type: media_type,
+		condition: condition,
+	})
+}
+
+/// 'not <in parens>' or '<in parens>' joined by only 'and's or only 'or's.
+/// 'or' is not allowed after a media type
+fn parse_condition(values: &[&ComponentValue], allow_or: bool) -> Option<MediaCondition> {
+	if values.is_empty() {
+		return None;
+	}
+
+	if ident(values[0]).as_ref().map(|n| &n[..]) == Some("not") {
+		if values.len() != 2 {
+			return None;
+		}
+		return parse_in_parens(values[1]).map(|c| MediaCondition::Not(Box::new(c)));
+	}
+
+	if values.len() % 2 == 0 {
+		return None;
+	}
+
+	let mut conditions = vec![match parse_in_parens(values[0]) {
+		Some(c) => c,
+		None => return None,
+	}];
+	let operator = values.get(1).and_then(|v| ident(v));
+
+	for pair in values[1..].chunks(2) {
+		let op = ident(pair[0]);
+		let valid = match op.as_ref().map(|o| &o[..]) {
+			Some("and") => true,
+			Some("or") => allow_or,
+			_ => false,
+		};
+		if !valid || op != operator {
+			return None;
+		}
+
+		match parse_in_parens(pair[1]) {
+			Some(c) => conditions.push(c),
+			None => return None,
+		}
+	}
+
+	Some(match (conditions.len(), operator.as_ref().map(|o| &o[..])) {
+		(1, _) => conditions.pop().unwrap(),
+		(_, Some("and")) => MediaCondition::And(conditions),
+		_ => MediaCondition::Or(conditions),
+	})
+}
+
+/// '(<condition>)', '(<feature>)' or, as Unknown, any other parenthesized
+/// or function value
+/// www.w3.org/TR/mediaqueries-4/#typedef-media-in-parens
+fn parse_in_parens(value: &ComponentValue) -> Option<MediaCondition> {
+	match *value {
+		ComponentValue::Block(Token::OpenParen, ref inner) => {
+			let inner: Vec<&ComponentValue> = inner.iter()
+				.filter(|v| **v != ComponentValue::Token(Token::Whitespace))
+				.collect();
+
+			Some(parse_feature(&inner[..]).map(MediaCondition::Feature)
+				.or_else(|| parse_condition(&inner[..], true))
+				.unwrap_or_else(|| MediaCondition::Unknown(serialize_component_value(value))))
+		}
+		ComponentValue::Function(_, _) => Some(MediaCondition::Unknown(serialize_component_value(value))),
+		_ => None,
+	}
+}
+
+/// Features compared by number, which take 'min-' and 'max-' prefixes and
+/// the range syntax. The others are compared by keyword
+fn is_range_feature(name: &str) -> bool {
+	match name {
+		"width" | "height" | "aspect-ratio" | "resolution" => true,
+		_ => false,
+	}
+}
+
+/// Parse '<name>', '<name>: <value>' or the range syntax. None if the
+/// feature is unknown or its value is not valid for it
+/// www.w3.org/TR/mediaqueries-4/#typedef-media-feature
+fn parse_feature(values: &[&ComponentValue]) -> Option<MediaFeature> {
+	let name = values.first().and_then(|v| ident(v));
+
+	// '<name>'
+	if values.len() == 1 {
+		return name.and_then(|name| {
+			feature_value(&name[..], None).map(|_| MediaFeature { name: name, comparisons: Vec::new() })
+		});
+	}
+
+	// '<name>: <value>'
+	if values.len() > 2 && values[1] == &ComponentValue::Token(Token::Colon) {
+		let name = match name {
+			Some(n) => n,
+			None => return None,
+		};
+		let (comparison, name) = if name.starts_with("min-") {
+			(Comparison::Ge, name[4..].to_string())
+		} else if name.starts_with("max-") {
+			(Comparison::Le, name[4..].to_string())
+		} else {
+			(Comparison::Eq, name)
+		};
+
+		if comparison != Comparison::Eq && !is_range_feature(&name[..]) {
+			return None;
+		}
+
+		return feature_value(&name[..], Some(&values[2..])).map(|value| MediaFeature {
+			name: name,
+			comparisons: vec![(comparison, value)],
+		});
+	}
+
+	parse_range(values)
+}
+
+/// 'name < value', 'value < name' or 'value < name < value', with any of
+/// '<', '<=', '>', '>=' and, when there is one comparison only, '='
+/// www.w3.org/TR/mediaqueries-4/#typedef-mf-range
+fn parse_range(values: &[&ComponentValue]) -> Option<MediaFeature> {
+	let mut parts: Vec<&[&ComponentValue]> = Vec::new();
+	let mut comparisons = Vec::new();
+	let mut start = 0;
+	let mut i = 0;
+
+	while i < values.len() {
+		let delim = |j: usize| match values.get(j) {
+			Some(&&ComponentValue::Token(Token::Delim(c))) => Some(c),
+			_ => None,
+		};
+		let equals = delim(i + 1) == Some('=');
+		let comparison = match delim(i) {
+			Some('<') if equals => Some(Comparison::Le),
+			Some('<') => Some(Comparison::Lt),
+			Some('>') if equals => Some(Comparison::Ge),
+			Some('>') => Some(Comparison::Gt),
+			Some('=') => Some(Comparison::Eq),
+			_ => None,
+		};
+
+		match comparison {
+			Some(c) => {
+				parts.push(&values[start..i]);
+				comparisons.push(c);
+				i += if equals && c != Comparison::Eq { 2 } else { 1 };
+				start = i;
+			}
+			None => i += 1,
+		}
+	}
+	parts.push(&values[start..]);
+
+	let flip = |c: Comparison| match c {
+		Comparison::Lt => Comparison::Gt,
+		Comparison::Le => Comparison::Ge,
+		Comparison::Eq => Comparison::Eq,
+		Comparison::Ge => Comparison::Le,
+		Comparison::Gt => Comparison::Lt,
+	};
+	let single_name = |part: &[&ComponentValue]| if part.len() == 1 { ident(part[0]) } else { None };
+
+	let (name, comparisons) = match (parts.len(), comparisons.len()) {
+		(2, 1) => match (single_name(parts[0]), single_name(parts[1])) {
+			(Some(name), _) if is_range_feature(&name[..]) => {
+				let value = feature_value(&name[..], Some(parts[1]));
+				(name, vec![(comparisons[0], value)])
+			}
+			(_, Some(name)) => {
+				let value = feature_value(&name[..], Some(parts[0]));
+				(name, vec![(flip(comparisons[0]), value)])
+			}
+			_ => return None,
+		},
+		(3, 2) => {
+			let ascending = |c: Comparison| c == Comparison::Lt || c == Comparison::Le;
+			let descending = |c: Comparison| c == Comparison::Gt || c == Comparison::Ge;
+			let same_way = (ascending(comparisons[0]) && ascending(comparisons[1])) ||
+						   (descending(comparisons[0]) && descending(comparisons[1]));
+			let name = match single_name(parts[1]) {
+				Some(n) if same_way => n,
+				_ => return None,
+			};
+			let low = feature_value(&name[..], Some(parts[0]));
+			let high = feature_value(&name[..], Some(parts[2]));
+			(name, vec![(flip(comparisons[0]), low), (comparisons[1], high)])
+		}
+		_ => return None,
+	};
+
+	if !is_range_feature(&name[..]) || comparisons.iter().any(|c| c.1.is_none()) {
+		return None;
+	}
+
+	Some(MediaFeature {
+		name: name,
+		comparisons: comparisons.into_iter().map(|(c, v)| (c, v.unwrap())).collect(),
+	})
+}
+
+/// Parse 'values' as a value of the feature 'name'. With no values, return
+/// a placeholder if the feature is known, for a test in a boolean context
+fn feature_value(name: &str, values: Option<&[&ComponentValue]>) -> Option<MediaValue> {
+	let values = match values {
+		Some(v) => v,
+		None => {
+			return match name {
+				"width" | "height" | "aspect-ratio" | "resolution" |
+				"orientation" | "prefers-color-scheme" => Some(MediaValue::Keyword(String::new())),
+				_ => None,
+			};
+		}
+	};
+
+	let number = |v: &ComponentValue| match *v {
+		ComponentValue::Token(Token::Number(n)) => Some(n.value as f32),
+		_ => None,
+	};
+
+	match (name, values.len()) {
+		("width", 1) | ("height", 1) => match *values[0] {
+			ComponentValue::Token(Token::Number(n)) if n.value == 0.0 => Some(MediaValue::Length(0.0)),
+			ComponentValue::Token(Token::Dimension(n, ref unit)) => {
+				length_in_px(n.value as f32, &unit.to_ascii_lowercase()[..]).map(MediaValue::Length)
+			}
+			_ => None,
+		},
+		("aspect-ratio", 1) => number(values[0]).map(|n| MediaValue::Ratio(n, 1.0)),
+		("aspect-ratio", 3) if *values[1] == ComponentValue::Token(Token::Delim('/')) => {
+			match (number(values[0]), number(values[2])) {
+				(Some(a), Some(b)) if a >= 0.0 && b >= 0.0 => Some(MediaValue::Ratio(a, b)),
+				_ => None,
+			}
+		}
+		("resolution", 1) => match *values[0] {
+			ComponentValue::Token(Token::Dimension(n, ref unit)) => {
+				let n = n.value as f32;
+				match &unit.to_ascii_lowercase()[..] {
+					"dppx" | "x" => Some(MediaValue::Resolution(n)),
+					"dpi" => Some(MediaValue::Resolution(n / 96.0)),
+					"dpcm" => Some(MediaValue::Resolution(n * 2.54 / 96.0)),
+					_ => None,
+				}
+			}
+			_ => None,
+		},
+		("orientation", 1) => match ident(values[0]) {
+			Some(ref k) if k == "portrait" || k == "landscape" => Some(MediaValue::Keyword(k.clone())),
+			_ => None,
+		},
+		("prefers-color-scheme", 1) => match ident(values[0]) {
+			Some(ref k) if k == "light" || k == "dark" => Some(MediaValue::Keyword(k.clone())),
+			_ => None,
+		},
+		_ => None,
+	}
+}
+
+/// Absolute lengths and font relative ones, taking the font size to be the
+/// initial 16px
+fn length_in_px(n: f32, unit: &str) -> Option<f32> {
+	match unit {
+		"px" => Some(n),
+		"em" | "rem" => Some(n * 16.0),
+		"in" => Some(n * 96.0),
+		"cm" => Some(n * 96.0 / 2.54),
+		"mm" => Some(n * 96.0 / 25.4),
+		"q" => Some(n * 96.0 / 101.6),
+		"pt" => Some(n * 96.0 / 72.0),
+		"pc" => Some(n * 16.0),
+		_ => None,
+	}
+}
+
+#[cfg(test)]
+mod test_media {
+	use super::{parse_media_query_list, MediaEnvironment, MediaType, ColorScheme};
+
+	fn matches(text: &str, environment: &MediaEnvironment) -> bool {
+		parse_media_query_list(text).matches(environment)
+	}
+
+	#[test]
+	fn media_types() {
+		let screen = MediaEnvironment::screen(800.0, 600.0);
+		let mut print = screen.clone();
+		print.media_type = MediaType::Print;
+
+		assert!(matches("", &screen));
+		assert!(matches("all", &screen));
+		assert!(matches("screen, print", &print));
+		assert!(matches("only screen", &screen));
+		assert!(!matches("not screen", &screen));
+		assert!(matches("not screen", &print));
+		assert!(!matches("tv", &screen));
+		assert!(matches("not tv", &screen));
+		assert!(!matches("screen and", &screen));
+		assert!(matches("screen and, print", &print));
+	}
+
+	#[test]
+	fn media_features() {
+		let env = MediaEnvironment::screen(800.0, 600.0);
+
+		assert!(matches("(min-width: 600px)", &env));
+		assert!(!matches("(max-width: 40em)", &env));
+		assert!(matches("screen and (width: 800px) and (height: 600px)", &env));
+		assert!(matches("(orientation: landscape)", &env));
+		assert!(matches("(aspect-ratio: 4/3)", &env));
+		assert!(matches("(min-aspect-ratio: 1)", &env));
+		assert!(matches("(min-resolution: 96dpi)", &env));
+		assert!(!matches("(resolution: 2x)", &env));
+		assert!(matches("(prefers-color-scheme: light)", &env));
+		assert!(matches("(width)", &env));
+		assert!(!matches("(min-orientation: landscape)", &env));
+
+		let mut dark = MediaEnvironment::screen(400.0, 900.0);
+		dark.color_scheme = ColorScheme::Dark;
+		assert!(matches("(orientation: portrait) and (prefers-color-scheme: dark)", &dark));
+	}
+
+	#[test]
+	fn media_ranges_and_logic() {
+		let env = MediaEnvironment::screen(800.0, 600.0);
+
+		assert!(matches("(width >= 800px)", &env));
+		assert!(!matches("(width > 800px)", &env));
+		assert!(matches("(400px < width <= 800px)", &env));
+		assert!(matches("(1000px > width > 100px)", &env));
+		assert!(!matches("(400px < width > 100px)", &env));
+		assert!(matches("(600px = height)", &env));
+
+		assert!(matches("(width < 600px) or (height < 700px)", &env));
+		assert!(!matches("screen and (width < 600px) or (height < 700px)", &env));
+		assert!(matches("not ((width < 600px) and (height < 700px))", &env));
+		assert!(!matches("(width < 600px) and (height < 700px) or (color)", &env));
+
+		// an unknown feature is neither true nor false
+		assert!(!matches("(hover: hover)", &env));
+		assert!(!matches("not (hover: hover)", &env));
+		assert!(matches("(hover: hover) or (width)", &env));
+	}
+}
@@ -1,3 +1,14 @@
+pub mod tokenizer;
 pub mod parser;
 pub mod stylesheet;
+pub mod selector;
+pub mod pseudo_class;
+pub mod media;
+pub mod color;
+pub mod calc;
+pub mod shorthand;
+pub mod cascade;
+pub mod custom;
+pub mod serializer;
+pub mod user_agent;
 pub mod box_model;
\ No newline at end of file
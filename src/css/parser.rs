@@ -1,133 +1,1100 @@
-use text_parser;
-use dom_tree;
+use std::collections::HashMap;
+
+use dom_tree::Namespace;
 use super::stylesheet;
+use super::selector;
+use super::media::MediaQueryList;
+use super::cascade::CssWideKeyword;
+use super::custom;
+use super::tokenizer::{self, Token, SourceToken};
+
+/// A token, or a function or block together with everything inside it
+/// www.w3.org/TR/css-syntax-3/#component-value
+#[derive(Debug, Clone, PartialEq)]
+pub enum ComponentValue {
+	Token(Token),
+	/// A function's name and its arguments
+	Function(String, Vec<ComponentValue>),
+	/// A '{}', '[]' or '()' block, identified by its opening token
+	Block(Token, Vec<ComponentValue>),
+}
+
+/// Something wrong with the input that the parser recovered from, at a
+/// 1-based line and column of the source. 'url' names the imported
+/// stylesheet the warning is in, None for the stylesheet being parsed
+#[derive(Debug, Clone, PartialEq)]
+pub struct CssWarning {
+	pub line: usize,
+	pub column: usize,
+	pub kind: CssWarningKind,
+	pub url: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CssWarningKind {
+	/// A rule's prelude, given here, is not a valid selector list. The
+	/// rule was dropped
+	InvalidSelector(String),
+	/// The input ended before a rule's block
+	MissingBlock,
+	/// Something in a declaration block that is not 'name: value'
+	InvalidDeclaration,
+	UnknownProperty(String),
+	/// The value of the named property has a bad string or url in it
+	InvalidValue(String),
+	/// An at-rule that is not supported, or not allowed where it is
+	IgnoredAtRule(String),
+	/// An @namespace rule that is malformed or names an unknown namespace
+	InvalidNamespace,
+	/// A known at-rule, named here, with a malformed prelude or missing
+	/// its block
+	InvalidAtRule(String),
+	/// The stylesheet at this url could not be loaded for an @import
+	ImportFailed(String),
+	/// The input ended inside a block, function or string
+	UnexpectedEnd,
+}
+
+/// Called with the url of an @import rule for the text of the stylesheet
+/// it names; None if there is no such stylesheet
+pub type StyleSheetLoader = Box<FnMut(&str) -> Option<String>>;
+
+/// An at-rule as the syntax sees it. Its prelude and the contents of its
+/// block, if it has one, are ranges of tokens
+struct AtRule {
+	name: String,
+	prelude: (usize, usize),
+	block: Option<(usize, usize)>,
+}
+
+/// A declaration as the syntax sees it: the token it starts at, its name,
+/// the token range of its value, without any '!important', and whether it
+/// had one
+struct RawDeclaration {
+	start: usize,
+	name: String,
+	value: (usize, usize),
+	important: bool,
+}
 
+/// The parser works over the tokens of its input. Values are handed on as
+/// their source text, which is rebuilt from the tokens so comments are
+/// dropped. 'end' limits the tokens seen while the contents of a block are
+/// parsed. 'namespaces' holds the prefixes declared by @namespace rules
+/// seen so far. The default namespace, if any, is stored under the empty
+/// prefix. Parse errors are recovered from as the syntax spec says and
+/// collected in 'warnings'. Stylesheets named by @import rules are parsed
+/// with the text 'loader' gives for them; 'importing' holds the urls of
+/// the stylesheets being imported into this one, so an import cycle stops.
+/// The warnings of an imported stylesheet are kept in 'import_warnings'
+/// with the line and column of its @import, where they are merged in
 pub struct CssParser {
-	parse: text_parser::TextParser,
+	source: Vec<char>,
+	tokens: Vec<SourceToken>,
+	pos: usize,
+	end: usize,
+	namespaces: HashMap<String, Namespace>,
+	warnings: Vec<CssWarning>,
+	import_warnings: Vec<((usize, usize), CssWarning)>,
+	loader: Option<StyleSheetLoader>,
+	importing: Vec<String>,
 }
 
 impl CssParser {
 	pub fn new(input: String) -> CssParser {
-		let parser = text_parser::TextParser::new(input);
+		let (source, tokens) = tokenizer::tokenize(&input[..]);
+		let end = tokens.len();
+
 		CssParser {
-			parse: parser,
+			source: source,
+			tokens: tokens,
+			pos: 0,
+			end: end,
+			namespaces: HashMap::new(),
+			warnings: Vec::new(),
+			import_warnings: Vec::new(),
+			loader: None,
+			importing: Vec::new(),
 		}
 	}
 
-	pub fn parse_selector(&mut self) -> Option<stylesheet::Selector> {
-		
-		match self.parse.peek_char() {
-			Some(c) => {
-				if c.is_whitespace() {
-					self.parse.consume_whitespace();
-				} 
-
-				let selector = self.parse.consume_while(|c| c != '{');
-
-				// Get rid of whitespace between text and leading {
-				match selector.as_slice().trim_right() {
-					"title" => Some(stylesheet::Selector::SelectorType(dom_tree::ElementType::Title)),
-					"body" => Some(stylesheet::Selector::SelectorType(dom_tree::ElementType::Body)),
-					"h1" | 
-					"h2" | 
-					"h3" | 
-					"h4" => Some(stylesheet::Selector::SelectorType(dom_tree::ElementType::Head)),
-					_ => None,
+	/// Use 'loader' for the stylesheets named by @import rules. Without
+	/// one, imports fail
+	pub fn set_loader(&mut self, loader: StyleSheetLoader) {
+		self.loader = Some(loader);
+	}
+
+	/// Resolve namespace prefixes in selectors with 'namespaces', as if
+	/// they had been declared by @namespace rules before the input
+	pub fn set_namespaces(&mut self, namespaces: HashMap<String, Namespace>) {
+		self.namespaces = namespaces;
+	}
+
+	/// The warnings collected so far
+	pub fn warnings(&self) -> &[CssWarning] {
+		&self.warnings[..]
+	}
+
+	/// Record a warning at the start of token 'i', or at the end of the
+	/// input if there is no such token
+	fn warn(&mut self, i: usize, kind: CssWarningKind) {
+		let (line, column) = self.position(i);
+
+		self.warnings.push(CssWarning {
+			line: line,
+			column: column,
+			kind: kind,
+			url: None,
+		});
+	}
+
+	/// The line and column token 'i' starts at, or of the end of the input
+	/// if there is no such token
+	fn position(&self, i: usize) -> (usize, usize) {
+		let offset = self.tokens.get(i).map_or(self.source.len(), |t| t.start);
+		let mut line = 1;
+		let mut column = 1;
+
+		for &c in &self.source[..offset] {
+			if c == '\n' {
+				line += 1;
+				column = 1;
+			} else {
+				column += 1;
+			}
+		}
+
+		(line, column)
+	}
+
+	/// Warn that the input ended early, once however many blocks were open
+	fn warn_unexpected_end(&mut self) {
+		if self.pos < self.tokens.len() {
+			return;
+		}
+		let warned = self.warnings.last().map_or(false, |w| w.kind == CssWarningKind::UnexpectedEnd);
+		if !warned {
+			let end = self.tokens.len();
+			self.warn(end, CssWarningKind::UnexpectedEnd);
+		}
+	}
+
+	fn peek(&self) -> Option<&Token> {
+		if self.pos < self.end {
+			Some(&self.tokens[self.pos].token)
+		} else {
+			None
+		}
+	}
+
+	fn consume_whitespace(&mut self) {
+		while self.peek() == Some(&Token::Whitespace) {
+			self.pos += 1;
+		}
+	}
+
+	/// Run 'f' over the tokens in [start, end) only, as the contents of a
+	/// block are parsed
+	fn within<T, F: FnOnce(&mut CssParser) -> T>(&mut self, start: usize, end: usize, f: F) -> T {
+		let (pos, limit) = (self.pos, self.end);
+		self.pos = start;
+		self.end = end;
+
+		let result = f(self);
+
+		self.pos = pos;
+		self.end = limit;
+		result
+	}
+
+	/// The token ranges of the component values in [start, end), leaving
+	/// out white space
+	fn component_ranges(&mut self, start: usize, end: usize) -> Vec<(usize, usize)> {
+		self.within(start, end, |parser| {
+			let mut ranges = Vec::new();
+			loop {
+				parser.consume_whitespace();
+				let first = parser.pos;
+				if parser.consume_component_value().is_none() {
+					return ranges;
+				}
+				ranges.push((first, parser.pos));
+			}
+		})
+	}
+
+	fn component_values(&mut self, start: usize, end: usize) -> Vec<ComponentValue> {
+		self.within(start, end, |parser| {
+			let mut values = Vec::new();
+			while let Some(value) = parser.consume_component_value() {
+				values.push(value);
+			}
+			values
+		})
+	}
+
+	/// The source text of the tokens in [start, end), with white space
+	/// collapsed and trimmed. The tokenizer drops comments, so a gap
+	/// between two tokens becomes a space to keep them apart, e.g. 'a/**/b'
+	/// is 'a b' rather than 'ab'
+	fn text(&self, start: usize, end: usize) -> String {
+		let mut text = String::new();
+		let mut previous_end: Option<usize> = None;
+
+		for t in &self.tokens[start..end] {
+			match previous_end {
+				Some(e) if e < t.start && !text.ends_with(' ') => text.push(' '),
+				_ => {}
+			}
+			previous_end = Some(t.end);
+
+			match t.token {
+				Token::Whitespace => {
+					if !text.ends_with(' ') {
+						text.push(' ');
+					}
 				}
+				_ => text.extend(self.source[t.start..t.end].iter().cloned()),
 			}
-			None => None,
 		}
 
+		text.trim().to_string()
+	}
+
+	/// www.w3.org/TR/css-syntax-3/#consume-component-value
+	fn consume_component_value(&mut self) -> Option<ComponentValue> {
+		let token = match self.peek() {
+			Some(t) => t.clone(),
+			None => return None,
+		};
+		self.pos += 1;
+
+		Some(match token {
+			Token::OpenCurly => ComponentValue::Block(token, self.consume_block_values(Token::CloseCurly)),
+			Token::OpenSquare => ComponentValue::Block(token, self.consume_block_values(Token::CloseSquare)),
+			Token::OpenParen => ComponentValue::Block(token, self.consume_block_values(Token::CloseParen)),
+			Token::Function(name) => ComponentValue::Function(name, self.consume_block_values(Token::CloseParen)),
+			t => ComponentValue::Token(t),
+		})
 	}
 
-	fn add_declaration_if_valid(&mut self, 
-								dec_list: &mut Vec<stylesheet::Declaration>,
-								prop : &mut Option<String>,
-								val : &mut Option<String>) {
-		if prop.is_some() && val.is_some() {
-			let parsed_prop = stylesheet::string_to_property(&prop.clone().unwrap());
-			let parsed_val = stylesheet::string_to_value(&val.clone().unwrap());
+	/// Consume the contents of a block or function whose opening token has
+	/// been consumed, up to and including 'close'
+	/// www.w3.org/TR/css-syntax-3/#consume-simple-block
+	fn consume_block_values(&mut self, close: Token) -> Vec<ComponentValue> {
+		let mut values = Vec::new();
 
-			if parsed_prop.is_some() {
-				dec_list.push(stylesheet::Declaration { 
-					property_name: parsed_prop.unwrap(), 
-					property_value: parsed_val
-				});	
-			} 								
+		loop {
+			match self.peek() {
+				None => {
+					self.warn_unexpected_end();
+					return values;
+				}
+				Some(t) if *t == close => {
+					self.pos += 1;
+					return values;
+				}
+				_ => {}
+			}
+			values.extend(self.consume_component_value());
 		}
 	}
 
-	pub fn parse_declaration(&mut self) -> Vec<stylesheet::Declaration> {
-		
-		let mut prop : Option<String> = None;	
-		let mut val : Option<String> = None;
+	/// As consume_block_values, but return the token range of the contents
+	fn consume_block_range(&mut self, close: Token) -> (usize, usize) {
+		let start = self.pos;
+
+		loop {
+			match self.peek() {
+				None => {
+					self.warn_unexpected_end();
+					return (start, self.pos);
+				}
+				Some(t) if *t == close => {
+					self.pos += 1;
+					return (start, self.pos - 1);
+				}
+				_ => {}
+			}
+			self.consume_component_value();
+		}
+	}
 
-		let mut retval : Vec<stylesheet::Declaration> = Vec::new();
+	/// www.w3.org/TR/css-syntax-3/#consume-at-rule
+	fn consume_at_rule(&mut self) -> AtRule {
+		let name = match self.peek().cloned() {
+			Some(Token::AtKeyword(name)) => name,
+			_ => String::new(),
+		};
+		self.pos += 1;
 
+		let start = self.pos;
 		loop {
+			match self.peek().cloned() {
+				None => break,
+				Some(Token::Semicolon) => {
+					self.pos += 1;
+					return AtRule { name: name, prelude: (start, self.pos - 1), block: None };
+				}
+				Some(Token::OpenCurly) => {
+					let prelude = (start, self.pos);
+					self.pos += 1;
+					let block = self.consume_block_range(Token::CloseCurly);
+					return AtRule { name: name, prelude: prelude, block: Some(block) };
+				}
+				Some(_) => {
+					self.consume_component_value();
+				}
+			}
+		}
+
+		AtRule { name: name, prelude: (start, self.pos), block: None }
+	}
 
+	/// Consume component values up to a '{' at this level, the prelude of
+	/// a qualified rule, and return their token range. The '{' is left
+	/// www.w3.org/TR/css-syntax-3/#consume-qualified-rule
+	fn consume_prelude(&mut self) -> (usize, usize) {
+		let start = self.pos;
 
-			if self.parse.end_of_string() {
-				break;
+		while self.peek().map_or(false, |t| *t != Token::OpenCurly) {
+			self.consume_component_value();
+		}
+
+		(start, self.pos)
+	}
+
+	fn consume_until_semicolon(&mut self) {
+		while self.peek().map_or(false, |t| *t != Token::Semicolon) {
+			self.consume_component_value();
+		}
+	}
+
+	/// Parse the tokens in [start, end), the contents of a block, as
+	/// property declarations
+	fn parse_declarations_in(&mut self, start: usize, end: usize) -> Vec<stylesheet::Declaration> {
+		let raw = self.within(start, end, |parser| parser.consume_declaration_list());
+		raw.iter().flat_map(|d| self.property_declarations(d).into_iter()).collect()
+	}
+
+	/// At-rules inside the list are consumed and ignored. Anything that
+	/// does not start with a name is dropped up to the next ';'
+	/// www.w3.org/TR/css-syntax-3/#consume-list-of-declarations
+	fn consume_declaration_list(&mut self) -> Vec<RawDeclaration> {
+		let mut declarations = Vec::new();
+
+		loop {
+			match self.peek().cloned() {
+				None => return declarations,
+				Some(Token::Whitespace) | Some(Token::Semicolon) => self.pos += 1,
+				Some(Token::AtKeyword(name)) => {
+					let start = self.pos;
+					self.consume_at_rule();
+					self.warn(start, CssWarningKind::IgnoredAtRule(name));
+				}
+				Some(Token::Ident(_)) => {
+					let start = self.pos;
+					self.consume_until_semicolon();
+					let end = self.pos;
+					declarations.extend(self.consume_declaration(start, end));
+				}
+				Some(_) => {
+					let start = self.pos;
+					self.consume_until_semicolon();
+					self.warn(start, CssWarningKind::InvalidDeclaration);
+				}
 			}
-			match self.parse.peek_char() {
-				Some(c) => {
+		}
+	}
 
-					match c {
-						c if c.is_whitespace() => {
-							self.parse.consume_whitespace();
-						}
-						'{' => {
-							self.parse.consume_char();
+	/// Split the tokens in [start, end) into 'name: value'. Return None,
+	/// with a warning, if there is no ':'. A trailing '!important' is not
+	/// part of the value but makes the declaration important
+	/// www.w3.org/TR/css-syntax-3/#consume-declaration
+	fn consume_declaration(&mut self, start: usize, end: usize) -> Option<RawDeclaration> {
+		// custom property names are case-sensitive
+		let name = match self.tokens[start].token {
+			Token::Ident(ref name) if custom::is_custom_property_name(name) => name.clone(),
+			Token::Ident(ref name) => name.to_ascii_lowercase(),
+			_ => return None,
+		};
+
+		let mut colon = start + 1;
+		while colon < end && self.tokens[colon].token == Token::Whitespace {
+			colon += 1;
+		}
+		if colon == end || self.tokens[colon].token != Token::Colon {
+			self.warn(start, CssWarningKind::InvalidDeclaration);
+			return None;
+		}
+
+		let mut value_end = self.trim_whitespace_end(colon + 1, end);
+		let mut important = match self.tokens[value_end - 1].token {
+			Token::Ident(ref i) if value_end > colon + 1 => i.eq_ignore_ascii_case("important"),
+			_ => false,
+		};
+		if important {
+			let bang = self.trim_whitespace_end(colon + 1, value_end - 1);
+			if bang > colon + 1 && self.tokens[bang - 1].token == Token::Delim('!') {
+				value_end = bang - 1;
+			} else {
+				important = false;
+			}
+		}
+
+		Some(RawDeclaration {
+			start: start,
+			name: name,
+			value: (colon + 1, value_end),
+			important: important,
+		})
+	}
+
+	/// The property declarations 'raw' sets, several for a shorthand.
+	/// Return none, with a warning, if the property is unknown or the value
+	/// has a bad token in it
+	fn property_declarations(&mut self, raw: &RawDeclaration) -> Vec<stylesheet::Declaration> {
+		let (start, end) = raw.value;
+		let mut declarations = match stylesheet::string_to_declarations(&raw.name[..], &self.text(start, end)) {
+			Some(d) => d,
+			None => {
+				self.warn(raw.start, CssWarningKind::UnknownProperty(raw.name.clone()));
+				return Vec::new();
+			}
+		};
+
+		let bad = (start..end).find(|&i| match self.tokens[i].token {
+			Token::BadString | Token::BadUrl => true,
+			_ => false,
+		});
+		if let Some(i) = bad {
+			self.warn(i, CssWarningKind::InvalidValue(raw.name.clone()));
+			return Vec::new();
+		}
+
+		for declaration in declarations.iter_mut() {
+			declaration.important = raw.important;
+		}
+		declarations
+	}
+
+	/// The end of [start, end) without its trailing whitespace tokens
+	fn trim_whitespace_end(&self, start: usize, end: usize) -> usize {
+		let mut end = end;
+		while end > start && self.tokens[end - 1].token == Token::Whitespace {
+			end -= 1;
+		}
+		end
+	}
+
+	/// Parse an at-rule. 'top_level' is false for rules nested in another
+	/// rule's block, where @import, @namespace and @charset are not allowed.
+	/// Unknown at-rules are skipped with their blocks. An @import rule is
+	/// returned without its stylesheet; see load_import
+	pub fn parse_at_rule(&mut self, top_level: bool) -> Option<stylesheet::CssRule> {
+		self.consume_whitespace();
+
+		match self.peek() {
+			Some(&Token::AtKeyword(_)) => {}
+			_ => return None,
+		}
+
+		let start = self.pos;
+		let rule = self.consume_at_rule();
+		let name = rule.name.to_ascii_lowercase();
+		let (prelude_start, prelude_end) = rule.prelude;
+
+		let allowed = match &name[..] {
+			"media" | "supports" | "font-face" | "keyframes" | "page" | "layer" | "property" => true,
+			"import" | "namespace" | "charset" => top_level,
+			_ => false,
+		};
+		if !allowed {
+			self.warn(start, CssWarningKind::IgnoredAtRule(name));
+			return None;
+		}
+
+		let parsed = match (&name[..], rule.block) {
+			("media", Some((s, e))) => Some(stylesheet::CssRule::Media(stylesheet::MediaRule {
+				media: MediaQueryList::from_component_values(&self.component_values(prelude_start, prelude_end)[..]),
+				rules: self.within(s, e, |parser| parser.consume_rule_list(false)),
+			})),
+			("supports", Some((s, e))) => match self.supports_condition(prelude_start, prelude_end) {
+				Some(supported) => Some(stylesheet::CssRule::Supports(stylesheet::SupportsRule {
+					condition: self.text(prelude_start, prelude_end),
+					supported: supported,
+					rules: self.within(s, e, |parser| parser.consume_rule_list(false)),
+				})),
+				None => None,
+			},
+			("font-face", Some((s, e))) => {
+				let raw = self.within(s, e, |parser| parser.consume_declaration_list());
+				Some(stylesheet::CssRule::FontFace(stylesheet::FontFaceRule {
+					descriptors: raw.iter().map(|d| (d.name.clone(), self.text(d.value.0, d.value.1))).collect(),
+				}))
+			}
+			("keyframes", Some((s, e))) => {
+				let values = self.component_values(prelude_start, prelude_end);
+				let whitespace = ComponentValue::Token(Token::Whitespace);
+				let names: Vec<&ComponentValue> = values.iter().filter(|&v| *v != whitespace).collect();
+				let name = match (names.len(), names.first()) {
+					(1, Some(&&ComponentValue::Token(Token::Ident(ref n)))) => Some(n.clone()),
+					(1, Some(&&ComponentValue::Token(Token::QuotedString(ref n)))) => Some(n.clone()),
+					_ => None,
+				};
+				name.map(|name| stylesheet::CssRule::Keyframes(stylesheet::KeyframesRule {
+					name: name,
+					keyframes: self.within(s, e, |parser| parser.consume_keyframe_list()),
+				}))
+			}
+			("layer", block) => {
+				let values = self.component_values(prelude_start, prelude_end);
+				match (layer_names(&values[..]), block) {
+					(Some(names), None) if !names.is_empty() => {
+						Some(stylesheet::CssRule::Layer(stylesheet::LayerRule {
+							names: names,
+							rules: None,
+						}))
+					}
+					(Some(names), Some((s, e))) if names.len() <= 1 => {
+						Some(stylesheet::CssRule::Layer(stylesheet::LayerRule {
+							names: names,
+							rules: Some(self.within(s, e, |parser| parser.consume_rule_list(false))),
+						}))
+					}
+					_ => None,
+				}
+			}
+			("property", Some((s, e))) => {
+				let values = self.component_values(prelude_start, prelude_end);
+				let whitespace = ComponentValue::Token(Token::Whitespace);
+				let names: Vec<&ComponentValue> = values.iter().filter(|&v| *v != whitespace).collect();
+				let raw = self.within(s, e, |parser| parser.consume_declaration_list());
+				let descriptor = |name: &str| {
+					raw.iter().rev().find(|d| d.name == name).map(|d| self.text(d.value.0, d.value.1))
+				};
+				match (names.len(), names.first()) {
+					(1, Some(&&ComponentValue::Token(Token::Ident(ref name)))) => {
+						property_rule(name.clone(), descriptor("syntax"),
+									  descriptor("inherits"), descriptor("initial-value"))
+							.map(stylesheet::CssRule::Property)
+					}
+					_ => None,
+				}
+			}
+			("page", Some((s, e))) => Some(stylesheet::CssRule::Page(stylesheet::PageRule {
+				selector: self.text(prelude_start, prelude_end),
+				declarations: self.parse_declarations_in(s, e),
+			})),
+			("import", None) => {
+				let ranges = self.component_ranges(prelude_start, prelude_end);
+				let url = ranges.first().and_then(|&(s, e)| {
+					self.component_values(s, e).first().and_then(|v| url_value(v)).map(|u| u.clone())
+				});
+				let media = match ranges.first() {
+					Some(&(_, e)) => MediaQueryList::from_component_values(&self.component_values(e, prelude_end)[..]),
+					None => MediaQueryList { queries: Vec::new() },
+				};
+				url.map(|url| stylesheet::CssRule::Import(stylesheet::ImportRule {
+					url: url,
+					media: media,
+					stylesheet: None,
+				}))
+			}
+			("namespace", None) => {
+				let values = self.component_values(prelude_start, prelude_end);
+				match namespace_prelude(&values[..]) {
+					Some((prefix, ns)) => Some(stylesheet::CssRule::Namespace(stylesheet::NamespaceRule {
+						prefix: prefix,
+						namespace: ns,
+					})),
+					None => {
+						self.warn(start, CssWarningKind::InvalidNamespace);
+						return None;
+					}
+				}
+			}
+			("charset", None) => return None,
+			_ => None,
+		};
+
+		if parsed.is_none() {
+			self.warn(start, CssWarningKind::InvalidAtRule(name));
+		}
+		parsed
+	}
+
+	/// Parse the stylesheet an @import rule names into the rule. It is
+	/// parsed with this parser's loader and on its own, so the namespaces
+	/// declared in either do not apply to the other. Its warnings are
+	/// tagged with its url, unless they come from a stylesheet it imports
+	fn load_import(&mut self, start: usize, import: &mut stylesheet::ImportRule) {
+		let text = if self.importing.contains(&import.url) {
+			None
+		} else {
+			match self.loader {
+				Some(ref mut load) => load(&import.url[..]),
+				None => None,
+			}
+		};
+
+		let text = match text {
+			Some(t) => t,
+			None => {
+				self.warn(start, CssWarningKind::ImportFailed(import.url.clone()));
+				return;
+			}
+		};
+
+		let mut parser = CssParser::new(text);
+		parser.loader = self.loader.take();
+		parser.importing = self.importing.clone();
+		parser.importing.push(import.url.clone());
+
+		let (stylesheet, warnings) = parser.parse_css_with_warnings();
+		import.stylesheet = Some(stylesheet);
+		self.loader = parser.loader.take();
+
+		let position = self.position(start);
+		for mut warning in warnings.into_iter() {
+			if warning.url.is_none() {
+				warning.url = Some(import.url.clone());
+			}
+			self.import_warnings.push((position, warning));
+		}
+	}
+
+	/// Evaluate the @supports condition in the tokens [start, end). None if
+	/// it is not a condition
+	/// www.w3.org/TR/css-conditional-3/#typedef-supports-condition
+	fn supports_condition(&mut self, start: usize, end: usize) -> Option<bool> {
+		let ranges = self.component_ranges(start, end);
+		let keyword = |parser: &CssParser, (s, _): (usize, usize)| match parser.tokens[s].token {
+			Token::Ident(ref i) => Some(i.to_ascii_lowercase()),
+			_ => None,
+		};
+
+		if ranges.is_empty() {
+			return None;
+		}
+
+		if keyword(self, ranges[0]).as_ref().map(|k| &k[..]) == Some("not") {
+			if ranges.len() != 2 {
+				return None;
+			}
+			return self.supports_in_parens(ranges[1]).map(|s| !s);
+		}
+
+		// 'a and b and c' or 'a or b or c', never mixed without parentheses
+		if ranges.len() % 2 == 0 {
+			return None;
+		}
+		let operator = if ranges.len() > 1 { keyword(self, ranges[1]) } else { None };
+		let mut result = match self.supports_in_parens(ranges[0]) {
+			Some(r) => r,
+			None => return None,
+		};
+
+		for pair in ranges[1..].chunks(2) {
+			let op = keyword(self, pair[0]);
+			let and = match op.as_ref().map(|o| &o[..]) {
+				Some("and") => true,
+				Some("or") => false,
+				_ => return None,
+			};
+			if op != operator {
+				return None;
+			}
+
+			let next = match self.supports_in_parens(pair[1]) {
+				Some(n) => n,
+				None => return None,
+			};
+			result = if and { result && next } else { result || next };
+		}
+
+		Some(result)
+	}
+
+	/// A parenthesized condition or declaration, or a 'selector()' test.
+	/// Anything else in parentheses or a function is unknown and false
+	/// www.w3.org/TR/css-conditional-3/#typedef-supports-in-parens
+	fn supports_in_parens(&mut self, (start, end): (usize, usize)) -> Option<bool> {
+		let inner_end = if end > start + 1 && self.tokens[end - 1].token == Token::CloseParen {
+			end - 1
+		} else {
+			end
+		};
+
+		match self.tokens[start].token.clone() {
+			Token::OpenParen => {
+				// parentheses around a condition are not a malformed declaration
+				let declaration = match self.component_ranges(start + 1, inner_end).first() {
+					Some(&(s, _)) => {
+						let warnings = self.warnings.len();
+						let raw = self.consume_declaration(s, inner_end);
+						self.warnings.truncate(warnings);
+						raw
+					}
+					_ => None,
+				};
+
+				match declaration {
+					Some(raw) => {
+						let (s, e) = raw.value;
+						let declarations = stylesheet::string_to_declarations(&raw.name[..], &self.text(s, e));
+						Some(declarations.map_or(false, |d| {
+							d.iter().all(|d| d.property_value != stylesheet::Value::Missing)
+						}))
+					}
+					None => Some(self.supports_condition(start + 1, inner_end).unwrap_or(false)),
+				}
+			}
+			Token::Function(ref name) if name.eq_ignore_ascii_case("selector") => {
+				let text = self.text(start + 1, inner_end);
+				Some(selector::parse_selector(&text[..], &self.namespaces).is_some())
+			}
+			Token::Function(_) => Some(false),
+			_ => None,
+		}
+	}
+
+	/// Consume the keyframe blocks of a @keyframes rule. A block whose
+	/// prelude is not a list of 'from', 'to' and percentages is dropped
+	/// www.w3.org/TR/css-animations-1/#typedef-keyframe-selector
+	fn consume_keyframe_list(&mut self) -> Vec<stylesheet::Keyframe> {
+		let mut keyframes = Vec::new();
+
+		loop {
+			match self.peek().cloned() {
+				None => return keyframes,
+				Some(Token::Whitespace) => self.pos += 1,
+				Some(Token::AtKeyword(name)) => {
+					let start = self.pos;
+					self.consume_at_rule();
+					self.warn(start, CssWarningKind::IgnoredAtRule(name));
+				}
+				Some(_) => {
+					let (start, end) = self.consume_prelude();
+					if self.peek().is_none() {
+						self.warn(end, CssWarningKind::MissingBlock);
+						return keyframes;
+					}
+					self.pos += 1;
+					let (s, e) = self.consume_block_range(Token::CloseCurly);
+
+					match self.keyframe_offsets(start, end) {
+						Some(offsets) => keyframes.push(stylesheet::Keyframe {
+							offsets: offsets,
+							declarations: self.parse_declarations_in(s, e),
+						}),
+						None => {
+							let text = self.text(start, end);
+							self.warn(start, CssWarningKind::InvalidSelector(text));
 						}
-						':' => {
-							self.parse.consume_char();
-							val = Some(self.parse.consume_while(|c| match c {
-								';' | '}' => false,
-								_ => true}));
+					}
+				}
+			}
+		}
+	}
+
+	fn keyframe_offsets(&mut self, start: usize, end: usize) -> Option<Vec<f32>> {
+		let mut offsets = Vec::new();
+		let ranges = self.component_ranges(start, end);
+
+		for (i, &(s, e)) in ranges.iter().enumerate() {
+			if i % 2 == 1 {
+				if self.tokens[s].token != Token::Comma {
+					return None;
+				}
+				continue;
+			}
+
+			let offset = match self.tokens[s].token {
+				_ if e != s + 1 => None,
+				Token::Ident(ref i) if i.eq_ignore_ascii_case("from") => Some(0.0),
+				Token::Ident(ref i) if i.eq_ignore_ascii_case("to") => Some(100.0),
+				Token::Percentage(n) if n.value >= 0.0 && n.value <= 100.0 => Some(n.value as f32),
+				_ => None,
+			};
+			match offset {
+				Some(o) => offsets.push(o),
+				None => return None,
+			}
+		}
+
+		if offsets.is_empty() || ranges.len() % 2 == 0 {
+			None
+		} else {
+			Some(offsets)
+		}
+	}
+
+	/// Parse a selector up to the '{' of its block
+	pub fn parse_selector(&mut self) -> Option<stylesheet::Selector> {
+		let (start, end) = self.consume_prelude();
+		selector::parse_selector(&self.text(start, end)[..], &self.namespaces)
+	}
+
+	/// Parse a comma separated selector list up to the '{' of its block.
+	/// Return None if any selector in the list is invalid
+	pub fn parse_selector_list(&mut self) -> Option<Vec<stylesheet::Selector>> {
+		let (start, end) = self.consume_prelude();
+
+		if start == end {
+			return None;
+		}
+
+		let text = self.text(start, end);
+		let selectors = selector::parse_selector_list(&text[..], &self.namespaces);
+		if selectors.is_none() {
+			self.warn(start, CssWarningKind::InvalidSelector(text));
+		}
+
+		selectors
+	}
+
+	/// Parse a '{...}' block of declarations. Without the '{', parse the
+	/// declarations up to the '}' that closes the block
+	pub fn parse_declaration(&mut self) -> Vec<stylesheet::Declaration> {
+		self.consume_whitespace();
+
+		if self.peek() == Some(&Token::OpenCurly) {
+			self.pos += 1;
+		}
+
+		let (start, end) = self.consume_block_range(Token::CloseCurly);
+		self.parse_declarations_in(start, end)
+	}
+
+	/// Consume a list of rules. A style rule whose prelude is not a valid
+	/// selector list is dropped along with its block. At the top level,
+	/// @import rules are only allowed before any others and @namespace
+	/// rules only before any but @import
+	/// www.w3.org/TR/css-syntax-3/#consume-list-of-rules
+	fn consume_rule_list(&mut self, top_level: bool) -> Vec<stylesheet::CssRule> {
+		let mut rules: Vec<stylesheet::CssRule> = Vec::new();
+
+		loop {
+			match self.peek().cloned() {
+				None => return rules,
+				Some(Token::Whitespace) => self.pos += 1,
+				Some(Token::CDO) | Some(Token::CDC) if top_level => self.pos += 1,
+				Some(Token::AtKeyword(_)) => {
+					// @layer statements may come before @import rules
+					let start = self.pos;
+					let only_imports = rules.iter().all(|r| match *r {
+						stylesheet::CssRule::Import(_) => true,
+						stylesheet::CssRule::Layer(stylesheet::LayerRule { rules: None, .. }) => true,
+						_ => false,
+					});
+					let only_preamble = rules.iter().all(|r| match *r {
+						stylesheet::CssRule::Import(_) | stylesheet::CssRule::Namespace(_) => true,
+						stylesheet::CssRule::Layer(stylesheet::LayerRule { rules: None, .. }) => true,
+						_ => false,
+					});
+
+					match self.parse_at_rule(top_level) {
+						Some(stylesheet::CssRule::Import(_)) if !only_imports => {
+							self.warn(start, CssWarningKind::IgnoredAtRule("import".to_string()));
 						}
-						'}' => { 
-							self.parse.consume_char();
-							self.add_declaration_if_valid(&mut retval, &mut prop, &mut val);
-							break;
+						Some(stylesheet::CssRule::Namespace(_)) if !only_preamble => {
+							self.warn(start, CssWarningKind::IgnoredAtRule("namespace".to_string()));
 						}
-						';' => {
-							self.parse.consume_char();
-							self.add_declaration_if_valid(&mut retval, &mut prop, &mut val);
-							prop = None;
-							val = None;
+						Some(stylesheet::CssRule::Import(mut import)) => {
+							self.load_import(start, &mut import);
+							rules.push(stylesheet::CssRule::Import(import));
 						}
-						_ => {
-							prop = Some(self.parse.consume_while(|c| match c {
-								':' => false,
-								_ => true}));
+						Some(stylesheet::CssRule::Namespace(ns)) => {
+							self.namespaces.insert(ns.prefix.clone(), ns.namespace);
+							rules.push(stylesheet::CssRule::Namespace(ns));
 						}
+						Some(rule) => rules.push(rule),
+						None => {}
+					}
+				}
+				Some(_) => {
+					let sel = self.parse_selector_list();
+
+					// a rule with no block is dropped
+					if self.peek().is_none() {
+						let end = self.pos;
+						self.warn(end, CssWarningKind::MissingBlock);
+						return rules;
 					}
-				} // Some
-				None => {}
-			} // match
-		} //loop
-		retval
+
+					let dec = self.parse_declaration();
+
+					match sel {
+						Some(s) => rules.push(stylesheet::CssRule::Style(stylesheet::StyleRule {
+							selectors: s,
+							declarations: dec,
+							source_index: 0,
+						})),
+						None => {}
+					}
+				}
+			}
+		}
 	}
 
+	/// Parse the rest of the input as a list of component values
+	/// www.w3.org/TR/css-syntax-3/#parse-list-of-component-values
+	pub fn parse_component_values(&mut self) -> Vec<ComponentValue> {
+		let (start, end) = (self.pos, self.end);
+		self.pos = end;
+		self.component_values(start, end)
+	}
+
+	/// Parse the input as a single rule, at the top level of a stylesheet
+	/// if 'top_level'. Return None if it is not exactly one valid rule
+	/// www.w3.org/TR/css-syntax-3/#parse-rule
+	pub fn parse_rule(&mut self, top_level: bool) -> Option<stylesheet::CssRule> {
+		let mut rules = self.consume_rule_list(top_level);
+
+		if rules.len() == 1 {
+			rules.pop()
+		} else {
+			None
+		}
+	}
+
+	/// Parse the rules of a stylesheet. The warnings are left in source
+	/// order, those of an imported stylesheet where it is imported
 	pub fn parse_css(&mut self) -> stylesheet::StyleSheet {
-		let mut rule = stylesheet::Rule::new();
+		let rules = self.consume_rule_list(true);
+		self.warnings.sort_by(|a, b| (a.line, a.column).cmp(&(b.line, b.column)));
 
-		while !self.parse.end_of_string() {
-			let sel = self.parse_selector();
-			let dec = self.parse_declaration();
+		let own = ::std::mem::replace(&mut self.warnings, Vec::new());
+		let mut imported = ::std::mem::replace(&mut self.import_warnings, Vec::new()).into_iter().peekable();
+		for warning in own.into_iter() {
+			while imported.peek().map_or(false, |&(at, _)| at < (warning.line, warning.column)) {
+				self.warnings.push(imported.next().unwrap().1);
+			}
+			self.warnings.push(warning);
+		}
+		self.warnings.extend(imported.map(|(_, w)| w));
 
-			if sel.is_none() {
-				continue;
+		stylesheet::StyleSheet::from_rules(rules)
+	}
+
+	/// Parse the rules of a stylesheet and return them with the warnings
+	/// found along the way
+	pub fn parse_css_with_warnings(&mut self) -> (stylesheet::StyleSheet, Vec<CssWarning>) {
+		let stylesheet = self.parse_css();
+		(stylesheet, ::std::mem::replace(&mut self.warnings, Vec::new()))
+	}
+}
+
+/// The url in 'url(...)', 'url("...")' or a plain string
+pub fn url_value(value: &ComponentValue) -> Option<&String> {
+	let whitespace = ComponentValue::Token(Token::Whitespace);
+
+	match *value {
+		ComponentValue::Token(Token::Url(ref url)) => Some(url),
+		ComponentValue::Token(Token::QuotedString(ref url)) => Some(url),
+		ComponentValue::Function(ref name, ref args) if name.eq_ignore_ascii_case("url") => {
+			let mut args = args.iter().filter(|&v| *v != whitespace);
+			match (args.next(), args.next()) {
+				(Some(&ComponentValue::Token(Token::QuotedString(ref url))), None) => Some(url),
+				_ => None,
 			}
+		}
+		_ => None,
+	}
+}
+
+/// The comma separated layer names of an @layer prelude, each a dotted
+/// list of identifiers. None if a name is not valid or is a CSS-wide
+/// keyword
+fn layer_names(prelude: &[ComponentValue]) -> Option<Vec<String>> {
+	let whitespace = ComponentValue::Token(Token::Whitespace);
+	let mut names = Vec::new();
+
+	if prelude.iter().all(|v| *v == whitespace) {
+		return Some(names);
+	}
+
+	for item in prelude.split(|v| *v == ComponentValue::Token(Token::Comma)) {
+		let start = item.iter().position(|v| *v != whitespace).unwrap_or(item.len());
+		let end = item.iter().rposition(|v| *v != whitespace).map_or(start, |e| e + 1);
 
-			rule.rule_map.insert(sel.unwrap(), dec);
+		let mut name = String::new();
+		for (i, part) in item[start..end].iter().enumerate() {
+			match (i % 2, part) {
+				(0, &ComponentValue::Token(Token::Ident(ref n))) => name.push_str(n),
+				(1, &ComponentValue::Token(Token::Delim('.'))) => name.push('.'),
+				_ => return None,
+			}
 		}
 
-		stylesheet::StyleSheet {
-			ruleset: rule,
+		if (end - start) % 2 == 0 || CssWideKeyword::from_name(&name[..]).is_some() {
+			return None;
 		}
+		names.push(name);
 	}
+
+	Some(names)
+}
+
+/// The @property rule for the custom property 'name' with the 'syntax',
+/// 'inherits' and 'initial-value' descriptors given. None unless the
+/// syntax is a supported string, 'inherits' is 'true' or 'false' and the
+/// initial value matches the syntax; only the universal syntax '*' can go
+/// without one
+/// www.w3.org/TR/css-properties-values-api-1/#the-property-rule
+fn property_rule(name: String,
+				 syntax: Option<String>,
+				 inherits: Option<String>,
+				 initial_value: Option<String>) -> Option<stylesheet::PropertyRule> {
+	if !custom::is_custom_property_name(&name[..]) {
+		return None;
+	}
+
+	let syntax = match syntax.map(|s| CssParser::new(s).parse_component_values()) {
+		Some(ref values) if values.len() == 1 => match values[0] {
+			ComponentValue::Token(Token::QuotedString(ref s)) if custom::is_valid_syntax(&s[..]) => s.trim().to_string(),
+			_ => return None,
+		},
+		_ => return None,
+	};
+
+	let inherits = match inherits.as_ref().map(|i| &i[..]) {
+		Some("true") => true,
+		Some("false") => false,
+		_ => return None,
+	};
+
+	let valid_initial = match initial_value {
+		Some(ref value) => custom::matches_syntax(&syntax[..], &value[..]),
+		None => syntax == "*",
+	};
+	if !valid_initial {
+		return None;
+	}
+
+	Some(stylesheet::PropertyRule {
+		name: name,
+		syntax: syntax,
+		inherits: inherits,
+		initial_value: initial_value,
+	})
+}
+
+/// The prefix and namespace declared by the prelude of an @namespace rule,
+/// None if it is malformed or the namespace is unknown
+fn namespace_prelude(prelude: &[ComponentValue]) -> Option<(String, Namespace)> {
+	let whitespace = ComponentValue::Token(Token::Whitespace);
+	let prelude: Vec<&ComponentValue> = prelude.iter().filter(|&v| *v != whitespace).collect();
+	let (prefix, url) = match (prelude.len(), prelude.first()) {
+		(1, _) => ("", prelude[0]),
+		(2, Some(&&ComponentValue::Token(Token::Ident(ref prefix)))) => (&prefix[..], prelude[1]),
+		_ => return None,
+	};
+
+	url_value(url)
+		.and_then(|url| Namespace::from_url(&url[..]))
+		.map(|ns| (prefix.to_string(), ns))
 }
 
 #[test]
@@ -194,19 +1161,18 @@ fn test_full_css_parse_one_line() {
 	let mut css = CssParser::new(css_text.to_string());	
 
 	let stylesheet = css.parse_css();
-	let rules = &stylesheet.ruleset.rule_map;
-	let head = &stylesheet::Selector::SelectorType(dom_tree::ElementType::Head);
+	let rules = stylesheet.rules();
+	let head = &stylesheet::Selector::type_selector("h1");
 
 	assert!(!rules.is_empty());
-	assert!(rules
-			.contains_key(head));
+	assert!(rule_for(&stylesheet, head).is_some());
 
-	let decs = rules.get(head);
+	let decs = rule_for(&stylesheet, head);
 
 	assert!(decs.is_some());
 	assert_eq!(decs.unwrap().len(), 1);
 	assert!(decs.unwrap()[0].property_name == stylesheet::Property::FontSize);
-	assert!(decs.unwrap()[0].property_value == stylesheet::Value::Size(12,stylesheet::Unit::Px));
+	assert!(decs.unwrap()[0].property_value == stylesheet::Value::Size(12.0,stylesheet::Unit::Px));
 
 }
 
@@ -221,21 +1187,20 @@ fn test_full_css_parse_multi_line() {
 	let mut css = CssParser::new(css_text.to_string());	
 
 	let stylesheet = css.parse_css();
-	let rules = &stylesheet.ruleset.rule_map;
-	let head = &stylesheet::Selector::SelectorType(dom_tree::ElementType::Head);
-	let decs = rules.get(head);
+	let rules = stylesheet.rules();
+	let head = &stylesheet::Selector::type_selector("h1");
+	let decs = rule_for(&stylesheet, head);
 
 	assert!(!rules.is_empty());
-	assert!(rules
-			.contains_key(head));
+	assert!(rule_for(&stylesheet, head).is_some());
 
 	let props = [stylesheet::Property::FontSize, 
 				 stylesheet::Property::LineHeight,
 				 stylesheet::Property::Color];
 
-	let vals = [stylesheet::Value::Size(12,stylesheet::Unit::Px),
-				stylesheet::Value::Size(32,stylesheet::Unit::Px),
-				stylesheet::Value::Missing];
+	let vals = [stylesheet::Value::Size(12.0,stylesheet::Unit::Px),
+				stylesheet::Value::Size(32.0,stylesheet::Unit::Px),
+				stylesheet::Value::ColorValue(stylesheet::Color::rgb(255, 0, 0))];
 
 	assert!(decs.is_some());
 	assert_eq!(decs.unwrap().len(), num_decs);
@@ -265,33 +1230,31 @@ fn test_full_css_parse_multi_selects() {
 	let mut css = CssParser::new(css_text.to_string());	
 
 	let stylesheet = css.parse_css();
-	let rules = &stylesheet.ruleset.rule_map;
-	let head = &stylesheet::Selector::SelectorType(dom_tree::ElementType::Head);
-	let body = &stylesheet::Selector::SelectorType(dom_tree::ElementType::Body);	
-	let decs_head = rules.get(head);
-	let decs_body = rules.get(body);
+	let rules = stylesheet.rules();
+	let head = &stylesheet::Selector::type_selector("h1");
+	let body = &stylesheet::Selector::type_selector("body");	
+	let decs_head = rule_for(&stylesheet, head);
+	let decs_body = rule_for(&stylesheet, body);
 
 	assert!(!rules.is_empty());
-	assert!(rules
-			.contains_key(head));
-	assert!(rules
-			.contains_key(body));
+	assert!(rule_for(&stylesheet, head).is_some());
+	assert!(rule_for(&stylesheet, body).is_some());
 
 	let props = [stylesheet::Property::FontSize, 
 				 stylesheet::Property::LineHeight,
 				 stylesheet::Property::Color];
 
- 	let vals = [stylesheet::Value::Size(12,stylesheet::Unit::Px),
-				stylesheet::Value::Size(32,stylesheet::Unit::Px),
-				stylesheet::Value::Missing];
+ 	let vals = [stylesheet::Value::Size(12.0,stylesheet::Unit::Px),
+				stylesheet::Value::Size(32.0,stylesheet::Unit::Px),
+				stylesheet::Value::ColorValue(stylesheet::Color::rgb(255, 0, 0))];
 
 	let body_props = [stylesheet::Property::Color, 
 				 	  stylesheet::Property::FontSize,
 					  stylesheet::Property::LineHeight];
 
- 	let body_vals = [stylesheet::Value::Missing,
-					 stylesheet::Value::Size(32,stylesheet::Unit::Px),
-					 stylesheet::Value::Size(34,stylesheet::Unit::Px)];					  
+ 	let body_vals = [stylesheet::Value::ColorValue(stylesheet::Color::rgb(255, 0, 0)),
+					 stylesheet::Value::Size(32.0,stylesheet::Unit::Px),
+					 stylesheet::Value::Size(34.0,stylesheet::Unit::Px)];					  
 
 	assert!(decs_head.is_some());
 	assert!(decs_body.is_some());
@@ -308,3 +1271,385 @@ fn test_full_css_parse_multi_selects() {
 		assert!(decs_body.unwrap()[i].property_value == body_vals[i]);
 	}
 }
+
+/// The declarations of the last rule whose selector list contains
+/// 'selector'
+#[cfg(test)]
+fn rule_for<'a>(stylesheet: &'a stylesheet::StyleSheet,
+				selector: &stylesheet::Selector) -> Option<&'a Vec<stylesheet::Declaration>> {
+	stylesheet.style_rules().into_iter().rev()
+		.find(|r| r.selectors.contains(selector))
+		.map(|r| &r.declarations)
+}
+
+#[cfg(test)]
+use super::media;
+
+#[cfg(test)]
+fn svg_selector(name: &str) -> stylesheet::Selector {
+	stylesheet::Selector::from_compound(selector::CompoundSelector {
+		simple: vec![selector::SimpleSelector::Type(selector::NamespaceConstraint::Is(Namespace::Svg),
+													 name.to_string())],
+	})
+}
+
+#[test]
+fn test_parse_namespaced_selector() {
+	let css_text = "@namespace svg url(http://www.w3.org/2000/svg);
+					svg|circle { line-height: 2px }
+					math|mi { line-height: 4px }";
+	let mut css = CssParser::new(css_text.to_string());
+
+	let stylesheet = css.parse_css();
+	let rules = stylesheet.style_rules();
+	let circle = &svg_selector("circle");
+
+	assert_eq!(rules.len(), 1);
+	assert!(rule_for(&stylesheet, circle).is_some());
+}
+
+#[test]
+fn test_parse_default_namespace() {
+	let css_text = "@namespace \"http://www.w3.org/2000/svg\";
+					title { line-height: 2px }";
+	let mut css = CssParser::new(css_text.to_string());
+
+	let stylesheet = css.parse_css();
+
+	assert!(rule_for(&stylesheet, &svg_selector("title")).is_some());
+	assert!(!rule_for(&stylesheet, &stylesheet::Selector::type_selector("title")).is_some());
+}
+
+#[test]
+fn test_parse_selector_list() {
+	let css_text = "h1, h2 ,.title { font-size: 12px }
+					p, p! { line-height: 2px }";
+	let mut css = CssParser::new(css_text.to_string());
+
+	let stylesheet = css.parse_css();
+	let rules = stylesheet.style_rules();
+
+	assert_eq!(rules.len(), 1);
+	assert_eq!(rules[0].selectors.len(), 3);
+	assert!(rule_for(&stylesheet, &stylesheet::Selector::type_selector("h1")).is_some());
+	assert!(rule_for(&stylesheet, &stylesheet::Selector::type_selector("h2")).is_some());
+	assert!(!rule_for(&stylesheet, &stylesheet::Selector::type_selector("p")).is_some());
+
+	assert_eq!(rules[0].declarations.len(), 1);
+	assert!(rules[0].declarations[0].property_name == stylesheet::Property::FontSize);
+}
+
+#[test]
+fn test_rules_keep_source_order() {
+	let css_text = "p { line-height: 1px }
+					.a { line-height: 2px }
+					p { font-size: 3px }";
+	let mut css = CssParser::new(css_text.to_string());
+
+	let stylesheet = css.parse_css();
+	let rules = stylesheet.style_rules();
+
+	// the second 'p' rule does not replace the first
+	assert_eq!(rules.len(), 3);
+	assert!(rules[0].declarations[0].property_name == stylesheet::Property::LineHeight);
+	assert!(rules[2].declarations[0].property_name == stylesheet::Property::FontSize);
+
+	for (i, rule) in rules.iter().enumerate() {
+		assert_eq!(rule.source_index, i);
+	}
+}
+
+#[test]
+fn test_parse_comments_and_strings() {
+	let css_text = "/* h2 { line-height: 1px } */
+					h1 /* title */ { content: \"a;b}\"; line-height: /* x */ 4px !important }
+					p { line-height: url(a;b) 2px; font-size: 3px }
+					div/**/p { line-height: 2px }";
+	let mut css = CssParser::new(css_text.to_string());
+
+	let stylesheet = css.parse_css();
+	let head = &stylesheet::Selector::type_selector("h1");
+	let para = &stylesheet::Selector::type_selector("p");
+
+	assert_eq!(stylesheet.rules().len(), 3);
+
+	let decs = rule_for(&stylesheet, head).unwrap();
+	assert_eq!(decs.len(), 2);
+	assert!(decs[0].property_value ==
+			stylesheet::Value::Content(vec![stylesheet::ContentItem::Text("a;b}".to_string())]));
+	assert!(decs[1].property_value == stylesheet::Value::Size(4.0, stylesheet::Unit::Px));
+
+	let decs = rule_for(&stylesheet, para).unwrap();
+	assert_eq!(decs.len(), 2);
+	assert!(decs[1].property_name == stylesheet::Property::FontSize);
+
+	// a comment separates tokens like white space does
+	let descendant = selector::parse_selector("div p", &HashMap::new()).unwrap();
+	assert!(rule_for(&stylesheet, &descendant).is_some());
+}
+
+#[test]
+fn test_skip_unknown_at_rules() {
+	let css_text = "@font-feature-values Font One { @styleset { nice: 1; } h1 { line-height: 1px } }
+					@charset \"utf-8\";
+					<!-- h1 { line-height: 2px } -->";
+	let mut css = CssParser::new(css_text.to_string());
+
+	let stylesheet = css.parse_css();
+	let decs = rule_for(&stylesheet, &stylesheet::Selector::type_selector("h1")).unwrap();
+
+	assert_eq!(stylesheet.rules().len(), 1);
+	assert!(decs[0].property_value == stylesheet::Value::Size(2.0, stylesheet::Unit::Px));
+}
+
+#[test]
+fn test_recover_from_malformed_declarations() {
+	let css_text = "h1 { line-height: 1px; {x: y; z}; color red; @foo bar; font-size: 3px }
+					p { line-height: 2px }";
+	let mut css = CssParser::new(css_text.to_string());
+
+	let stylesheet = css.parse_css();
+	let head = rule_for(&stylesheet, &stylesheet::Selector::type_selector("h1")).unwrap();
+	let para = rule_for(&stylesheet, &stylesheet::Selector::type_selector("p")).unwrap();
+
+	assert_eq!(head.len(), 2);
+	assert!(head[1].property_name == stylesheet::Property::FontSize);
+	assert_eq!(para.len(), 1);
+}
+
+#[test]
+fn test_css_warnings() {
+	let css_text = "h1 { widht: 1px; font-size: 2px }
+p! { line-height: 1px }
+  @counter-style x { }
+h2 { content: \"a
+; line-height: 3px; 1px }
+h3";
+	let mut css = CssParser::new(css_text.to_string());
+
+	let (stylesheet, warnings) = css.parse_css_with_warnings();
+	let kinds: Vec<(usize, usize, CssWarningKind)> = warnings.into_iter()
+		.map(|w| (w.line, w.column, w.kind))
+		.collect();
+
+	assert_eq!(stylesheet.rules().len(), 2);
+	assert_eq!(kinds, vec![
+		(1, 6, CssWarningKind::UnknownProperty("widht".to_string())),
+		(2, 1, CssWarningKind::InvalidSelector("p!".to_string())),
+		(3, 3, CssWarningKind::IgnoredAtRule("counter-style".to_string())),
+		(4, 15, CssWarningKind::InvalidValue("content".to_string())),
+		(5, 21, CssWarningKind::InvalidDeclaration),
+		(6, 3, CssWarningKind::MissingBlock),
+	]);
+	assert!(css.warnings().is_empty());
+}
+
+#[test]
+fn test_parse_at_rules() {
+	let css_text = "@media screen and (min-width: 10px) { h1 { line-height: 1px } @page { margin-top: 2px } }
+					@supports (line-height: 2px) and (not (widht: 1px)) { p { line-height: 2px } }
+					@supports selector(a > b) or (color: nonsense) { }
+					@supports line-height: 2px { }
+					@font-face { font-family: \"Doc\"; src: url(doc.woff) }
+					@keyframes fade { from { line-height: 1px } 50%, to { line-height: 4px } 120% { } }
+					@page :first { margin-top: 3px }";
+	let mut css = CssParser::new(css_text.to_string());
+
+	let (stylesheet, warnings) = css.parse_css_with_warnings();
+	let rules = stylesheet.rules();
+
+	assert_eq!(rules.len(), 6);
+	match rules[0] {
+		stylesheet::CssRule::Media(ref m) => {
+			assert_eq!(m.media, media::parse_media_query_list("screen and (min-width: 10px)"));
+			assert_eq!(m.rules.len(), 2);
+		}
+		_ => panic!("expected @media"),
+	}
+	match (&rules[1], &rules[2]) {
+		(&stylesheet::CssRule::Supports(ref a), &stylesheet::CssRule::Supports(ref b)) => {
+			assert!(a.supported);
+			assert!(b.supported);
+			assert_eq!(a.rules.len(), 1);
+		}
+		_ => panic!("expected @supports"),
+	}
+	match rules[3] {
+		stylesheet::CssRule::FontFace(ref f) => {
+			assert_eq!(f.descriptors, vec![("font-family".to_string(), "\"Doc\"".to_string()),
+										   ("src".to_string(), "url(doc.woff)".to_string())]);
+		}
+		_ => panic!("expected @font-face"),
+	}
+	match rules[4] {
+		stylesheet::CssRule::Keyframes(ref k) => {
+			assert_eq!(k.name, "fade");
+			assert_eq!(k.keyframes.len(), 2);
+			assert_eq!(k.keyframes[1].offsets, vec![50.0, 100.0]);
+		}
+		_ => panic!("expected @keyframes"),
+	}
+	match rules[5] {
+		stylesheet::CssRule::Page(ref p) => {
+			assert_eq!(p.selector, ":first");
+			assert_eq!(p.declarations.len(), 1);
+		}
+		_ => panic!("expected @page"),
+	}
+
+	// the style rules nested in at-rules are numbered in source order
+	let style_rules = stylesheet.style_rules();
+	assert_eq!(style_rules.len(), 2);
+	assert_eq!(style_rules[1].source_index, 1);
+
+	let kinds: Vec<CssWarningKind> = warnings.into_iter().map(|w| w.kind).collect();
+	assert_eq!(kinds, vec![CssWarningKind::InvalidAtRule("supports".to_string()),
+						   CssWarningKind::InvalidSelector("120%".to_string())]);
+}
+
+#[test]
+fn test_import_rules() {
+	let mut css = CssParser::new("@import url(a.css) print; @import 'b.css';
+								  @namespace svg url(http://www.w3.org/2000/svg);
+								  @import 'c.css';
+								  p { line-height: 1px }".to_string());
+	css.set_loader(Box::new(|url: &str| match url {
+		"a.css" => Some("@import 'a.css';\nh1 { line-height: 2px; colour: red }".to_string()),
+		_ => None,
+	}));
+
+	let (stylesheet, warnings) = css.parse_css_with_warnings();
+	let rules = stylesheet.rules();
+
+	assert_eq!(rules.len(), 4);
+	match rules[0] {
+		stylesheet::CssRule::Import(ref i) => {
+			assert_eq!(i.url, "a.css");
+			assert_eq!(i.media, media::parse_media_query_list("print"));
+			// a.css importing itself stops there
+			let imported = i.stylesheet.as_ref().unwrap();
+			assert_eq!(imported.rules().len(), 2);
+			match imported.rules()[0] {
+				stylesheet::CssRule::Import(ref again) => assert!(again.stylesheet.is_none()),
+				_ => panic!("expected @import"),
+			}
+		}
+		_ => panic!("expected @import"),
+	}
+
+	// imported rules come before the importing sheet's own
+	let style_rules = stylesheet.style_rules();
+	assert_eq!(style_rules.len(), 2);
+	assert!(style_rules[0].selectors[0] == stylesheet::Selector::type_selector("h1"));
+
+	// warnings in a.css are given where it is imported, with their
+	// position in a.css
+	let kinds: Vec<(Option<&str>, usize, CssWarningKind)> = warnings.iter()
+		.map(|w| (w.url.as_ref().map(|u| &u[..]), w.line, w.kind.clone())).collect();
+	assert_eq!(kinds, vec![(Some("a.css"), 1, CssWarningKind::ImportFailed("a.css".to_string())),
+						   (Some("a.css"), 2, CssWarningKind::UnknownProperty("colour".to_string())),
+						   (None, 1, CssWarningKind::ImportFailed("b.css".to_string())),
+						   (None, 3, CssWarningKind::IgnoredAtRule("import".to_string()))]);
+}
+
+#[test]
+fn test_layer_rules_and_importance() {
+	let css_text = "@layer reset, theme.dark; @import 'a.css';
+					@layer { p { line-height: 1px !important; font-size: 2px ! IMPORTANT } }
+					@layer theme { h1 { line-height: 3px important } }
+					@layer a, b { h2 { line-height: 4px } }
+					@layer inherit;";
+	let (stylesheet, warnings) = CssParser::new(css_text.to_string()).parse_css_with_warnings();
+	let rules = stylesheet.rules();
+
+	assert_eq!(rules.len(), 4);
+	match rules[0] {
+		stylesheet::CssRule::Layer(ref l) => {
+			assert_eq!(l.names, vec!["reset".to_string(), "theme.dark".to_string()]);
+			assert!(l.rules.is_none());
+		}
+		_ => panic!("expected @layer"),
+	}
+	match rules[2] {
+		stylesheet::CssRule::Layer(ref l) => {
+			assert!(l.names.is_empty());
+			assert_eq!(l.rules.as_ref().unwrap().len(), 1);
+		}
+		_ => panic!("expected @layer"),
+	}
+
+	let style_rules = stylesheet.style_rules();
+	assert_eq!(style_rules.len(), 2);
+	assert!(style_rules[0].declarations.iter().all(|d| d.important));
+	// 'important' needs its '!'
+	assert!(!style_rules[1].declarations[0].important);
+	assert!(style_rules[1].declarations[0].property_value == stylesheet::Value::Missing);
+
+	let kinds: Vec<&CssWarningKind> = warnings.iter().map(|w| &w.kind).collect();
+	// the @import after an @layer statement is loaded, not ignored
+	assert_eq!(kinds, vec![&CssWarningKind::ImportFailed("a.css".to_string()),
+						   &CssWarningKind::InvalidAtRule("layer".to_string()),
+						   &CssWarningKind::InvalidAtRule("layer".to_string())]);
+}
+
+#[test]
+fn test_shorthand_declarations() {
+	let css_text = "p { margin: 1px 2px; border-top: solid; font: nonsense }
+					@supports (padding: 1px 2px 3px) { a { color: red } }
+					@supports (padding: 1px -2px) { b { color: red } }";
+	let stylesheet = CssParser::new(css_text.to_string()).parse_css();
+	let decs = rule_for(&stylesheet, &stylesheet::Selector::type_selector("p")).unwrap();
+
+	let props: Vec<stylesheet::Property> = decs.iter().map(|d| d.property_name).collect();
+	assert_eq!(props[..4].to_vec(), vec![stylesheet::Property::MarginTop, stylesheet::Property::MarginRight,
+										 stylesheet::Property::MarginBottom, stylesheet::Property::MarginLeft]);
+	assert_eq!(decs[1].property_value, stylesheet::Value::Size(2.0, stylesheet::Unit::Px));
+	assert_eq!(decs[5].property_value, stylesheet::Value::Keyword("solid".to_string()));
+
+	// An invalid shorthand sets all of its longhands, to nothing
+	assert_eq!(decs.len(), 4 + 3 + 7);
+	assert!(decs[7..].iter().all(|d| d.property_value == stylesheet::Value::Missing));
+
+	let supported: Vec<bool> = stylesheet.rules().iter().filter_map(|r| match *r {
+		stylesheet::CssRule::Supports(ref s) => Some(s.supported),
+		_ => None,
+	}).collect();
+	assert_eq!(supported, vec![true, false]);
+}
+
+#[test]
+fn test_custom_properties() {
+	let css_text = "@property --size { syntax: '<length> | auto'; inherits: false; initial-value: 0px }
+					@property --\\61ny { syntax: '*'; inherits: true }
+					@property --bad { syntax: '<length>'; inherits: false; initial-value: red }
+					@property --no-initial { syntax: '<color>'; inherits: true }
+					@property size { syntax: '*'; inherits: true }
+					div { --Brand: #f00 !important; --empty:; color: var(--Brand); margin: var(--size) 0 }";
+	let (stylesheet, warnings) = CssParser::new(css_text.to_string()).parse_css_with_warnings();
+
+	let registered = stylesheet.property_rules();
+	assert_eq!(registered.len(), 2);
+	assert_eq!(*registered[0], stylesheet::PropertyRule {
+		name: "--size".to_string(),
+		syntax: "<length> | auto".to_string(),
+		inherits: false,
+		initial_value: Some("0px".to_string()),
+	});
+	// the name is read as an identifier, escapes and all
+	assert_eq!(registered[1].name, "--any");
+	assert!(registered[1].inherits && registered[1].initial_value.is_none());
+
+	let declarations = &stylesheet.style_rules()[0].declarations;
+	assert_eq!(declarations.len(), 7);
+	// custom property names keep their case
+	assert_eq!(declarations[0].custom_name(), Some("--Brand"));
+	assert!(declarations[0].property_value == stylesheet::Value::Custom("--Brand".to_string(), "#f00".to_string()));
+	assert!(declarations[0].important);
+	assert!(declarations[1].property_value == stylesheet::Value::Custom("--empty".to_string(), "".to_string()));
+	assert!(declarations[2].property_value == stylesheet::Value::Unsubstituted("var(--Brand)".to_string()));
+	assert!(declarations[3].property_value ==
+			stylesheet::Value::PendingSubstitution("margin".to_string(), "var(--size) 0".to_string()));
+
+	let kinds: Vec<&CssWarningKind> = warnings.iter().map(|w| &w.kind).collect();
+	assert_eq!(kinds, vec![&CssWarningKind::InvalidAtRule("property".to_string()); 3]);
+}
@@ -0,0 +1,639 @@
+use std::collections::HashMap;
+
+use text_parser;
+use dom_tree;
+use dom_tree::Namespace;
+use super::selector;
+use super::selector::{Selector, Combinator, MatchContext, Specificity};
+
+/// www.w3.org/TR/selectors-4/#pseudo-classes
+#[derive(Debug, Clone, PartialEq, Hash, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum PseudoClass {
+	Root,
+	Empty,
+	FirstChild,
+	LastChild,
+	OnlyChild,
+	FirstOfType,
+	LastOfType,
+	OnlyOfType,
+	/// ':nth-child(an+b [of S])', only siblings matching S are counted. An
+	/// empty list counts every sibling
+	NthChild(Nth, Vec<Selector>),
+	NthLastChild(Nth, Vec<Selector>),
+	NthOfType(Nth),
+	NthLastOfType(Nth),
+	Not(Vec<Selector>),
+	Is(Vec<Selector>),
+	/// Like Is, but adds nothing to specificity
+	Where(Vec<Selector>),
+	Has(Vec<RelativeSelector>),
+	/// State pseudo-classes, read from dom_tree::ElementState
+	Hover,
+	Active,
+	Focus,
+	Checked,
+	Link,
+	Visited,
+}
+
+/// The 'an+b' argument of the :nth-* pseudo-classes. It selects the
+/// elements at position a*n + b for some n >= 0, counting from 1
+/// www.w3.org/TR/css-syntax-3/#anb-microsyntax
+#[derive(Debug, Copy, Clone, PartialEq, Hash, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Nth {
+	pub a: i32,
+	pub b: i32,
+}
+
+/// An argument of :has(). 'combinator' relates the element :has() is
+/// tested on to the first compound of 'selector'; it is Descendant when
+/// the argument does not start with one
+/// www.w3.org/TR/selectors-4/#relative
+#[derive(Debug, Clone, PartialEq, Hash, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RelativeSelector {
+	pub combinator: Combinator,
+	pub selector: Selector,
+}
+
+impl PseudoClass {
+	/// Pseudo-classes count like a class, except the logical ones which
+	/// take the specificity of their most specific argument
+	pub fn specificity(&self) -> Specificity {
+		match *self {
+			PseudoClass::Where(_) => Specificity(0, 0, 0),
+			PseudoClass::Not(ref list) |
+			PseudoClass::Is(ref list) => max_specificity(list.iter()),
+			PseudoClass::Has(ref list) => max_specificity(list.iter().map(|r| &r.selector)),
+			PseudoClass::NthChild(_, ref of) |
+			PseudoClass::NthLastChild(_, ref of) => Specificity(0, 1, 0) + max_specificity(of.iter()),
+			_ => Specificity(0, 1, 0),
+		}
+	}
+
+	/// Return true if this is :has(), or one of the selectors it takes uses
+	/// :has()
+	pub fn uses_has(&self) -> bool {
+		match *self {
+			PseudoClass::Has(_) => true,
+			PseudoClass::Not(ref list) |
+			PseudoClass::Is(ref list) |
+			PseudoClass::Where(ref list) |
+			PseudoClass::NthChild(_, ref list) |
+			PseudoClass::NthLastChild(_, ref list) => list.iter().any(|s| s.uses_has()),
+			_ => false,
+		}
+	}
+
+	/// Return true if one of the selectors this pseudo-class takes uses a
+	/// '+' or '~' combinator, also to start a relative selector
+	pub fn uses_sibling_combinators(&self) -> bool {
+		match *self {
+			PseudoClass::Has(ref relative) => relative.iter().any(|r| match r.combinator {
+				Combinator::NextSibling | Combinator::SubsequentSibling => true,
+				_ => r.selector.uses_sibling_combinators(),
+			}),
+			PseudoClass::Not(ref list) |
+			PseudoClass::Is(ref list) |
+			PseudoClass::Where(ref list) |
+			PseudoClass::NthChild(_, ref list) |
+			PseudoClass::NthLastChild(_, ref list) => list.iter().any(|s| s.uses_sibling_combinators()),
+			_ => false,
+		}
+	}
+
+	/// How many levels above a changed element the element this :has() is
+	/// tested on can be, so the change can make it match or stop matching.
+	/// Each combinator of a relative selector moves at most one level; a
+	/// descendant combinator can reach any ancestor, which is None. 0 if
+	/// there is no :has()
+	pub fn has_reach(&self) -> Option<usize> {
+		match *self {
+			PseudoClass::Has(ref relative) => relative.iter().fold(Some(0), |reach, r| {
+				let unbounded = r.combinator == Combinator::Descendant ||
+								r.selector.combinators.contains(&Combinator::Descendant);
+				let levels = if unbounded { None } else { Some(r.selector.combinators.len() + 1) };
+				selector::max_reach(reach, levels)
+			}),
+			PseudoClass::Not(ref list) |
+			PseudoClass::Is(ref list) |
+			PseudoClass::Where(ref list) |
+			PseudoClass::NthChild(_, ref list) |
+			PseudoClass::NthLastChild(_, ref list) => {
+				list.iter().fold(Some(0), |reach, s| selector::max_reach(reach, s.has_reach()))
+			}
+			_ => Some(0),
+		}
+	}
+
+	pub fn matches(&self, context: &MatchContext) -> bool {
+		let element = context.element;
+		let of_type = |s: &MatchContext| s.element.namespace == element.namespace &&
+										 s.element.tag_name == element.tag_name;
+
+		match *self {
+			PseudoClass::Root => context.parent.is_none(),
+			PseudoClass::Empty => {
+				element.children.is_empty() && element.text.as_ref().map_or(true, |t| t.is_empty())
+			}
+			PseudoClass::FirstChild => position(context, false, &|_: &MatchContext| true) == 1,
+			PseudoClass::LastChild => position(context, true, &|_: &MatchContext| true) == 1,
+			PseudoClass::OnlyChild => {
+				position(context, false, &|_: &MatchContext| true) == 1 &&
+				position(context, true, &|_: &MatchContext| true) == 1
+			}
+			PseudoClass::FirstOfType => position(context, false, &of_type) == 1,
+			PseudoClass::LastOfType => position(context, true, &of_type) == 1,
+			PseudoClass::OnlyOfType => {
+				position(context, false, &of_type) == 1 && position(context, true, &of_type) == 1
+			}
+			PseudoClass::NthChild(nth, ref of) |
+			PseudoClass::NthLastChild(nth, ref of) => {
+				let from_end = match *self {
+					PseudoClass::NthLastChild(..) => true,
+					_ => false,
+				};
+				let counted = |s: &MatchContext| of.is_empty() || of.iter().any(|sel| sel.matches(s));
+
+				counted(context) && nth.matches(position(context, from_end, &counted))
+			}
+			PseudoClass::NthOfType(nth) => nth.matches(position(context, false, &of_type)),
+			PseudoClass::NthLastOfType(nth) => nth.matches(position(context, true, &of_type)),
+			PseudoClass::Not(ref list) => !list.iter().any(|s| s.matches(context)),
+			PseudoClass::Is(ref list) |
+			PseudoClass::Where(ref list) => list.iter().any(|s| s.matches(context)),
+			PseudoClass::Has(ref list) => list.iter().any(|r| r.matches(context)),
+			PseudoClass::Hover => element.state.hover,
+			PseudoClass::Active => element.state.active,
+			PseudoClass::Focus => element.state.focus,
+			PseudoClass::Checked => element.state.checked,
+			PseudoClass::Link => is_link(element) && !element.state.visited,
+			PseudoClass::Visited => is_link(element) && element.state.visited,
+		}
+	}
+}
+
+impl Nth {
+	/// Return true if the 1-based 'position' is selected
+	pub fn matches(&self, position: i32) -> bool {
+		let offset = position - self.b;
+
+		match self.a {
+			0 => offset == 0,
+			a => offset % a == 0 && offset / a >= 0,
+		}
+	}
+}
+
+impl RelativeSelector {
+	/// Return true if an element related to 'anchor' as the combinator
+	/// requires is matched by the selector. The selector may continue past
+	/// that element, e.g. ':has(> ul li)', so all of the candidates'
+	/// descendants are tried too
+	pub fn matches(&self, anchor: &MatchContext) -> bool {
+		match self.combinator {
+			Combinator::Descendant | Combinator::Child => {
+				(0..anchor.element.children.len()).any(|i| self.matches_in_subtree(&anchor.child(i), anchor))
+			}
+			Combinator::NextSibling | Combinator::SubsequentSibling => {
+				let len = match anchor.parent {
+					Some(p) => p.element.children.len(),
+					None => return false,
+				};
+
+				(anchor.index + 1..len).any(|j| {
+					match anchor.sibling(j) {
+						Some(s) => self.matches_in_subtree(&s, anchor),
+						None => false,
+					}
+				})
+			}
+		}
+	}
+
+	fn matches_in_subtree(&self, context: &MatchContext, anchor: &MatchContext) -> bool {
+		self.selector.matches_relative(context, anchor, self.combinator) ||
+		(0..context.element.children.len()).any(|i| self.matches_in_subtree(&context.child(i), anchor))
+	}
+}
+
+fn max_specificity<'a, I: Iterator<Item=&'a Selector>>(selectors: I) -> Specificity {
+	selectors.map(|s| s.specificity()).max().unwrap_or(Specificity::default())
+}
+
+/// The 1-based position of the element among its siblings for which
+/// 'counted' is true, from the first sibling or, with 'from_end', from the
+/// last. An element without a parent is its only sibling
+fn position<F: Fn(&MatchContext) -> bool>(context: &MatchContext, from_end: bool, counted: &F) -> i32 {
+	let len = match context.parent {
+		Some(p) => p.element.children.len(),
+		None => return 1,
+	};
+
+	let others = if from_end { context.index + 1..len } else { 0..context.index };
+	let before = others.filter(|&j| {
+		match context.sibling(j) {
+			Some(s) => counted(&s),
+			None => false,
+		}
+	}).count();
+
+	before as i32 + 1
+}
+
+/// Html <a>, <area> and <link> elements with an href are links
+fn is_link(element: &dom_tree::Element) -> bool {
+	element.namespace == Namespace::Html &&
+	(element.tag_name == "a" || element.tag_name == "area" || element.tag_name == "link") &&
+	element.get_attribute("href").is_some()
+}
+
+/// Parse the pseudo-class 'name', whose name has been consumed already
+/// and lowercased. Selectors in the arguments are parsed with 'namespaces'
+/// like the rest of the selector. Return None for unknown or malformed
+/// pseudo-classes
+pub fn parse_pseudo_class(name: &str,
+						  parse: &mut text_parser::TextParser,
+						  namespaces: &HashMap<String, Namespace>) -> Option<PseudoClass> {
+	if !parse.consume_if_char_matches('(') {
+		return match name {
+			"root" => Some(PseudoClass::Root),
+			"empty" => Some(PseudoClass::Empty),
+			"first-child" => Some(PseudoClass::FirstChild),
+			"last-child" => Some(PseudoClass::LastChild),
+			"only-child" => Some(PseudoClass::OnlyChild),
+			"first-of-type" => Some(PseudoClass::FirstOfType),
+			"last-of-type" => Some(PseudoClass::LastOfType),
+			"only-of-type" => Some(PseudoClass::OnlyOfType),
+			"hover" => Some(PseudoClass::Hover),
+			"active" => Some(PseudoClass::Active),
+			"focus" => Some(PseudoClass::Focus),
+			"checked" => Some(PseudoClass::Checked),
+			"link" => Some(PseudoClass::Link),
+			"visited" => Some(PseudoClass::Visited),
+			_ => None,
+		};
+	}
+
+	let argument = match consume_argument(parse) {
+		Some(a) => a,
+		None => return None,
+	};
+	let argument = argument.trim();
+
+	match name {
+		"nth-child" => parse_nth_of(argument, namespaces).map(|(nth, of)| PseudoClass::NthChild(nth, of)),
+		"nth-last-child" => parse_nth_of(argument, namespaces).map(|(nth, of)| PseudoClass::NthLastChild(nth, of)),
+		"nth-of-type" => parse_nth(argument).map(PseudoClass::NthOfType),
+		"nth-last-of-type" => parse_nth(argument).map(PseudoClass::NthLastOfType),
+		"not" => parse_element_selector_list(argument, namespaces).map(PseudoClass::Not),
+		"is" => Some(PseudoClass::Is(parse_forgiving_selector_list(argument, namespaces))),
+		"where" => Some(PseudoClass::Where(parse_forgiving_selector_list(argument, namespaces))),
+		"has" => parse_relative_selector_list(argument, namespaces).map(PseudoClass::Has),
+		_ => None,
+	}
+}
+
+/// Consume the text up to the ')' closing a function's arguments, after
+/// its '('. Nested parentheses and strings are skipped over
+fn consume_argument(parse: &mut text_parser::TextParser) -> Option<String> {
+	let mut result = String::new();
+	let mut depth = 0;
+	let mut quote: Option<char> = None;
+
+	loop {
+		let c = match parse.consume_char() {
+			Some(c) => c,
+			None => return None,
+		};
+
+		match quote {
+			Some(q) => {
+				if c == q {
+					quote = None;
+				} else if c == '\\' {
+					result.push(c);
+					match parse.consume_char() {
+						Some(e) => result.push(e),
+						None => return None,
+					}
+					continue;
+				}
+			}
+			None => match c {
+				'"' | '\'' => quote = Some(c),
+				'\\' => {
+					result.push(c);
+					match parse.consume_char() {
+						Some(e) => result.push(e),
+						None => return None,
+					}
+					continue;
+				}
+				'(' => depth += 1,
+				')' if depth == 0 => return Some(result),
+				')' => depth -= 1,
+				_ => {}
+			},
+		}
+
+		result.push(c);
+	}
+}
+
+/// Parse 'an+b', 'odd' or 'even'. Whitespace is only allowed around the
+/// sign between 'an' and 'b'
+fn parse_nth(text: &str) -> Option<Nth> {
+	let text = text.to_ascii_lowercase();
+
+	match &text[..] {
+		"odd" => return Some(Nth { a: 2, b: 1 }),
+		"even" => return Some(Nth { a: 2, b: 0 }),
+		_ => {}
+	}
+
+	let n = match text.find('n') {
+		Some(n) => n,
+		None => return parse_integer(&text[..]).map(|b| Nth { a: 0, b: b }),
+	};
+
+	let a = match &text[..n] {
+		"" | "+" => 1,
+		"-" => -1,
+		a => match parse_integer(a) {
+			Some(a) => a,
+			None => return None,
+		},
+	};
+
+	let rest = text[n + 1..].trim();
+	if rest.is_empty() {
+		return Some(Nth { a: a, b: 0 });
+	}
+
+	let sign = match rest.chars().next() {
+		Some('+') => 1,
+		Some('-') => -1,
+		_ => return None,
+	};
+
+	let b = rest[1..].trim();
+	if b.starts_with('+') || b.starts_with('-') {
+		return None;
+	}
+
+	parse_integer(b).map(|b| Nth { a: a, b: sign * b })
+}
+
+/// Parse an optionally signed run of digits
+fn parse_integer(text: &str) -> Option<i32> {
+	let digits = if text.starts_with('+') || text.starts_with('-') { &text[1..] } else { text };
+
+	if digits.is_empty() || !digits.chars().all(|c| c.is_digit(10)) {
+		return None;
+	}
+
+	text.trim_left_matches('+').parse::<i32>().ok()
+}
+
+/// Parse the argument of :nth-child() and :nth-last-child(), 'an+b' with
+/// an optional 'of S' selector list
+fn parse_nth_of(text: &str, namespaces: &HashMap<String, Namespace>) -> Option<(Nth, Vec<Selector>)> {
+	let (nth, of) = match text.find(" of ") {
+		Some(i) => (&text[..i], Some(&text[i + 4..])),
+		None => (text, None),
+	};
+
+	let nth = match parse_nth(nth.trim()) {
+		Some(n) => n,
+		None => return None,
+	};
+
+	match of {
+		Some(list) => parse_element_selector_list(list, namespaces).map(|of| (nth, of)),
+		None => Some((nth, Vec::new())),
+	}
+}
+
+/// Parse a selector list that selects elements. Pseudo-elements are not
+/// allowed in the arguments of pseudo-classes
+fn parse_element_selector_list(text: &str, namespaces: &HashMap<String, Namespace>) -> Option<Vec<Selector>> {
+	match selector::parse_selector_list(text, namespaces) {
+		Some(ref list) if list.iter().any(|s| s.pseudo_element().is_some()) => None,
+		list => list,
+	}
+}
+
+/// :is() and :where() drop the selectors they cannot parse instead of
+/// failing as a whole
+/// www.w3.org/TR/selectors-4/#typedef-forgiving-selector-list
+fn parse_forgiving_selector_list(text: &str, namespaces: &HashMap<String, Namespace>) -> Vec<Selector> {
+	selector::split_selector_list(text).iter()
+		.filter_map(|s| selector::parse_selector(&s[..], namespaces))
+		.filter(|s| s.pseudo_element().is_none())
+		.collect()
+}
+
+/// Parse the argument of :has(), a list of selectors that may each start
+/// with a combinator. :has() cannot be nested, not even inside :is()
+fn parse_relative_selector_list(text: &str,
+								namespaces: &HashMap<String, Namespace>) -> Option<Vec<RelativeSelector>> {
+	let mut list = Vec::new();
+
+	for part in selector::split_selector_list(text).iter() {
+		let part = part.trim();
+		let (combinator, rest) = match part.chars().next() {
+			Some('>') => (Combinator::Child, &part[1..]),
+			Some('+') => (Combinator::NextSibling, &part[1..]),
+			Some('~') => (Combinator::SubsequentSibling, &part[1..]),
+			_ => (Combinator::Descendant, part),
+		};
+
+		match selector::parse_selector(rest, namespaces) {
+			Some(ref s) if s.pseudo_element().is_some() || s.uses_has() => return None,
+			Some(s) => list.push(RelativeSelector { combinator: combinator, selector: s }),
+			None => return None,
+		}
+	}
+
+	Some(list)
+}
+
+#[cfg(test)]
+mod test_pseudo_class {
+	use std::collections::HashMap;
+
+	use dom_tree;
+	use super::super::selector::{parse_selector, Selector, MatchContext, Specificity};
+	use super::{parse_nth, Nth};
+
+	fn parse(text: &str) -> Selector {
+		parse_selector(text, &HashMap::new()).unwrap()
+	}
+
+	fn element(tag_name: &str, attributes: &[(&str, &str)]) -> dom_tree::Element {
+		let mut attrs = HashMap::new();
+		for &(name, value) in attributes.iter() {
+			attrs.insert(name.to_string(), value.to_string());
+		}
+		dom_tree::new_named_element(dom_tree::Namespace::Html, tag_name, attrs, None)
+	}
+
+	#[test]
+	fn parse_nth_arguments() {
+		let nth = |a, b| Some(Nth { a: a, b: b });
+
+		assert_eq!(parse_nth("odd"), nth(2, 1));
+		assert_eq!(parse_nth("EVEN"), nth(2, 0));
+		assert_eq!(parse_nth("5"), nth(0, 5));
+		assert_eq!(parse_nth("-2"), nth(0, -2));
+		assert_eq!(parse_nth("n"), nth(1, 0));
+		assert_eq!(parse_nth("-n+3"), nth(-1, 3));
+		assert_eq!(parse_nth("+2n"), nth(2, 0));
+		assert_eq!(parse_nth("3n - 1"), nth(3, -1));
+		assert_eq!(parse_nth("10N+ 7"), nth(10, 7));
+
+		for text in ["", "n+", "2 n", "3n - -1", "a", "2n+1.5", "+-n"].iter() {
+			assert!(parse_nth(text).is_none(), "{} should not parse", text);
+		}
+
+		assert!(Nth { a: 2, b: 1 }.matches(3));
+		assert!(!Nth { a: 2, b: 1 }.matches(4));
+		assert!(Nth { a: -1, b: 3 }.matches(3));
+		assert!(!Nth { a: -1, b: 3 }.matches(4));
+		assert!(Nth { a: 0, b: 2 }.matches(2));
+	}
+
+	#[test]
+	fn parse_invalid_pseudo_classes() {
+		for text in [":", ":unknown", "a:first-child()", ":nth-child(x)", ":not()", ":not(a!)",
+					 ":has(> )", ":nth-child(2n of)", ":is(a", ":has(:has(a))",
+					 ":has(> :not(:has(a)))"].iter() {
+			assert!(parse_selector(text, &HashMap::new()).is_none(), "{} should not parse", text);
+		}
+
+		// forgiving lists keep the selectors that parse
+		assert!(parse_selector(":is(a!, b)", &HashMap::new()).is_some());
+		assert!(parse_selector(":where()", &HashMap::new()).is_some());
+	}
+
+	#[test]
+	fn match_structural_pseudo_classes() {
+		// <ul><li/><p/><li class=x/><li class=x/><li>text</li></ul>
+		let mut ul = element("ul", &[]);
+		ul.add_child_element(element("li", &[]));
+		ul.add_child_element(element("p", &[]));
+		ul.add_child_element(element("li", &[("class", "x")]));
+		ul.add_child_element(element("li", &[("class", "x")]));
+		ul.add_child_element(dom_tree::new_named_element(dom_tree::Namespace::Html, "li",
+														 HashMap::new(), Some("text".to_string())));
+
+		let root = MatchContext::root(&ul);
+		let c: Vec<MatchContext> = (0..5).map(|i| root.child(i)).collect();
+		let matching = |text: &str| -> Vec<usize> {
+			let s = parse(text);
+			(0..5).filter(|&i| s.matches(&c[i])).collect()
+		};
+
+		assert!(parse(":root").matches(&root));
+		assert!(!parse(":root").matches(&c[0]));
+		assert_eq!(matching(":first-child"), vec![0]);
+		assert_eq!(matching(":last-child"), vec![4]);
+		assert_eq!(matching(":only-child"), Vec::<usize>::new());
+		assert_eq!(matching(":only-of-type"), vec![1]);
+		assert_eq!(matching("li:first-of-type"), vec![0]);
+		assert_eq!(matching(":last-of-type"), vec![1, 4]);
+		assert_eq!(matching(":empty"), vec![0, 1, 2, 3]);
+		assert_eq!(matching(":nth-child(odd)"), vec![0, 2, 4]);
+		assert_eq!(matching(":nth-child(-n+2)"), vec![0, 1]);
+		assert_eq!(matching(":nth-last-child(2)"), vec![3]);
+		assert_eq!(matching(":nth-of-type(2n)"), vec![2, 4]);
+		assert_eq!(matching(":nth-last-of-type(1)"), vec![1, 4]);
+		assert_eq!(matching(":nth-child(2 of .x)"), vec![3]);
+		assert_eq!(matching(":nth-last-child(1 of li)"), vec![4]);
+		assert_eq!(matching("ul > :nth-child(2n+1 of li.x, p)"), vec![1, 3]);
+	}
+
+	#[test]
+	fn match_logical_pseudo_classes() {
+		// <div><section><h1/><p class=a/></section><p/></div>
+		let mut section = element("section", &[]);
+		section.add_child_element(element("h1", &[]));
+		section.add_child_element(element("p", &[("class", "a")]));
+		let mut div = element("div", &[]);
+		div.add_child_element(section);
+		div.add_child_element(element("p", &[]));
+
+		let root = MatchContext::root(&div);
+		let section = root.child(0);
+		let h1 = section.child(0);
+		let pa = section.child(1);
+		let p = root.child(1);
+
+		assert!(parse("p:not(.a)").matches(&p));
+		assert!(!parse("p:not(.a)").matches(&pa));
+		assert!(parse(":not(div > p, h1)").matches(&pa));
+		assert!(parse(":is(h1, .a)").matches(&pa));
+		assert!(parse(":where(section) :is(h1, .a)").matches(&h1));
+		assert!(!parse(":is(div > *)").matches(&pa));
+
+		assert!(parse("div:has(.a)").matches(&root));
+		assert!(!parse("div:has(> .a)").matches(&root));
+		assert!(parse("div:has(> section .a)").matches(&root));
+		assert!(parse(":has(> h1 + p)").matches(&section));
+		assert!(parse("section:has(+ p)").matches(&section));
+		assert!(parse("h1:has(~ .a)").matches(&h1));
+		assert!(!parse("h1:has(+ h1)").matches(&h1));
+		assert!(!parse(":has(section)").matches(&section));
+	}
+
+	#[test]
+	fn match_state_pseudo_classes() {
+		let mut a = element("a", &[("href", "/x")]);
+		let anchor = element("a", &[]);
+		let checkbox = element("input", &[("type", "checkbox"), ("checked", "")]);
+
+		assert!(parse(":link").matches(&MatchContext::root(&a)));
+		assert!(!parse(":link").matches(&MatchContext::root(&anchor)));
+		assert!(!parse("a:hover").matches(&MatchContext::root(&a)));
+		assert!(parse(":checked").matches(&MatchContext::root(&checkbox)));
+
+		a.state.hover = true;
+		a.state.visited = true;
+		assert!(parse("a:hover").matches(&MatchContext::root(&a)));
+		assert!(parse(":visited").matches(&MatchContext::root(&a)));
+		assert!(!parse(":link").matches(&MatchContext::root(&a)));
+		assert!(!parse(":focus").matches(&MatchContext::root(&a)));
+		assert!(!parse(":active").matches(&MatchContext::root(&a)));
+	}
+
+	#[test]
+	fn pseudo_class_specificity() {
+		let spec = |text: &str| parse(text).specificity();
+
+		assert_eq!(spec("li:first-child"), Specificity(0, 1, 1));
+		assert_eq!(spec(":hover"), Specificity(0, 1, 0));
+		assert_eq!(spec(":is(#a, p)"), Specificity(1, 0, 0));
+		assert_eq!(spec(":not(.a, p)"), Specificity(0, 1, 0));
+		assert_eq!(spec("p:where(#a, .b)"), Specificity(0, 0, 1));
+		assert_eq!(spec(":has(> img.x)"), Specificity(0, 1, 1));
+		assert_eq!(spec(":nth-child(2 of #a, p)"), Specificity(1, 1, 0));
+		assert_eq!(spec(":nth-of-type(2)"), Specificity(0, 1, 0));
+	}
+
+	#[test]
+	fn has_reach_and_sibling_combinators() {
+		assert_eq!(parse("ul li").has_reach(), Some(0));
+		assert_eq!(parse("ul:has(> li)").has_reach(), Some(1));
+		assert_eq!(parse(":is(p, div:has(> a > img, + p))").has_reach(), Some(2));
+		assert_eq!(parse("div:has(> p, img)").has_reach(), None);
+		assert_eq!(parse("div:has(> section p)").has_reach(), None);
+
+		assert!(!parse("div:has(> p) a").uses_sibling_combinators());
+		assert!(parse("div:has(~ p)").uses_sibling_combinators());
+		assert!(parse(":not(h1 + p)").uses_sibling_combinators());
+	}
+}
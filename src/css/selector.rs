@@ -0,0 +1,1158 @@
+use std::collections::HashMap;
+
+use text_parser;
+use dom_tree;
+use dom_tree::Namespace;
+use super::pseudo_class;
+use super::pseudo_class::PseudoClass;
+
+/// A Selector is a complex selector: compound selectors joined by
+/// combinators, e.g. 'nav > ul li.active'. 'combinators[i]' joins
+/// 'compounds[i]' to 'compounds[i + 1]'; the last compound is the one the
+/// selected element itself must match
+/// www.w3.org/TR/selectors-4/#complex
+#[derive(Debug, Clone, PartialEq, Hash, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Selector {
+	pub compounds: Vec<CompoundSelector>,
+	pub combinators: Vec<Combinator>,
+}
+
+/// A list of simple selectors that must all match the same element, e.g.
+/// 'div.note#main[lang]'
+/// www.w3.org/TR/selectors-4/#compound
+#[derive(Debug, Clone, PartialEq, Hash, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CompoundSelector {
+	pub simple: Vec<SimpleSelector>,
+}
+
+/// The specificity (a, b, c) of a selector: the number of id selectors,
+/// of class, attribute and pseudo-class selectors, and of type selectors.
+/// Specificities compare lexicographically, so they can be sorted directly
+/// www.w3.org/TR/selectors-4/#specificity-rules
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Specificity(pub u32, pub u32, pub u32);
+
+impl ::std::ops::Add for Specificity {
+	type Output = Specificity;
+
+	fn add(self, other: Specificity) -> Specificity {
+		Specificity(self.0 + other.0, self.1 + other.1, self.2 + other.2)
+	}
+}
+
+/// www.w3.org/TR/selectors-4/#combinators
+#[derive(Debug, Copy, Clone, PartialEq, Hash, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Combinator {
+	/// 'a b'
+	Descendant,
+	/// 'a > b'
+	Child,
+	/// 'a + b'
+	NextSibling,
+	/// 'a ~ b'
+	SubsequentSibling,
+}
+
+/// Where an element sits in the DOM, so selectors can look at its
+/// ancestors and preceding siblings. 'index' is the element's position in
+/// its parent's children. Contexts are built on the way down the tree,
+/// each one borrowing its parent's
+pub struct MatchContext<'a> {
+	pub element: &'a dom_tree::Element,
+	pub parent: Option<&'a MatchContext<'a>>,
+	pub index: usize,
+}
+
+impl<'a> MatchContext<'a> {
+	/// Context for an element matched without regard to its ancestors
+	pub fn root(element: &'a dom_tree::Element) -> MatchContext<'a> {
+		MatchContext {
+			element: element,
+			parent: None,
+			index: 0,
+		}
+	}
+
+	/// Context for child 'index' of this context's element
+	pub fn child(&'a self, index: usize) -> MatchContext<'a> {
+		MatchContext {
+			element: &self.element.children[index],
+			parent: Some(self),
+			index: index,
+		}
+	}
+
+	/// Context for the sibling 'index' of this element, None for the root
+	pub fn sibling(&self, index: usize) -> Option<MatchContext<'a>> {
+		self.parent.map(|p| {
+			MatchContext {
+				element: &p.element.children[index],
+				parent: Some(p),
+				index: index,
+			}
+		})
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Hash, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SimpleSelector {
+	/// '*' or 'ns|*'
+	Universal(NamespaceConstraint),
+	/// 'name' or 'ns|name'
+	Type(NamespaceConstraint, String),
+	/// '#name'
+	Id(String),
+	/// '.name'
+	Class(String),
+	/// '[name]', '[name=value]', ...
+	Attribute(AttributeSelector),
+	/// ':first-child', ':not(...)', ...
+	PseudoClass(PseudoClass),
+	/// '::before', ... Always the last simple selector of the last compound
+	PseudoElement(PseudoElement),
+}
+
+/// www.w3.org/TR/css-pseudo-4/
+#[derive(Debug, Copy, Clone, PartialEq, Hash, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum PseudoElement {
+	Before,
+	After,
+	FirstLine,
+	FirstLetter,
+}
+
+impl PseudoElement {
+	pub fn from_name(name: &str) -> Option<PseudoElement> {
+		match name {
+			"before" => Some(PseudoElement::Before),
+			"after" => Some(PseudoElement::After),
+			"first-line" => Some(PseudoElement::FirstLine),
+			"first-letter" => Some(PseudoElement::FirstLetter),
+			_ => None,
+		}
+	}
+
+	pub fn name(&self) -> &'static str {
+		match *self {
+			PseudoElement::Before => "before",
+			PseudoElement::After => "after",
+			PseudoElement::FirstLine => "first-line",
+			PseudoElement::FirstLetter => "first-letter",
+		}
+	}
+}
+
+/// Which namespaces a type or universal selector accepts. Without a
+/// prefix or a default @namespace, elements in any namespace match
+#[derive(Debug, Copy, Clone, PartialEq, Hash, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum NamespaceConstraint {
+	Any,
+	/// '|name' selects elements without a namespace, which the DOM never
+	/// creates
+	NoNamespace,
+	Is(Namespace),
+}
+
+#[derive(Debug, Clone, PartialEq, Hash, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AttributeSelector {
+	pub name: String,
+	pub operator: AttributeOperator,
+	pub value: String,
+	pub case: AttributeCase,
+}
+
+/// www.w3.org/TR/selectors-4/#attribute-selectors
+#[derive(Debug, Copy, Clone, PartialEq, Hash, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum AttributeOperator {
+	/// [attr]
+	Exists,
+	/// [attr=value]
+	Equals,
+	/// [attr~=value], value is one of a whitespace separated list
+	Includes,
+	/// [attr|=value], value exactly or followed by '-'
+	DashMatch,
+	/// [attr^=value]
+	Prefix,
+	/// [attr$=value]
+	Suffix,
+	/// [attr*=value]
+	Substring,
+}
+
+/// The 'i' and 's' flags of an attribute selector. Without a flag, values
+/// of a few html attributes such as 'type' and 'lang' compare ignoring
+/// ascii case; everything else is case sensitive
+#[derive(Debug, Copy, Clone, PartialEq, Hash, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum AttributeCase {
+	Default,
+	Insensitive,
+	Sensitive,
+}
+
+impl Selector {
+	/// A selector with a single type selector for 'name' in any namespace
+	pub fn type_selector(name: &str) -> Selector {
+		Selector::from_compound(CompoundSelector {
+			simple: vec![SimpleSelector::Type(NamespaceConstraint::Any, name.to_string())],
+		})
+	}
+
+	/// A selector made of one compound selector and no combinators
+	pub fn from_compound(compound: CompoundSelector) -> Selector {
+		Selector {
+			compounds: vec![compound],
+			combinators: Vec::new(),
+		}
+	}
+
+	pub fn specificity(&self) -> Specificity {
+		self.compounds.iter().fold(Specificity::default(), |acc, c| acc + c.specificity())
+	}
+
+	/// The pseudo-element the selector styles, None if it styles elements
+	pub fn pseudo_element(&self) -> Option<PseudoElement> {
+		match self.compounds.last().and_then(|c| c.simple.last()) {
+			Some(&SimpleSelector::PseudoElement(p)) => Some(p),
+			_ => None,
+		}
+	}
+
+	/// Return true if the selector has a '+' or '~' combinator, also in
+	/// the arguments of its pseudo-classes
+	pub fn uses_sibling_combinators(&self) -> bool {
+		self.combinators.iter().any(|c| match *c {
+			Combinator::NextSibling | Combinator::SubsequentSibling => true,
+			_ => false,
+		}) || self.compounds.iter().any(|c| c.simple.iter().any(|s| match *s {
+			SimpleSelector::PseudoClass(ref p) => p.uses_sibling_combinators(),
+			_ => false,
+		}))
+	}
+
+	/// Return true if :has() appears in the selector, also in the arguments
+	/// of other pseudo-classes
+	pub fn uses_has(&self) -> bool {
+		self.compounds.iter().any(|c| c.simple.iter().any(|s| match *s {
+			SimpleSelector::PseudoClass(ref p) => p.uses_has(),
+			_ => false,
+		}))
+	}
+
+	/// How far above a changed element a :has() in the selector can be
+	/// tested, as for PseudoClass::has_reach
+	pub fn has_reach(&self) -> Option<usize> {
+		self.compounds.iter().flat_map(|c| c.simple.iter()).fold(Some(0), |reach, s| match *s {
+			SimpleSelector::PseudoClass(ref p) => max_reach(reach, p.has_reach()),
+			_ => reach,
+		})
+	}
+
+	/// Return true if the element in 'context' is selected. Matching goes
+	/// right to left: the last compound is tested against the element, then
+	/// each combinator is followed to the ancestors or siblings that the
+	/// compound before it must match
+	pub fn matches(&self, context: &MatchContext) -> bool {
+		match self.compounds.len() {
+			0 => false,
+			_ if self.pseudo_element().is_some() => false,
+			n => self.matches_from(n - 1, context, None),
+		}
+	}
+
+	/// Return true if the selector styles 'pseudo' of the element in
+	/// 'context'
+	pub fn matches_pseudo(&self, context: &MatchContext, pseudo: PseudoElement) -> bool {
+		match self.compounds.len() {
+			0 => false,
+			_ if self.pseudo_element() != Some(pseudo) => false,
+			n => self.matches_from(n - 1, context, None),
+		}
+	}
+
+	/// Match a selector of :has() against the element in 'context'. The
+	/// element its first compound matches must also be related to 'anchor'
+	/// by 'combinator'
+	pub fn matches_relative(&self, context: &MatchContext, anchor: &MatchContext, combinator: Combinator) -> bool {
+		match self.compounds.len() {
+			0 => false,
+			n => self.matches_from(n - 1, context, Some((anchor, combinator))),
+		}
+	}
+
+	fn matches_from(&self, i: usize, context: &MatchContext, anchor: Option<(&MatchContext, Combinator)>) -> bool {
+		if !self.compounds[i].matches(context) {
+			return false;
+		}
+
+		if i == 0 {
+			return anchor.map_or(true, |(a, combinator)| is_related(a, context, combinator));
+		}
+
+		match self.combinators[i - 1] {
+			Combinator::Child => {
+				context.parent.map_or(false, |p| self.matches_from(i - 1, p, anchor))
+			}
+			Combinator::Descendant => {
+				let mut ancestor = context.parent;
+				while let Some(a) = ancestor {
+					if self.matches_from(i - 1, a, anchor) {
+						return true;
+					}
+					ancestor = a.parent;
+				}
+				false
+			}
+			Combinator::NextSibling => {
+				if context.index == 0 {
+					return false;
+				}
+				match context.sibling(context.index - 1) {
+					Some(s) => self.matches_from(i - 1, &s, anchor),
+					None => false,
+				}
+			}
+			Combinator::SubsequentSibling => {
+				(0..context.index).rev().any(|j| {
+					match context.sibling(j) {
+						Some(s) => self.matches_from(i - 1, &s, anchor),
+						None => false,
+					}
+				})
+			}
+		}
+	}
+}
+
+/// The farther of two reaches, where None is no limit
+pub fn max_reach(a: Option<usize>, b: Option<usize>) -> Option<usize> {
+	match (a, b) {
+		(Some(a), Some(b)) => Some(::std::cmp::max(a, b)),
+		_ => None,
+	}
+}
+
+impl CompoundSelector {
+	pub fn specificity(&self) -> Specificity {
+		self.simple.iter().fold(Specificity::default(), |acc, s| acc + s.specificity())
+	}
+
+	/// Return true if every simple selector matches the element in 'context'
+	pub fn matches(&self, context: &MatchContext) -> bool {
+		self.simple.iter().all(|s| s.matches(context))
+	}
+}
+
+impl SimpleSelector {
+	pub fn specificity(&self) -> Specificity {
+		match *self {
+			SimpleSelector::Universal(_) => Specificity(0, 0, 0),
+			SimpleSelector::Type(..) => Specificity(0, 0, 1),
+			SimpleSelector::Id(_) => Specificity(1, 0, 0),
+			SimpleSelector::Class(_) |
+			SimpleSelector::Attribute(_) => Specificity(0, 1, 0),
+			SimpleSelector::PseudoClass(ref p) => p.specificity(),
+			SimpleSelector::PseudoElement(_) => Specificity(0, 0, 1),
+		}
+	}
+
+	pub fn matches(&self, context: &MatchContext) -> bool {
+		let element = context.element;
+
+		match *self {
+			SimpleSelector::Universal(ns) => ns.matches(element),
+			SimpleSelector::Type(ns, ref name) => {
+				// html element names are compared ignoring case
+				let name_matches = match element.namespace {
+					Namespace::Html => name.to_ascii_lowercase() == element.tag_name,
+					_ => *name == element.tag_name,
+				};
+				ns.matches(element) && name_matches
+			}
+			SimpleSelector::Id(ref id) => element.get_attribute("id") == Some(&id[..]),
+			SimpleSelector::Class(ref class) => {
+				match element.get_attribute("class") {
+					Some(classes) => classes.split(|c: char| c.is_whitespace()).any(|c| c == &class[..]),
+					None => false,
+				}
+			}
+			SimpleSelector::Attribute(ref attr) => attr.matches(element),
+			SimpleSelector::PseudoClass(ref p) => p.matches(context),
+			// selectors with a pseudo-element are checked for it as a whole
+			SimpleSelector::PseudoElement(_) => true,
+		}
+	}
+}
+
+impl NamespaceConstraint {
+	pub fn matches(&self, element: &dom_tree::Element) -> bool {
+		match *self {
+			NamespaceConstraint::Any => true,
+			NamespaceConstraint::NoNamespace => false,
+			NamespaceConstraint::Is(ns) => element.namespace == ns,
+		}
+	}
+}
+
+impl AttributeSelector {
+	pub fn matches(&self, element: &dom_tree::Element) -> bool {
+		let name = match element.namespace {
+			Namespace::Html => self.name.to_ascii_lowercase(),
+			_ => self.name.clone(),
+		};
+
+		let actual = match element.get_attribute(&name[..]) {
+			Some(v) => v,
+			None => return false,
+		};
+
+		let ignore_case = match self.case {
+			AttributeCase::Insensitive => true,
+			AttributeCase::Sensitive => false,
+			AttributeCase::Default => element.namespace == Namespace::Html &&
+									  has_case_insensitive_value(&name[..]),
+		};
+
+		let (actual, value) = if ignore_case {
+			(actual.to_ascii_lowercase(), self.value.to_ascii_lowercase())
+		} else {
+			(actual.to_string(), self.value.clone())
+		};
+
+		match self.operator {
+			AttributeOperator::Exists => true,
+			AttributeOperator::Equals => actual == value,
+			AttributeOperator::Includes => {
+				!value.is_empty() && !value.contains(|c: char| c.is_whitespace()) &&
+				actual.split(|c: char| c.is_whitespace()).any(|v| v == value)
+			}
+			AttributeOperator::DashMatch => {
+				actual == value || actual.starts_with(&format!("{}-", value)[..])
+			}
+			AttributeOperator::Prefix => !value.is_empty() && actual.starts_with(&value[..]),
+			AttributeOperator::Suffix => !value.is_empty() && actual.ends_with(&value[..]),
+			AttributeOperator::Substring => !value.is_empty() && actual.contains(&value[..]),
+		}
+	}
+}
+
+/// Return true if 'context' is related to 'anchor' as 'combinator' requires,
+/// e.g. is a child of it for Combinator::Child
+fn is_related(anchor: &MatchContext, context: &MatchContext, combinator: Combinator) -> bool {
+	let same = |a: &MatchContext, b: &MatchContext| {
+		a.element as *const dom_tree::Element == b.element as *const dom_tree::Element
+	};
+	let same_parent = match (context.parent, anchor.parent) {
+		(Some(a), Some(b)) => same(a, b),
+		_ => false,
+	};
+
+	match combinator {
+		Combinator::Child => context.parent.map_or(false, |p| same(p, anchor)),
+		Combinator::Descendant => {
+			let mut ancestor = context.parent;
+			while let Some(a) = ancestor {
+				if same(a, anchor) {
+					return true;
+				}
+				ancestor = a.parent;
+			}
+			false
+		}
+		Combinator::NextSibling => same_parent && context.index == anchor.index + 1,
+		Combinator::SubsequentSibling => same_parent && context.index > anchor.index,
+	}
+}
+
+/// Html attributes whose values selectors compare ignoring ascii case
+/// html.spec.whatwg.org/multipage/semantics-other.html#case-sensitivity-of-selectors
+fn has_case_insensitive_value(name: &str) -> bool {
+	match name {
+		"accept" | "accept-charset" | "align" | "alink" | "axis" | "bgcolor" |
+		"charset" | "checked" | "clear" | "codetype" | "color" | "compact" |
+		"declare" | "defer" | "dir" | "direction" | "disabled" | "enctype" |
+		"face" | "frame" | "hreflang" | "http-equiv" | "lang" | "language" |
+		"link" | "media" | "method" | "multiple" | "nohref" | "noresize" |
+		"noshade" | "nowrap" | "readonly" | "rel" | "rev" | "rules" | "scope" |
+		"scrolling" | "selected" | "shape" | "target" | "text" | "type" |
+		"valign" | "valuetype" | "vlink" => true,
+		_ => false,
+	}
+}
+
+/// Sort 'selectors' from lowest to highest specificity. Selectors with the
+/// same specificity keep their relative order
+pub fn sort_by_specificity(selectors: &mut Vec<Selector>) {
+	selectors.sort_by(|a, b| a.specificity().cmp(&b.specificity()));
+}
+
+/// Parse a comma separated list of complex selectors, e.g. 'h1, h2, .title'.
+/// Per the spec, one invalid selector makes the whole list invalid
+/// www.w3.org/TR/selectors-4/#selector-list
+pub fn parse_selector_list(text: &str, namespaces: &HashMap<String, Namespace>) -> Option<Vec<Selector>> {
+	let mut selectors = Vec::new();
+
+	for part in split_selector_list(text).iter() {
+		match parse_selector(&part[..], namespaces) {
+			Some(s) => selectors.push(s),
+			None => return None,
+		}
+	}
+
+	Some(selectors)
+}
+
+/// Split 'text' at the commas that are not inside brackets, parentheses
+/// or strings
+pub fn split_selector_list(text: &str) -> Vec<String> {
+	let mut parts = Vec::new();
+	let mut current = String::new();
+	let mut depth = 0;
+	let mut quote: Option<char> = None;
+	let mut escaped = false;
+
+	for c in text.chars() {
+		if escaped {
+			escaped = false;
+		} else if c == '\\' {
+			escaped = true;
+		} else if quote.is_some() {
+			if Some(c) == quote {
+				quote = None;
+			}
+		} else {
+			match c {
+				'"' | '\'' => quote = Some(c),
+				'[' | '(' => depth += 1,
+				']' | ')' if depth > 0 => depth -= 1,
+				',' if depth == 0 => {
+					parts.push(::std::mem::replace(&mut current, String::new()));
+					continue;
+				}
+				_ => {}
+			}
+		}
+
+		current.push(c);
+	}
+
+	parts.push(current);
+	parts
+}
+
+/// Parse the text of a complex selector. 'namespaces' maps the prefixes
+/// declared with @namespace, the default namespace is under "". Return
+/// None if the text is not a valid selector
+pub fn parse_selector(text: &str, namespaces: &HashMap<String, Namespace>) -> Option<Selector> {
+	let mut parse = text_parser::TextParser::new(text.trim().to_string());
+	let mut compounds = Vec::new();
+	let mut combinators = Vec::new();
+
+	loop {
+		match parse_compound_selector(&mut parse, namespaces) {
+			Some(c) => compounds.push(c),
+			None => return None,
+		}
+
+		let had_space = parse.peek_char().map_or(false, |c| c.is_whitespace());
+		parse.consume_whitespace();
+
+		let combinator = match parse.peek_char() {
+			None => break,
+			Some('>') => Combinator::Child,
+			Some('+') => Combinator::NextSibling,
+			Some('~') => Combinator::SubsequentSibling,
+			Some(_) if had_space => Combinator::Descendant,
+			Some(_) => return None,
+		};
+
+		if combinator != Combinator::Descendant {
+			parse.consume_char();
+			parse.consume_whitespace();
+		}
+
+		combinators.push(combinator);
+	}
+
+	// a pseudo-element can only be on the element the selector styles
+	let last = compounds.len() - 1;
+	if compounds[..last].iter().any(|c| c.simple.iter().any(|s| match *s {
+		SimpleSelector::PseudoElement(_) => true,
+		_ => false,
+	})) {
+		return None;
+	}
+
+	Some(Selector {
+		compounds: compounds,
+		combinators: combinators,
+	})
+}
+
+/// Parse a compound selector, stopping at whitespace, a combinator or
+/// the end of the input
+fn parse_compound_selector(parse: &mut text_parser::TextParser,
+						   namespaces: &HashMap<String, Namespace>) -> Option<CompoundSelector> {
+	let mut simple = Vec::new();
+
+	match parse_type_selector(parse, namespaces) {
+		Some(Ok(s)) => simple.push(s),
+		Some(Err(())) => return None,
+		None => {}
+	}
+
+	loop {
+		let s = match parse.peek_char() {
+			None => break,
+			Some(c) if c.is_whitespace() => break,
+			Some('>') | Some('+') | Some('~') => break,
+			Some('#') => {
+				parse.consume_char();
+				consume_name(parse).map(SimpleSelector::Id)
+			}
+			Some('.') => {
+				parse.consume_char();
+				consume_ident(parse).map(SimpleSelector::Class)
+			}
+			Some('[') => {
+				parse.consume_char();
+				parse_attribute_selector(parse).map(SimpleSelector::Attribute)
+			}
+			Some(':') => {
+				parse.consume_char();
+				parse_pseudo(parse, namespaces)
+			}
+			Some(_) => None,
+		};
+
+		match s {
+			Some(s) => simple.push(s),
+			None => return None,
+		}
+
+		// nothing may follow a pseudo-element in its compound
+		match simple.last() {
+			Some(&SimpleSelector::PseudoElement(_)) => match parse.peek_char() {
+				None | Some('>') | Some('+') | Some('~') => break,
+				Some(c) if c.is_whitespace() => break,
+				Some(_) => return None,
+			},
+			_ => {}
+		}
+	}
+
+	if simple.is_empty() {
+		None
+	} else {
+		Some(CompoundSelector { simple: simple })
+	}
+}
+
+/// Parse a pseudo-class or pseudo-element after the first ':'. The css2
+/// pseudo-elements may also be written with a single colon
+fn parse_pseudo(parse: &mut text_parser::TextParser,
+				namespaces: &HashMap<String, Namespace>) -> Option<SimpleSelector> {
+	let double_colon = parse.consume_if_char_matches(':');
+
+	let name = match consume_ident(parse) {
+		Some(n) => n.to_ascii_lowercase(),
+		None => return None,
+	};
+
+	let pseudo_element = PseudoElement::from_name(&name[..]);
+	if double_colon || (pseudo_element.is_some() && parse.peek_char() != Some('(')) {
+		return pseudo_element.map(SimpleSelector::PseudoElement);
+	}
+
+	pseudo_class::parse_pseudo_class(&name[..], parse, namespaces).map(SimpleSelector::PseudoClass)
+}
+
+/// Parse an optional leading type or universal selector with its namespace
+/// prefix. Return None if there is none, Err if it is malformed
+fn parse_type_selector(parse: &mut text_parser::TextParser,
+					   namespaces: &HashMap<String, Namespace>)
+					   -> Option<Result<SimpleSelector, ()>> {
+	let first = match parse.peek_char() {
+		Some('*') => {
+			parse.consume_char();
+			Some("*".to_string())
+		}
+		Some('|') => Some(String::new()),
+		Some(c) if is_name_start(c) || c == '-' || c == '\\' => {
+			match consume_ident(parse) {
+				Some(i) => Some(i),
+				None => return Some(Err(())),
+			}
+		}
+		_ => None,
+	};
+
+	let first = match first {
+		Some(f) => f,
+		None => return None,
+	};
+
+	// 'prefix|name'; a lone '|' followed by '=' would be an attribute
+	// operator, which cannot appear here
+	let (constraint, name) = if parse.peek_char() == Some('|') {
+		parse.consume_char();
+
+		let constraint = match &first[..] {
+			"*" => NamespaceConstraint::Any,
+			"" => NamespaceConstraint::NoNamespace,
+			prefix => match namespaces.get(prefix) {
+				Some(&ns) => NamespaceConstraint::Is(ns),
+				None => return Some(Err(())),
+			},
+		};
+
+		let name = if parse.consume_if_char_matches('*') {
+			"*".to_string()
+		} else {
+			match consume_ident(parse) {
+				Some(n) => n,
+				None => return Some(Err(())),
+			}
+		};
+
+		(constraint, name)
+	} else {
+		let constraint = match namespaces.get("") {
+			Some(&ns) => NamespaceConstraint::Is(ns),
+			None => NamespaceConstraint::Any,
+		};
+
+		(constraint, first)
+	};
+
+	if name == "*" {
+		Some(Ok(SimpleSelector::Universal(constraint)))
+	} else {
+		Some(Ok(SimpleSelector::Type(constraint, name)))
+	}
+}
+
+/// Parse the inside of '[...]', after the opening bracket
+fn parse_attribute_selector(parse: &mut text_parser::TextParser) -> Option<AttributeSelector> {
+	parse.consume_whitespace();
+
+	let name = match consume_ident(parse) {
+		Some(n) => n,
+		None => return None,
+	};
+
+	parse.consume_whitespace();
+
+	let operator = match parse.consume_char() {
+		Some(']') => {
+			return Some(AttributeSelector {
+				name: name,
+				operator: AttributeOperator::Exists,
+				value: String::new(),
+				case: AttributeCase::Default,
+			});
+		}
+		Some('=') => AttributeOperator::Equals,
+		Some(c) => {
+			let op = match c {
+				'~' => AttributeOperator::Includes,
+				'|' => AttributeOperator::DashMatch,
+				'^' => AttributeOperator::Prefix,
+				'$' => AttributeOperator::Suffix,
+				'*' => AttributeOperator::Substring,
+				_ => return None,
+			};
+
+			if !parse.consume_if_char_matches('=') {
+				return None;
+			}
+			op
+		}
+		None => return None,
+	};
+
+	parse.consume_whitespace();
+
+	let value = match parse.peek_char() {
+		Some(q) if q == '"' || q == '\'' => consume_string(parse),
+		_ => consume_ident(parse),
+	};
+	let value = match value {
+		Some(v) => v,
+		None => return None,
+	};
+
+	parse.consume_whitespace();
+
+	let case = if parse.consume_if_char_matches('i') || parse.consume_if_char_matches('I') {
+		AttributeCase::Insensitive
+	} else if parse.consume_if_char_matches('s') || parse.consume_if_char_matches('S') {
+		AttributeCase::Sensitive
+	} else {
+		AttributeCase::Default
+	};
+
+	parse.consume_whitespace();
+
+	if !parse.consume_if_char_matches(']') {
+		return None;
+	}
+
+	Some(AttributeSelector {
+		name: name,
+		operator: operator,
+		value: value,
+		case: case,
+	})
+}
+
+fn is_name_start(c: char) -> bool {
+	c.is_alphabetic() || c == '_' || c as u32 >= 0x80
+}
+
+fn is_name_char(c: char) -> bool {
+	is_name_start(c) || c.is_digit(10) || c == '-'
+}
+
+/// Consume an identifier: a name that does not start with a digit, or
+/// with '-' followed by a digit
+/// www.w3.org/TR/css-syntax-3/#ident-token-diagram
+pub fn consume_ident(parse: &mut text_parser::TextParser) -> Option<String> {
+	match (parse.peek_char(), parse.peek_next_char()) {
+		(Some(c), _) if c.is_digit(10) => return None,
+		(Some('-'), Some(c)) if c.is_digit(10) => return None,
+		_ => {}
+	}
+
+	match consume_name(parse) {
+		Some(ref n) if n == "-" => None,
+		n => n,
+	}
+}
+
+/// Consume a run of name characters and escapes. Return None if there
+/// are none
+pub fn consume_name(parse: &mut text_parser::TextParser) -> Option<String> {
+	let mut result = String::new();
+
+	loop {
+		match parse.peek_char() {
+			Some('\\') => {
+				parse.consume_char();
+				match consume_escape(parse) {
+					Some(c) => result.push(c),
+					None => return None,
+				}
+			}
+			Some(c) if is_name_char(c) => {
+				parse.consume_char();
+				result.push(c);
+			}
+			_ => break,
+		}
+	}
+
+	if result.is_empty() { None } else { Some(result) }
+}
+
+/// Consume what follows a '\': up to six hex digits and one optional
+/// whitespace, or any single other character
+fn consume_escape(parse: &mut text_parser::TextParser) -> Option<char> {
+	let mut hex = String::new();
+
+	while hex.len() < 6 {
+		match parse.peek_char() {
+			Some(c) if c.is_digit(16) => {
+				parse.consume_char();
+				hex.push(c);
+			}
+			_ => break,
+		}
+	}
+
+	if hex.is_empty() {
+		return match parse.consume_char() {
+			Some('\n') | None => None,
+			c => c,
+		};
+	}
+
+	match parse.peek_char() {
+		Some(c) if c.is_whitespace() => { parse.consume_char(); }
+		_ => {}
+	}
+
+	let code = u32::from_str_radix(&hex[..], 16).unwrap_or(0xFFFD);
+	match ::std::char::from_u32(code) {
+		Some(c) if code != 0 => Some(c),
+		_ => Some('\u{FFFD}'),
+	}
+}
+
+/// Consume a quoted string, including its quotes. Return the contents
+pub fn consume_string(parse: &mut text_parser::TextParser) -> Option<String> {
+	let quote = match parse.consume_char() {
+		Some(q) => q,
+		None => return None,
+	};
+	let mut result = String::new();
+
+	loop {
+		match parse.consume_char() {
+			None => return None,
+			Some(c) if c == quote => return Some(result),
+			Some('\\') => {
+				match parse.peek_char() {
+					// an escaped newline continues the string
+					Some('\n') => { parse.consume_char(); }
+					_ => match consume_escape(parse) {
+						Some(c) => result.push(c),
+						None => return None,
+					},
+				}
+			}
+			Some(c) => result.push(c),
+		}
+	}
+}
+
+#[cfg(test)]
+mod test_selector {
+	use std::collections::HashMap;
+
+	use dom_tree;
+	use super::{parse_selector, parse_selector_list, sort_by_specificity, Specificity,
+				Selector, SimpleSelector, PseudoElement, NamespaceConstraint, Combinator,
+				AttributeSelector, AttributeOperator, AttributeCase, MatchContext};
+
+	fn parse(text: &str) -> Option<Selector> {
+		parse_selector(text, &HashMap::new())
+	}
+
+	fn element(tag_name: &str, attributes: &[(&str, &str)]) -> dom_tree::Element {
+		let mut attrs = HashMap::new();
+		for &(name, value) in attributes.iter() {
+			attrs.insert(name.to_string(), value.to_string());
+		}
+		dom_tree::new_named_element(dom_tree::Namespace::Html, tag_name, attrs, None)
+	}
+
+	#[test]
+	fn parse_compound_selector() {
+		let sel = parse("div.note#main[lang]").unwrap();
+
+		assert_eq!(sel.compounds[0].simple, vec![
+			SimpleSelector::Type(NamespaceConstraint::Any, "div".to_string()),
+			SimpleSelector::Class("note".to_string()),
+			SimpleSelector::Id("main".to_string()),
+			SimpleSelector::Attribute(AttributeSelector {
+				name: "lang".to_string(),
+				operator: AttributeOperator::Exists,
+				value: String::new(),
+				case: AttributeCase::Default,
+			}),
+		]);
+
+		let sel = parse("*[ data-x ^= \"a b\" i ]").unwrap();
+		assert_eq!(sel.compounds[0].simple[0], SimpleSelector::Universal(NamespaceConstraint::Any));
+		assert_eq!(sel.compounds[0].simple[1], SimpleSelector::Attribute(AttributeSelector {
+			name: "data-x".to_string(),
+			operator: AttributeOperator::Prefix,
+			value: "a b".to_string(),
+			case: AttributeCase::Insensitive,
+		}));
+
+		assert_eq!(parse(".a\\:b").unwrap().compounds[0].simple[0], SimpleSelector::Class("a:b".to_string()));
+	}
+
+	#[test]
+	fn parse_invalid_selectors() {
+		for text in ["", "#", ".", ".1a", "[attr", "[=x]", "[a~x]", "a[b=c d]",
+					 "foo|a", "a!", "-1a", "> a", "a >", "a + + b", "a ~"].iter() {
+			assert!(parse(text).is_none(), "{} should not parse", text);
+		}
+	}
+
+	#[test]
+	fn match_simple_selectors() {
+		let e = element("div", &[("id", "main"), ("class", "note  wide"), ("lang", "en-US"),
+								 ("type", "Text"), ("title", "Hello world")]);
+
+		let matches = |text: &str| parse(text).unwrap().matches(&MatchContext::root(&e));
+
+		assert!(matches("*"));
+		assert!(matches("DIV"));
+		assert!(!matches("span"));
+		assert!(matches("#main"));
+		assert!(!matches("#Main"));
+		assert!(matches(".note.wide"));
+		assert!(!matches(".not"));
+		assert!(matches("div.note#main[lang]"));
+		assert!(matches("[lang|=en]"));
+		assert!(!matches("[lang|=e]"));
+		assert!(matches("[title~=world]"));
+		assert!(!matches("[title~=wor]"));
+		assert!(matches("[title^=Hell]"));
+		assert!(matches("[title$=rld]"));
+		assert!(matches("[title*='o w']"));
+		assert!(!matches("[title*='']"));
+		assert!(matches("[title='hello WORLD' i]"));
+		assert!(!matches("[title='hello WORLD']"));
+		// 'type' values are case-insensitive in html unless 's' is given
+		assert!(matches("[type=text]"));
+		assert!(!matches("[type=text s]"));
+	}
+
+	#[test]
+	fn parse_combinators() {
+		let sel = parse("nav a").unwrap();
+		assert_eq!(sel.compounds.len(), 2);
+		assert_eq!(sel.combinators, vec![Combinator::Descendant]);
+
+		let sel = parse("ul>li  +  li ~p [a~=b]").unwrap();
+		assert_eq!(sel.compounds.len(), 5);
+		assert_eq!(sel.combinators, vec![Combinator::Child, Combinator::NextSibling,
+										 Combinator::SubsequentSibling, Combinator::Descendant]);
+		assert_eq!(sel.compounds[4].simple[0], SimpleSelector::Attribute(AttributeSelector {
+			name: "a".to_string(),
+			operator: AttributeOperator::Includes,
+			value: "b".to_string(),
+			case: AttributeCase::Default,
+		}));
+		assert!(sel.uses_sibling_combinators());
+		assert!(!parse("nav > ul a").unwrap().uses_sibling_combinators());
+
+		assert_eq!(parse("h1").unwrap(), Selector::type_selector("h1"));
+	}
+
+	#[test]
+	fn match_combinators() {
+		// <div class=a><section><p/><h1/><p class=x/><p class=y/></section></div>
+		let mut section = element("section", &[]);
+		section.add_child_element(element("p", &[]));
+		section.add_child_element(element("h1", &[]));
+		section.add_child_element(element("p", &[("class", "x")]));
+		section.add_child_element(element("p", &[("class", "y")]));
+		let mut div = element("div", &[("class", "a")]);
+		div.add_child_element(section);
+
+		let root = MatchContext::root(&div);
+		let section = root.child(0);
+		let p0 = section.child(0);
+		let px = section.child(2);
+		let py = section.child(3);
+
+		let matches = |text: &str, ctx: &MatchContext| parse(text).unwrap().matches(ctx);
+
+		assert!(matches("div p", &px));
+		assert!(matches(".a section > p", &px));
+		assert!(!matches(".a > p", &px));
+		assert!(matches("div > section > p", &p0));
+		assert!(matches("h1 + p", &px));
+		assert!(!matches("h1 + p", &py));
+		assert!(matches("h1 ~ p", &py));
+		assert!(!matches("h1 ~ p", &p0));
+		assert!(matches("p + h1 + .x + .y", &py));
+		assert!(matches("div.a section h1 ~ p.x + p", &py));
+		assert!(!matches("div.a section h1 ~ p.y + p", &py));
+		// the first sibling chain that fits is not the only one tried
+		assert!(matches("p ~ p.y", &py));
+		assert!(!matches("section ~ p", &px));
+	}
+
+	#[test]
+	fn match_deep_nesting() {
+		let mut leaf = element("span", &[]);
+		for i in 0..20 {
+			let mut parent = element("div", &[("class", if i == 10 { "mid" } else { "" })]);
+			parent.add_child_element(leaf);
+			leaf = parent;
+		}
+
+		fn at_leaf<F: Fn(&MatchContext)>(ctx: &MatchContext, f: &F) {
+			if ctx.element.children.is_empty() {
+				f(ctx)
+			} else {
+				at_leaf(&ctx.child(0), f)
+			}
+		}
+
+		at_leaf(&MatchContext::root(&leaf), &|span: &MatchContext| {
+			assert!(span.element.is(dom_tree::Namespace::Html, "span"));
+			assert!(parse("div div .mid div div span").unwrap().matches(span));
+			assert!(!parse(".mid > div > span").unwrap().matches(span));
+		});
+	}
+
+	#[test]
+	fn parse_selector_lists() {
+		let list = parse_selector_list("h1, h2 > a,.title[data-x=\"a,b\"]", &HashMap::new()).unwrap();
+
+		assert_eq!(list.len(), 3);
+		assert_eq!(list[0], Selector::type_selector("h1"));
+		assert_eq!(list[1].combinators, vec![Combinator::Child]);
+		assert_eq!(list[2].compounds[0].simple.len(), 2);
+
+		assert!(parse_selector_list("h1, , h2", &HashMap::new()).is_none());
+		assert!(parse_selector_list("h1, h2!", &HashMap::new()).is_none());
+	}
+
+	#[test]
+	fn selector_specificity() {
+		let spec = |text: &str| parse(text).unwrap().specificity();
+
+		assert_eq!(spec("*"), Specificity(0, 0, 0));
+		assert_eq!(spec("li"), Specificity(0, 0, 1));
+		assert_eq!(spec("ul li"), Specificity(0, 0, 2));
+		assert_eq!(spec("ul ol+li"), Specificity(0, 0, 3));
+		assert_eq!(spec("h1 + *[rel=up]"), Specificity(0, 1, 1));
+		assert_eq!(spec("ul ol li.red"), Specificity(0, 1, 3));
+		assert_eq!(spec("li.red.level"), Specificity(0, 2, 1));
+		assert_eq!(spec("#x34y"), Specificity(1, 0, 0));
+		assert_eq!(spec("div#a.b > p"), Specificity(1, 1, 2));
+
+		assert!(spec("#a") > spec(".a.b.c.d.e.f.g.h.i.j.k"));
+		assert!(spec(".a") > spec("a b c d e f g h i j k"));
+
+		let mut list = parse_selector_list("#a, p, .b, a b, *", &HashMap::new()).unwrap();
+		sort_by_specificity(&mut list);
+		let sorted: Vec<Specificity> = list.iter().map(|s| s.specificity()).collect();
+		assert_eq!(sorted, vec![Specificity(0, 0, 0), Specificity(0, 0, 1), Specificity(0, 0, 2),
+								Specificity(0, 1, 0), Specificity(1, 0, 0)]);
+	}
+
+	#[test]
+	fn parse_pseudo_elements() {
+		assert_eq!(parse("p::before").unwrap().pseudo_element(), Some(PseudoElement::Before));
+		assert_eq!(parse("li:AFTER").unwrap().pseudo_element(), Some(PseudoElement::After));
+		assert_eq!(parse("div > p.x::first-line").unwrap().pseudo_element(), Some(PseudoElement::FirstLine));
+		assert_eq!(parse("::first-letter").unwrap().pseudo_element(), Some(PseudoElement::FirstLetter));
+		assert_eq!(parse("p:first-child").unwrap().pseudo_element(), None);
+		assert_eq!(parse("p::before").unwrap().specificity(), Specificity(0, 0, 2));
+
+		for text in ["p::before span", "p::before.x", "p::marker", "::before::after",
+					 ":not(::before)", ":nth-child(1 of p::after)", ":has(::before)"].iter() {
+			assert!(parse(text).is_none(), "{} should not parse", text);
+		}
+
+		// :is() forgives the invalid argument and matches nothing
+		assert!(!parse(":is(p::after)").unwrap().matches(&MatchContext::root(&element("p", &[]))));
+
+		let p = element("p", &[]);
+		let context = MatchContext::root(&p);
+		assert!(!parse("p::before").unwrap().matches(&context));
+		assert!(parse("p::before").unwrap().matches_pseudo(&context, PseudoElement::Before));
+		assert!(!parse("p::before").unwrap().matches_pseudo(&context, PseudoElement::After));
+		assert!(!parse("p").unwrap().matches_pseudo(&context, PseudoElement::Before));
+	}
+}
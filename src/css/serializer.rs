@@ -0,0 +1,889 @@
+use std::collections::HashMap;
+
+use dom_tree::Namespace;
+use super::stylesheet::{StyleSheet, CssRule, StyleRule, Declaration, Value, ContentItem, CounterStyle,
+						BlockType, WhiteSpaceType};
+use super::selector::{Selector, CompoundSelector, SimpleSelector, Combinator, NamespaceConstraint,
+					  AttributeOperator, AttributeCase};
+use super::pseudo_class::{PseudoClass, Nth};
+use super::media::{MediaQueryList, MediaQuery, MediaType, MediaCondition, MediaFeature, MediaValue, Comparison};
+use super::calc::CalcNode;
+use super::color::Color;
+use super::shorthand::Shorthand;
+use super::parser::ComponentValue;
+use super::tokenizer::Token;
+
+/// Shorthands whose four longhands, top, right, bottom and left, are
+/// written back as the shorthand when a block sets all of them
+const SIDE_SHORTHANDS: [&'static str; 6] = ["margin", "padding", "inset", "border-width",
+											"border-style", "border-color"];
+
+/// The CSS text of 'sheet', one top level rule per line. Nested rules are
+/// indented by two spaces for each level. Prefixes of selectors are
+/// written with the sheet's @namespace rules; None if one of them has
+/// a namespace no rule declares a prefix for
+/// www.w3.org/TR/cssom-1/#serialize-a-css-rule
+pub fn serialize_stylesheet(sheet: &StyleSheet) -> Option<String> {
+	let namespaces = sheet.namespaces();
+	let rules: Option<Vec<String>> = sheet.rules().iter().map(|r| serialize_rule(r, &namespaces)).collect();
+	rules.map(|rules| rules.join("\n"))
+}
+
+/// The CSS text of 'rule'. 'namespaces' maps the prefixes of the
+/// stylesheet it is in, as for selector::parse_selector. The stylesheet
+/// of an @import rule is not part of it. None if a selector in it can not
+/// be written with those prefixes
+pub fn serialize_rule(rule: &CssRule, namespaces: &HashMap<String, Namespace>) -> Option<String> {
+	Some(match *rule {
+		CssRule::Style(ref style) => return serialize_style_rule(style, namespaces),
+		CssRule::Media(ref media) => {
+			let prelude = prefixed("@media", &serialize_media_query_list(&media.media));
+			return grouping_rule(&prelude, &media.rules[..], namespaces);
+		}
+		CssRule::Supports(ref supports) => {
+			return grouping_rule(&prefixed("@supports", &supports.condition), &supports.rules[..], namespaces);
+		}
+		CssRule::Import(ref import) => {
+			let url = format!("url({})", serialize_string(&import.url));
+			format!("{};", prefixed(&prefixed("@import", &url), &serialize_media_query_list(&import.media)))
+		}
+		CssRule::FontFace(ref font_face) => {
+			let descriptors: Vec<String> = font_face.descriptors.iter().map(|&(ref name, ref value)| {
+				format!("{}: {};", name, value)
+			}).collect();
+			block("@font-face", &descriptors.join(" "))
+		}
+		CssRule::Keyframes(ref keyframes) => {
+			let blocks: Vec<String> = keyframes.keyframes.iter().map(|keyframe| {
+				let offsets: Vec<String> = keyframe.offsets.iter().map(|&o| format!("{}%", number(o))).collect();
+				block(&offsets.join(", "), &serialize_declarations(&keyframe.declarations[..]))
+			}).collect();
+			nested_block(&format!("@keyframes {}", serialize_identifier(&keyframes.name)), &blocks[..])
+		}
+		CssRule::Page(ref page) => {
+			block(&prefixed("@page", &page.selector), &serialize_declarations(&page.declarations[..]))
+		}
+		CssRule::Namespace(ref namespace) => {
+			let url = format!("url({})", serialize_string(namespace.namespace.url()));
+			let prefix = if namespace.prefix.is_empty() { String::new() } else { serialize_identifier(&namespace.prefix) };
+			format!("{};", prefixed(&prefixed("@namespace", &prefix), &url))
+		}
+		CssRule::Layer(ref layer) => {
+			let prelude = prefixed("@layer", &layer.names.join(", "));
+			match layer.rules {
+				Some(ref rules) => return grouping_rule(&prelude, &rules[..], namespaces),
+				None => format!("{};", prelude),
+			}
+		}
+		CssRule::Property(ref property) => {
+			let mut descriptors = vec![format!("syntax: {};", serialize_string(&property.syntax)),
+									   format!("inherits: {};", property.inherits)];
+			match property.initial_value {
+				Some(ref value) => descriptors.push(format!("initial-value: {};", value)),
+				None => {}
+			}
+			block(&format!("@property {}", serialize_identifier(&property.name)), &descriptors.join(" "))
+		}
+	})
+}
+
+/// 'selectors { declarations }'
+fn serialize_style_rule(rule: &StyleRule, namespaces: &HashMap<String, Namespace>) -> Option<String> {
+	serialize_selector_list(&rule.selectors[..], namespaces)
+		.map(|selectors| block(&selectors, &serialize_declarations(&rule.declarations[..])))
+}
+
+/// 'prelude { text }', or 'prelude { }' without text
+fn block(prelude: &str, text: &str) -> String {
+	if text.is_empty() {
+		format!("{} {{ }}", prelude)
+	} else {
+		format!("{} {{ {} }}", prelude, text)
+	}
+}
+
+/// 'prelude {', then each of 'items' on lines of their own, indented,
+/// then '}'
+fn nested_block(prelude: &str, items: &[String]) -> String {
+	let mut text = format!("{} {{\n", prelude);
+	for item in items.iter() {
+		for line in item.lines() {
+			text.push_str("  ");
+			text.push_str(line);
+			text.push('\n');
+		}
+	}
+	text.push('}');
+	text
+}
+
+fn grouping_rule(prelude: &str, rules: &[CssRule], namespaces: &HashMap<String, Namespace>) -> Option<String> {
+	let rules: Option<Vec<String>> = rules.iter().map(|r| serialize_rule(r, namespaces)).collect();
+	rules.map(|rules| nested_block(prelude, &rules[..]))
+}
+
+/// 'keyword text', or just 'keyword' if there is no text
+fn prefixed(keyword: &str, text: &str) -> String {
+	if text.is_empty() {
+		keyword.to_string()
+	} else {
+		format!("{} {}", keyword, text)
+	}
+}
+
+/// The declarations of a block as 'name: value;' separated by spaces.
+/// Invalid declarations are left out. Longhands set together by a
+/// shorthand with var() in its value, and the four longhands of a box side
+/// shorthand, are written as the shorthand
+/// www.w3.org/TR/cssom-1/#serialize-a-css-declaration-block
+pub fn serialize_declarations(declarations: &[Declaration]) -> String {
+	let mut written = vec![false; declarations.len()];
+	let mut text = Vec::new();
+
+	for (i, declaration) in declarations.iter().enumerate() {
+		if written[i] || declaration.property_value == Value::Missing {
+			continue;
+		}
+
+		let (name, value) = match declaration.property_value {
+			Value::PendingSubstitution(ref shorthand, ref value) => {
+				for (j, other) in declarations.iter().enumerate().skip(i) {
+					if other.property_value == declaration.property_value && other.important == declaration.important {
+						written[j] = true;
+					}
+				}
+				(shorthand.clone(), value.clone())
+			}
+			Value::Custom(ref name, ref value) => (serialize_identifier(name), value.clone()),
+			Value::CustomCssWide(ref name, keyword) => (serialize_identifier(name), keyword.name().to_string()),
+			ref value => match side_shorthand(declarations, i) {
+				Some((shorthand, members, sides)) => {
+					for &j in members.iter() {
+						written[j] = true;
+					}
+					(shorthand.to_string(), sides)
+				}
+				None => (declaration.property_name.name().to_string(), serialize_value(value)),
+			},
+		};
+
+		let important = if declaration.important { " !important" } else { "" };
+		text.push(format!("{}: {}{};", name, value, important));
+	}
+
+	text.join(" ")
+}
+
+/// The value of the shorthand 'name' given the declarations of its
+/// longhands, one each. None if they cannot be written as it
+pub fn serialize_shorthand(name: &str, declarations: &[Declaration]) -> Option<String> {
+	match declarations.first().map(|d| &d.property_value) {
+		Some(&Value::PendingSubstitution(ref shorthand, ref text)) => {
+			let same = declarations.iter().all(|d| d.property_value == declarations[0].property_value);
+			if shorthand == name && same { Some(text.clone()) } else { None }
+		}
+		Some(_) => match side_shorthand(declarations, 0) {
+			Some((shorthand, _, value)) if shorthand == name => Some(value),
+			_ => None,
+		},
+		None => None,
+	}
+}
+
+/// The side shorthand declaration 'i' can be written as with the others
+/// of its block: its name, the positions of its longhands' declarations
+/// and its value. There is one if each longhand is declared exactly once,
+/// all with the same importance, and the values are all CSS-wide keywords
+/// or none are
+fn side_shorthand(declarations: &[Declaration], i: usize) -> Option<(&'static str, Vec<usize>, String)> {
+	let declaration = &declarations[i];
+
+	for &name in SIDE_SHORTHANDS.iter() {
+		let longhands = Shorthand::from_name(name).unwrap().longhands();
+		if !longhands.contains(&declaration.property_name) {
+			continue;
+		}
+
+		let mut members = Vec::new();
+		for &longhand in longhands.iter() {
+			let found: Vec<usize> = (0..declarations.len())
+				.filter(|&j| declarations[j].property_name == longhand)
+				.collect();
+			match (found.len(), found.first()) {
+				(1, Some(&j)) if declarations[j].important == declaration.important => members.push(j),
+				_ => return None,
+			}
+		}
+
+		let values: Vec<&Value> = members.iter().map(|&j| &declarations[j].property_value).collect();
+		let keywords = values.iter().filter(|v| v.css_wide_keyword().is_some()).count();
+		let plain = values.iter().all(|v| match **v {
+			Value::Missing | Value::Unsubstituted(_) | Value::PendingSubstitution(_, _) => false,
+			_ => true,
+		});
+		if !plain || (keywords != 0 && keywords != 4) {
+			return None;
+		}
+
+		let sides: Vec<String> = values.iter().map(|v| serialize_value(v)).collect();
+		let same = sides[1..].iter().all(|s| *s == sides[0]);
+		if keywords == 4 && !same {
+			return None;
+		}
+
+		let count = if same {
+			1
+		} else if sides[2] == sides[0] && sides[3] == sides[1] {
+			2
+		} else if sides[3] == sides[1] {
+			3
+		} else {
+			4
+		};
+		return Some((name, members, sides[..count].join(" ")));
+	}
+
+	None
+}
+
+/// The CSS text of a declared value
+/// www.w3.org/TR/cssom-1/#serialize-a-css-value
+pub fn serialize_value(value: &Value) -> String {
+	match *value {
+		Value::Size(n, unit) => format!("{}{}", number(n), unit.name()),
+		Value::Percentage(n) => format!("{}%", number(n)),
+		Value::Number(n) => number(n),
+		Value::Calc(ref c) => match *c {
+			CalcNode::Min(_) | CalcNode::Max(_) | CalcNode::Clamp(_, _, _) => calc_text(c),
+			_ => format!("calc({})", calc_text(c)),
+		},
+		Value::ColorValue(c) => serialize_color(c),
+		Value::CurrentColor => "currentcolor".to_string(),
+		Value::Block(block) => match block {
+			BlockType::Inline => "inline",
+			BlockType::Block => "block",
+			BlockType::None => "none",
+		}.to_string(),
+		Value::WhiteSpace(white_space) => match white_space {
+			WhiteSpaceType::Normal => "normal",
+			WhiteSpaceType::Pre => "pre",
+			WhiteSpaceType::Nowrap => "nowrap",
+			WhiteSpaceType::PreWrap => "pre-wrap",
+			WhiteSpaceType::PreLine => "pre-line",
+			WhiteSpaceType::BreakSpaces => "break-spaces",
+		}.to_string(),
+		Value::Content(ref items) if items.is_empty() => "none".to_string(),
+		Value::Content(ref items) => {
+			let items: Vec<String> = items.iter().map(content_item).collect();
+			items.join(" ")
+		}
+		Value::Counters(ref counters) if counters.is_empty() => "none".to_string(),
+		Value::Counters(ref counters) => {
+			let counters: Vec<String> = counters.iter().map(|&(ref name, value)| {
+				format!("{} {}", serialize_identifier(name), value)
+			}).collect();
+			counters.join(" ")
+		}
+		Value::Keyword(ref keyword) => keyword.clone(),
+		Value::Url(ref url) => format!("url({})", serialize_string(url)),
+		Value::FontFamily(ref families) => {
+			let families: Vec<String> = families.iter().map(|f| font_family(f)).collect();
+			families.join(", ")
+		}
+		Value::List(ref values) => {
+			let values: Vec<String> = values.iter().map(serialize_value).collect();
+			values.join(" ")
+		}
+		Value::CssWide(keyword) | Value::CustomCssWide(_, keyword) => keyword.name().to_string(),
+		Value::Custom(_, ref text) | Value::Unsubstituted(ref text) |
+		Value::PendingSubstitution(_, ref text) => text.clone(),
+		Value::Missing => String::new(),
+	}
+}
+
+/// A number in the shortest form that reads back as the same number
+fn number(n: f32) -> String {
+	if n == 0.0 { "0".to_string() } else { n.to_string() }
+}
+
+/// 'rgb(r, g, b)' for opaque colors, 'rgba(r, g, b, alpha)' otherwise.
+/// The alpha is given with as few decimals as keep it the same
+/// www.w3.org/TR/css-color-4/#serializing-sRGB-values
+pub fn serialize_color(color: Color) -> String {
+	if color.alpha == 255 {
+		return format!("rgb({}, {}, {})", color.red, color.green, color.blue);
+	}
+
+	let mut alpha = color.alpha as f32 / 255.0;
+	let rounded = (alpha * 100.0).round() / 100.0;
+	if (rounded * 255.0).round() as u8 == color.alpha {
+		alpha = rounded;
+	} else {
+		alpha = (alpha * 1000.0).round() / 1000.0;
+	}
+
+	format!("rgba({}, {}, {}, {})", color.red, color.green, color.blue, number(alpha))
+}
+
+/// The inside of a math function: sums and products, with parentheses
+/// where the tree would otherwise read back differently
+fn calc_text(node: &CalcNode) -> String {
+	let is_sum = |n: &CalcNode| match *n {
+		CalcNode::Add(_, _) | CalcNode::Subtract(_, _) => true,
+		_ => false,
+	};
+	let is_product = |n: &CalcNode| match *n {
+		CalcNode::Multiply(_, _) | CalcNode::Divide(_, _) => true,
+		_ => false,
+	};
+	let operand = |n: &CalcNode, grouped: bool| {
+		if grouped { format!("({})", calc_text(n)) } else { calc_text(n) }
+	};
+	let arguments = |nodes: &[&CalcNode]| {
+		let nodes: Vec<String> = nodes.iter().map(|n| calc_text(n)).collect();
+		nodes.join(", ")
+	};
+
+	match *node {
+		CalcNode::Number(n) => number(n),
+		CalcNode::Percentage(n) => format!("{}%", number(n)),
+		CalcNode::Length(n, unit) => format!("{}{}", number(n), unit.name()),
+		CalcNode::Add(ref a, ref b) => format!("{} + {}", calc_text(a), calc_text(b)),
+		CalcNode::Subtract(ref a, ref b) => format!("{} - {}", calc_text(a), operand(b, is_sum(b))),
+		CalcNode::Multiply(ref a, ref b) => format!("{} * {}", operand(a, is_sum(a)), operand(b, is_sum(b) || is_product(b))),
+		CalcNode::Divide(ref a, ref b) => format!("{} / {}", operand(a, is_sum(a)), operand(b, is_sum(b) || is_product(b))),
+		CalcNode::Min(ref nodes) => format!("min({})", arguments(&nodes.iter().collect::<Vec<&CalcNode>>()[..])),
+		CalcNode::Max(ref nodes) => format!("max({})", arguments(&nodes.iter().collect::<Vec<&CalcNode>>()[..])),
+		CalcNode::Clamp(ref min, ref value, ref max) => format!("clamp({})", arguments(&[&**min, &**value, &**max])),
+	}
+}
+
+fn content_item(item: &ContentItem) -> String {
+	let style = |style: CounterStyle| {
+		if style == CounterStyle::Decimal { String::new() } else { format!(", {}", style.name()) }
+	};
+
+	match *item {
+		ContentItem::Text(ref text) => serialize_string(text),
+		ContentItem::Attr(ref name) => format!("attr({})", serialize_identifier(name)),
+		ContentItem::Counter(ref name, s) => format!("counter({}{})", serialize_identifier(name), style(s)),
+		ContentItem::Counters(ref name, ref separator, s) => {
+			format!("counters({}, {}{})", serialize_identifier(name), serialize_string(separator), style(s))
+		}
+		ContentItem::OpenQuote => "open-quote".to_string(),
+		ContentItem::CloseQuote => "close-quote".to_string(),
+		ContentItem::NoOpenQuote => "no-open-quote".to_string(),
+		ContentItem::NoCloseQuote => "no-close-quote".to_string(),
+	}
+}
+
+/// A family name as identifiers if it reads back as the same name,
+/// otherwise as a string
+fn font_family(family: &str) -> String {
+	let words: Vec<&str> = family.split(' ').collect();
+	if words.iter().all(|w| !w.is_empty() && serialize_identifier(w) == *w) {
+		family.to_string()
+	} else {
+		serialize_string(family)
+	}
+}
+
+/// 'text' as an identifier, escaping the characters that cannot appear
+/// where they are
+/// www.w3.org/TR/cssom-1/#serialize-an-identifier
+pub fn serialize_identifier(text: &str) -> String {
+	let chars: Vec<char> = text.chars().collect();
+	let mut result = String::new();
+
+	for (i, &c) in chars.iter().enumerate() {
+		let leading_digit = c.is_digit(10) && (i == 0 || (i == 1 && chars[0] == '-'));
+
+		match c {
+			'\0' => result.push('\u{FFFD}'),
+			'\u{1}'...'\u{1f}' | '\u{7f}' => result.push_str(&format!("\\{:x} ", c as u32)),
+			_ if leading_digit => result.push_str(&format!("\\{:x} ", c as u32)),
+			'-' if chars.len() == 1 => result.push_str("\\-"),
+			'a'...'z' | 'A'...'Z' | '0'...'9' | '-' | '_' => result.push(c),
+			_ if c as u32 >= 0x80 => result.push(c),
+			_ => {
+				result.push('\\');
+				result.push(c);
+			}
+		}
+	}
+
+	result
+}
+
+/// 'text' in double quotes
+/// www.w3.org/TR/cssom-1/#serialize-a-string
+pub fn serialize_string(text: &str) -> String {
+	let mut result = String::from("\"");
+
+	for c in text.chars() {
+		match c {
+			'\0' => result.push('\u{FFFD}'),
+			'\u{1}'...'\u{1f}' | '\u{7f}' => result.push_str(&format!("\\{:x} ", c as u32)),
+			'"' | '\\' => {
+				result.push('\\');
+				result.push(c);
+			}
+			_ => result.push(c),
+		}
+	}
+
+	result.push('"');
+	result
+}
+
+/// The selectors of a list separated by ', '. None if one of them can not
+/// be written
+pub fn serialize_selector_list(selectors: &[Selector], namespaces: &HashMap<String, Namespace>) -> Option<String> {
+	let selectors: Option<Vec<String>> = selectors.iter().map(|s| serialize_selector(s, namespaces)).collect();
+	selectors.map(|selectors| selectors.join(", "))
+}
+
+/// The CSS text of a complex selector. Namespaces are written with a
+/// prefix 'namespaces' has for them; a type selector in the default
+/// namespace needs none. None if 'namespaces' has no prefix for a
+/// namespace it is in, as leaving the prefix out would match more
+/// www.w3.org/TR/cssom-1/#serialize-a-selector
+pub fn serialize_selector(selector: &Selector, namespaces: &HashMap<String, Namespace>) -> Option<String> {
+	let mut text = String::new();
+
+	for (i, compound) in selector.compounds.iter().enumerate() {
+		if i > 0 {
+			text.push_str(match selector.combinators[i - 1] {
+				Combinator::Descendant => " ",
+				Combinator::Child => " > ",
+				Combinator::NextSibling => " + ",
+				Combinator::SubsequentSibling => " ~ ",
+			});
+		}
+		match compound_selector(compound, namespaces) {
+			Some(compound) => text.push_str(&compound),
+			None => return None,
+		}
+	}
+
+	Some(text)
+}
+
+fn compound_selector(compound: &CompoundSelector, namespaces: &HashMap<String, Namespace>) -> Option<String> {
+	let mut text = String::new();
+
+	for (i, simple) in compound.simple.iter().enumerate() {
+		let part = match *simple {
+			// '*' goes without saying before other simple selectors
+			SimpleSelector::Universal(constraint) => {
+				let prefix = match namespace_prefix(constraint, namespaces) {
+					Some(prefix) => prefix,
+					None => return None,
+				};
+				if prefix.is_empty() && i + 1 < compound.simple.len() {
+					String::new()
+				} else {
+					format!("{}*", prefix)
+				}
+			}
+			SimpleSelector::Type(constraint, ref name) => match namespace_prefix(constraint, namespaces) {
+				Some(prefix) => format!("{}{}", prefix, serialize_identifier(name)),
+				None => return None,
+			},
+			SimpleSelector::Id(ref id) => format!("#{}", serialize_identifier(id)),
+			SimpleSelector::Class(ref class) => format!(".{}", serialize_identifier(class)),
+			SimpleSelector::Attribute(ref attribute) => {
+				let operator = match attribute.operator {
+					AttributeOperator::Exists => "",
+					AttributeOperator::Equals => "=",
+					AttributeOperator::Includes => "~=",
+					AttributeOperator::DashMatch => "|=",
+					AttributeOperator::Prefix => "^=",
+					AttributeOperator::Suffix => "$=",
+					AttributeOperator::Substring => "*=",
+				};
+				let value = if attribute.operator == AttributeOperator::Exists {
+					String::new()
+				} else {
+					serialize_string(&attribute.value)
+				};
+				let case = match attribute.case {
+					AttributeCase::Default => "",
+					AttributeCase::Insensitive => " i",
+					AttributeCase::Sensitive => " s",
+				};
+				format!("[{}{}{}{}]", serialize_identifier(&attribute.name), operator, value, case)
+			}
+			SimpleSelector::PseudoClass(ref pseudo) => match pseudo_class(pseudo, namespaces) {
+				Some(pseudo) => format!(":{}", pseudo),
+				None => return None,
+			},
+			SimpleSelector::PseudoElement(pseudo) => format!("::{}", pseudo.name()),
+		};
+		text.push_str(&part);
+	}
+
+	Some(text)
+}
+
+/// The 'prefix|' a type or universal selector is written with. None for
+/// a namespace that has no prefix and is not the default one
+fn namespace_prefix(constraint: NamespaceConstraint, namespaces: &HashMap<String, Namespace>) -> Option<String> {
+	let default = namespaces.get("").cloned();
+
+	match constraint {
+		NamespaceConstraint::Any if default.is_some() => Some("*|".to_string()),
+		NamespaceConstraint::Any => Some(String::new()),
+		NamespaceConstraint::NoNamespace => Some("|".to_string()),
+		NamespaceConstraint::Is(namespace) if default == Some(namespace) => Some(String::new()),
+		NamespaceConstraint::Is(namespace) => {
+			namespaces.iter()
+				.filter(|&(prefix, &n)| n == namespace && !prefix.is_empty())
+				.map(|(prefix, _)| prefix)
+				.min()
+				.map(|prefix| format!("{}|", serialize_identifier(prefix)))
+		}
+	}
+}
+
+fn pseudo_class(pseudo: &PseudoClass, namespaces: &HashMap<String, Namespace>) -> Option<String> {
+	let list = |selectors: &Vec<Selector>| serialize_selector_list(&selectors[..], namespaces);
+	let nth_of = |nth: &Nth, of: &Vec<Selector>| {
+		if of.is_empty() { Some(nth_text(nth)) } else { list(of).map(|of| format!("{} of {}", nth_text(nth), of)) }
+	};
+
+	Some(match *pseudo {
+		PseudoClass::Root => "root".to_string(),
+		PseudoClass::Empty => "empty".to_string(),
+		PseudoClass::FirstChild => "first-child".to_string(),
+		PseudoClass::LastChild => "last-child".to_string(),
+		PseudoClass::OnlyChild => "only-child".to_string(),
+		PseudoClass::FirstOfType => "first-of-type".to_string(),
+		PseudoClass::LastOfType => "last-of-type".to_string(),
+		PseudoClass::OnlyOfType => "only-of-type".to_string(),
+		PseudoClass::NthChild(ref nth, ref of) => return nth_of(nth, of).map(|n| format!("nth-child({})", n)),
+		PseudoClass::NthLastChild(ref nth, ref of) => return nth_of(nth, of).map(|n| format!("nth-last-child({})", n)),
+		PseudoClass::NthOfType(ref nth) => format!("nth-of-type({})", nth_text(nth)),
+		PseudoClass::NthLastOfType(ref nth) => format!("nth-last-of-type({})", nth_text(nth)),
+		PseudoClass::Not(ref selectors) => return list(selectors).map(|l| format!("not({})", l)),
+		PseudoClass::Is(ref selectors) => return list(selectors).map(|l| format!("is({})", l)),
+		PseudoClass::Where(ref selectors) => return list(selectors).map(|l| format!("where({})", l)),
+		PseudoClass::Has(ref relative) => {
+			let relative: Option<Vec<String>> = relative.iter().map(|r| {
+				let combinator = match r.combinator {
+					Combinator::Descendant => "",
+					Combinator::Child => "> ",
+					Combinator::NextSibling => "+ ",
+					Combinator::SubsequentSibling => "~ ",
+				};
+				serialize_selector(&r.selector, namespaces).map(|s| format!("{}{}", combinator, s))
+			}).collect();
+			return relative.map(|relative| format!("has({})", relative.join(", ")));
+		}
+		PseudoClass::Hover => "hover".to_string(),
+		PseudoClass::Active => "active".to_string(),
+		PseudoClass::Focus => "focus".to_string(),
+		PseudoClass::Checked => "checked".to_string(),
+		PseudoClass::Link => "link".to_string(),
+		PseudoClass::Visited => "visited".to_string(),
+	})
+}
+
+/// 'an+b' with the parts that are zero left out
+/// www.w3.org/TR/css-syntax-3/#serializing-anb
+fn nth_text(nth: &Nth) -> String {
+	let a = match nth.a {
+		0 => return nth.b.to_string(),
+		1 => "n".to_string(),
+		-1 => "-n".to_string(),
+		a => format!("{}n", a),
+	};
+
+	match nth.b {
+		0 => a,
+		b if b > 0 => format!("{}+{}", a, b),
+		b => format!("{}{}", a, b),
+	}
+}
+
+/// The queries of a list separated by ', '. An empty list is empty text
+/// www.w3.org/TR/cssom-1/#serialize-a-media-query-list
+pub fn serialize_media_query_list(list: &MediaQueryList) -> String {
+	let queries: Vec<String> = list.queries.iter().map(media_query).collect();
+	queries.join(", ")
+}
+
+fn media_query(query: &MediaQuery) -> String {
+	let media_type = match query.media_type {
+		MediaType::All => "all".to_string(),
+		MediaType::Screen => "screen".to_string(),
+		MediaType::Print => "print".to_string(),
+		MediaType::Unknown(ref name) => serialize_identifier(name),
+	};
+
+	match (query.negated, &query.media_type, &query.condition) {
+		(false, &MediaType::All, &Some(ref condition)) => media_condition(condition),
+		(negated, _, condition) => {
+			let mut text = if negated { format!("not {}", media_type) } else { media_type };
+			match *condition {
+				Some(ref c) => {
+					text.push_str(" and ");
+					text.push_str(&media_condition(c));
+				}
+				None => {}
+			}
+			text
+		}
+	}
+}
+
+fn media_condition(condition: &MediaCondition) -> String {
+	let joined = |conditions: &Vec<MediaCondition>, operator: &str| {
+		let conditions: Vec<String> = conditions.iter().map(media_in_parens).collect();
+		conditions.join(operator)
+	};
+
+	match *condition {
+		MediaCondition::Not(ref c) => format!("not {}", media_in_parens(c)),
+		MediaCondition::And(ref conditions) => joined(conditions, " and "),
+		MediaCondition::Or(ref conditions) => joined(conditions, " or "),
+		_ => media_in_parens(condition),
+	}
+}
+
+/// A condition in parentheses. An unknown one is written as it was read
+fn media_in_parens(condition: &MediaCondition) -> String {
+	match *condition {
+		MediaCondition::Feature(ref feature) => media_feature(feature),
+		MediaCondition::Unknown(ref text) => text.clone(),
+		ref c => format!("({})", media_condition(c)),
+	}
+}
+
+/// A component value as css text. Whitespace runs become one space
+/// www.w3.org/TR/css-syntax-3/#serialization
+pub fn serialize_component_value(value: &ComponentValue) -> String {
+	match *value {
+		ComponentValue::Token(ref token) => token_text(token),
+		ComponentValue::Function(ref name, ref args) => format!("{}({})", serialize_identifier(name), component_values(args)),
+		ComponentValue::Block(ref open, ref inner) => {
+			let close = match *open {
+				Token::OpenSquare => "]",
+				Token::OpenCurly => "}",
+				_ => ")",
+			};
+			format!("{}{}{}", token_text(open), component_values(inner), close)
+		}
+	}
+}
+
+fn component_values(values: &[ComponentValue]) -> String {
+	values.iter().map(serialize_component_value).collect::<Vec<String>>().concat()
+}
+
+fn token_text(token: &Token) -> String {
+	match *token {
+		Token::Ident(ref name) => serialize_identifier(name),
+		Token::Function(ref name) => format!("{}(", serialize_identifier(name)),
+		Token::AtKeyword(ref name) => format!("@{}", serialize_identifier(name)),
+		Token::Hash(ref name, _) => format!("#{}", serialize_identifier(name)),
+		Token::QuotedString(ref text) => serialize_string(text),
+		Token::Url(ref url) => format!("url({})", serialize_string(url)),
+		Token::BadString | Token::BadUrl => String::new(),
+		Token::Delim(c) => c.to_string(),
+		Token::Number(n) => n.value.to_string(),
+		Token::Percentage(n) => format!("{}%", n.value),
+		Token::Dimension(n, ref unit) => format!("{}{}", n.value, serialize_identifier(unit)),
+		Token::Whitespace => " ".to_string(),
+		Token::CDO => "<!--".to_string(),
+		Token::CDC => "-->".to_string(),
+		Token::Colon => ":".to_string(),
+		Token::Semicolon => ";".to_string(),
+		Token::Comma => ",".to_string(),
+		Token::OpenSquare => "[".to_string(),
+		Token::CloseSquare => "]".to_string(),
+		Token::OpenParen => "(".to_string(),
+		Token::CloseParen => ")".to_string(),
+		Token::OpenCurly => "{".to_string(),
+		Token::CloseCurly => "}".to_string(),
+	}
+}
+
+/// '(name)', '(name: value)', '(name < value)' or
+/// '(value < name < value)'
+fn media_feature(feature: &MediaFeature) -> String {
+	let operator = |comparison: Comparison, flipped: bool| match (comparison, flipped) {
+		(Comparison::Lt, false) | (Comparison::Gt, true) => "<",
+		(Comparison::Le, false) | (Comparison::Ge, true) => "<=",
+		(Comparison::Eq, _) => "=",
+		(Comparison::Ge, false) | (Comparison::Le, true) => ">=",
+		(Comparison::Gt, false) | (Comparison::Lt, true) => ">",
+	};
+	let name = &feature.name;
+
+	match (feature.comparisons.len(), feature.comparisons.first()) {
+		(1, Some(&(Comparison::Eq, ref value))) => format!("({}: {})", name, media_value(value)),
+		(1, Some(&(comparison, ref value))) => {
+			format!("({} {} {})", name, operator(comparison, false), media_value(value))
+		}
+		(2, Some(&(low, ref low_value))) => {
+			let (high, ref high_value) = feature.comparisons[1];
+			format!("({} {} {} {} {})", media_value(low_value), operator(low, true), name,
+					operator(high, false), media_value(high_value))
+		}
+		_ => format!("({})", name),
+	}
+}
+
+fn media_value(value: &MediaValue) -> String {
+	match *value {
+		MediaValue::Length(n) => format!("{}px", number(n)),
+		MediaValue::Ratio(a, b) => format!("{}/{}", number(a), number(b)),
+		MediaValue::Resolution(n) => format!("{}dppx", number(n)),
+		MediaValue::Keyword(ref keyword) => keyword.clone(),
+	}
+}
+
+#[cfg(test)]
+mod test_serializer {
+	use std::collections::HashMap;
+
+	use super::{serialize_stylesheet, serialize_declarations, serialize_identifier, serialize_string,
+				serialize_media_query_list, serialize_selector_list};
+	use super::super::selector::parse_selector_list;
+	use super::super::parser::CssParser;
+	use super::super::media::parse_media_query_list;
+	use super::super::stylesheet::{StyleSheet, string_to_declarations};
+
+	fn round_trip(css: &str) -> String {
+		let text = serialize_stylesheet(&CssParser::new(css.to_string()).parse_css()).unwrap();
+		assert_eq!(serialize_stylesheet(&CssParser::new(text.clone()).parse_css()), Some(text.clone()));
+		text
+	}
+
+	fn declarations(items: &[(&str, &str)]) -> String {
+		let declarations: Vec<_> = items.iter()
+			.flat_map(|&(name, value)| string_to_declarations(name, value).unwrap().into_iter())
+			.collect();
+		serialize_declarations(&declarations[..])
+	}
+
+	#[test]
+	fn serializer_values() {
+		assert_eq!(declarations(&[("font-size", "1.5EM"), ("line-height", "120%"), ("flex-grow", "2")]),
+				   "font-size: 1.5em; line-height: 120%; flex-grow: 2;");
+		assert_eq!(declarations(&[("color", "#ff000080"), ("background-color", "blue")]),
+				   "color: rgba(255, 0, 0, 0.5); background-color: rgb(0, 0, 255);");
+		assert_eq!(declarations(&[("left", "calc((1px + 2%) * 3 - 4em / (2 * 2))")]),
+				   "left: calc((1px + 2%) * 3 - 4em / (2 * 2));");
+		assert_eq!(declarations(&[("top", "clamp(1px, 2vw, 3rem)")]), "top: clamp(1px, 2vw, 3rem);");
+		assert_eq!(declarations(&[("content", "open-quote \"a\\\"b\" counters(item, \".\", upper-roman) attr(title)")]),
+				   "content: open-quote \"a\\\"b\" counters(item, \".\", upper-roman) attr(title);");
+		assert_eq!(declarations(&[("counter-reset", "a b 2")]), "counter-reset: a 0 b 2;");
+		assert_eq!(declarations(&[("font-family", "\"Times New Roman\", Open Sans, serif")]),
+				   "font-family: Times New Roman, Open Sans, serif;");
+		assert_eq!(declarations(&[("background-image", "url(a.png)"), ("color", "inherit")]),
+				   "background-image: url(\"a.png\"); color: inherit;");
+		assert_eq!(declarations(&[("--Accent", " red "), ("color", "var(--Accent)")]),
+				   "--Accent: red; color: var(--Accent);");
+		assert_eq!(declarations(&[("--Accent", " INHERIT ")]), "--Accent: inherit;");
+	}
+
+	#[test]
+	fn serializer_shorthands() {
+		assert_eq!(declarations(&[("margin", "1px 2px")]), "margin: 1px 2px;");
+		assert_eq!(declarations(&[("padding", "1px 2px 1px 2px")]), "padding: 1px 2px;");
+		assert_eq!(declarations(&[("border-width", "1px 2px 3px")]), "border-width: 1px 2px 3px;");
+		assert_eq!(declarations(&[("margin", "inherit")]), "margin: inherit;");
+		assert_eq!(declarations(&[("margin", "1px"), ("margin-top", "2px")]),
+				   "margin-top: 1px; margin-right: 1px; margin-bottom: 1px; margin-left: 1px; margin-top: 2px;");
+		assert_eq!(declarations(&[("margin-top", "1px"), ("margin-left", "initial")]),
+				   "margin-top: 1px; margin-left: initial;");
+		assert_eq!(declarations(&[("margin", "var(--m) 1px")]), "margin: var(--m) 1px;");
+		assert_eq!(declarations(&[("gap", "1px 2px")]), "row-gap: 1px; column-gap: 2px;");
+	}
+
+	#[test]
+	fn serializer_escaping() {
+		assert_eq!(serialize_identifier("a-b_c"), "a-b_c");
+		assert_eq!(serialize_identifier("1a"), "\\31 a");
+		assert_eq!(serialize_identifier("-2"), "-\\32 ");
+		assert_eq!(serialize_identifier("-"), "\\-");
+		assert_eq!(serialize_identifier("a.b"), "a\\.b");
+		assert_eq!(serialize_string("say \"hi\"\n"), "\"say \\\"hi\\\"\\a \"");
+	}
+
+	#[test]
+	fn serializer_media_queries() {
+		let serialize = |text: &str| serialize_media_query_list(&parse_media_query_list(text));
+
+		assert_eq!(serialize(""), "");
+		assert_eq!(serialize("SCREEN and (min-width: 40em), print"), "screen and (width >= 640px), print");
+		assert_eq!(serialize("(400px < width <= 800px) or (orientation: portrait)"),
+				   "(400px < width <= 800px) or (orientation: portrait)");
+		assert_eq!(serialize("(1000px > width > 100px)"), "(1000px > width > 100px)");
+		assert_eq!(serialize("not ((width) and (aspect-ratio: 16/9))"), "not ((width) and (aspect-ratio: 16/9))");
+		assert_eq!(serialize("only screen and (resolution: 192dpi), tv and"), "screen and (resolution: 2dppx), not all");
+		assert_eq!(serialize("(hover: hover)"), "(hover: hover)");
+		assert_eq!(serialize("screen and (grid: 'x' 2em 50%), (width) or foo(bar)"),
+				   "screen and (grid: \"x\" 2em 50%), (width) or foo(bar)");
+	}
+
+	#[test]
+	fn serializer_rules() {
+		assert_eq!(round_trip("A , b>c:NTH-CHILD(odd of .x) ~ d::before{color:red;margin:0 1px}"),
+				   "A, b > c:nth-child(2n+1 of .x) ~ d::before { color: rgb(255, 0, 0); margin: 0px 1px; }");
+		assert_eq!(round_trip("*.a, [lang|=en i], :is(p, :not(#x)):has(> img, + p) {}"),
+				   ".a, [lang|=\"en\" i], :is(p, :not(#x)):has(> img, + p) { }");
+		assert_eq!(round_trip("@import 'base.css' screen; @namespace svg url(http://www.w3.org/2000/svg);\
+							   svg|rect, |x, *|y {}"),
+				   "@import url(\"base.css\") screen;\n\
+					@namespace svg url(\"http://www.w3.org/2000/svg\");\n\
+					svg|rect, |x, y { }");
+		assert_eq!(round_trip("@layer base, theme; @layer base { @media (width > 1px) { @supports (display: grid) { p { } } } }"),
+				   "@layer base, theme;\n\
+					@layer base {\n  @media (width > 1px) {\n    @supports (display: grid) {\n      p { }\n    }\n  }\n}");
+		assert_eq!(round_trip("@keyframes spin { from { left: 0 } 50%, TO { left: 10px } }"),
+				   "@keyframes spin {\n  0% { left: 0px; }\n  50%, 100% { left: 10px; }\n}");
+		assert_eq!(round_trip("@font-face { font-family: x; src: url(x.woff) } @page :first { margin: 1in }"),
+				   "@font-face { font-family: x; src: url(x.woff); }\n@page :first { margin: 1in; }");
+		assert_eq!(round_trip("@property --size { syntax: '<length>'; inherits: false; initial-value: 0px }"),
+				   "@property --size { syntax: \"<length>\"; inherits: false; initial-value: 0px; }");
+		assert_eq!(round_trip("@property --a\\.b { syntax: '*'; inherits: true }"),
+				   "@property --a\\.b { syntax: \"*\"; inherits: true; }");
+
+		let mut namespaces = HashMap::new();
+		namespaces.insert(String::new(), ::dom_tree::Namespace::Html);
+		let sheet = CssParser::new("@namespace url(http://www.w3.org/1999/xhtml); p, *|q, * {}".to_string()).parse_css();
+		assert_eq!(sheet.namespaces(), namespaces);
+		assert_eq!(serialize_stylesheet(&sheet),
+				   Some("@namespace url(\"http://www.w3.org/1999/xhtml\");\np, *|q, * { }".to_string()));
+	}
+
+	#[test]
+	fn serializer_namespace_without_prefix() {
+		let mut namespaces = HashMap::new();
+		namespaces.insert("svg".to_string(), ::dom_tree::Namespace::Svg);
+		let serialize = |text: &str, namespaces: &HashMap<String, ::dom_tree::Namespace>| {
+			let mut declared = HashMap::new();
+			declared.insert("svg".to_string(), ::dom_tree::Namespace::Svg);
+			serialize_selector_list(&parse_selector_list(text, &declared).unwrap()[..], namespaces)
+		};
+
+		assert_eq!(serialize("svg|rect, p", &namespaces), Some("svg|rect, p".to_string()));
+		// leaving the prefix out would match rect in any namespace
+		assert_eq!(serialize("svg|rect, p", &HashMap::new()), None);
+		assert_eq!(serialize("p:is(svg|*)", &HashMap::new()), None);
+		assert_eq!(serialize("p:has(> svg|a)", &HashMap::new()), None);
+		assert_eq!(serialize(":nth-child(2n of svg|a)", &HashMap::new()), None);
+		assert_eq!(serialize(":nth-child(2n of svg|a)", &namespaces), Some(":nth-child(2n of svg|a)".to_string()));
+
+		namespaces.clear();
+		namespaces.insert(String::new(), ::dom_tree::Namespace::Svg);
+		assert_eq!(serialize("svg|rect", &namespaces), Some("rect".to_string()));
+
+		let sheet = CssParser::new("@namespace svg url(http://www.w3.org/2000/svg); svg|rect {}".to_string()).parse_css();
+		let rules = sheet.rules()[1..].to_vec();
+		assert_eq!(serialize_stylesheet(&StyleSheet::from_rules(rules)), None);
+	}
+}
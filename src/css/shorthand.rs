@@ -0,0 +1,547 @@
+use super::tokenizer::{tokenize, Token};
+use super::stylesheet::{Property, Value, Unit, Declaration, string_to_property_value};
+use super::color::Color;
+use super::cascade::CssWideKeyword;
+
+/// Properties that set several longhands at once. A shorthand that does
+/// not parse sets all of its longhands to Value::Missing
+/// www.w3.org/TR/css-cascade-4/#shorthand
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Shorthand {
+	Margin,
+	Padding,
+	Inset,
+	Border,
+	BorderWidth,
+	BorderStyle,
+	BorderColor,
+	BorderTop,
+	BorderRight,
+	BorderBottom,
+	BorderLeft,
+	Font,
+	Background,
+	Flex,
+	Gap,
+}
+
+const MARGIN: [Property; 4] = [Property::MarginTop, Property::MarginRight,
+							   Property::MarginBottom, Property::MarginLeft];
+const PADDING: [Property; 4] = [Property::PaddingTop, Property::PaddingRight,
+								Property::PaddingBottom, Property::PaddingLeft];
+const INSET: [Property; 4] = [Property::Top, Property::Right, Property::Bottom, Property::Left];
+const BORDER_WIDTH: [Property; 4] = [Property::BorderTopWidth, Property::BorderRightWidth,
+									 Property::BorderBottomWidth, Property::BorderLeftWidth];
+const BORDER_STYLE: [Property; 4] = [Property::BorderTopStyle, Property::BorderRightStyle,
+									 Property::BorderBottomStyle, Property::BorderLeftStyle];
+const BORDER_COLOR: [Property; 4] = [Property::BorderTopColor, Property::BorderRightColor,
+									 Property::BorderBottomColor, Property::BorderLeftColor];
+
+impl Shorthand {
+	pub fn from_name(name: &str) -> Option<Shorthand> {
+		match name {
+			"margin" => Some(Shorthand::Margin),
+			"padding" => Some(Shorthand::Padding),
+			"inset" => Some(Shorthand::Inset),
+			"border" => Some(Shorthand::Border),
+			"border-width" => Some(Shorthand::BorderWidth),
+			"border-style" => Some(Shorthand::BorderStyle),
+			"border-color" => Some(Shorthand::BorderColor),
+			"border-top" => Some(Shorthand::BorderTop),
+			"border-right" => Some(Shorthand::BorderRight),
+			"border-bottom" => Some(Shorthand::BorderBottom),
+			"border-left" => Some(Shorthand::BorderLeft),
+			"font" => Some(Shorthand::Font),
+			"background" => Some(Shorthand::Background),
+			"flex" => Some(Shorthand::Flex),
+			"gap" => Some(Shorthand::Gap),
+			_ => None,
+		}
+	}
+
+	/// The longhands this shorthand sets, in the order it sets them
+	pub fn longhands(&self) -> Vec<Property> {
+		match *self {
+			Shorthand::Margin => MARGIN.to_vec(),
+			Shorthand::Padding => PADDING.to_vec(),
+			Shorthand::Inset => INSET.to_vec(),
+			Shorthand::BorderWidth => BORDER_WIDTH.to_vec(),
+			Shorthand::BorderStyle => BORDER_STYLE.to_vec(),
+			Shorthand::BorderColor => BORDER_COLOR.to_vec(),
+			Shorthand::BorderTop => border_side(0),
+			Shorthand::BorderRight => border_side(1),
+			Shorthand::BorderBottom => border_side(2),
+			Shorthand::BorderLeft => border_side(3),
+			Shorthand::Border => (0..4).flat_map(border_side).collect(),
+			Shorthand::Font => vec![Property::FontStyle, Property::FontVariant, Property::FontWeight,
+									Property::FontStretch, Property::FontSize, Property::LineHeight,
+									Property::FontFamily],
+			Shorthand::Background => vec![Property::BackgroundColor, Property::BackgroundImage,
+										  Property::BackgroundRepeat, Property::BackgroundAttachment,
+										  Property::BackgroundPosition, Property::BackgroundSize,
+										  Property::BackgroundOrigin, Property::BackgroundClip],
+			Shorthand::Flex => vec![Property::FlexGrow, Property::FlexShrink, Property::FlexBasis],
+			Shorthand::Gap => vec![Property::RowGap, Property::ColumnGap],
+		}
+	}
+
+	/// The values of the longhands, in the order of longhands(). None if
+	/// 'value' does not parse. A CSS-wide keyword on its own sets every
+	/// longhand to itself, but cannot be one of several components
+	pub fn expand(&self, value: &str) -> Option<Vec<Value>> {
+		match CssWideKeyword::from_name(value.trim()) {
+			Some(keyword) => return Some(vec![Value::CssWide(keyword); self.longhands().len()]),
+			None => {}
+		}
+
+		let items = split_components(value);
+		if items.iter().any(|i| CssWideKeyword::from_name(i).is_some()) {
+			return None;
+		}
+
+		match *self {
+			Shorthand::Margin | Shorthand::Padding | Shorthand::Inset |
+			Shorthand::BorderWidth | Shorthand::BorderStyle | Shorthand::BorderColor => {
+				expand_sides(self.longhands()[0], &items[..])
+			}
+			Shorthand::BorderTop | Shorthand::BorderRight |
+			Shorthand::BorderBottom | Shorthand::BorderLeft => expand_border(&items[..]),
+			Shorthand::Border => expand_border(&items[..]).map(|side| {
+				side.iter().cycle().take(12).cloned().collect()
+			}),
+			Shorthand::Font => expand_font(&items[..]),
+			Shorthand::Background => expand_background(&items[..]),
+			Shorthand::Flex => expand_flex(&items[..]),
+			Shorthand::Gap => {
+				match items.len() {
+					1 | 2 => {
+						let row = string_to_property_value(Property::RowGap, &items[0][..]);
+						let column = string_to_property_value(Property::ColumnGap, &items[items.len() - 1][..]);
+						all_valid(vec![row, column])
+					}
+					_ => None,
+				}
+			}
+		}
+	}
+
+	/// The declarations this shorthand expands to
+	pub fn declarations(&self, value: &str) -> Vec<Declaration> {
+		let longhands = self.longhands();
+		let values = self.expand(value).unwrap_or(vec![Value::Missing; longhands.len()]);
+
+		longhands.into_iter().zip(values.into_iter()).map(|(p, v)| {
+			Declaration {
+				property_name: p,
+				property_value: v,
+				important: false,
+			}
+		}).collect()
+	}
+}
+
+/// The width, style and color of side 'n', counting clockwise from the top
+fn border_side(n: usize) -> Vec<Property> {
+	vec![BORDER_WIDTH[n], BORDER_STYLE[n], BORDER_COLOR[n]]
+}
+
+fn all_valid(values: Vec<Value>) -> Option<Vec<Value>> {
+	if values.iter().any(|v| *v == Value::Missing) {
+		None
+	} else {
+		Some(values)
+	}
+}
+
+/// Split css text into its top level component values, as source text.
+/// White space separates them; ',' and '/' are components of their own
+pub fn split_components(text: &str) -> Vec<String> {
+	let (chars, tokens) = tokenize(text);
+	let mut components = Vec::new();
+	let mut current: Option<(usize, usize)> = None;
+	let mut depth = 0;
+
+	for token in tokens.iter() {
+		let separator = depth == 0 && match token.token {
+			Token::Whitespace | Token::Comma | Token::Delim('/') => true,
+			_ => false,
+		};
+
+		if separator {
+			if let Some((start, end)) = current.take() {
+				components.push(chars[start..end].iter().cloned().collect());
+			}
+			if token.token != Token::Whitespace {
+				components.push(chars[token.start..token.end].iter().cloned().collect());
+			}
+			continue;
+		}
+
+		match token.token {
+			Token::Function(_) | Token::OpenParen | Token::OpenSquare | Token::OpenCurly => depth += 1,
+			Token::CloseParen | Token::CloseSquare | Token::CloseCurly if depth > 0 => depth -= 1,
+			_ => {}
+		}
+
+		current = Some(match current {
+			Some((start, _)) => (start, token.end),
+			None => (token.start, token.end),
+		});
+	}
+
+	if let Some((start, end)) = current {
+		components.push(chars[start..end].iter().cloned().collect());
+	}
+
+	components
+}
+
+/// One to four values for the top, right, bottom and left. Missing ones
+/// are copied from the opposite side, or the top for the left
+fn expand_sides(property: Property, items: &[String]) -> Option<Vec<Value>> {
+	let values: Vec<Value> = items.iter().map(|i| string_to_property_value(property, &i[..])).collect();
+	let sides = match values.len() {
+		1 => [0, 0, 0, 0],
+		2 => [0, 1, 0, 1],
+		3 => [0, 1, 2, 1],
+		4 => [0, 1, 2, 3],
+		_ => return None,
+	};
+
+	all_valid(sides.iter().map(|&i| values[i].clone()).collect())
+}
+
+/// A width, a style and a color in any order, each optional. Left out
+/// ones are reset to 'medium', 'none' and 'currentColor'
+/// www.w3.org/TR/css-backgrounds-3/#border-shorthands
+fn expand_border(items: &[String]) -> Option<Vec<Value>> {
+	let longhands = border_side(0);
+	let mut values = vec![None, None, None];
+
+	if items.is_empty() || items.len() > 3 {
+		return None;
+	}
+
+	for item in items.iter() {
+		let found = longhands.iter().enumerate().filter(|&(i, _)| values[i].is_none()).filter_map(|(i, &p)| {
+			match string_to_property_value(p, &item[..]) {
+				Value::Missing => None,
+				v => Some((i, v)),
+			}
+		}).next();
+
+		match found {
+			Some((i, v)) => values[i] = Some(v),
+			None => return None,
+		}
+	}
+
+	let initial = [Value::Size(3.0, Unit::Px), Value::Keyword("none".to_string()), Value::CurrentColor];
+	Some(values.into_iter().zip(initial.iter()).map(|(v, i)| v.unwrap_or(i.clone())).collect())
+}
+
+/// '[style || variant || weight || stretch]? size [/ line-height]? family'.
+/// Everything left out is reset to 'normal'
+/// www.w3.org/TR/css-fonts-4/#font-prop
+fn expand_font(items: &[String]) -> Option<Vec<Value>> {
+	let before_size = [Property::FontStyle, Property::FontVariant, Property::FontWeight, Property::FontStretch];
+	let mut values = vec![None, None, None, None];
+	let mut i = 0;
+
+	// 'normal' sets nothing, the properties it would apply to are reset anyway
+	while i < items.len() && i < 4 {
+		if items[i].eq_ignore_ascii_case("normal") {
+			i += 1;
+			continue;
+		}
+
+		let found = before_size.iter().enumerate().filter(|&(n, _)| values[n].is_none()).filter_map(|(n, &p)| {
+			match string_to_property_value(p, &items[i][..]) {
+				Value::Missing => None,
+				// Only the keywords of font-stretch are allowed here
+				Value::Percentage(_) => None,
+				v => Some((n, v)),
+			}
+		}).next();
+
+		match found {
+			Some((n, v)) => values[n] = Some(v),
+			None => break,
+		}
+		i += 1;
+	}
+
+	let size = match items.get(i) {
+		Some(item) => string_to_property_value(Property::FontSize, &item[..]),
+		None => return None,
+	};
+	i += 1;
+
+	let line_height = if items.get(i).map_or(false, |item| item == "/") {
+		match items.get(i + 1) {
+			Some(item) => {
+				i += 2;
+				string_to_property_value(Property::LineHeight, &item[..])
+			}
+			None => return None,
+		}
+	} else {
+		Value::Keyword("normal".to_string())
+	};
+
+	if i >= items.len() {
+		return None;
+	}
+	let family = string_to_property_value(Property::FontFamily, &items[i..].join(" ")[..]);
+
+	let normal = Value::Keyword("normal".to_string());
+	let mut result: Vec<Value> = values.into_iter().enumerate().map(|(n, v)| {
+		match v {
+			Some(v) => v,
+			None if n == 2 => Value::Number(400.0),
+			None => normal.clone(),
+		}
+	}).collect();
+	result.push(size);
+	result.push(line_height);
+	result.push(family);
+
+	all_valid(result)
+}
+
+/// A color, an image, a repeat style, an attachment, a position with an
+/// optional '/ size' and one or two boxes, in any order. Only a single
+/// background layer is supported
+/// www.w3.org/TR/css-backgrounds-3/#background
+fn expand_background(items: &[String]) -> Option<Vec<Value>> {
+	let is = |item: &str, property: Property| string_to_property_value(property, item) != Value::Missing;
+	let mut color = None;
+	let mut image = None;
+	let mut repeat: Option<String> = None;
+	let mut attachment = None;
+	let mut position: Option<String> = None;
+	let mut size: Option<String> = None;
+	let mut boxes = Vec::new();
+	let mut i = 0;
+
+	// Take the longest run of items from 'i' that make a value of
+	// 'property', unless 'found' already holds one
+	let run = |found: &Option<String>, i: usize, most: usize, property: Property| {
+		if found.is_some() {
+			return None;
+		}
+		(1..most + 1).rev().filter(|&n| i + n <= items.len())
+			.map(|n| (n, items[i..i + n].join(" ")))
+			.find(|&(_, ref text)| is(&text[..], property))
+	};
+
+	while i < items.len() {
+		let item = &items[i][..];
+
+		if color.is_none() && is(item, Property::BackgroundColor) {
+			color = Some(string_to_property_value(Property::BackgroundColor, item));
+		} else if image.is_none() && is(item, Property::BackgroundImage) {
+			image = Some(string_to_property_value(Property::BackgroundImage, item));
+		} else if attachment.is_none() && is(item, Property::BackgroundAttachment) {
+			attachment = Some(string_to_property_value(Property::BackgroundAttachment, item));
+		} else if boxes.len() < 2 && is(item, Property::BackgroundOrigin) {
+			boxes.push(string_to_property_value(Property::BackgroundOrigin, item));
+		} else if let Some((n, text)) = run(&repeat, i, 2, Property::BackgroundRepeat) {
+			repeat = Some(text);
+			i += n;
+			continue;
+		} else if let Some((n, text)) = run(&position, i, 4, Property::BackgroundPosition) {
+			position = Some(text);
+			i += n;
+
+			if items.get(i).map_or(false, |item| item == "/") {
+				match run(&size, i + 1, 2, Property::BackgroundSize) {
+					Some((n, text)) => {
+						size = Some(text);
+						i += n + 1;
+					}
+					None => return None,
+				}
+			}
+			continue;
+		} else {
+			return None;
+		}
+		i += 1;
+	}
+
+	let value = |text: Option<String>, property: Property, initial: &str| {
+		string_to_property_value(property, &text.unwrap_or(initial.to_string())[..])
+	};
+	let origin = boxes.get(0).cloned().unwrap_or(Value::Keyword("padding-box".to_string()));
+	let clip = boxes.get(1).cloned().or(boxes.get(0).cloned()).unwrap_or(Value::Keyword("border-box".to_string()));
+
+	all_valid(vec![color.unwrap_or(Value::ColorValue(Color::transparent())),
+				   image.unwrap_or(Value::Keyword("none".to_string())),
+				   value(repeat, Property::BackgroundRepeat, "repeat"),
+				   attachment.unwrap_or(Value::Keyword("scroll".to_string())),
+				   value(position, Property::BackgroundPosition, "0% 0%"),
+				   value(size, Property::BackgroundSize, "auto"),
+				   origin,
+				   clip])
+}
+
+/// 'none', 'auto', or '<grow> [<shrink>]? || <basis>'. A grow factor
+/// without a basis makes the basis 0%
+/// www.w3.org/TR/css-flexbox-1/#flex-property
+fn expand_flex(items: &[String]) -> Option<Vec<Value>> {
+	let keyword = |k: &str| Value::Keyword(k.to_string());
+
+	if items.len() == 1 {
+		match &items[0].to_ascii_lowercase()[..] {
+			"none" => return Some(vec![Value::Number(0.0), Value::Number(0.0), keyword("auto")]),
+			"auto" => return Some(vec![Value::Number(1.0), Value::Number(1.0), keyword("auto")]),
+			_ => {}
+		}
+	}
+
+	let factor = |item: &String| match string_to_property_value(Property::FlexGrow, &item[..]) {
+		Value::Missing => None,
+		v => Some(v),
+	};
+
+	let mut grow = None;
+	let mut shrink = None;
+	let mut basis = None;
+	let mut i = 0;
+
+	while i < items.len() {
+		match factor(&items[i]) {
+			Some(g) if grow.is_none() => {
+				grow = Some(g);
+				if let Some(s) = items.get(i + 1).and_then(|item| factor(item)) {
+					shrink = Some(s);
+					i += 1;
+				}
+			}
+			_ if basis.is_none() => {
+				match string_to_property_value(Property::FlexBasis, &items[i][..]) {
+					Value::Missing => return None,
+					b => basis = Some(b),
+				}
+			}
+			_ => return None,
+		}
+		i += 1;
+	}
+
+	if items.is_empty() {
+		return None;
+	}
+
+	Some(vec![grow.unwrap_or(Value::Number(1.0)),
+			  shrink.unwrap_or(Value::Number(1.0)),
+			  basis.unwrap_or(Value::Percentage(0.0))])
+}
+
+#[cfg(test)]
+mod test_shorthand {
+	use super::{Shorthand, split_components};
+	use super::super::stylesheet::{Value, Unit};
+	use super::super::color::Color;
+	use super::super::cascade::CssWideKeyword;
+
+	fn expand(name: &str, value: &str) -> Option<Vec<Value>> {
+		Shorthand::from_name(name).unwrap().expand(value)
+	}
+
+	fn px(n: f32) -> Value {
+		Value::Size(n, Unit::Px)
+	}
+
+	fn keyword(k: &str) -> Value {
+		Value::Keyword(k.to_string())
+	}
+
+	#[test]
+	fn split_into_components() {
+		assert_eq!(split_components(" 1px  calc(1px + 2px)/a, \"b c\" "),
+				   vec!["1px", "calc(1px + 2px)", "/", "a", ",", "\"b c\""]);
+	}
+
+	#[test]
+	fn box_sides() {
+		assert_eq!(expand("margin", "1px"), Some(vec![px(1.0), px(1.0), px(1.0), px(1.0)]));
+		assert_eq!(expand("margin", "1px 2px"), Some(vec![px(1.0), px(2.0), px(1.0), px(2.0)]));
+		assert_eq!(expand("margin", "0 auto"), Some(vec![px(0.0), keyword("auto"), px(0.0), keyword("auto")]));
+		assert_eq!(expand("padding", "1px 2px 3px"), Some(vec![px(1.0), px(2.0), px(3.0), px(2.0)]));
+		assert_eq!(expand("inset", "1px auto 3px 0"), Some(vec![px(1.0), keyword("auto"), px(3.0), px(0.0)]));
+		assert_eq!(expand("border-style", "solid none"),
+				   Some(vec![keyword("solid"), keyword("none"), keyword("solid"), keyword("none")]));
+		assert_eq!(expand("padding", "-1px"), None);
+		assert_eq!(expand("margin", "1px 2px 3px 4px 5px"), None);
+		assert_eq!(expand("margin", ""), None);
+	}
+
+	#[test]
+	fn borders() {
+		let red = Value::ColorValue(Color::rgb(255, 0, 0));
+
+		assert_eq!(expand("border-top", "red 2px"), Some(vec![px(2.0), keyword("none"), red.clone()]));
+		assert_eq!(expand("border-left", "dotted"), Some(vec![px(3.0), keyword("dotted"), Value::CurrentColor]));
+		assert_eq!(expand("border", "thin solid red").map(|v| v.len()), Some(12));
+		assert_eq!(expand("border", "thin solid red").unwrap()[11], red);
+		assert_eq!(expand("border", "1px 2px"), None);
+		assert_eq!(expand("border", "solid solid"), None);
+	}
+
+	#[test]
+	fn fonts() {
+		assert_eq!(expand("font", "italic bold 12px/1.5 \"Helvetica Neue\", serif"),
+				   Some(vec![keyword("italic"), keyword("normal"), Value::Number(700.0), keyword("normal"),
+							 px(12.0), Value::Number(1.5),
+							 Value::FontFamily(vec!["Helvetica Neue".to_string(), "serif".to_string()])]));
+		assert_eq!(expand("font", "normal small-caps 120% Times New Roman").unwrap()[1], keyword("small-caps"));
+		assert_eq!(expand("font", "1em serif").unwrap()[5], keyword("normal"));
+		assert_eq!(expand("font", "bold serif"), None);
+		assert_eq!(expand("font", "12px"), None);
+		assert_eq!(expand("font", "12px /"), None);
+	}
+
+	#[test]
+	fn backgrounds() {
+		let values = expand("background", "url(a.png) no-repeat fixed right 5px / cover #fff content-box").unwrap();
+		assert_eq!(values, vec![Value::ColorValue(Color::rgb(255, 255, 255)),
+								Value::Url("a.png".to_string()),
+								Value::List(vec![keyword("no-repeat"), keyword("no-repeat")]),
+								keyword("fixed"),
+								Value::List(vec![keyword("right"), px(5.0)]),
+								keyword("cover"),
+								keyword("content-box"),
+								keyword("content-box")]);
+
+		let values = expand("background", "red").unwrap();
+		assert_eq!(values[1], keyword("none"));
+		assert_eq!(values[4], Value::List(vec![Value::Percentage(0.0), Value::Percentage(0.0)]));
+		assert_eq!(values[7], keyword("border-box"));
+
+		assert_eq!(expand("background", "red blue"), None);
+		assert_eq!(expand("background", "red, blue"), None);
+	}
+
+	#[test]
+	fn flex_and_gap() {
+		assert_eq!(expand("flex", "none"), Some(vec![Value::Number(0.0), Value::Number(0.0), keyword("auto")]));
+		assert_eq!(expand("flex", "2"), Some(vec![Value::Number(2.0), Value::Number(1.0), Value::Percentage(0.0)]));
+		assert_eq!(expand("flex", "2 3 10px"), Some(vec![Value::Number(2.0), Value::Number(3.0), px(10.0)]));
+		assert_eq!(expand("flex", "10px 2"), Some(vec![Value::Number(2.0), Value::Number(1.0), px(10.0)]));
+		assert_eq!(expand("flex", "1 2 3"), None);
+
+		assert_eq!(expand("gap", "1px"), Some(vec![px(1.0), px(1.0)]));
+		assert_eq!(expand("gap", "normal 10%"), Some(vec![keyword("normal"), Value::Percentage(10.0)]));
+		assert_eq!(expand("gap", "1px 2px 3px"), None);
+	}
+
+	#[test]
+	fn css_wide_keywords() {
+		let inherit = Value::CssWide(CssWideKeyword::Inherit);
+		assert_eq!(expand("border-color", "INHERIT"), Some(vec![inherit.clone(); 4]));
+		assert_eq!(expand("font", " inherit"), Some(vec![inherit; 7]));
+		assert_eq!(expand("margin", "1px unset"), None);
+	}
+}
@@ -2,48 +2,990 @@ use text_parser;
 use std::collections::HashMap;
 
 use dom_tree;
+use dom_tree::Namespace;
+pub use super::selector::Selector;
+pub use super::color::Color;
+use super::selector;
+use super::selector::{MatchContext, PseudoElement, SimpleSelector, Specificity};
+use super::media::{MediaQueryList, MediaEnvironment, Viewport};
+use super::color;
+use super::calc;
+use super::calc::{CalcNode, CalcType};
+use super::parser;
+use super::parser::{CssParser, ComponentValue};
+use super::shorthand;
+use super::custom;
+use super::custom::PropertyRegistry;
+use super::serializer;
+use super::cascade;
+use super::cascade::{CascadeEntry, CascadeLayers, CssWideKeyword, Origin};
+use super::tokenizer::Token;
 
-/// A stylesheet contains the rules to apply to the DOM
+/// A stylesheet contains the rules to apply to the DOM, in source order.
+/// Style rules, including those nested in at-rules and imported
+/// stylesheets, are also filed in an index so only the ones that could
+/// match an element are tested against it. 'origin' says where the
+/// stylesheet comes from; stylesheets are author stylesheets unless told
+/// otherwise.
+/// Serialized as '{"rules": [CssRule, ...]}'; the index and the cascade
+/// layers are rebuilt when deserializing
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "RuleList"))]
 pub struct StyleSheet {
-	pub ruleset : Rule,
+	rules: Vec<CssRule>,
+	#[cfg_attr(feature = "serde", serde(skip))]
+	index: RuleIndex,
+	/// Where each style rule is in the tree of rules, in source order. The
+	/// index refers to style rules by their position in this list
+	#[cfg_attr(feature = "serde", serde(skip))]
+	style_paths: Vec<Vec<usize>>,
+	#[cfg_attr(feature = "serde", serde(skip))]
+	layers: CascadeLayers,
+	#[cfg_attr(feature = "serde", serde(skip))]
+	origin: Origin,
 }
 
-/// A Rule is a dict key'd by a selector, and storing a 
-/// list of declarations
-pub struct Rule {
-	pub rule_map: HashMap<Selector, Vec<Declaration>>,
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct RuleList {
+	rules: Vec<CssRule>,
 }
 
-impl Rule {
-	pub fn new() -> Rule {
-		let rule_map = HashMap::new();
+#[cfg(feature = "serde")]
+impl From<RuleList> for StyleSheet {
+	fn from(list: RuleList) -> StyleSheet {
+		StyleSheet::from_rules(list.rules)
+	}
+}
+
+/// A rule of a stylesheet. Serialized as the rule it holds; their fields
+/// tell the kinds of rule apart
+/// www.w3.org/TR/cssom-1/#css-rules
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(untagged))]
+pub enum CssRule {
+	Style(StyleRule),
+	Media(MediaRule),
+	Supports(SupportsRule),
+	Import(ImportRule),
+	FontFace(FontFaceRule),
+	Keyframes(KeyframesRule),
+	Page(PageRule),
+	Namespace(NamespaceRule),
+	Layer(LayerRule),
+	Property(PropertyRule),
+}
+
+/// A style rule: a selector list and the declarations that apply to the
+/// elements it selects. 'source_index' is the rule's position among all
+/// the style rules of its stylesheet in source order, so the later of two
+/// equally specific rules wins.
+/// Serialized as '{"selectors": [Selector, ...], "declarations":
+/// [Declaration, ...], "source_index": usize}'
+/// www.w3.org/TR/css-syntax-3/#style-rule
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct StyleRule {
+	pub selectors: Vec<Selector>,
+	pub declarations: Vec<Declaration>,
+	pub source_index: usize,
+}
+
+/// '@media <media query list> { <rules> }'. The rules apply only where
+/// the media query list matches
+/// www.w3.org/TR/css-conditional-3/#at-media
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MediaRule {
+	pub media: MediaQueryList,
+	pub rules: Vec<CssRule>,
+}
+
+/// '@supports <condition> { <rules> }'. 'supported' is the condition as
+/// evaluated against the properties and selectors this engine knows
+/// www.w3.org/TR/css-conditional-3/#at-supports
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SupportsRule {
+	pub condition: String,
+	pub supported: bool,
+	pub rules: Vec<CssRule>,
+}
+
+/// '@import <url> [<media query list>];'. 'stylesheet' is None if it could
+/// not be loaded
+/// www.w3.org/TR/css-cascade-4/#at-import
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ImportRule {
+	pub url: String,
+	pub media: MediaQueryList,
+	pub stylesheet: Option<StyleSheet>,
+}
+
+/// '@font-face { <descriptors> }'. Descriptors are not properties, so they
+/// are kept as name and value text
+/// www.w3.org/TR/css-fonts-4/#font-face-rule
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FontFaceRule {
+	pub descriptors: Vec<(String, String)>,
+}
+
+/// '@keyframes <name> { <keyframes> }'
+/// www.w3.org/TR/css-animations-1/#keyframes
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct KeyframesRule {
+	pub name: String,
+	pub keyframes: Vec<Keyframe>,
+}
+
+/// A block of a @keyframes rule and the percentages of the animation it
+/// applies at; 'from' is 0 and 'to' is 100
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Keyframe {
+	pub offsets: Vec<f32>,
+	pub declarations: Vec<Declaration>,
+}
+
+/// '@page [<selector>] { <declarations> }'
+/// www.w3.org/TR/css-page-3/#at-page-rule
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PageRule {
+	pub selector: String,
+	pub declarations: Vec<Declaration>,
+}
+
+/// '@namespace [<prefix>] <url>;'. The default namespace has an empty
+/// prefix
+/// www.w3.org/TR/css-namespaces-3/#declaration
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NamespaceRule {
+	pub prefix: String,
+	pub namespace: Namespace,
+}
+
+/// '@layer <name>, ...;' puts the layers named in layer order, before any
+/// not seen yet. '@layer [<name>] { <rules> }' puts the rules in a layer;
+/// without a name the layer is anonymous. 'rules' is None for the first
+/// form
+/// www.w3.org/TR/css-cascade-5/#layering
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LayerRule {
+	pub names: Vec<String>,
+	pub rules: Option<Vec<CssRule>>,
+}
+
+/// '@property <custom property name> { <descriptors> }', registering a
+/// custom property with a syntax its values must match, whether it is
+/// inherited and its initial value. The syntax is kept without its quotes
+/// www.w3.org/TR/css-properties-values-api-1/#at-property-rule
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PropertyRule {
+	pub name: String,
+	pub syntax: String,
+	pub inherits: bool,
+	pub initial_value: Option<String>,
+}
+
+impl CssRule {
+	/// The rules nested in a grouping rule or an imported stylesheet
+	pub fn child_rules(&self) -> Option<&[CssRule]> {
+		match *self {
+			CssRule::Media(ref m) => Some(&m.rules[..]),
+			CssRule::Supports(ref s) => Some(&s.rules[..]),
+			CssRule::Layer(LayerRule { rules: Some(ref r), .. }) => Some(&r[..]),
+			CssRule::Import(ImportRule { stylesheet: Some(ref s), .. }) => Some(&s.rules[..]),
+			_ => None,
+		}
+	}
+
+	fn child_rules_mut(&mut self) -> Option<&mut Vec<CssRule>> {
+		match *self {
+			CssRule::Media(ref mut m) => Some(&mut m.rules),
+			CssRule::Supports(ref mut s) => Some(&mut s.rules),
+			CssRule::Layer(LayerRule { rules: Some(ref mut r), .. }) => Some(r),
+			CssRule::Import(ImportRule { stylesheet: Some(ref mut s), .. }) => Some(&mut s.rules),
+			_ => None,
+		}
+	}
+
+	/// The declarations of a style or @page rule
+	pub fn declarations(&self) -> Option<&[Declaration]> {
+		match *self {
+			CssRule::Style(ref style) => Some(&style.declarations[..]),
+			CssRule::Page(ref page) => Some(&page.declarations[..]),
+			_ => None,
+		}
+	}
+
+	fn declarations_mut(&mut self) -> Option<&mut Vec<Declaration>> {
+		match *self {
+			CssRule::Style(ref mut style) => Some(&mut style.declarations),
+			CssRule::Page(ref mut page) => Some(&mut page.declarations),
+			_ => None,
+		}
+	}
+
+	/// Where the rule may go in the top level rule list: @import rules
+	/// first, then @namespace rules, then the others. @layer statements may
+	/// go anywhere
+	fn preamble_order(&self) -> Option<usize> {
+		match *self {
+			CssRule::Import(_) => Some(0),
+			CssRule::Namespace(_) => Some(1),
+			CssRule::Layer(LayerRule { rules: None, .. }) => None,
+			_ => Some(2),
+		}
+	}
+
+	/// Whether the rules nested in this one apply in 'media'
+	pub fn applies(&self, media: &MediaEnvironment) -> bool {
+		match *self {
+			CssRule::Media(ref m) => m.media.matches(media),
+			CssRule::Supports(ref s) => s.supported,
+			CssRule::Import(ref i) => i.media.matches(media),
+			_ => true,
+		}
+	}
+}
+
+/// A rule that matched an element, with the specificity of the most
+/// specific of its selectors that matched and the position of its cascade
+/// layer in layer order
+pub struct MatchedRule<'a> {
+	pub rule: &'a StyleRule,
+	pub specificity: Specificity,
+	pub layer: usize,
+}
+
+/// Buckets of (rule, selector) positions keyed by the id, class or tag
+/// name the selector's last compound requires. Selectors requiring none of
+/// them are 'universal' and tried against every element
+#[derive(Default, Clone)]
+struct RuleIndex {
+	by_id: HashMap<String, Vec<(usize, usize)>>,
+	by_class: HashMap<String, Vec<(usize, usize)>>,
+	by_tag: HashMap<String, Vec<(usize, usize)>>,
+	universal: Vec<(usize, usize)>,
+}
+
+impl RuleIndex {
+	fn add(&mut self, selector: &Selector, entry: (usize, usize)) {
+		let simple = match selector.compounds.last() {
+			Some(c) => &c.simple,
+			None => return,
+		};
+
+		// an id is the most selective key, then a class, then the tag
+		let id = simple.iter().filter_map(|s| match *s {
+			SimpleSelector::Id(ref id) => Some(id.clone()),
+			_ => None,
+		}).next();
+		let class = simple.iter().filter_map(|s| match *s {
+			SimpleSelector::Class(ref class) => Some(class.clone()),
+			_ => None,
+		}).next();
+		let tag = simple.iter().filter_map(|s| match *s {
+			SimpleSelector::Type(_, ref name) => Some(name.to_ascii_lowercase()),
+			_ => None,
+		}).next();
+
+		let bucket = match (id, class, tag) {
+			(Some(id), _, _) => self.by_id.entry(id).or_insert(Vec::new()),
+			(None, Some(class), _) => self.by_class.entry(class).or_insert(Vec::new()),
+			(None, None, Some(tag)) => self.by_tag.entry(tag).or_insert(Vec::new()),
+			(None, None, None) => &mut self.universal,
+		};
+
+		bucket.push(entry);
+	}
+
+	/// Every (rule, selector) position that could match 'element'
+	fn candidates(&self, element: &dom_tree::Element) -> Vec<(usize, usize)> {
+		let mut result = self.universal.clone();
+
+		match element.get_attribute("id").and_then(|id| self.by_id.get(id)) {
+			Some(bucket) => result.extend(bucket.iter().cloned()),
+			None => {}
+		}
+
+		match self.by_tag.get(&element.tag_name.to_ascii_lowercase()) {
+			Some(bucket) => result.extend(bucket.iter().cloned()),
+			None => {}
+		}
+
+		let mut classes: Vec<&str> = match element.get_attribute("class") {
+			Some(c) => c.split(|c: char| c.is_whitespace()).filter(|c| !c.is_empty()).collect(),
+			None => Vec::new(),
+		};
+		classes.sort();
+		classes.dedup();
+
+		for class in classes.iter() {
+			match self.by_class.get(*class) {
+				Some(bucket) => result.extend(bucket.iter().cloned()),
+				None => {}
+			}
+		}
+
+		result
+	}
+}
+
+impl StyleSheet {
+	pub fn new() -> StyleSheet {
+		StyleSheet {
+			rules: Vec::new(),
+			index: RuleIndex::default(),
+			style_paths: Vec::new(),
+			layers: CascadeLayers::new(),
+			origin: Origin::Author,
+		}
+	}
+
+	/// Where the stylesheet comes from
+	pub fn origin(&self) -> Origin {
+		self.origin
+	}
+
+	pub fn set_origin(&mut self, origin: Origin) {
+		self.origin = origin;
+	}
+
+	/// A stylesheet of 'rules', in source order
+	pub fn from_rules(rules: Vec<CssRule>) -> StyleSheet {
+		let mut sheet = StyleSheet::new();
+		for rule in rules.into_iter() {
+			sheet.push(rule);
+		}
+		sheet
+	}
+
+	/// The top level rules in source order
+	pub fn rules(&self) -> &[CssRule] {
+		&self.rules[..]
+	}
+
+	/// Every style rule, including those nested in at-rules and imported
+	/// stylesheets, in source order
+	pub fn style_rules(&self) -> Vec<&StyleRule> {
+		(0..self.style_paths.len()).map(|n| self.style_rule(n)).collect()
+	}
+
+	/// Return true if a selector of any style rule uses '+' or '~', so
+	/// changing an element can restyle its later siblings
+	pub fn uses_sibling_combinators(&self) -> bool {
+		self.style_rules().iter().any(|r| r.selectors.iter().any(|s| s.uses_sibling_combinators()))
+	}
+
+	/// How far above a changed element a :has() in a style rule can be
+	/// tested, as for PseudoClass::has_reach
+	pub fn has_reach(&self) -> Option<usize> {
+		self.style_rules().iter().flat_map(|r| r.selectors.iter())
+			.fold(Some(0), |reach, s| selector::max_reach(reach, s.has_reach()))
+	}
+
+	/// Every @property rule, including those nested in at-rules and
+	/// imported stylesheets, in source order
+	pub fn property_rules(&self) -> Vec<&PropertyRule> {
+		let mut found = Vec::new();
+		collect_property_rules(&self.rules[..], &mut found);
+		found
+	}
+
+	/// Append a style rule that comes after every rule added so far
+	pub fn add_rule(&mut self, selectors: Vec<Selector>, declarations: Vec<Declaration>) {
+		self.push(CssRule::Style(StyleRule {
+			selectors: selectors,
+			declarations: declarations,
+			source_index: 0,
+		}));
+	}
+
+	/// Append a rule that comes after every rule added so far
+	pub fn push(&mut self, rule: CssRule) {
+		let position = self.rules.len();
+		self.rules.push(rule);
+
+		number_rules(&mut self.rules[position..], position, &mut Vec::new(), 0,
+					 &mut self.layers, &mut self.index, &mut self.style_paths);
+	}
+
+	/// The prefixes declared by the stylesheet's @namespace rules, the
+	/// default namespace under "", as selector::parse_selector takes them
+	pub fn namespaces(&self) -> HashMap<String, Namespace> {
+		self.rules.iter().filter_map(|r| match *r {
+			CssRule::Namespace(ref n) => Some((n.prefix.clone(), n.namespace)),
+			_ => None,
+		}).collect()
+	}
+
+	/// The rule at 'path': the position of a top level rule, then of a
+	/// rule nested in it, and so on
+	pub fn rule_at(&self, path: &[usize]) -> Option<&CssRule> {
+		if path.is_empty() {
+			return None;
+		}
+
+		let mut rules = &self.rules[..];
+		for &i in path[..path.len() - 1].iter() {
+			rules = match rules.get(i).and_then(|r| r.child_rules()) {
+				Some(r) => r,
+				None => return None,
+			};
+		}
+
+		rules.get(path[path.len() - 1])
+	}
+
+	/// The rule list at 'parent', the top level list for the empty path.
+	/// The rules of an imported stylesheet cannot be edited through the
+	/// stylesheet importing it
+	fn rule_list_mut(&mut self, parent: &[usize]) -> Option<&mut Vec<CssRule>> {
+		let mut rules = &mut self.rules;
+
+		for &i in parent.iter() {
+			let list = rules;
+			rules = match list.get_mut(i) {
+				Some(&mut CssRule::Import(_)) | None => return None,
+				Some(rule) => match rule.child_rules_mut() {
+					Some(r) => r,
+					None => return None,
+				},
+			};
+		}
+
+		Some(rules)
+	}
+
+	fn rule_mut(&mut self, path: &[usize]) -> Option<&mut CssRule> {
+		if path.is_empty() {
+			return None;
+		}
+
+		let last = path[path.len() - 1];
+		self.rule_list_mut(&path[..path.len() - 1]).and_then(|rules| rules.get_mut(last))
+	}
+
+	/// Parse 'text' as a single rule and insert it before rule 'index' of
+	/// the rule list at 'parent'. Selectors may use the prefixes of the
+	/// stylesheet's @namespace rules. Return false if the text is not one
+	/// valid rule or the rule cannot go there; see insert
+	/// www.w3.org/TR/cssom-1/#insert-a-css-rule
+	pub fn insert_rule(&mut self, parent: &[usize], index: usize, text: &str) -> bool {
+		let mut parser = CssParser::new(text.to_string());
+		parser.set_namespaces(self.namespaces());
+
+		match parser.parse_rule(parent.is_empty()) {
+			Some(rule) => self.insert(parent, index, rule),
+			None => false,
+		}
+	}
+
+	/// Insert 'rule' before rule 'index' of the rule list at 'parent', the
+	/// top level list for the empty path. Return false, leaving the
+	/// stylesheet as it was, if there is no such position or the rule is
+	/// not allowed there: @import and @namespace rules only go at the top
+	/// level before the others, and an @namespace rule only into a
+	/// stylesheet with no other rules, whose prefixes it would change
+	pub fn insert(&mut self, parent: &[usize], index: usize, rule: CssRule) -> bool {
+		let top_level = parent.is_empty();
+
+		{
+			let rules = match self.rule_list_mut(parent) {
+				Some(r) => r,
+				None => return false,
+			};
+			if index > rules.len() {
+				return false;
+			}
+
+			let namespace_allowed = match rule {
+				CssRule::Namespace(_) => rules.iter().all(|r| r.preamble_order().map_or(true, |o| o < 2)),
+				_ => true,
+			};
+			rules.insert(index, rule);
+
+			let ordered = if top_level {
+				let orders: Vec<usize> = rules.iter().filter_map(|r| r.preamble_order()).collect();
+				orders.windows(2).all(|w| w[0] <= w[1])
+			} else {
+				rules[index].preamble_order().map_or(true, |o| o == 2)
+			};
+			if !namespace_allowed || !ordered {
+				rules.remove(index);
+				return false;
+			}
+		}
+
+		self.renumber();
+		true
+	}
+
+	/// Remove and return rule 'index' of the rule list at 'parent'. An
+	/// @namespace rule cannot be removed while other rules may use its
+	/// prefix
+	/// www.w3.org/TR/cssom-1/#remove-a-css-rule
+	pub fn delete_rule(&mut self, parent: &[usize], index: usize) -> Option<CssRule> {
+		let removed = {
+			let rules = match self.rule_list_mut(parent) {
+				Some(r) => r,
+				None => return None,
+			};
+
+			let in_use = match rules.get(index) {
+				None => return None,
+				Some(&CssRule::Namespace(_)) => rules.iter().any(|r| r.preamble_order() == Some(2)),
+				Some(_) => false,
+			};
+			if in_use {
+				return None;
+			}
+			rules.remove(index)
+		};
+
+		self.renumber();
+		Some(removed)
+	}
+
+	/// Replace the selectors of the style rule at 'path' with the selector
+	/// list in 'text'. Return false if there is no style rule there or the
+	/// list is not valid
+	pub fn set_selector_text(&mut self, path: &[usize], text: &str) -> bool {
+		let selectors = match selector::parse_selector_list(text, &self.namespaces()) {
+			Some(s) => s,
+			None => return false,
+		};
+
+		match self.rule_mut(path) {
+			Some(&mut CssRule::Style(ref mut style)) => style.selectors = selectors,
+			_ => return false,
+		}
+
+		self.renumber();
+		true
+	}
 
-		Rule {
-			rule_map: rule_map,
+	/// The declarations of the style or @page rule at 'path'
+	pub fn declarations(&self, path: &[usize]) -> Option<&[Declaration]> {
+		self.rule_at(path).and_then(|r| r.declarations())
+	}
+
+	/// The value of the property 'name' in the rule at 'path', as CSS text.
+	/// Of several declarations of a longhand the last one counts. A
+	/// shorthand has a value only if all of its longhands are declared and
+	/// can be written as it. None if the property is not declared there
+	/// www.w3.org/TR/cssom-1/#dom-cssstyledeclaration-getpropertyvalue
+	pub fn property_value(&self, path: &[usize], name: &str) -> Option<String> {
+		let (declarations, targets) = match (self.declarations(path), property_targets(name)) {
+			(Some(d), Some(t)) => (d, t),
+			_ => return None,
+		};
+
+		let mut found = Vec::new();
+		for target in targets.iter() {
+			match declarations.iter().rev().find(|d| d.same_property(target)) {
+				Some(d) => found.push(d.clone()),
+				None => return None,
+			}
+		}
+
+		if found.len() == 1 {
+			Some(serializer::serialize_value(&found[0].property_value))
+		} else {
+			serializer::serialize_shorthand(name.trim(), &found[..])
+		}
+	}
+
+	/// Declare the property 'name' to be 'value' in the rule at 'path'. A
+	/// shorthand declares all of its longhands. Declarations of the same
+	/// properties are replaced where they are, new ones go last. An empty
+	/// value removes the property. Return false if there is no style or
+	/// @page rule there, or the property is unknown or the value invalid
+	/// www.w3.org/TR/cssom-1/#dom-cssstyledeclaration-setproperty
+	pub fn set_property(&mut self, path: &[usize], name: &str, value: &str, important: bool) -> bool {
+		if value.trim().is_empty() {
+			return self.remove_property(path, name);
+		}
+
+		let new = match string_to_declarations(&property_name(name)[..], value) {
+			Some(ref d) if d.iter().any(|d| d.property_value == Value::Missing) => return false,
+			Some(d) => d,
+			None => return false,
+		};
+		let declarations = match self.rule_mut(path).and_then(|r| r.declarations_mut()) {
+			Some(d) => d,
+			None => return false,
+		};
+
+		for mut declaration in new.into_iter() {
+			declaration.important = important;
+
+			match declarations.iter().position(|d| d.same_property(&declaration)) {
+				Some(i) => {
+					let mut j = i + 1;
+					while j < declarations.len() {
+						if declarations[j].same_property(&declaration) {
+							declarations.remove(j);
+						} else {
+							j += 1;
+						}
+					}
+					declarations[i] = declaration;
+				}
+				None => declarations.push(declaration),
+			}
+		}
+
+		true
+	}
+
+	/// Remove every declaration of the property 'name', all of its
+	/// longhands for a shorthand, from the rule at 'path'. Return false if
+	/// nothing was declared
+	/// www.w3.org/TR/cssom-1/#dom-cssstyledeclaration-removeproperty
+	pub fn remove_property(&mut self, path: &[usize], name: &str) -> bool {
+		let targets = match property_targets(name) {
+			Some(t) => t,
+			None => return false,
+		};
+		let declarations = match self.rule_mut(path).and_then(|r| r.declarations_mut()) {
+			Some(d) => d,
+			None => return false,
+		};
+
+		let count = declarations.len();
+		declarations.retain(|d| !targets.iter().any(|t| d.same_property(t)));
+		declarations.len() != count
+	}
+
+	/// Number the style rules again and rebuild the index and the cascade
+	/// layers, once rules have been inserted or removed
+	fn renumber(&mut self) {
+		self.index = RuleIndex::default();
+		self.style_paths = Vec::new();
+		self.layers = CascadeLayers::new();
+
+		number_rules(&mut self.rules[..], 0, &mut Vec::new(), 0,
+					 &mut self.layers, &mut self.index, &mut self.style_paths);
+	}
+
+	/// Style rule 'n' in source order
+	fn style_rule(&self, n: usize) -> &StyleRule {
+		let path = &self.style_paths[n];
+		let mut rules = &self.rules[..];
+
+		for &i in path[..path.len() - 1].iter() {
+			rules = rules[i].child_rules().unwrap();
+		}
+
+		match rules[path[path.len() - 1]] {
+			CssRule::Style(ref style) => style,
+			_ => unreachable!(),
+		}
+	}
+
+	/// Whether every rule style rule 'n' is nested in applies in 'media'
+	fn style_rule_applies(&self, n: usize, media: &MediaEnvironment) -> bool {
+		let path = &self.style_paths[n];
+		let mut rules = &self.rules[..];
+
+		for &i in path[..path.len() - 1].iter() {
+			if !rules[i].applies(media) {
+				return false;
+			}
+			rules = rules[i].child_rules().unwrap();
+		}
+
+		true
+	}
+
+	/// Return every rule that applies in 'media' with a selector matching
+	/// the element in 'context', from the lowest to the highest precedence
+	/// of their normal declarations: by cascade layer, then by specificity,
+	/// then by source order
+	pub fn matching_rules(&self, context: &MatchContext, media: &MediaEnvironment) -> Vec<MatchedRule> {
+		self.sorted_matches(context, media, |selector| selector.matches(context))
+	}
+
+	/// Return the declarations of the rule that wins for the element in
+	/// 'context'. See cascaded_declarations for the value each property
+	/// ends up with
+	pub fn matching_declarations(&self,
+								 context: &MatchContext,
+								 media: &MediaEnvironment) -> Option<&Vec<Declaration>> {
+		self.matching_rules(context, media).pop().map(|m| &m.rule.declarations)
+	}
+
+	/// Like matching_declarations, for the rules styling 'pseudo' of the
+	/// element in 'context'
+	pub fn matching_pseudo_declarations(&self,
+										context: &MatchContext,
+										media: &MediaEnvironment,
+										pseudo: PseudoElement) -> Option<&Vec<Declaration>> {
+		self.sorted_matches(context, media, |selector| selector.matches_pseudo(context, pseudo))
+			.pop()
+			.map(|m| &m.rule.declarations)
+	}
+
+	/// Return the declaration the cascade picks for each property set by
+	/// the rules that match the element in 'context', in cascade order.
+	/// None if no rule matches
+	pub fn cascaded_declarations(&self,
+								 context: &MatchContext,
+								 media: &MediaEnvironment) -> Option<Vec<Declaration>> {
+		cascade_stylesheets(&[self], context, media, None)
+	}
+
+	/// Like cascaded_declarations, for the rules styling 'pseudo' of the
+	/// element in 'context'
+	pub fn cascaded_pseudo_declarations(&self,
+										context: &MatchContext,
+										media: &MediaEnvironment,
+										pseudo: PseudoElement) -> Option<Vec<Declaration>> {
+		cascade_stylesheets(&[self], context, media, Some(pseudo))
+	}
+
+	fn sorted_matches<F>(&self, context: &MatchContext, media: &MediaEnvironment, f: F) -> Vec<MatchedRule>
+		where F: Fn(&Selector) -> bool {
+		let mut specificities: HashMap<usize, Specificity> = HashMap::new();
+
+		for (n, i) in self.index.candidates(context.element).into_iter() {
+			let selector = &self.style_rule(n).selectors[i];
+
+			if f(selector) && self.style_rule_applies(n, media) {
+				let specificity = selector.specificity();
+				let best = specificities.entry(n).or_insert(specificity);
+				if specificity > *best {
+					*best = specificity;
+				}
+			}
+		}
+
+		let mut matched: Vec<MatchedRule> = specificities.into_iter().map(|(n, specificity)| {
+			MatchedRule {
+				rule: self.style_rule(n),
+				specificity: specificity,
+				layer: self.layers.rule_rank(n),
+			}
+		}).collect();
+
+		matched.sort_by(|a, b| {
+			(a.layer, a.specificity, a.rule.source_index).cmp(&(b.layer, b.specificity, b.rule.source_index))
+		});
+		matched
+	}
+}
+
+/// The stylesheets an element is styled by, in order, from any origin.
+/// Cascade layers of the same name in stylesheets of the same origin are
+/// one layer, ordered by where any of those stylesheets first names it.
+/// The custom properties their @property rules register apply to all of
+/// them
+pub struct StyleSheetList<'a> {
+	sheets: Vec<&'a StyleSheet>,
+	/// For each stylesheet, the position in layer order of each of its
+	/// layers among those of its origin
+	layer_ranks: Vec<Vec<usize>>,
+	registry: PropertyRegistry,
+}
+
+impl<'a> StyleSheetList<'a> {
+	pub fn new(sheets: &[&'a StyleSheet]) -> StyleSheetList<'a> {
+		let mut merged: HashMap<Origin, CascadeLayers> = HashMap::new();
+		let layers: Vec<Vec<usize>> = sheets.iter().map(|sheet| {
+			merged.entry(sheet.origin).or_insert_with(CascadeLayers::new).merge(&sheet.layers)
+		}).collect();
+
+		let layer_ranks = sheets.iter().zip(layers.iter()).map(|(sheet, layers)| {
+			layers.iter().map(|&layer| merged[&sheet.origin].layer_rank(layer)).collect()
+		}).collect();
+
+		let mut registry = PropertyRegistry::new();
+		for rule in sheets.iter().flat_map(|sheet| sheet.property_rules().into_iter()) {
+			registry.register(rule);
+		}
+
+		StyleSheetList {
+			sheets: sheets.to_vec(),
+			layer_ranks: layer_ranks,
+			registry: registry,
+		}
+	}
+
+	pub fn sheets(&self) -> &[&'a StyleSheet] {
+		&self.sheets[..]
+	}
+
+	/// The custom properties registered by the stylesheets
+	pub fn registry(&self) -> &PropertyRegistry {
+		&self.registry
+	}
+
+	/// Return the declaration the cascade picks for each property set by
+	/// the rules that match the element in 'context', or its 'pseudo'
+	/// element if one is given, in cascade order. The rules of a stylesheet
+	/// come after those of the stylesheets before it in source order. None
+	/// if no rule matches
+	pub fn cascade(&self,
+				   context: &MatchContext,
+				   media: &MediaEnvironment,
+				   pseudo: Option<PseudoElement>) -> Option<Vec<Declaration>> {
+		let mut entries = Vec::new();
+		let mut matched_any = false;
+
+		for (n, sheet) in self.sheets.iter().enumerate() {
+			let matched = match pseudo {
+				Some(p) => sheet.sorted_matches(context, media, |selector| selector.matches_pseudo(context, p)),
+				None => sheet.matching_rules(context, media),
+			};
+			matched_any = matched_any || !matched.is_empty();
+
+			for m in matched.iter() {
+				let layer = self.layer_ranks[n][sheet.layers.rule_layer(m.rule.source_index)];
+
+				for (i, declaration) in m.rule.declarations.iter().enumerate() {
+					entries.push(CascadeEntry {
+						declaration: declaration,
+						origin: sheet.origin,
+						layer: layer,
+						specificity: m.specificity,
+						order: (n, m.rule.source_index, i),
+					});
+				}
+			}
+		}
+
+		if matched_any {
+			Some(cascade::cascade(entries))
+		} else {
+			None
+		}
+	}
+}
+
+/// Return the declaration the cascade picks for each property set by the
+/// rules of 'sheets' that match the element in 'context', or its 'pseudo'
+/// element if one is given. See StyleSheetList::cascade; build one
+/// StyleSheetList to style many elements with the same stylesheets
+pub fn cascade_stylesheets(sheets: &[&StyleSheet],
+						   context: &MatchContext,
+						   media: &MediaEnvironment,
+						   pseudo: Option<PseudoElement>) -> Option<Vec<Declaration>> {
+	StyleSheetList::new(sheets).cascade(context, media, pseudo)
+}
+
+fn collect_property_rules<'a>(rules: &'a [CssRule], found: &mut Vec<&'a PropertyRule>) {
+	for rule in rules.iter() {
+		match *rule {
+			CssRule::Property(ref p) => found.push(p),
+			_ => collect_property_rules(rule.child_rules().unwrap_or(&[]), found),
 		}
 	}
 }
 
-/// A Selector is an element to which style rules apply
-/// www.w3.org/TR/CSS2/selector.html
-/// Only TypeSelectors are currently supported, and are
-/// implemented by matching element types from dom_tree 
-#[derive(Debug, Copy, PartialEq, Hash, Eq)]
-pub enum Selector {
-	SelectorType(dom_tree::ElementType),
+/// Property names other than custom ones are not case-sensitive
+fn property_name(name: &str) -> String {
+	let name = name.trim();
+
+	if custom::is_custom_property_name(name) {
+		name.to_string()
+	} else {
+		name.to_ascii_lowercase()
+	}
+}
+
+/// Declarations of the longhands the property 'name' sets, to compare
+/// others with Declaration::same_property. None if it is unknown
+fn property_targets(name: &str) -> Option<Vec<Declaration>> {
+	string_to_declarations(&property_name(name)[..], "initial")
+}
+
+/// Number the style rules in 'rules', which start at position 'first' of
+/// the rule list at 'path' and are in cascade layer 'layer', after the ones
+/// already in 'paths', and file them in 'index' and 'layers'
+fn number_rules(rules: &mut [CssRule],
+				first: usize,
+				path: &mut Vec<usize>,
+				layer: usize,
+				layers: &mut CascadeLayers,
+				index: &mut RuleIndex,
+				paths: &mut Vec<Vec<usize>>) {
+	for (i, rule) in rules.iter_mut().enumerate() {
+		path.push(first + i);
+
+		match *rule {
+			CssRule::Style(ref mut style) => {
+				let n = paths.len();
+				style.source_index = n;
+				for (j, selector) in style.selectors.iter().enumerate() {
+					index.add(selector, (n, j));
+				}
+				paths.push(path.clone());
+				layers.add_rule(layer);
+			}
+			CssRule::Layer(ref mut layer_rule) => {
+				let named: Vec<usize> = layer_rule.names.iter().map(|name| layers.declare(layer, name)).collect();
+				match layer_rule.rules {
+					Some(ref mut children) => {
+						let inner = match named.first() {
+							Some(&l) => l,
+							None => layers.anonymous(layer),
+						};
+						number_rules(&mut children[..], 0, path, inner, layers, index, paths);
+					}
+					None => {}
+				}
+			}
+			ref mut other => match other.child_rules_mut() {
+				Some(children) => number_rules(&mut children[..], 0, path, layer, layers, index, paths),
+				None => {}
+			},
+		}
+
+		path.pop();
+	}
 }
 
 /// A declaration is the CSS property and value to 
-/// apply to a selector. 
+/// apply to a selector. 'important' is set by a trailing '!important'
 /// www.w3.org/TR/CSS2/syndata.html#declaration
-#[derive(Copy)]
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Declaration {
 	pub property_name : Property,
 	pub property_value : Value,
+	pub important : bool,
+}
+
+impl Declaration {
+	/// The name of the custom property this declaration sets, if it sets
+	/// one
+	pub fn custom_name(&self) -> Option<&str> {
+		match self.property_value {
+			Value::Custom(ref name, _) | Value::CustomCssWide(ref name, _) => Some(&name[..]),
+			_ => None,
+		}
+	}
+
+	/// Whether this declaration and 'other' set the same property
+	pub fn same_property(&self, other: &Declaration) -> bool {
+		self.property_name == other.property_name && self.custom_name() == other.custom_name()
+	}
 }
 
 /// Supported CSS properties
-#[derive(Debug, PartialEq, Copy)]
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Property {
 	FontSize,
 	LineHeight,
@@ -57,43 +999,543 @@ pub enum Property {
 	PaddingBottom,
 	PaddingLeft,
 	PaddingRight,
-	BorderTopHeight,
-	BorderBottomHeight,
-	BorderLeftHeight,
-	BorderRightHeight,
+	BorderTopWidth,
+	BorderBottomWidth,
+	BorderLeftWidth,
+	BorderRightWidth,
+	WhiteSpace,
+	Content,
+	CounterReset,
+	CounterSet,
+	CounterIncrement,
+	BorderTopStyle,
+	BorderBottomStyle,
+	BorderLeftStyle,
+	BorderRightStyle,
+	BorderTopColor,
+	BorderBottomColor,
+	BorderLeftColor,
+	BorderRightColor,
+	FontStyle,
+	FontVariant,
+	FontWeight,
+	FontStretch,
+	FontFamily,
+	BackgroundColor,
+	BackgroundImage,
+	BackgroundRepeat,
+	BackgroundAttachment,
+	BackgroundPosition,
+	BackgroundSize,
+	BackgroundOrigin,
+	BackgroundClip,
+	Top,
+	Bottom,
+	Left,
+	Right,
+	FlexGrow,
+	FlexShrink,
+	FlexBasis,
+	RowGap,
+	ColumnGap,
+	ListStyleType,
+	ListStylePosition,
+	/// A custom property, '--name'. Its name is kept in its value, see
+	/// Value::Custom
+	Custom,
+}
+
+/// Every property, in the order they are declared, so 'property as usize'
+/// is a property's position here. Property::Custom, the last, is left out:
+/// custom properties are kept by name
+pub static ALL_PROPERTIES: [Property; 53] = [
+	Property::FontSize, Property::LineHeight, Property::Color, Property::Display,
+	Property::MarginTop, Property::MarginBottom, Property::MarginLeft, Property::MarginRight,
+	Property::PaddingTop, Property::PaddingBottom, Property::PaddingLeft, Property::PaddingRight,
+	Property::BorderTopWidth, Property::BorderBottomWidth, Property::BorderLeftWidth,
+	Property::BorderRightWidth, Property::WhiteSpace, Property::Content, Property::CounterReset,
+	Property::CounterSet, Property::CounterIncrement, Property::BorderTopStyle,
+	Property::BorderBottomStyle, Property::BorderLeftStyle, Property::BorderRightStyle,
+	Property::BorderTopColor, Property::BorderBottomColor, Property::BorderLeftColor,
+	Property::BorderRightColor, Property::FontStyle, Property::FontVariant, Property::FontWeight,
+	Property::FontStretch, Property::FontFamily, Property::BackgroundColor,
+	Property::BackgroundImage, Property::BackgroundRepeat, Property::BackgroundAttachment,
+	Property::BackgroundPosition, Property::BackgroundSize, Property::BackgroundOrigin,
+	Property::BackgroundClip, Property::Top, Property::Bottom, Property::Left, Property::Right,
+	Property::FlexGrow, Property::FlexShrink, Property::FlexBasis, Property::RowGap,
+	Property::ColumnGap, Property::ListStyleType, Property::ListStylePosition,
+];
+
+impl Property {
+	/// Whether the property takes its parent's value when the cascade
+	/// gives it none
+	/// www.w3.org/TR/css-cascade-5/#inheriting
+	pub fn inherited(&self) -> bool {
+		match *self {
+			Property::FontSize | Property::LineHeight | Property::Color | Property::WhiteSpace |
+			Property::FontStyle | Property::FontVariant | Property::FontWeight | Property::FontStretch |
+			Property::FontFamily | Property::ListStyleType | Property::ListStylePosition |
+			Property::Custom => true,
+			_ => false,
+		}
+	}
+
+	/// Whether the property's lengths cannot be negative. A math expression
+	/// can still resolve to a negative length; it is clamped to 0 when the
+	/// value is computed
+	/// www.w3.org/TR/css-values-4/#calc-range
+	pub fn non_negative(&self) -> bool {
+		match *self {
+			Property::PaddingTop | Property::PaddingBottom | Property::PaddingLeft |
+			Property::PaddingRight | Property::BorderTopWidth | Property::BorderBottomWidth |
+			Property::BorderLeftWidth | Property::BorderRightWidth | Property::FontSize |
+			Property::LineHeight | Property::FlexBasis | Property::RowGap | Property::ColumnGap => true,
+			_ => false,
+		}
+	}
+
+	/// The initial value of the property, as written in its definition
+	/// www.w3.org/TR/css-cascade-5/#initial-values
+	pub fn initial_css(&self) -> &'static str {
+		match *self {
+			Property::FontSize => "medium",
+			Property::LineHeight | Property::WhiteSpace | Property::Content | Property::FontStyle |
+			Property::FontVariant | Property::FontWeight | Property::FontStretch |
+			Property::RowGap | Property::ColumnGap => "normal",
+			Property::Color => "black",
+			Property::Display => "inline",
+			Property::MarginTop | Property::MarginBottom | Property::MarginLeft | Property::MarginRight |
+			Property::PaddingTop | Property::PaddingBottom | Property::PaddingLeft | Property::PaddingRight |
+			Property::FlexGrow => "0",
+			Property::BorderTopWidth | Property::BorderBottomWidth |
+			Property::BorderLeftWidth | Property::BorderRightWidth => "medium",
+			Property::CounterReset | Property::CounterSet | Property::CounterIncrement |
+			Property::BorderTopStyle | Property::BorderBottomStyle |
+			Property::BorderLeftStyle | Property::BorderRightStyle | Property::BackgroundImage => "none",
+			Property::BorderTopColor | Property::BorderBottomColor |
+			Property::BorderLeftColor | Property::BorderRightColor => "currentcolor",
+			Property::FontFamily => "serif",
+			Property::BackgroundColor => "transparent",
+			Property::BackgroundRepeat => "repeat",
+			Property::BackgroundAttachment => "scroll",
+			Property::BackgroundPosition => "0% 0%",
+			Property::BackgroundSize | Property::Top | Property::Bottom | Property::Left | Property::Right |
+			Property::FlexBasis => "auto",
+			Property::BackgroundOrigin => "padding-box",
+			Property::BackgroundClip => "border-box",
+			Property::FlexShrink => "1",
+			Property::ListStyleType => "disc",
+			Property::ListStylePosition => "outside",
+			// the guaranteed-invalid value
+			Property::Custom => "",
+		}
+	}
+
+	/// The initial value of the property
+	pub fn initial_value(&self) -> Value {
+		string_to_property_value(*self, self.initial_css())
+	}
+
+	/// The name the property is declared with. A custom property's name is
+	/// kept in its value, so it has none here
+	pub fn name(&self) -> &'static str {
+		match *self {
+			Property::FontSize => "font-size",
+			Property::LineHeight => "line-height",
+			Property::Color => "color",
+			Property::Display => "display",
+			Property::MarginTop => "margin-top",
+			Property::MarginBottom => "margin-bottom",
+			Property::MarginLeft => "margin-left",
+			Property::MarginRight => "margin-right",
+			Property::PaddingTop => "padding-top",
+			Property::PaddingBottom => "padding-bottom",
+			Property::PaddingLeft => "padding-left",
+			Property::PaddingRight => "padding-right",
+			Property::BorderTopWidth => "border-top-width",
+			Property::BorderBottomWidth => "border-bottom-width",
+			Property::BorderLeftWidth => "border-left-width",
+			Property::BorderRightWidth => "border-right-width",
+			Property::WhiteSpace => "white-space",
+			Property::Content => "content",
+			Property::CounterReset => "counter-reset",
+			Property::CounterSet => "counter-set",
+			Property::CounterIncrement => "counter-increment",
+			Property::BorderTopStyle => "border-top-style",
+			Property::BorderBottomStyle => "border-bottom-style",
+			Property::BorderLeftStyle => "border-left-style",
+			Property::BorderRightStyle => "border-right-style",
+			Property::BorderTopColor => "border-top-color",
+			Property::BorderBottomColor => "border-bottom-color",
+			Property::BorderLeftColor => "border-left-color",
+			Property::BorderRightColor => "border-right-color",
+			Property::FontStyle => "font-style",
+			Property::FontVariant => "font-variant",
+			Property::FontWeight => "font-weight",
+			Property::FontStretch => "font-stretch",
+			Property::FontFamily => "font-family",
+			Property::BackgroundColor => "background-color",
+			Property::BackgroundImage => "background-image",
+			Property::BackgroundRepeat => "background-repeat",
+			Property::BackgroundAttachment => "background-attachment",
+			Property::BackgroundPosition => "background-position",
+			Property::BackgroundSize => "background-size",
+			Property::BackgroundOrigin => "background-origin",
+			Property::BackgroundClip => "background-clip",
+			Property::Top => "top",
+			Property::Bottom => "bottom",
+			Property::Left => "left",
+			Property::Right => "right",
+			Property::FlexGrow => "flex-grow",
+			Property::FlexShrink => "flex-shrink",
+			Property::FlexBasis => "flex-basis",
+			Property::RowGap => "row-gap",
+			Property::ColumnGap => "column-gap",
+			Property::ListStyleType => "list-style-type",
+			Property::ListStylePosition => "list-style-position",
+			Property::Custom => "",
+		}
+	}
 }
 
 /// Supported CSS values to apply to Properties
 #[allow(dead_code)]
-#[derive(PartialEq, Copy)]
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Value {
-	Size(u32, Unit),
+	/// A length
+	Size(f32, Unit),
+	Percentage(f32),
+	Number(f32),
+	/// A calc(), min(), max() or clamp() expression
+	Calc(CalcNode),
 	ColorValue(Color),
+	/// 'currentColor', the value of the element's 'color' property
+	CurrentColor,
 	Block(BlockType),
+	WhiteSpace(WhiteSpaceType),
+	/// The items of 'content', none for 'none' and 'normal'
+	Content(Vec<ContentItem>),
+	/// The 'name [integer]' pairs of the counter properties, none for 'none'
+	Counters(Vec<(String, i32)>),
+	/// A keyword without a type of its own, such as 'solid' or 'italic'
+	Keyword(String),
+	/// An image given by 'url(...)'
+	Url(String),
+	/// The font family names, in order of preference
+	FontFamily(Vec<String>),
+	/// The values of a property that takes several, such as
+	/// background-position
+	List(Vec<Value>),
+	/// 'initial', 'inherit', 'unset', 'revert' or 'revert-layer', which
+	/// every property takes
+	CssWide(CssWideKeyword),
+	/// The name of a custom property and the source text of its value
+	Custom(String, String),
+	/// The name of a custom property set to a CSS-wide keyword
+	CustomCssWide(String, CssWideKeyword),
+	/// The source text of a value with var() in it. It is parsed once the
+	/// custom properties it uses are substituted, at computed-value time
+	Unsubstituted(String),
+	/// A longhand set by a shorthand, named here, whose value has var() in
+	/// it, and the shorthand's source text
+	PendingSubstitution(String, String),
 	Missing,
 }
 
-#[derive(PartialEq, Copy)]
+/// What ::before and ::after boxes are filled with
+/// www.w3.org/TR/css-content-3/#content-property
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ContentItem {
+	/// A quoted string
+	Text(String),
+	/// 'attr(name)', the value of the element's attribute or nothing
+	Attr(String),
+	/// 'counter(name, style)', the innermost value of the counter
+	Counter(String, CounterStyle),
+	/// 'counters(name, "separator", style)', the value of every counter
+	/// with that name in scope, outermost first
+	Counters(String, String, CounterStyle),
+	OpenQuote,
+	CloseQuote,
+	NoOpenQuote,
+	NoCloseQuote,
+}
+
+/// How a counter value is written
+/// www.w3.org/TR/css-counter-styles-3/#predefined-counters
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum CounterStyle {
+	Decimal,
+	LowerAlpha,
+	UpperAlpha,
+	LowerRoman,
+	UpperRoman,
+	Disc,
+	Circle,
+	Square,
+	None,
+}
+
+impl CounterStyle {
+	pub fn from_name(name: &str) -> Option<CounterStyle> {
+		match name {
+			"decimal" => Some(CounterStyle::Decimal),
+			"lower-alpha" | "lower-latin" => Some(CounterStyle::LowerAlpha),
+			"upper-alpha" | "upper-latin" => Some(CounterStyle::UpperAlpha),
+			"lower-roman" => Some(CounterStyle::LowerRoman),
+			"upper-roman" => Some(CounterStyle::UpperRoman),
+			"disc" => Some(CounterStyle::Disc),
+			"circle" => Some(CounterStyle::Circle),
+			"square" => Some(CounterStyle::Square),
+			"none" => Some(CounterStyle::None),
+			_ => None,
+		}
+	}
+
+	pub fn name(&self) -> &'static str {
+		match *self {
+			CounterStyle::Decimal => "decimal",
+			CounterStyle::LowerAlpha => "lower-alpha",
+			CounterStyle::UpperAlpha => "upper-alpha",
+			CounterStyle::LowerRoman => "lower-roman",
+			CounterStyle::UpperRoman => "upper-roman",
+			CounterStyle::Disc => "disc",
+			CounterStyle::Circle => "circle",
+			CounterStyle::Square => "square",
+			CounterStyle::None => "none",
+		}
+	}
+
+	/// Write 'value' in this style. Values a style cannot represent, such
+	/// as 0 in roman numerals, fall back to decimal
+	pub fn format(&self, value: i32) -> String {
+		match *self {
+			CounterStyle::Decimal => value.to_string(),
+			CounterStyle::LowerAlpha | CounterStyle::UpperAlpha if value > 0 => {
+				let mut letters = Vec::new();
+				let mut n = value;
+				while n > 0 {
+					n -= 1;
+					letters.push((b'a' + (n % 26) as u8) as char);
+					n /= 26;
+				}
+				let result: String = letters.into_iter().rev().collect();
+
+				if *self == CounterStyle::UpperAlpha { result.to_ascii_uppercase() } else { result }
+			}
+			CounterStyle::LowerRoman | CounterStyle::UpperRoman if value > 0 && value < 4000 => {
+				let numerals = [(1000, "m"), (900, "cm"), (500, "d"), (400, "cd"), (100, "c"), (90, "xc"),
+								(50, "l"), (40, "xl"), (10, "x"), (9, "ix"), (5, "v"), (4, "iv"), (1, "i")];
+				let mut result = String::new();
+				let mut n = value;
+				for &(size, numeral) in numerals.iter() {
+					while n >= size {
+						result.push_str(numeral);
+						n -= size;
+					}
+				}
+
+				if *self == CounterStyle::UpperRoman { result.to_ascii_uppercase() } else { result }
+			}
+			CounterStyle::Disc => "\u{2022}".to_string(),
+			CounterStyle::Circle => "\u{25E6}".to_string(),
+			CounterStyle::Square => "\u{25AA}".to_string(),
+			CounterStyle::None => String::new(),
+			_ => value.to_string(),
+		}
+	}
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum BlockType {
 	Inline,
 	Block,
 	None,
 }
 
+/// How white space inside an element's text is handled
+/// www.w3.org/TR/css-text-3/#white-space-property
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum WhiteSpaceType {
+	Normal,
+	Pre,
+	Nowrap,
+	PreWrap,
+	PreLine,
+	BreakSpaces,
+}
+
+impl WhiteSpaceType {
+	/// True if runs of spaces and tabs collapse to a single space
+	pub fn collapses_spaces(&self) -> bool {
+		match *self {
+			WhiteSpaceType::Normal | WhiteSpaceType::Nowrap | WhiteSpaceType::PreLine => true,
+			_ => false,
+		}
+	}
+
+	/// True if newlines in the source are kept as line breaks
+	pub fn preserves_newlines(&self) -> bool {
+		*self != WhiteSpaceType::Normal && *self != WhiteSpaceType::Nowrap
+	}
+}
+
 /// Supported units of measurement for CSS
-#[derive(PartialEq, Copy)]
+/// www.w3.org/TR/css-values-4/#lengths
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Unit {
 	Px,
+	Cm,
+	Mm,
+	Q,
+	In,
+	Pt,
+	Pc,
 	Em,
+	Rem,
+	Ex,
+	Ch,
+	Vw,
+	Vh,
+	Vmin,
+	Vmax,
+	Dvh,
 }
 
-#[derive(PartialEq, Copy)]
-#[allow(dead_code)]
-pub struct Color {
-	red: u8,
-	green: u8,
-	blue: u8,
-	alpha: u8,
+impl Unit {
+	/// Look up a unit by name, ignoring case
+	pub fn from_name(name: &str) -> Option<Unit> {
+		match &name.to_ascii_lowercase()[..] {
+			"px" => Some(Unit::Px),
+			"cm" => Some(Unit::Cm),
+			"mm" => Some(Unit::Mm),
+			"q" => Some(Unit::Q),
+			"in" => Some(Unit::In),
+			"pt" => Some(Unit::Pt),
+			"pc" => Some(Unit::Pc),
+			"em" => Some(Unit::Em),
+			"rem" => Some(Unit::Rem),
+			"ex" => Some(Unit::Ex),
+			"ch" => Some(Unit::Ch),
+			"vw" => Some(Unit::Vw),
+			"vh" => Some(Unit::Vh),
+			"vmin" => Some(Unit::Vmin),
+			"vmax" => Some(Unit::Vmax),
+			"dvh" => Some(Unit::Dvh),
+			_ => None,
+		}
+	}
+
+	/// The unit's name, in lowercase
+	pub fn name(&self) -> &'static str {
+		match *self {
+			Unit::Px => "px",
+			Unit::Cm => "cm",
+			Unit::Mm => "mm",
+			Unit::Q => "q",
+			Unit::In => "in",
+			Unit::Pt => "pt",
+			Unit::Pc => "pc",
+			Unit::Em => "em",
+			Unit::Rem => "rem",
+			Unit::Ex => "ex",
+			Unit::Ch => "ch",
+			Unit::Vw => "vw",
+			Unit::Vh => "vh",
+			Unit::Vmin => "vmin",
+			Unit::Vmax => "vmax",
+			Unit::Dvh => "dvh",
+		}
+	}
+
+	/// The size of one of this unit in px. Without font metrics an 'ex' and
+	/// a 'ch' are taken to be half an 'em'. The viewport does not change
+	/// size dynamically, so a 'dvh' is a 'vh'
+	pub fn to_px(&self, context: &LengthContext) -> f32 {
+		let viewport = &context.viewport;
+
+		match *self {
+			Unit::Px => 1.0,
+			Unit::Cm => 96.0 / 2.54,
+			Unit::Mm => 96.0 / 25.4,
+			Unit::Q => 96.0 / 101.6,
+			Unit::In => 96.0,
+			Unit::Pt => 96.0 / 72.0,
+			Unit::Pc => 16.0,
+			Unit::Em => context.font_size,
+			Unit::Rem => context.root_font_size,
+			Unit::Ex | Unit::Ch => context.font_size / 2.0,
+			Unit::Vw => viewport.width / 100.0,
+			Unit::Vh | Unit::Dvh => viewport.height / 100.0,
+			Unit::Vmin => viewport.width.min(viewport.height) / 100.0,
+			Unit::Vmax => viewport.width.max(viewport.height) / 100.0,
+		}
+	}
+}
+
+/// What relative lengths are resolved against: the element's font size,
+/// the root element's font size and the viewport
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LengthContext {
+	pub font_size: f32,
+	pub root_font_size: f32,
+	pub viewport: Viewport,
+}
+
+impl Default for LengthContext {
+	/// 16px fonts in an 800 by 600 viewport
+	fn default() -> LengthContext {
+		LengthContext {
+			font_size: 16.0,
+			root_font_size: 16.0,
+			viewport: MediaEnvironment::default().viewport,
+		}
+	}
+}
+
+impl Value {
+	/// The CSS-wide keyword this value is, if it is one
+	pub fn css_wide_keyword(&self) -> Option<CssWideKeyword> {
+		match *self {
+			Value::CssWide(keyword) | Value::CustomCssWide(_, keyword) => Some(keyword),
+			_ => None,
+		}
+	}
+
+	/// The length this value is in px, None if it is not a length.
+	/// Percentages depend on the property and are not resolved here
+	pub fn to_px(&self, context: &LengthContext) -> Option<f32> {
+		match *self {
+			Value::Size(n, unit) => Some(n * unit.to_px(context)),
+			Value::Number(n) if n == 0.0 => Some(0.0),
+			Value::Calc(ref c) if c.calc_type() == Some(CalcType::Length) => c.resolve(context, None),
+			_ => None,
+		}
+	}
+
+	/// The length this value is in px, with percentages taken of 'basis'
+	pub fn resolve_length(&self, context: &LengthContext, basis: f32) -> Option<f32> {
+		match *self {
+			Value::Percentage(p) => Some(basis * p / 100.0),
+			Value::Calc(ref c) if c.calc_type() != Some(CalcType::Number) => c.resolve(context, Some(basis)),
+			_ => self.to_px(context),
+		}
+	}
+
+	/// The number this value is, None if it is not a number
+	pub fn to_number(&self, context: &LengthContext) -> Option<f32> {
+		match *self {
+			Value::Number(n) => Some(n),
+			Value::Calc(ref c) if c.calc_type() == Some(CalcType::Number) => c.resolve(context, None),
+			_ => None,
+		}
+	}
 }
 
 /// Take a string and match to the Property type. Return None
@@ -112,92 +1554,589 @@ pub fn string_to_property(string :&str) -> Option<Property> {
 		"padding-bottom" => Some(Property::PaddingBottom),
 		"padding-left" => Some(Property::PaddingLeft),
 		"padding-right" => Some(Property::PaddingRight),
-		"border-top-height" => Some(Property::BorderTopHeight),
-		"border-bottom-height" => Some(Property::BorderBottomHeight),
-		"border-left-height" => Some(Property::BorderLeftHeight),
-		"border-right-height" => Some(Property::BorderRightHeight),
+		"border-top-width" => Some(Property::BorderTopWidth),
+		"border-bottom-width" => Some(Property::BorderBottomWidth),
+		"border-left-width" => Some(Property::BorderLeftWidth),
+		"border-right-width" => Some(Property::BorderRightWidth),
+		"white-space" => Some(Property::WhiteSpace),
+		"content" => Some(Property::Content),
+		"counter-reset" => Some(Property::CounterReset),
+		"counter-set" => Some(Property::CounterSet),
+		"counter-increment" => Some(Property::CounterIncrement),
+		"border-top-style" => Some(Property::BorderTopStyle),
+		"border-bottom-style" => Some(Property::BorderBottomStyle),
+		"border-left-style" => Some(Property::BorderLeftStyle),
+		"border-right-style" => Some(Property::BorderRightStyle),
+		"border-top-color" => Some(Property::BorderTopColor),
+		"border-bottom-color" => Some(Property::BorderBottomColor),
+		"border-left-color" => Some(Property::BorderLeftColor),
+		"border-right-color" => Some(Property::BorderRightColor),
+		"font-style" => Some(Property::FontStyle),
+		"font-variant" => Some(Property::FontVariant),
+		"font-weight" => Some(Property::FontWeight),
+		"font-stretch" => Some(Property::FontStretch),
+		"font-family" => Some(Property::FontFamily),
+		"background-color" => Some(Property::BackgroundColor),
+		"background-image" => Some(Property::BackgroundImage),
+		"background-repeat" => Some(Property::BackgroundRepeat),
+		"background-attachment" => Some(Property::BackgroundAttachment),
+		"background-position" => Some(Property::BackgroundPosition),
+		"background-size" => Some(Property::BackgroundSize),
+		"background-origin" => Some(Property::BackgroundOrigin),
+		"background-clip" => Some(Property::BackgroundClip),
+		"top" => Some(Property::Top),
+		"bottom" => Some(Property::Bottom),
+		"left" => Some(Property::Left),
+		"right" => Some(Property::Right),
+		"flex-grow" => Some(Property::FlexGrow),
+		"flex-shrink" => Some(Property::FlexShrink),
+		"flex-basis" => Some(Property::FlexBasis),
+		"row-gap" => Some(Property::RowGap),
+		"column-gap" => Some(Property::ColumnGap),
+		"list-style-type" => Some(Property::ListStyleType),
+		"list-style-position" => Some(Property::ListStylePosition),
 		_ => None,
 	}
 }
 
+/// Parse the declaration 'name: value' into the declarations of the
+/// longhands it sets: just the one for a longhand, all of a shorthand's.
+/// Return None if the property is unknown
+pub fn string_to_declarations(name: &str, value: &str) -> Option<Vec<Declaration>> {
+	let name = name.trim();
+	let declaration = |property, value| Declaration {
+		property_name: property,
+		property_value: value,
+		important: false,
+	};
+
+	if custom::is_custom_property_name(name) {
+		let value = match CssWideKeyword::from_name(value.trim()) {
+			Some(keyword) => Value::CustomCssWide(name.to_string(), keyword),
+			None => Value::Custom(name.to_string(), value.trim().to_string()),
+		};
+		return Some(vec![declaration(Property::Custom, value)]);
+	}
+
+	let has_var = custom::contains_var(value);
+
+	match string_to_property(name) {
+		Some(property) if has_var => Some(vec![declaration(property, Value::Unsubstituted(value.trim().to_string()))]),
+		Some(property) => Some(vec![declaration(property, string_to_property_value(property, value))]),
+		None => shorthand::Shorthand::from_name(name).map(|s| {
+			if has_var {
+				s.longhands().into_iter().map(|p| {
+					declaration(p, Value::PendingSubstitution(name.to_string(), value.trim().to_string()))
+				}).collect()
+			} else {
+				s.declarations(value)
+			}
+		}),
+	}
+}
+
 /// Take a string and match to the Value type. Return Value::Missing
 /// if no match
 pub fn string_to_value(string :&str) -> Value {
 	let input = string.trim().to_string();
-	let mut parse = text_parser::TextParser::new(input);
+	let mut parse = text_parser::TextParser::new(input.clone());
 
 	match parse.peek_char() {
 		None => Value::Missing,
 		Some(c) if c.is_whitespace() => Value::Missing,
-		Some(c) if match c {'0'...'9' => true, _ => false,} => {
-			parse_size_units(&mut parse)
+		Some(c) if match c {'0'...'9' | '.' | '+' | '-' => true, _ => false,} => {
+			parse_numeric(&input[..])
 		}
 		Some(c) if match c {'a'...'z' | 'A'...'Z' => true, _ => false,} => {
-			parse_alpha(&mut parse)
+			if input.contains('(') {
+				parse_numeric(&input[..])
+			} else {
+				parse_alpha(&mut parse)
+			}
 		}		
 		_ => Value::Missing,
 	}
 }
 
-fn parse_alpha(parse: &mut text_parser::TextParser) -> Value {
-	let val = parse.consume_while(|c| match c {
-			  	'a'...'z' | 'A'...'Z' => true,
-				_ => false
-			  });
+/// Parse the value of 'property'. Every property takes the CSS-wide
+/// keywords. Properties with a grammar of their own are parsed here,
+/// everything else goes through string_to_value
+pub fn string_to_property_value(property: Property, string: &str) -> Value {
+	match CssWideKeyword::from_name(string.trim()) {
+		Some(keyword) => return Value::CssWide(keyword),
+		None => {}
+	}
 
-	match val.as_slice() {
-		"block" => Value::Block(BlockType::Block),
-		"inline" => Value::Block(BlockType::Inline),
+	match property {
+		Property::Content => parse_content(string),
+		Property::CounterReset | Property::CounterSet => parse_counters(string, 0),
+		Property::CounterIncrement => parse_counters(string, 1),
+		Property::Color => parse_color(string),
+		Property::MarginTop | Property::MarginBottom |
+		Property::MarginLeft | Property::MarginRight => {
+			or_keyword(parse_length(string, true, true), string, &["auto"])
+		}
+		Property::PaddingTop | Property::PaddingBottom |
+		Property::PaddingLeft | Property::PaddingRight => parse_length(string, false, true),
+		Property::FontSize => {
+			or_keyword(parse_length(string, false, true), string,
+					   &["xx-small", "x-small", "small", "medium", "large", "x-large",
+						 "xx-large", "xxx-large", "larger", "smaller"])
+		}
+		Property::BorderTopWidth | Property::BorderBottomWidth |
+		Property::BorderLeftWidth | Property::BorderRightWidth => parse_border_width(string),
+		Property::LineHeight => match string_to_value(string) {
+			Value::Size(n, _) | Value::Percentage(n) | Value::Number(n) if n < 0.0 => Value::Missing,
+			value @ Value::Size(_, _) | value @ Value::Percentage(_) |
+			value @ Value::Number(_) | value @ Value::Calc(_) => value,
+			_ => parse_keyword(string, &["normal"]),
+		},
+		Property::BorderTopStyle | Property::BorderBottomStyle |
+		Property::BorderLeftStyle | Property::BorderRightStyle => {
+			parse_keyword(string, &["none", "hidden", "dotted", "dashed", "solid",
+									"double", "groove", "ridge", "inset", "outset"])
+		}
+		Property::BorderTopColor | Property::BorderBottomColor |
+		Property::BorderLeftColor | Property::BorderRightColor |
+		Property::BackgroundColor => parse_color(string),
+		Property::FontStyle => parse_keyword(string, &["normal", "italic", "oblique"]),
+		Property::FontVariant => parse_keyword(string, &["normal", "small-caps"]),
+		Property::FontWeight => parse_font_weight(string),
+		Property::FontStretch => match string_to_value(string) {
+			Value::Percentage(n) if n >= 0.0 => Value::Percentage(n),
+			_ => parse_keyword(string, &["normal", "ultra-condensed", "extra-condensed", "condensed",
+										 "semi-condensed", "semi-expanded", "expanded",
+										 "extra-expanded", "ultra-expanded"]),
+		},
+		Property::FontFamily => parse_font_family(string),
+		Property::BackgroundImage => parse_image(string),
+		Property::BackgroundRepeat => parse_background_repeat(string),
+		Property::BackgroundAttachment => parse_keyword(string, &["scroll", "fixed", "local"]),
+		Property::BackgroundPosition => parse_background_position(string),
+		Property::BackgroundSize => parse_background_size(string),
+		Property::BackgroundOrigin | Property::BackgroundClip => {
+			parse_keyword(string, &["border-box", "padding-box", "content-box"])
+		}
+		Property::Top | Property::Bottom | Property::Left | Property::Right => {
+			or_keyword(parse_length(string, true, true), string, &["auto"])
+		}
+		Property::FlexGrow | Property::FlexShrink => match string_to_value(string) {
+			Value::Number(n) if n >= 0.0 => Value::Number(n),
+			_ => Value::Missing,
+		},
+		Property::FlexBasis => or_keyword(parse_length(string, false, true), string, &["auto", "content"]),
+		Property::RowGap | Property::ColumnGap => or_keyword(parse_length(string, false, true), string, &["normal"]),
+		Property::Display => match string_to_value(string) {
+			value @ Value::Block(_) => value,
+			_ => parse_keyword(string, &["list-item", "inline-block", "flow-root", "table", "inline-table",
+										 "table-row-group", "table-header-group", "table-footer-group",
+										 "table-row", "table-cell", "table-column-group", "table-column",
+										 "table-caption", "flex", "inline-flex", "grid", "inline-grid",
+										 "ruby", "ruby-text", "contents"]),
+		},
+		Property::ListStyleType => parse_keyword(string, &["disc", "circle", "square", "decimal",
+														   "lower-alpha", "lower-latin", "upper-alpha",
+														   "upper-latin", "lower-roman", "upper-roman", "none"]),
+		Property::ListStylePosition => parse_keyword(string, &["inside", "outside"]),
+		_ => string_to_value(string),
+	}
+}
+
+/// The keyword 'string' is, if it is one of 'keywords'
+fn parse_keyword(string: &str, keywords: &[&str]) -> Value {
+	let keyword = string.trim().to_ascii_lowercase();
+
+	if keywords.contains(&&keyword[..]) {
+		Value::Keyword(keyword)
+	} else {
+		Value::Missing
+	}
+}
+
+/// 'value' unless it is missing, in which case one of 'keywords'
+fn or_keyword(value: Value, string: &str, keywords: &[&str]) -> Value {
+	match value {
+		Value::Missing => parse_keyword(string, keywords),
+		value => value,
+	}
+}
+
+/// A length or 'thin', 'medium' or 'thick'
+/// www.w3.org/TR/css-backgrounds-3/#border-width
+fn parse_border_width(string: &str) -> Value {
+	match parse_keyword(string, &["thin", "medium", "thick"]) {
+		Value::Keyword(ref k) if k == "thin" => Value::Size(1.0, Unit::Px),
+		Value::Keyword(ref k) if k == "medium" => Value::Size(3.0, Unit::Px),
+		Value::Keyword(_) => Value::Size(5.0, Unit::Px),
+		_ => parse_length(string, false, false),
+	}
+}
+
+/// A number from 1 to 1000, 'normal' and 'bold' being 400 and 700, or
+/// 'bolder' or 'lighter'
+fn parse_font_weight(string: &str) -> Value {
+	match string_to_value(string) {
+		Value::Number(n) if n >= 1.0 && n <= 1000.0 => Value::Number(n),
+		_ => match parse_keyword(string, &["normal", "bold", "bolder", "lighter"]) {
+			Value::Keyword(ref k) if k == "normal" => Value::Number(400.0),
+			Value::Keyword(ref k) if k == "bold" => Value::Number(700.0),
+			value => value,
+		},
+	}
+}
+
+/// A comma separated list of family names, each a string or a sequence of
+/// identifiers
+/// www.w3.org/TR/css-fonts-4/#font-family-prop
+fn parse_font_family(string: &str) -> Value {
+	let values = CssParser::new(string.to_string()).parse_component_values();
+	let mut families = Vec::new();
+
+	for family in values.split(|v| *v == ComponentValue::Token(Token::Comma)) {
+		let parts: Vec<&ComponentValue> = family.iter()
+			.filter(|v| **v != ComponentValue::Token(Token::Whitespace))
+			.collect();
+
+		let name = match (parts.len(), parts.first()) {
+			(1, Some(&&ComponentValue::Token(Token::QuotedString(ref s)))) => s.clone(),
+			_ => {
+				let idents: Vec<String> = parts.iter().filter_map(|p| match **p {
+					ComponentValue::Token(Token::Ident(ref i)) => Some(i.clone()),
+					_ => None,
+				}).collect();
+
+				if idents.is_empty() || idents.len() != parts.len() {
+					return Value::Missing;
+				}
+				idents.join(" ")
+			}
+		};
+		families.push(name);
+	}
+
+	Value::FontFamily(families)
+}
+
+/// 'none' or 'url(...)'
+fn parse_image(string: &str) -> Value {
+	let values = CssParser::new(string.to_string()).parse_component_values();
+	let values: Vec<&ComponentValue> = values.iter()
+		.filter(|v| **v != ComponentValue::Token(Token::Whitespace))
+		.collect();
+
+	match (values.len(), values.first()) {
+		(1, Some(&&ComponentValue::Token(Token::QuotedString(_)))) => Value::Missing,
+		(1, Some(v)) => match parser::url_value(v) {
+			Some(url) => Value::Url(url.clone()),
+			None => parse_keyword(string, &["none"]),
+		},
 		_ => Value::Missing,
 	}
 }
 
-fn parse_size_units(parse: &mut text_parser::TextParser) -> Value {
-	let num = parse.consume_while(|c| match c {
-		'0'...'9' => true,
-		_ => false,
-	});
+/// The horizontal and vertical repeat style. 'repeat-x' and 'repeat-y'
+/// are written out as both
+fn parse_background_repeat(string: &str) -> Value {
+	let styles = ["repeat", "space", "round", "no-repeat"];
+	let items: Vec<Value> = shorthand::split_components(string).iter()
+		.map(|item| parse_keyword(item, &["repeat-x", "repeat-y", "repeat", "space", "round", "no-repeat"]))
+		.collect();
+	let keyword = |k: &str| Value::Keyword(k.to_string());
+
+	let (x, y) = match (items.len(), items.first()) {
+		(1, Some(&Value::Keyword(ref k))) if k == "repeat-x" => (keyword("repeat"), keyword("no-repeat")),
+		(1, Some(&Value::Keyword(ref k))) if k == "repeat-y" => (keyword("no-repeat"), keyword("repeat")),
+		(1, Some(&Value::Keyword(ref k))) => (keyword(k), keyword(k)),
+		(2, Some(&Value::Keyword(ref x))) => match items[1] {
+			Value::Keyword(ref y) if styles.contains(&&x[..]) && styles.contains(&&y[..]) => (keyword(x), keyword(y)),
+			_ => return Value::Missing,
+		},
+		_ => return Value::Missing,
+	};
 
-	let cur_char = parse.peek_char();
-	let next_char = parse.peek_next_char();
+	Value::List(vec![x, y])
+}
+
+/// One to four keywords and lengths, as written. Whether the keywords go
+/// together is not checked
+/// www.w3.org/TR/css-backgrounds-3/#background-position
+fn parse_background_position(string: &str) -> Value {
+	let items = shorthand::split_components(string);
+	if items.is_empty() || items.len() > 4 {
+		return Value::Missing;
+	}
 
-	let are_units = || cur_char.is_some() && 
-						next_char.is_some();
+	let mut values = Vec::new();
+	for item in items.iter() {
+		let value = or_keyword(parse_length(item, true, true), item, &["left", "center", "right", "top", "bottom"]);
+		if value == Value::Missing {
+			return Value::Missing;
+		}
+		values.push(value);
+	}
 
-	if are_units() {
-		let is_pixel = || {cur_char.unwrap() == 'p' && 
-						   next_char.unwrap() == 'x'};
-		let is_em = || {cur_char.unwrap() == 'e' && 
-						next_char.unwrap() == 'm'};
+	Value::List(values)
+}
 
-		let unit = if is_pixel() {
-			Unit::Px
-		} else if is_em() {
-			Unit::Em
-		} else {
-			Unit::Px
+/// 'cover', 'contain', or a width and an optional height, each a length
+/// or 'auto'
+fn parse_background_size(string: &str) -> Value {
+	let items = shorthand::split_components(string);
+	if items.len() == 1 {
+		match parse_keyword(string, &["cover", "contain"]) {
+			Value::Missing => {}
+			keyword => return keyword,
+		}
+	}
+	if items.is_empty() || items.len() > 2 {
+		return Value::Missing;
+	}
+
+	let mut values = Vec::new();
+	for item in items.iter() {
+		let value = or_keyword(parse_length(item, false, true), item, &["auto"]);
+		if value == Value::Missing {
+			return Value::Missing;
+		}
+		values.push(value);
+	}
+
+	Value::List(values)
+}
+
+/// Parse a length, or a percentage if 'percentage'. A unitless zero is a
+/// length of 0px. Math expressions out of range are not rejected, they are
+/// clamped when the value is computed; see Property::non_negative
+fn parse_length(string: &str, negative: bool, percentage: bool) -> Value {
+	match string_to_value(string) {
+		Value::Number(n) if n == 0.0 => Value::Size(0.0, Unit::Px),
+		Value::Size(n, _) | Value::Percentage(n) if n < 0.0 && !negative => Value::Missing,
+		Value::Size(n, unit) => Value::Size(n, unit),
+		Value::Percentage(n) if percentage => Value::Percentage(n),
+		Value::Calc(c) => match c.calc_type() {
+			Some(CalcType::Length) => Value::Calc(c),
+			Some(CalcType::Percentage) | Some(CalcType::LengthPercentage) if percentage => Value::Calc(c),
+			_ => Value::Missing,
+		},
+		_ => Value::Missing,
+	}
+}
+
+/// Parse a <color>, including 'currentColor'
+fn parse_color(string: &str) -> Value {
+	if string.trim().eq_ignore_ascii_case("currentcolor") {
+		return Value::CurrentColor;
+	}
+
+	match color::parse_color(string) {
+		Some(c) => Value::ColorValue(c),
+		None => Value::Missing,
+	}
+}
+
+/// Parse 'none', 'normal' or a list of strings, attr(), counter(),
+/// counters() and quotes
+fn parse_content(string: &str) -> Value {
+	let mut parse = text_parser::TextParser::new(string.trim().to_string());
+	let mut items = Vec::new();
+
+	loop {
+		parse.consume_whitespace();
+
+		let item = match parse.peek_char() {
+			None => break,
+			Some('"') | Some('\'') => selector::consume_string(&mut parse).map(ContentItem::Text),
+			Some(_) => {
+				let name = match selector::consume_ident(&mut parse) {
+					Some(n) => n.to_ascii_lowercase(),
+					None => return Value::Missing,
+				};
+
+				if parse.consume_if_char_matches('(') {
+					parse_content_function(&name[..], &mut parse)
+				} else {
+					match &name[..] {
+						"none" | "normal" if items.is_empty() && parse.end_of_string() => {
+							return Value::Content(Vec::new());
+						}
+						"open-quote" => Some(ContentItem::OpenQuote),
+						"close-quote" => Some(ContentItem::CloseQuote),
+						"no-open-quote" => Some(ContentItem::NoOpenQuote),
+						"no-close-quote" => Some(ContentItem::NoCloseQuote),
+						_ => None,
+					}
+				}
+			}
 		};
 
-		match num.parse::<u32>().ok() {
-			Some(n) => Value::Size(n, unit),
-			None => Value::Missing
+		match item {
+			Some(i) => items.push(i),
+			None => return Value::Missing,
+		}
+	}
+
+	if items.is_empty() {
+		Value::Missing
+	} else {
+		Value::Content(items)
+	}
+}
+
+/// Parse the arguments of attr(), counter() or counters() up to and
+/// including the closing ')'
+fn parse_content_function(name: &str, parse: &mut text_parser::TextParser) -> Option<ContentItem> {
+	parse.consume_whitespace();
+	let ident = match selector::consume_ident(parse) {
+		Some(i) => i,
+		None => return None,
+	};
+	parse.consume_whitespace();
+
+	let separator = if name == "counters" {
+		if !parse.consume_if_char_matches(',') {
+			return None;
+		}
+		parse.consume_whitespace();
+
+		match parse.peek_char() {
+			Some('"') | Some('\'') => {}
+			_ => return None,
 		}
-		
+		let separator = selector::consume_string(parse);
+		parse.consume_whitespace();
+		separator
+	} else {
+		None
+	};
+
+	let style = if name != "attr" && parse.consume_if_char_matches(',') {
+		parse.consume_whitespace();
+		let style = selector::consume_ident(parse).and_then(|s| CounterStyle::from_name(&s.to_ascii_lowercase()[..]));
+		parse.consume_whitespace();
+		style
 	} else {
+		Some(CounterStyle::Decimal)
+	};
+
+	if !parse.consume_if_char_matches(')') {
+		return None;
+	}
+
+	match (name, style, separator) {
+		("attr", _, _) => Some(ContentItem::Attr(ident)),
+		("counter", Some(style), _) => Some(ContentItem::Counter(ident, style)),
+		("counters", Some(style), Some(separator)) => Some(ContentItem::Counters(ident, separator, style)),
+		_ => None,
+	}
+}
+
+/// Parse 'none' or a list of counter names, each optionally followed by an
+/// integer. Names without one get 'default'
+fn parse_counters(string: &str, default: i32) -> Value {
+	let mut parse = text_parser::TextParser::new(string.trim().to_string());
+	let mut counters = Vec::new();
+
+	loop {
+		parse.consume_whitespace();
+		if parse.end_of_string() {
+			break;
+		}
+
+		let name = match selector::consume_ident(&mut parse) {
+			Some(n) => n,
+			None => return Value::Missing,
+		};
+		parse.consume_whitespace();
+
+		if name == "none" {
+			return if counters.is_empty() && parse.end_of_string() {
+				Value::Counters(counters)
+			} else {
+				Value::Missing
+			};
+		}
+
+		let signed = match (parse.peek_char(), parse.peek_next_char()) {
+			(Some('-'), Some(c)) | (Some('+'), Some(c)) => c.is_digit(10),
+			(Some(c), _) => c.is_digit(10),
+			_ => false,
+		};
+
+		let value = if signed {
+			let sign = parse.consume_while(|c| c == '-' || c == '+');
+			let digits = parse.consume_while(|c| c.is_digit(10));
+			match digits.parse::<i32>().ok() {
+				Some(n) if sign == "-" => -n,
+				Some(n) => n,
+				None => return Value::Missing,
+			}
+		} else {
+			default
+		};
+
+		counters.push((name, value));
+	}
+
+	if counters.is_empty() {
 		Value::Missing
+	} else {
+		Value::Counters(counters)
 	}
 }
 
-pub fn box_value_from_declaration(decl: &Vec<Declaration>, prop: Property) -> u32 {
-	let val = decl.iter().find(|x| x.property_name == prop);
-	match val {
-		Some(v) => { 
-			match v.property_value {
-				Value::Size(num, _) => num,
-				_ => 0,
+fn parse_alpha(parse: &mut text_parser::TextParser) -> Value {
+	let val = parse.consume_while(|c| match c {
+			  	'a'...'z' | 'A'...'Z' | '-' => true,
+				_ => false
+			  });
+
+	match val.as_slice() {
+		"block" => Value::Block(BlockType::Block),
+		"inline" => Value::Block(BlockType::Inline),
+		"none" => Value::Block(BlockType::None),
+		"normal" => Value::WhiteSpace(WhiteSpaceType::Normal),
+		"pre" => Value::WhiteSpace(WhiteSpaceType::Pre),
+		"nowrap" => Value::WhiteSpace(WhiteSpaceType::Nowrap),
+		"pre-wrap" => Value::WhiteSpace(WhiteSpaceType::PreWrap),
+		"pre-line" => Value::WhiteSpace(WhiteSpaceType::PreLine),
+		"break-spaces" => Value::WhiteSpace(WhiteSpaceType::BreakSpaces),
+		_ => Value::Missing,
+	}
+}
+
+/// Parse a number, a percentage, a length or a math expression
+fn parse_numeric(string: &str) -> Value {
+	let values = CssParser::new(string.to_string()).parse_component_values();
+
+	match (values.len(), values.first()) {
+		(1, Some(&ComponentValue::Token(Token::Number(n)))) => Value::Number(n.value as f32),
+		(1, Some(&ComponentValue::Token(Token::Percentage(n)))) => Value::Percentage(n.value as f32),
+		(1, Some(&ComponentValue::Token(Token::Dimension(n, ref unit)))) => {
+			match Unit::from_name(&unit[..]) {
+				Some(unit) => Value::Size(n.value as f32, unit),
+				None => Value::Missing,
 			}
 		}
-		None => 0,
+		(1, Some(value)) => match calc::parse_math_function(value) {
+			Some(c) => Value::Calc(c),
+			None => Value::Missing,
+		},
+		_ => Value::Missing,
+	}
+}
+
+/// The length 'prop' is declared to be in px, resolved against 'context'.
+/// 0 if it is not declared or is not a length. Lengths of properties that
+/// cannot be negative are clamped to 0
+pub fn box_value_from_declaration(decl: &Vec<Declaration>, prop: Property, context: &LengthContext) -> f32 {
+	let val = decl.iter().find(|x| x.property_name == prop);
+	match val {
+		Some(v) => match v.property_value.to_px(context) {
+			Some(n) if n < 0.0 && prop.non_negative() => 0.0,
+			Some(n) => n,
+			None => 0.0,
+		},
+		None => 0.0,
 	}
 }
 
@@ -211,6 +2150,25 @@ fn test_string_to_property() {
 	assert!(invalid_prop.is_none());
 }
 
+#[test]
+fn test_property_initial_values() {
+	// every property but Custom is listed, in declaration order
+	assert_eq!(ALL_PROPERTIES.len(), Property::Custom as usize);
+	for (i, property) in ALL_PROPERTIES.iter().enumerate() {
+		assert_eq!(*property as usize, i);
+		assert!(property.initial_value() != Value::Missing, "{:?}", property);
+	}
+
+	// the initial value of a custom property is the guaranteed-invalid value
+	assert!(Property::Custom.initial_value() == Value::Missing);
+	assert!(Property::Custom.inherited());
+
+	assert!(Property::FontWeight.initial_value() == Value::Number(400.0));
+	assert!(Property::BorderTopColor.initial_value() == Value::CurrentColor);
+	assert!(Property::Color.inherited());
+	assert!(!Property::MarginTop.inherited());
+}
+
 #[test]
 fn test_string_to_value() {
 	let val = string_to_value("sdlfj");
@@ -220,15 +2178,30 @@ fn test_string_to_value() {
 #[test]
 fn test_value_parsing() {
 	let mut val = string_to_value("12px");
-	assert!(val == Value::Size(12, Unit::Px));
+	assert!(val == Value::Size(12.0, Unit::Px));
 
 	val = string_to_value("15em");
-	assert!(val == Value::Size(15, Unit::Em));
+	assert!(val == Value::Size(15.0, Unit::Em));
 
 	val = string_to_value("143cm");
-	assert!(val == Value::Size(143, Unit::Px));
+	assert!(val == Value::Size(143.0, Unit::Cm));
 
 	val = string_to_value("143");
+	assert!(val == Value::Number(143.0));
+
+	val = string_to_value("-1.5E1Q");
+	assert!(val == Value::Size(-15.0, Unit::Q));
+
+	val = string_to_value(".5vmin");
+	assert!(val == Value::Size(0.5, Unit::Vmin));
+
+	val = string_to_value("+50%");
+	assert!(val == Value::Percentage(50.0));
+
+	val = string_to_value("143furlongs");
+	assert!(val == Value::Missing);
+
+	val = string_to_value("1px 2px");
 	assert!(val == Value::Missing);
 
 	val = string_to_value("block");
@@ -236,4 +2209,297 @@ fn test_value_parsing() {
 
 	val = string_to_value("inline");
 	assert!(val == Value::Block(BlockType::Inline));
-}
\ No newline at end of file
+
+	val = string_to_value("none");
+	assert!(val == Value::Block(BlockType::None));
+
+	val = string_to_value("pre-line");
+	assert!(val == Value::WhiteSpace(WhiteSpaceType::PreLine));
+}
+
+#[test]
+fn test_content_parsing() {
+	let val = string_to_property_value(Property::Content, "\"Chapter \" counter(chapter, upper-roman) \": \" attr(title)");
+	assert!(val == Value::Content(vec![ContentItem::Text("Chapter ".to_string()),
+									   ContentItem::Counter("chapter".to_string(), CounterStyle::UpperRoman),
+									   ContentItem::Text(": ".to_string()),
+									   ContentItem::Attr("title".to_string())]));
+
+	let val = string_to_property_value(Property::Content, "counters(item, '.') open-quote");
+	assert!(val == Value::Content(vec![ContentItem::Counters("item".to_string(), ".".to_string(), CounterStyle::Decimal),
+									   ContentItem::OpenQuote]));
+
+	assert!(string_to_property_value(Property::Content, "none") == Value::Content(Vec::new()));
+	assert!(string_to_property_value(Property::Content, "\"a\" none") == Value::Missing);
+	assert!(string_to_property_value(Property::Content, "counter(a, nope)") == Value::Missing);
+	assert!(string_to_property_value(Property::Content, "counters(a)") == Value::Missing);
+	assert!(string_to_property_value(Property::Content, "\"open") == Value::Missing);
+}
+
+#[test]
+fn test_counter_parsing() {
+	let val = string_to_property_value(Property::CounterReset, "chapter section 4 item -2");
+	assert!(val == Value::Counters(vec![("chapter".to_string(), 0),
+										("section".to_string(), 4),
+										("item".to_string(), -2)]));
+
+	let val = string_to_property_value(Property::CounterIncrement, "chapter");
+	assert!(val == Value::Counters(vec![("chapter".to_string(), 1)]));
+
+	assert!(string_to_property_value(Property::CounterIncrement, "none") == Value::Counters(Vec::new()));
+	assert!(string_to_property_value(Property::CounterReset, "3") == Value::Missing);
+
+	assert_eq!(CounterStyle::LowerAlpha.format(28), "ab");
+	assert_eq!(CounterStyle::UpperRoman.format(1994), "MCMXCIV");
+	assert_eq!(CounterStyle::LowerRoman.format(0), "0");
+}
+
+#[test]
+fn test_length_parsing() {
+	assert!(string_to_property_value(Property::MarginTop, "0") == Value::Size(0.0, Unit::Px));
+	assert!(string_to_property_value(Property::MarginTop, "-2px") == Value::Size(-2.0, Unit::Px));
+	assert!(string_to_property_value(Property::MarginTop, "3") == Value::Missing);
+	assert!(string_to_property_value(Property::PaddingTop, "-2px") == Value::Missing);
+	assert!(string_to_property_value(Property::PaddingTop, "10%") == Value::Percentage(10.0));
+	assert!(string_to_property_value(Property::BorderTopWidth, "10%") == Value::Missing);
+	assert!(string_to_property_value(Property::BorderTopWidth, "thick") == Value::Size(5.0, Unit::Px));
+	assert!(string_to_property_value(Property::LineHeight, "1.5") == Value::Number(1.5));
+	assert!(string_to_property_value(Property::LineHeight, "-1.5") == Value::Missing);
+
+	let context = LengthContext {
+		font_size: 20.0,
+		root_font_size: 10.0,
+		viewport: Viewport { width: 1000.0, height: 500.0 },
+	};
+	let px = |text: &str| string_to_value(text).to_px(&context);
+
+	assert_eq!(px("1in"), Some(96.0));
+	assert_eq!(px("12pt"), Some(16.0));
+	assert_eq!(px("2.54cm"), Some(96.0));
+	assert_eq!(px("1.5em"), Some(30.0));
+	assert_eq!(px("2rem"), Some(20.0));
+	assert_eq!(px("1ex"), Some(10.0));
+	assert_eq!(px("10vw"), Some(100.0));
+	assert_eq!(px("10dvh"), Some(50.0));
+	assert_eq!(px("10vmax"), Some(100.0));
+	assert_eq!(px("0"), Some(0.0));
+	assert_eq!(px("50%"), None);
+	assert_eq!(px("calc(1in - 2em)"), Some(56.0));
+
+	let calc = string_to_property_value(Property::PaddingLeft, "calc(100% - 2em)");
+	assert_eq!(calc.resolve_length(&context, 100.0), Some(60.0));
+	assert!(string_to_property_value(Property::BorderTopWidth, "calc(100% - 2em)") == Value::Missing);
+	assert!(string_to_property_value(Property::MarginTop, "calc(2 * 3)") == Value::Missing);
+	assert!(string_to_property_value(Property::FontSize, "clamp(1rem, 2vw, 2rem)").to_px(&context) == Some(20.0));
+	assert!(string_to_property_value(Property::LineHeight, "max(1, 1.5)").to_number(&context) == Some(1.5));
+
+	// math can go out of range; the lengths are clamped instead
+	let declarations = vec![
+		Declaration { property_name: Property::PaddingTop, property_value: string_to_value("calc(-10px)"),
+					  important: false },
+		Declaration { property_name: Property::BorderLeftWidth,
+					  property_value: string_to_property_value(Property::BorderLeftWidth, "calc(1px - 1em)"),
+					  important: false },
+		Declaration { property_name: Property::MarginTop, property_value: string_to_value("calc(-10px)"),
+					  important: false },
+	];
+	assert_eq!(box_value_from_declaration(&declarations, Property::PaddingTop, &context), 0.0);
+	assert_eq!(box_value_from_declaration(&declarations, Property::BorderLeftWidth, &context), 0.0);
+	assert_eq!(box_value_from_declaration(&declarations, Property::MarginTop, &context), -10.0);
+}
+
+#[test]
+fn test_keyword_parsing() {
+	assert!(string_to_property_value(Property::BorderTopStyle, "Dashed") == Value::Keyword("dashed".to_string()));
+	assert!(string_to_property_value(Property::BorderTopStyle, "wavy") == Value::Missing);
+	assert!(string_to_property_value(Property::FontWeight, "bold") == Value::Number(700.0));
+	assert!(string_to_property_value(Property::FontWeight, "1001") == Value::Missing);
+	assert!(string_to_property_value(Property::FontSize, "larger") == Value::Keyword("larger".to_string()));
+	assert!(string_to_property_value(Property::FontSize, "bigger") == Value::Missing);
+	assert!(string_to_property_value(Property::LineHeight, "normal") == Value::Keyword("normal".to_string()));
+	assert!(string_to_property_value(Property::FontFamily, "\"Times New\", Open  Sans, serif") ==
+			Value::FontFamily(vec!["Times New".to_string(), "Open Sans".to_string(), "serif".to_string()]));
+	assert!(string_to_property_value(Property::FontFamily, "Open, 12px") == Value::Missing);
+	assert!(string_to_property_value(Property::BackgroundImage, "url(a.png)") == Value::Url("a.png".to_string()));
+	assert!(string_to_property_value(Property::BackgroundImage, "\"a.png\"") == Value::Missing);
+	assert!(string_to_property_value(Property::BackgroundRepeat, "repeat-y") ==
+			Value::List(vec![Value::Keyword("no-repeat".to_string()), Value::Keyword("repeat".to_string())]));
+	assert!(string_to_property_value(Property::BackgroundPosition, "right 10px top") ==
+			Value::List(vec![Value::Keyword("right".to_string()), Value::Size(10.0, Unit::Px),
+							 Value::Keyword("top".to_string())]));
+	assert!(string_to_property_value(Property::BackgroundSize, "cover") == Value::Keyword("cover".to_string()));
+	assert!(string_to_property_value(Property::Left, "auto") == Value::Keyword("auto".to_string()));
+	assert!(string_to_property_value(Property::FlexBasis, "content") == Value::Keyword("content".to_string()));
+	assert!(string_to_property_value(Property::Display, "inline") == Value::Block(BlockType::Inline));
+	assert!(string_to_property_value(Property::Display, "table-cell") == Value::Keyword("table-cell".to_string()));
+	assert!(string_to_property_value(Property::Display, "normal") == Value::Missing);
+	assert!(string_to_property_value(Property::ListStyleType, "upper-roman") == Value::Keyword("upper-roman".to_string()));
+	assert!(string_to_property_value(Property::ListStylePosition, "inside") == Value::Keyword("inside".to_string()));
+}
+
+#[test]
+fn test_color_parsing() {
+	assert!(string_to_property_value(Property::Color, "red") == Value::ColorValue(Color::rgb(255, 0, 0)));
+	assert!(string_to_property_value(Property::Color, "rgb(0 0 255 / 50%)") == Value::ColorValue(Color::rgba(0, 0, 255, 128)));
+	assert!(string_to_property_value(Property::Color, "currentColor") == Value::CurrentColor);
+	assert!(string_to_property_value(Property::Color, "12px") == Value::Missing);
+}
+
+#[test]
+fn test_matching_rules_order() {
+	let mut attributes = HashMap::new();
+	attributes.insert("id".to_string(), "main".to_string());
+	attributes.insert("class".to_string(), "note wide note".to_string());
+	let div = dom_tree::new_named_element(dom_tree::Namespace::Html, "div", attributes, None);
+	let context = MatchContext::root(&div);
+
+	let no_namespaces = HashMap::new();
+	let mut sheet = StyleSheet::new();
+	for (i, text) in ["DIV", "#main", ".wide, .note.wide", "*", "span, div", ".other"].iter().enumerate() {
+		let selectors = selector::parse_selector_list(text, &no_namespaces).unwrap();
+		sheet.add_rule(selectors, vec![Declaration {
+			property_name: Property::LineHeight,
+			property_value: Value::Size(i as f32, Unit::Px),
+			important: false,
+		}]);
+	}
+
+	let media = MediaEnvironment::default();
+	let order: Vec<usize> = sheet.matching_rules(&context, &media).iter().map(|m| m.rule.source_index).collect();
+	assert_eq!(order, vec![3, 0, 4, 2, 1]);
+
+	// equally specific rules are decided by source order
+	let matched = sheet.matching_rules(&context, &media);
+	assert!(matched[1].specificity == matched[2].specificity);
+	assert!(matched[3].specificity == Specificity(0, 2, 0));
+	assert!(sheet.matching_declarations(&context, &media).unwrap()[0].property_value == Value::Size(1.0, Unit::Px));
+}
+
+#[test]
+fn test_cascaded_declarations() {
+	let mut attributes = HashMap::new();
+	attributes.insert("id".to_string(), "main".to_string());
+	let div = dom_tree::new_named_element(dom_tree::Namespace::Html, "div", attributes, None);
+	let context = MatchContext::root(&div);
+	let media = MediaEnvironment::default();
+
+	let css = "@layer base, theme;
+			   #main { font-size: 1px; color: red !important }
+			   @layer theme { div { font-size: 2px !important; line-height: 3px } }
+			   @layer base { div { font-size: 4px !important; line-height: 5px; color: blue !important } }
+			   div { line-height: revert-layer; padding-top: inherit }";
+	let sheet = CssParser::new(css.to_string()).parse_css();
+
+	let declarations = sheet.cascaded_declarations(&context, &media).unwrap();
+	let value = |property: Property| {
+		declarations.iter().find(|d| d.property_name == property).map(|d| d.property_value.clone())
+	};
+
+	// important declarations in earlier layers win, normal ones in later layers
+	assert_eq!(value(Property::FontSize), Some(Value::Size(4.0, Unit::Px)));
+	assert_eq!(value(Property::Color), Some(Value::ColorValue(Color::rgb(0, 0, 255))));
+	assert_eq!(value(Property::LineHeight), Some(Value::Size(3.0, Unit::Px)));
+	assert_eq!(value(Property::PaddingTop), Some(Value::CssWide(CssWideKeyword::Inherit)));
+	assert_eq!(declarations.len(), 4);
+	assert!(declarations.iter().all(|d| d.important == (d.property_name == Property::FontSize ||
+														   d.property_name == Property::Color)));
+
+	let span = dom_tree::new_named_element(dom_tree::Namespace::Html, "span", HashMap::new(), None);
+	assert!(sheet.cascaded_declarations(&MatchContext::root(&span), &media).is_none());
+}
+
+#[test]
+fn test_stylesheet_list() {
+	let mut attributes = HashMap::new();
+	attributes.insert("id".to_string(), "main".to_string());
+	let div = dom_tree::new_named_element(dom_tree::Namespace::Html, "div", attributes, None);
+	let context = MatchContext::root(&div);
+	let media = MediaEnvironment::default();
+
+	let first = CssParser::new("@layer reset, app;
+								@layer app { div { font-size: 1px } }
+								#main { color: red }".to_string()).parse_css();
+	let second = CssParser::new("@layer theme { div { font-size: 2px } }
+								 @layer reset { div { font-size: 5px; line-height: 3px } }
+								 div { color: blue; padding-top: 4px }".to_string()).parse_css();
+
+	let sheets = StyleSheetList::new(&[&first, &second]);
+	let declarations = sheets.cascade(&context, &media, None).unwrap();
+	let value = |property: Property| {
+		declarations.iter().find(|d| d.property_name == property).map(|d| d.property_value.clone())
+	};
+
+	// 'reset' and 'app' come before 'theme', which the second sheet adds
+	assert_eq!(value(Property::FontSize), Some(Value::Size(2.0, Unit::Px)));
+	assert_eq!(value(Property::LineHeight), Some(Value::Size(3.0, Unit::Px)));
+	// rules from both sheets apply, the id selector winning over the type
+	assert_eq!(value(Property::Color), Some(Value::ColorValue(Color::rgb(255, 0, 0))));
+	assert_eq!(value(Property::PaddingTop), Some(Value::Size(4.0, Unit::Px)));
+	assert_eq!(sheets.sheets().len(), 2);
+}
+
+#[test]
+fn test_property_names() {
+	for property in ALL_PROPERTIES.iter() {
+		assert_eq!(string_to_property(property.name()), Some(*property));
+	}
+	assert_eq!(Property::Custom.name(), "");
+}
+
+#[test]
+fn test_stylesheet_editing() {
+	let div = dom_tree::new_named_element(dom_tree::Namespace::Html, "div", HashMap::new(), None);
+	let context = MatchContext::root(&div);
+	let media = MediaEnvironment::default();
+
+	let mut sheet = CssParser::new("@namespace svg url(http://www.w3.org/2000/svg);
+									div { color: red; margin: 1px }
+									@media print { p { } }".to_string()).parse_css();
+	let color = |sheet: &StyleSheet| sheet.cascaded_declarations(&context, &media).and_then(|d| {
+		d.into_iter().find(|d| d.property_name == Property::Color).map(|d| d.property_value)
+	});
+
+	assert!(sheet.rule_at(&[2, 0]).and_then(|r| r.declarations()).is_some());
+	assert!(sheet.rule_at(&[]).is_none());
+	assert_eq!(sheet.property_value(&[1], "COLOR"), Some("rgb(255, 0, 0)".to_string()));
+	assert_eq!(sheet.property_value(&[1], "margin"), Some("1px".to_string()));
+	assert_eq!(sheet.property_value(&[1], "padding"), None);
+
+	// rules go where they are allowed, and are matched once inserted
+	assert!(sheet.insert_rule(&[], 3, "div { color: blue }"));
+	assert!(sheet.insert_rule(&[2], 0, "svg|rect { }"));
+	assert!(!sheet.insert_rule(&[2], 0, "@import url(a.css);"));
+	assert!(!sheet.insert_rule(&[], 1, "@import url(a.css);"));
+	assert!(!sheet.insert_rule(&[], 0, "@namespace x url(http://www.w3.org/1999/xhtml);"));
+	assert!(!sheet.insert_rule(&[], 9, "p { }"));
+	assert!(!sheet.insert_rule(&[], 0, "p { } q { }"));
+	assert!(!sheet.insert_rule(&[1], 0, "p { }"));
+	assert_eq!(sheet.style_rules().len(), 4);
+	assert_eq!(color(&sheet), Some(Value::ColorValue(Color::rgb(0, 0, 255))));
+
+	assert!(sheet.delete_rule(&[], 0).is_none());
+	assert!(sheet.delete_rule(&[], 3).is_some());
+	assert!(sheet.delete_rule(&[], 3).is_none());
+	assert_eq!(color(&sheet), Some(Value::ColorValue(Color::rgb(255, 0, 0))));
+
+	assert!(sheet.set_selector_text(&[1], "p, div"));
+	assert!(!sheet.set_selector_text(&[1], "p,"));
+	assert!(!sheet.set_selector_text(&[2], "p"));
+
+	// declarations are replaced where they are, new ones go last
+	assert!(sheet.set_property(&[1], "margin-left", "2px", false));
+	assert!(sheet.set_property(&[1], "color", "green", true));
+	assert!(sheet.set_property(&[1], "--Gap", "1em", false));
+	assert!(!sheet.set_property(&[1], "colour", "green", false));
+	assert!(!sheet.set_property(&[1], "color", "12px", false));
+	assert!(!sheet.set_property(&[2], "color", "green", false));
+	assert_eq!(sheet.property_value(&[1], "margin"), Some("1px 1px 1px 2px".to_string()));
+	assert_eq!(sheet.property_value(&[1], "--Gap"), Some("1em".to_string()));
+	assert!(sheet.set_property(&[1], "margin", "0 auto", false));
+	assert_eq!(sheet.property_value(&[1], "margin"), Some("0px auto".to_string()));
+	assert!(sheet.remove_property(&[1], "margin"));
+	assert!(!sheet.remove_property(&[1], "margin"));
+	assert!(sheet.set_property(&[1], "--Gap", "", false));
+	assert_eq!(serializer::serialize_rule(&sheet.rules()[1], &sheet.namespaces()),
+			   Some("p, div { color: rgb(0, 128, 0) !important; }".to_string()));
+}
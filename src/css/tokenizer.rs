@@ -0,0 +1,594 @@
+/// Tokens of the css syntax
+/// www.w3.org/TR/css-syntax-3/#tokenization
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+	Ident(String),
+	/// 'name(', the arguments follow as separate tokens
+	Function(String),
+	AtKeyword(String),
+	/// '#name'; true if the name would also be a valid identifier, as ids
+	/// have to be
+	Hash(String, bool),
+	QuotedString(String),
+	/// A string with an unescaped newline in it
+	BadString,
+	/// An unquoted 'url(...)'. 'url("...")' is a Function instead
+	Url(String),
+	BadUrl,
+	Delim(char),
+	Number(Number),
+	Percentage(Number),
+	Dimension(Number, String),
+	Whitespace,
+	/// '<!--'
+	CDO,
+	/// '-->'
+	CDC,
+	Colon,
+	Semicolon,
+	Comma,
+	OpenSquare,
+	CloseSquare,
+	OpenParen,
+	CloseParen,
+	OpenCurly,
+	CloseCurly,
+}
+
+/// A numeric value. 'integer' is false if it was written with a fraction
+/// or an exponent
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Number {
+	pub value: f64,
+	pub integer: bool,
+}
+
+/// A token and the range of source chars, [start, end), it was read from
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceToken {
+	pub token: Token,
+	pub start: usize,
+	pub end: usize,
+}
+
+/// Splits css text into tokens. Comments are dropped; everything else,
+/// including whitespace, becomes a token
+pub struct Tokenizer {
+	input: Vec<char>,
+	pos: usize,
+}
+
+impl Tokenizer {
+	/// Newlines are normalized to '\n' and NUL is replaced first
+	/// www.w3.org/TR/css-syntax-3/#input-preprocessing
+	pub fn new(input: &str) -> Tokenizer {
+		let mut chars = Vec::new();
+		let mut previous_cr = false;
+
+		for c in input.chars() {
+			match c {
+				'\r' => chars.push('\n'),
+				'\n' if previous_cr => {}
+				'\x0C' => chars.push('\n'),
+				'\0' => chars.push('\u{FFFD}'),
+				c => chars.push(c),
+			}
+			previous_cr = c == '\r';
+		}
+
+		Tokenizer {
+			input: chars,
+			pos: 0,
+		}
+	}
+
+	/// The preprocessed input that token ranges refer to
+	pub fn source(&self) -> &[char] {
+		&self.input[..]
+	}
+
+	fn peek_at(&self, offset: usize) -> Option<char> {
+		self.input.get(self.pos + offset).map(|&c| c)
+	}
+
+	fn consume(&mut self) -> Option<char> {
+		let c = self.peek_at(0);
+		if c.is_some() {
+			self.pos += 1;
+		}
+		c
+	}
+
+	/// Return the next token, None at the end of the input
+	/// www.w3.org/TR/css-syntax-3/#consume-token
+	pub fn next_token(&mut self) -> Option<SourceToken> {
+		self.consume_comments();
+
+		let start = self.pos;
+		let c = match self.consume() {
+			Some(c) => c,
+			None => return None,
+		};
+
+		let token = match c {
+			c if is_whitespace(c) => {
+				while self.peek_at(0).map_or(false, is_whitespace) {
+					self.pos += 1;
+				}
+				Token::Whitespace
+			}
+			'"' | '\'' => self.consume_string(c),
+			'#' => {
+				if self.peek_at(0).map_or(false, is_name) || self.starts_escape(0) {
+					let is_id = self.starts_ident(0);
+					Token::Hash(self.consume_name(), is_id)
+				} else {
+					Token::Delim('#')
+				}
+			}
+			'(' => Token::OpenParen,
+			')' => Token::CloseParen,
+			',' => Token::Comma,
+			':' => Token::Colon,
+			';' => Token::Semicolon,
+			'[' => Token::OpenSquare,
+			']' => Token::CloseSquare,
+			'{' => Token::OpenCurly,
+			'}' => Token::CloseCurly,
+			'+' | '.' if self.starts_number_at(self.pos - 1) => {
+				self.pos -= 1;
+				self.consume_numeric()
+			}
+			'-' if self.starts_number_at(self.pos - 1) => {
+				self.pos -= 1;
+				self.consume_numeric()
+			}
+			'-' if self.peek_at(0) == Some('-') && self.peek_at(1) == Some('>') => {
+				self.pos += 2;
+				Token::CDC
+			}
+			'-' if self.starts_ident_at(self.pos - 1) => {
+				self.pos -= 1;
+				self.consume_ident_like()
+			}
+			'<' if self.peek_at(0) == Some('!') && self.peek_at(1) == Some('-') && self.peek_at(2) == Some('-') => {
+				self.pos += 3;
+				Token::CDO
+			}
+			'@' if self.starts_ident(0) => Token::AtKeyword(self.consume_name()),
+			'\\' if self.starts_escape_at(self.pos - 1) => {
+				self.pos -= 1;
+				self.consume_ident_like()
+			}
+			c if c.is_digit(10) => {
+				self.pos -= 1;
+				self.consume_numeric()
+			}
+			c if is_name_start(c) => {
+				self.pos -= 1;
+				self.consume_ident_like()
+			}
+			c => Token::Delim(c),
+		};
+
+		Some(SourceToken {
+			token: token,
+			start: start,
+			end: self.pos,
+		})
+	}
+
+	/// www.w3.org/TR/css-syntax-3/#consume-comment
+	fn consume_comments(&mut self) {
+		while self.peek_at(0) == Some('/') && self.peek_at(1) == Some('*') {
+			self.pos += 2;
+			loop {
+				match self.consume() {
+					None => return,
+					Some('*') if self.peek_at(0) == Some('/') => {
+						self.pos += 1;
+						break;
+					}
+					_ => {}
+				}
+			}
+		}
+	}
+
+	fn starts_escape_at(&self, i: usize) -> bool {
+		self.input.get(i) == Some(&'\\') && self.input.get(i + 1).map_or(false, |&c| c != '\n')
+	}
+
+	fn starts_escape(&self, offset: usize) -> bool {
+		self.starts_escape_at(self.pos + offset)
+	}
+
+	/// www.w3.org/TR/css-syntax-3/#would-start-an-identifier
+	fn starts_ident_at(&self, i: usize) -> bool {
+		match self.input.get(i) {
+			Some(&'-') => {
+				self.input.get(i + 1).map_or(false, |&c| is_name_start(c) || c == '-') ||
+				self.starts_escape_at(i + 1)
+			}
+			Some(&'\\') => self.starts_escape_at(i),
+			Some(&c) => is_name_start(c),
+			None => false,
+		}
+	}
+
+	fn starts_ident(&self, offset: usize) -> bool {
+		self.starts_ident_at(self.pos + offset)
+	}
+
+	/// www.w3.org/TR/css-syntax-3/#starts-with-a-number
+	fn starts_number_at(&self, i: usize) -> bool {
+		let digit = |j: usize| self.input.get(j).map_or(false, |c| c.is_digit(10));
+
+		match self.input.get(i) {
+			Some(&'+') | Some(&'-') => digit(i + 1) || (self.input.get(i + 1) == Some(&'.') && digit(i + 2)),
+			Some(&'.') => digit(i + 1),
+			Some(c) => c.is_digit(10),
+			None => false,
+		}
+	}
+
+	/// Consume what follows a '\'
+	/// www.w3.org/TR/css-syntax-3/#consume-escaped-code-point
+	fn consume_escape(&mut self) -> char {
+		let mut hex = String::new();
+
+		while hex.len() < 6 && self.peek_at(0).map_or(false, |c| c.is_digit(16)) {
+			hex.push(self.consume().unwrap());
+		}
+
+		if hex.is_empty() {
+			return self.consume().unwrap_or('\u{FFFD}');
+		}
+
+		if self.peek_at(0).map_or(false, is_whitespace) {
+			self.pos += 1;
+		}
+
+		let code = u32::from_str_radix(&hex[..], 16).unwrap_or(0);
+		match ::std::char::from_u32(code) {
+			Some(c) if code != 0 => c,
+			_ => '\u{FFFD}',
+		}
+	}
+
+	/// www.w3.org/TR/css-syntax-3/#consume-name
+	fn consume_name(&mut self) -> String {
+		let mut result = String::new();
+
+		loop {
+			match self.peek_at(0) {
+				Some(c) if is_name(c) => {
+					self.pos += 1;
+					result.push(c);
+				}
+				Some('\\') if self.starts_escape(0) => {
+					self.pos += 1;
+					result.push(self.consume_escape());
+				}
+				_ => return result,
+			}
+		}
+	}
+
+	/// www.w3.org/TR/css-syntax-3/#consume-string-token
+	fn consume_string(&mut self, quote: char) -> Token {
+		let mut result = String::new();
+
+		loop {
+			match self.peek_at(0) {
+				None => return Token::QuotedString(result),
+				Some(c) if c == quote => {
+					self.pos += 1;
+					return Token::QuotedString(result);
+				}
+				// the newline is left for the next token
+				Some('\n') => return Token::BadString,
+				Some('\\') => {
+					self.pos += 1;
+					match self.peek_at(0) {
+						None => {}
+						Some('\n') => self.pos += 1,
+						Some(_) => result.push(self.consume_escape()),
+					}
+				}
+				Some(c) => {
+					self.pos += 1;
+					result.push(c);
+				}
+			}
+		}
+	}
+
+	/// www.w3.org/TR/css-syntax-3/#consume-number
+	fn consume_number(&mut self) -> Number {
+		let mut repr = String::new();
+		let mut integer = true;
+
+		if self.peek_at(0) == Some('+') || self.peek_at(0) == Some('-') {
+			repr.push(self.consume().unwrap());
+		}
+		self.consume_digits(&mut repr);
+
+		if self.peek_at(0) == Some('.') && self.peek_at(1).map_or(false, |c| c.is_digit(10)) {
+			repr.push(self.consume().unwrap());
+			self.consume_digits(&mut repr);
+			integer = false;
+		}
+
+		if self.peek_at(0) == Some('e') || self.peek_at(0) == Some('E') {
+			let digit_at = |t: &Tokenizer, i: usize| t.peek_at(i).map_or(false, |c| c.is_digit(10));
+			let signed = (self.peek_at(1) == Some('+') || self.peek_at(1) == Some('-')) && digit_at(self, 2);
+
+			if signed || digit_at(self, 1) {
+				self.pos += 1;
+				repr.push('e');
+				if signed {
+					repr.push(self.consume().unwrap());
+				}
+				self.consume_digits(&mut repr);
+				integer = false;
+			}
+		}
+
+		Number {
+			value: repr.parse::<f64>().unwrap_or(0.0),
+			integer: integer,
+		}
+	}
+
+	fn consume_digits(&mut self, repr: &mut String) {
+		while self.peek_at(0).map_or(false, |c| c.is_digit(10)) {
+			repr.push(self.consume().unwrap());
+		}
+	}
+
+	/// www.w3.org/TR/css-syntax-3/#consume-numeric-token
+	fn consume_numeric(&mut self) -> Token {
+		let number = self.consume_number();
+
+		if self.starts_ident(0) {
+			Token::Dimension(number, self.consume_name())
+		} else if self.peek_at(0) == Some('%') {
+			self.pos += 1;
+			Token::Percentage(number)
+		} else {
+			Token::Number(number)
+		}
+	}
+
+	/// www.w3.org/TR/css-syntax-3/#consume-ident-like-token
+	fn consume_ident_like(&mut self) -> Token {
+		let name = self.consume_name();
+
+		if self.peek_at(0) != Some('(') {
+			return Token::Ident(name);
+		}
+		self.pos += 1;
+
+		if !name.eq_ignore_ascii_case("url") {
+			return Token::Function(name);
+		}
+
+		// 'url(' followed by a quote is a function taking a string
+		let mut ahead = 0;
+		while self.peek_at(ahead).map_or(false, is_whitespace) {
+			ahead += 1;
+		}
+		match self.peek_at(ahead) {
+			Some('"') | Some('\'') => Token::Function(name),
+			_ => {
+				self.pos += ahead;
+				self.consume_url()
+			}
+		}
+	}
+
+	/// www.w3.org/TR/css-syntax-3/#consume-url-token
+	fn consume_url(&mut self) -> Token {
+		let mut result = String::new();
+
+		loop {
+			match self.consume() {
+				None | Some(')') => return Token::Url(result),
+				Some(c) if is_whitespace(c) => {
+					while self.peek_at(0).map_or(false, is_whitespace) {
+						self.pos += 1;
+					}
+					match self.peek_at(0) {
+						None => return Token::Url(result),
+						Some(')') => {
+							self.pos += 1;
+							return Token::Url(result);
+						}
+						_ => {
+							self.consume_bad_url_remnants();
+							return Token::BadUrl;
+						}
+					}
+				}
+				Some('"') | Some('\'') | Some('(') => {
+					self.consume_bad_url_remnants();
+					return Token::BadUrl;
+				}
+				Some(c) if is_non_printable(c) => {
+					self.consume_bad_url_remnants();
+					return Token::BadUrl;
+				}
+				Some('\\') => {
+					if self.peek_at(0).map_or(true, |c| c == '\n') {
+						self.consume_bad_url_remnants();
+						return Token::BadUrl;
+					}
+					result.push(self.consume_escape());
+				}
+				Some(c) => result.push(c),
+			}
+		}
+	}
+
+	/// www.w3.org/TR/css-syntax-3/#consume-remnants-of-bad-url
+	fn consume_bad_url_remnants(&mut self) {
+		loop {
+			match self.consume() {
+				None | Some(')') => return,
+				Some('\\') if self.peek_at(0).map_or(false, |c| c != '\n') => {
+					self.consume_escape();
+				}
+				_ => {}
+			}
+		}
+	}
+}
+
+impl Iterator for Tokenizer {
+	type Item = SourceToken;
+
+	fn next(&mut self) -> Option<SourceToken> {
+		self.next_token()
+	}
+}
+
+fn is_whitespace(c: char) -> bool {
+	c == ' ' || c == '\t' || c == '\n'
+}
+
+fn is_name_start(c: char) -> bool {
+	c.is_alphabetic() || c == '_' || c as u32 >= 0x80
+}
+
+fn is_name(c: char) -> bool {
+	is_name_start(c) || c.is_digit(10) || c == '-'
+}
+
+fn is_non_printable(c: char) -> bool {
+	match c as u32 {
+		0x0...0x8 | 0xB | 0xE...0x1F | 0x7F => true,
+		_ => false,
+	}
+}
+
+/// Tokenize all of 'input'. Return the preprocessed source with the tokens
+pub fn tokenize(input: &str) -> (Vec<char>, Vec<SourceToken>) {
+	let mut tokenizer = Tokenizer::new(input);
+	let mut tokens = Vec::new();
+
+	while let Some(t) = tokenizer.next_token() {
+		tokens.push(t);
+	}
+
+	(tokenizer.input, tokens)
+}
+
+#[cfg(test)]
+fn tokens(input: &str) -> Vec<Token> {
+	tokenize(input).1.into_iter().map(|t| t.token).collect()
+}
+
+#[cfg(test)]
+fn number(value: f64, integer: bool) -> Number {
+	Number { value: value, integer: integer }
+}
+
+#[test]
+fn test_tokenize_rule() {
+	assert_eq!(tokens("h1.a>p{color:red;}"), vec![
+		Token::Ident("h1".to_string()),
+		Token::Delim('.'),
+		Token::Ident("a".to_string()),
+		Token::Delim('>'),
+		Token::Ident("p".to_string()),
+		Token::OpenCurly,
+		Token::Ident("color".to_string()),
+		Token::Colon,
+		Token::Ident("red".to_string()),
+		Token::Semicolon,
+		Token::CloseCurly,
+	]);
+
+	assert_eq!(tokens("@media /* c */ #main #1a"), vec![
+		Token::AtKeyword("media".to_string()),
+		Token::Whitespace,
+		Token::Whitespace,
+		Token::Hash("main".to_string(), true),
+		Token::Whitespace,
+		Token::Hash("1a".to_string(), false),
+	]);
+
+	assert_eq!(tokens("<!-- --> -x --y"), vec![
+		Token::CDO, Token::Whitespace, Token::CDC, Token::Whitespace,
+		Token::Ident("-x".to_string()), Token::Whitespace, Token::Ident("--y".to_string()),
+	]);
+}
+
+#[test]
+fn test_tokenize_numbers() {
+	assert_eq!(tokens("12px -1.5em +.5 50% 1e3 2E-1x 3e"), vec![
+		Token::Dimension(number(12.0, true), "px".to_string()),
+		Token::Whitespace,
+		Token::Dimension(number(-1.5, false), "em".to_string()),
+		Token::Whitespace,
+		Token::Number(number(0.5, false)),
+		Token::Whitespace,
+		Token::Percentage(number(50.0, true)),
+		Token::Whitespace,
+		Token::Number(number(1000.0, false)),
+		Token::Whitespace,
+		Token::Dimension(number(0.2, false), "x".to_string()),
+		Token::Whitespace,
+		Token::Dimension(number(3.0, true), "e".to_string()),
+	]);
+
+	assert_eq!(tokens("- +a"), vec![Token::Delim('-'), Token::Whitespace, Token::Delim('+'),
+									 Token::Ident("a".to_string())]);
+}
+
+#[test]
+fn test_tokenize_strings_and_escapes() {
+	assert_eq!(tokens("'a;b' \"c\\\"d\" \"\\41 x\""), vec![
+		Token::QuotedString("a;b".to_string()),
+		Token::Whitespace,
+		Token::QuotedString("c\"d".to_string()),
+		Token::Whitespace,
+		Token::QuotedString("Ax".to_string()),
+	]);
+
+	assert_eq!(tokens("\"bad\nx"), vec![Token::BadString, Token::Whitespace, Token::Ident("x".to_string())]);
+	assert_eq!(tokens("a\\:b \\31 0"), vec![Token::Ident("a:b".to_string()), Token::Whitespace,
+											Token::Ident("10".to_string())]);
+	assert_eq!(tokens("\"open"), vec![Token::QuotedString("open".to_string())]);
+}
+
+#[test]
+fn test_tokenize_urls_and_functions() {
+	assert_eq!(tokens("url( a.png ) URL(\"b.png\") url(a b) rgb(1,2)"), vec![
+		Token::Url("a.png".to_string()),
+		Token::Whitespace,
+		Token::Function("URL".to_string()),
+		Token::QuotedString("b.png".to_string()),
+		Token::CloseParen,
+		Token::Whitespace,
+		Token::BadUrl,
+		Token::Whitespace,
+		Token::Function("rgb".to_string()),
+		Token::Number(number(1.0, true)),
+		Token::Comma,
+		Token::Number(number(2.0, true)),
+		Token::CloseParen,
+	]);
+}
+
+#[test]
+fn test_token_ranges() {
+	let (source, tokens) = tokenize("a\r\n/* x */{ }");
+	let text: String = source.iter().cloned().collect();
+
+	assert_eq!(text, "a\n/* x */{ }");
+	assert_eq!(tokens.len(), 5);
+	assert_eq!((tokens[1].start, tokens[1].end), (1, 2));
+	assert_eq!((tokens[2].start, tokens[2].end), (9, 10));
+}
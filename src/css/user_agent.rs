@@ -0,0 +1,48 @@
+use std::rc::Rc;
+
+use super::parser::CssParser;
+use super::stylesheet::StyleSheet;
+use super::cascade::Origin;
+
+/// The text of the default user agent stylesheet
+pub const DEFAULT_CSS: &'static str = include_str!("user_agent.css");
+
+thread_local!(static DEFAULT_STYLESHEET: Rc<StyleSheet> = Rc::new(stylesheet_from_css(DEFAULT_CSS)));
+
+/// The default user agent stylesheet: the display types, margins, fonts
+/// and list styles the HTML standard gives elements before any author
+/// rule applies. It has the user agent origin, the lowest in the cascade.
+/// It is parsed once per thread and shared by every style tree built there
+/// html.spec.whatwg.org/multipage/rendering.html
+pub fn default_stylesheet() -> Rc<StyleSheet> {
+	DEFAULT_STYLESHEET.with(|sheet| sheet.clone())
+}
+
+/// A stylesheet with the user agent origin parsed from 'css', for
+/// embedders that replace the default one
+pub fn stylesheet_from_css(css: &str) -> StyleSheet {
+	let mut sheet = CssParser::new(css.to_string()).parse_css();
+	sheet.set_origin(Origin::UserAgent);
+	sheet
+}
+
+#[cfg(test)]
+mod test_user_agent {
+	use std::rc::Rc;
+
+	use super::{default_stylesheet, DEFAULT_CSS};
+	use super::super::parser::CssParser;
+	use super::super::cascade::Origin;
+	use super::super::stylesheet::Value;
+
+	#[test]
+	fn user_agent_parses_cleanly() {
+		let (sheet, warnings) = CssParser::new(DEFAULT_CSS.to_string()).parse_css_with_warnings();
+		assert!(warnings.is_empty());
+		assert!(sheet.style_rules().iter().all(|r| !r.declarations.is_empty()));
+		assert!(sheet.style_rules().iter().all(|r| r.declarations.iter().all(|d| d.property_value != Value::Missing)));
+
+		assert_eq!(default_stylesheet().origin(), Origin::UserAgent);
+		assert!(Rc::ptr_eq(&default_stylesheet(), &default_stylesheet()));
+	}
+}
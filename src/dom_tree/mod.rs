@@ -1,39 +1,182 @@
 extern crate std;
 
 use std::fmt;
+use std::collections::HashMap;
+#[cfg(feature = "serde")]
+use std::collections::BTreeMap;
+#[cfg(feature = "serde")]
+use serde::{Serialize, Serializer};
+
+pub use self::mutation::{NodePath, MutationCallback, MutationKind, MutationRecord, ObserverOptions};
+
+mod mutation;
 
 #[derive(Debug, Copy, PartialEq, Hash, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum ElementType {
 	ClassE,
 	Head,
 	Title,
 	Body,
+	Other,
+}
+
+impl ElementType {
+	/// Map an html tag name to its ElementType. Tags without a dedicated
+	/// type become ElementType::Other
+	pub fn from_tag_name(name: &str) -> ElementType {
+		match name {
+			"html" => ElementType::ClassE,
+			"head" => ElementType::Head,
+			"title" => ElementType::Title,
+			"body" => ElementType::Body,
+			_ => ElementType::Other,
+		}
+	}
+
+	/// The html tag name of the type. ElementType::Other has no fixed name
+	pub fn tag_name(&self) -> &'static str {
+		match *self {
+			ElementType::ClassE => "html",
+			ElementType::Head => "head",
+			ElementType::Title => "title",
+			ElementType::Body => "body",
+			ElementType::Other => "",
+		}
+	}
+}
+
+/// The namespace an element belongs to. Everything is html except the
+/// contents of inline <svg> and <math> roots
+/// html.spec.whatwg.org/multipage/infrastructure.html#namespaces
+#[derive(Debug, Copy, PartialEq, Hash, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Namespace {
+	Html,
+	Svg,
+	MathMl,
+}
+
+impl Namespace {
+	pub fn url(&self) -> &'static str {
+		match *self {
+			Namespace::Html => "http://www.w3.org/1999/xhtml",
+			Namespace::Svg => "http://www.w3.org/2000/svg",
+			Namespace::MathMl => "http://www.w3.org/1998/Math/MathML",
+		}
+	}
+
+	/// Return the Namespace identified by 'url', None if it is not one
+	/// the DOM knows about
+	pub fn from_url(url: &str) -> Option<Namespace> {
+		match url {
+			"http://www.w3.org/1999/xhtml" => Some(Namespace::Html),
+			"http://www.w3.org/2000/svg" => Some(Namespace::Svg),
+			"http://www.w3.org/1998/Math/MathML" => Some(Namespace::MathMl),
+			_ => None,
+		}
+	}
 }
 
 #[derive(Debug, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Doctype {
 	Html,
 }
 
+/// Changes made through the Document's mutation methods are queued as
+/// MutationRecords until they are delivered to the registered observers.
+/// Editing 'element' directly bypasses the observers.
+/// Serialized as '{"d_type": "Html", "element": Element or null}'; the
+/// observers and queued records are not part of the serialized form
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Document {
 	d_type: Doctype,
 	pub element: Option<Element>,
+	#[cfg_attr(feature = "serde", serde(skip))]
+	observers: Vec<mutation::Observer>,
+	#[cfg_attr(feature = "serde", serde(skip))]
+	pending_mutations: Vec<MutationRecord>,
+	#[cfg_attr(feature = "serde", serde(skip))]
+	next_observer_id: usize,
 }
 
-/// Elements are nodes in the DOM tree
+/// Elements are nodes in the DOM tree. 'tag_name' is the name as it
+/// appeared in the markup (case corrected for svg and mathml), 'e_type' is
+/// only meaningful for html elements. 'text' is the text before the first
+/// child and 'tail' the text after the element's end tag, up to its next
+/// sibling, so an element's text runs are its 'text' and its children's
+/// 'tail' in order. The tail moves with the element when it is inserted
+/// or removed.
+/// Serialized as an object with one key per field; 'attributes' is an
+/// object sorted by attribute name
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Element {
 	pub e_type: ElementType,
+	pub namespace: Namespace,
+	pub tag_name: String,
+	#[cfg_attr(feature = "serde", serde(serialize_with = "serialize_sorted"))]
+	pub attributes: HashMap<String, String>,
 	pub text: Option<String>,
+	pub tail: Option<String>,
 	pub children: Vec<Element>,
+	pub template_contents: Option<DocumentFragment>,
+	#[cfg_attr(feature = "serde", serde(skip))]
+	pub state: ElementState,
+}
+
+/// State the embedding application tracks for an element, read by the
+/// :hover, :active, :focus, :checked and :visited pseudo-classes. It is
+/// not part of the markup, so it is not serialized
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct ElementState {
+	pub hover: bool,
+	pub active: bool,
+	pub focus: bool,
+	pub checked: bool,
+	pub visited: bool,
+}
+
+/// A DocumentFragment is a list of nodes that are not part of a document.
+/// The contents of a <template> are kept in one so they are never styled
+/// or laid out, and snippets are parsed into one before being inserted
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DocumentFragment {
+	pub text: Option<String>,
+	pub children: Vec<Element>,
+}
+
+impl DocumentFragment {
+	pub fn new() -> DocumentFragment {
+		DocumentFragment {
+			text: None,
+			children: Vec::new(),
+		}
+	}
+}
+
+/// Serialize a map with its keys in order, so output is the same from run
+/// to run
+#[cfg(feature = "serde")]
+pub fn serialize_sorted<K, V, S>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+	where K: Serialize + Ord + ::std::hash::Hash, V: Serialize, S: Serializer {
+	let sorted: BTreeMap<&K, &V> = map.iter().collect();
+	sorted.serialize(serializer)
 }
 
 /// Implement to help pretty_print display the DOM tree structure
 impl fmt::Display for Element {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let name = match self.namespace {
+			Namespace::Html => format!("{:?}", self.e_type),
+			ns => format!("{:?}:{}", ns, self.tag_name),
+		};
+
 		match self.text {
-			Some(ref s) => write!(f, "|__Element: {:?}, Text: {}", self.e_type, *s),
-			None    => write!(f, "|__Element: {:?}", self.e_type),
+			Some(ref s) => write!(f, "|__Element: {}, Text: {}", name, *s),
+			None    => write!(f, "|__Element: {}", name),
 		}
 	}
 }
@@ -43,6 +186,9 @@ impl Document {
 		Document {
 			d_type: d_type,
 			element: None,
+			observers: Vec::new(),
+			pending_mutations: Vec::new(),
+			next_observer_id: 0,
 		}
 	}
 }
@@ -52,8 +198,45 @@ pub fn new_element(e_type: ElementType, text: Option<String>) -> Element {
 
 	Element {
 		e_type: e_type,
+		namespace: Namespace::Html,
+		tag_name: e_type.tag_name().to_string(),
+		attributes: HashMap::new(),
 		text: text,
+		tail: None,
 		children: vec,
+		template_contents: None,
+		state: ElementState::default(),
+	}
+}
+
+/// Create an element from its tag name in the given namespace. Only html
+/// elements are given an ElementType other than ElementType::Other
+pub fn new_named_element(namespace: Namespace,
+						 tag_name: &str,
+						 attributes: HashMap<String, String>,
+						 text: Option<String>) -> Element {
+	let e_type = match namespace {
+		Namespace::Html => ElementType::from_tag_name(tag_name),
+		_ => ElementType::Other,
+	};
+
+	// checkboxes and options start out as checked by their markup
+	let checked = namespace == Namespace::Html && match tag_name {
+		"input" => attributes.contains_key("checked"),
+		"option" => attributes.contains_key("selected"),
+		_ => false,
+	};
+
+	Element {
+		e_type: e_type,
+		namespace: namespace,
+		tag_name: tag_name.to_string(),
+		attributes: attributes,
+		text: text,
+		tail: None,
+		children: Vec::new(),
+		template_contents: None,
+		state: ElementState { checked: checked, .. ElementState::default() },
 	}
 }
 
@@ -62,24 +245,12 @@ impl Element {
 	/// Used to create the first Element in the DOM-tree. Called
 	/// when a new Document is created
 	pub fn new_root(e_type: ElementType) -> Element {
-		let vec = Vec::new();
-
-		Element {
-			e_type: e_type,
-			text: None,
-			children: vec,
-		}
+		new_element(e_type, None)
 	}
 
 	/// Add a child element to an element. 'text' is optional. 
 	pub fn add_child(&mut self, e_type: ElementType, text: Option<String>) {
-		let vec = Vec::new();
-
-		let e = Element {
-			e_type: e_type,
-			text: text,
-			children: vec,
-		};
+		let e = new_element(e_type, text);
 
 		self.children.push(e);
 	}
@@ -88,6 +259,40 @@ impl Element {
 		self.children.push(e);
 	}
 
+	/// Return the value of attribute 'name', None if it is not set
+	pub fn get_attribute(&self, name: &str) -> Option<&str> {
+		self.attributes.get(name).map(|v| &v[..])
+	}
+
+	/// Return the text of the element and all of its descendants, in
+	/// document order, without regard to how it would be rendered
+	pub fn text_content(&self) -> String {
+		let mut result = String::new();
+		self.append_text_content(&mut result);
+		result
+	}
+
+	fn append_text_content(&self, result: &mut String) {
+		match self.text {
+			Some(ref s) => result.push_str(&s[..]),
+			None => {}
+		}
+
+		for c in self.children.iter() {
+			c.append_text_content(result);
+
+			match c.tail {
+				Some(ref s) => result.push_str(&s[..]),
+				None => {}
+			}
+		}
+	}
+
+	/// Return true if the element is 'tag_name' in 'namespace'
+	pub fn is(&self, namespace: Namespace, tag_name: &str) -> bool {
+		self.namespace == namespace && self.tag_name == tag_name
+	}
+
 }
 
 /// Print the DOM-tree of the given 'doc' in a readable way
@@ -108,6 +313,16 @@ fn pretty_print_element(depth: i32, e: &Element) {
 	}
 	println!("{}{}", s, e);
 
+	match e.template_contents {
+		Some(ref fragment) => {
+			println!("{}    |__#document-fragment", s);
+			for c in fragment.children.iter() {
+				pretty_print_element(depth + 2, c);
+			}
+		}
+		None => {}
+	}
+
 	for c in e.children.iter() {
 		pretty_print_element(depth + 1, c);
 	}
@@ -116,6 +331,18 @@ fn pretty_print_element(depth: i32, e: &Element) {
 #[cfg(test)]
 mod test_dom_tree {
 
+	#[test]
+	fn dom_text_content() {
+		let mut root = super::new_element(super::ElementType::Body, Some("a ".to_string()));
+		root.add_child(super::ElementType::Title, Some("b".to_string()));
+		root.add_child(super::ElementType::Title, None);
+		root.children[1].add_child(super::ElementType::Title, Some(" c".to_string()));
+		root.children[0].tail = Some(" d".to_string());
+
+		assert_eq!(root.text_content(), "a b d c");
+		assert_eq!(root.children[1].text_content(), " c");
+	}
+
 	#[test]
 	fn dom_add_child() {
 		let s: Option<String> = Some("x".to_string());
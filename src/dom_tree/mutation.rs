@@ -0,0 +1,428 @@
+use super::{Document, DocumentFragment, Element, ElementState};
+
+/// Nodes are addressed by the index of each element on the way down from
+/// the document element. The document element itself is the empty path
+pub type NodePath = Vec<usize>;
+
+/// Called with the records an observer is interested in
+pub type MutationCallback = Box<FnMut(&[MutationRecord])>;
+
+/// What changed on a mutation record's target
+/// dom.spec.whatwg.org/#interface-mutationrecord
+#[derive(Debug, Clone, PartialEq)]
+pub enum MutationKind {
+	/// 'added' children were inserted and 'removed' children were taken
+	/// out, starting at child 'index'
+	ChildList { index: usize, added: usize, removed: usize },
+	Attributes { name: String, old_value: Option<String> },
+	CharacterData { old_value: Option<String> },
+}
+
+/// 'target' is always the node's path in the current tree: queuing a child
+/// list change moves the targets of the records already queued below it,
+/// and drops the records of nodes it removed. The fields of a ChildList
+/// kind describe the child list right after that change
+#[derive(Debug, Clone, PartialEq)]
+pub struct MutationRecord {
+	pub target: NodePath,
+	pub kind: MutationKind,
+}
+
+/// Which records an observer receives. With 'subtree' set, mutations to
+/// descendants of the observed node are delivered too
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct ObserverOptions {
+	pub child_list: bool,
+	pub attributes: bool,
+	pub character_data: bool,
+	pub subtree: bool,
+}
+
+impl ObserverOptions {
+	/// Every kind of mutation, anywhere below the observed node
+	pub fn all() -> ObserverOptions {
+		ObserverOptions {
+			child_list: true,
+			attributes: true,
+			character_data: true,
+			subtree: true,
+		}
+	}
+
+	fn wants(&self, observed: &[usize], record: &MutationRecord) -> bool {
+		let kind = match record.kind {
+			MutationKind::ChildList { .. } => self.child_list,
+			MutationKind::Attributes { .. } => self.attributes,
+			MutationKind::CharacterData { .. } => self.character_data,
+		};
+
+		let target = &record.target[..];
+		let in_scope = target == observed ||
+					   (self.subtree && target.starts_with(observed));
+
+		kind && in_scope
+	}
+}
+
+pub struct Observer {
+	id: usize,
+	target: NodePath,
+	options: ObserverOptions,
+	callback: MutationCallback,
+}
+
+impl Document {
+	/// Register 'callback' for mutations to the node at 'target'. Returns
+	/// an id that can be passed to disconnect
+	pub fn observe(&mut self,
+				   target: NodePath,
+				   options: ObserverOptions,
+				   callback: MutationCallback) -> usize {
+		let id = self.next_observer_id;
+		self.next_observer_id += 1;
+
+		self.observers.push(Observer {
+			id: id,
+			target: target,
+			options: options,
+			callback: callback,
+		});
+
+		id
+	}
+
+	/// Stop delivering records to observer 'id'. Records already queued
+	/// for it are dropped
+	pub fn disconnect(&mut self, id: usize) {
+		self.observers.retain(|o| o.id != id);
+	}
+
+	/// Hand every queued record to the observers interested in it, in the
+	/// order the mutations happened, then clear the queue
+	pub fn deliver_mutations(&mut self) {
+		let records = ::std::mem::replace(&mut self.pending_mutations, Vec::new());
+
+		if records.is_empty() {
+			return;
+		}
+
+		for observer in self.observers.iter_mut() {
+			let wanted: Vec<MutationRecord> = records.iter()
+				.filter(|r| observer.options.wants(&observer.target[..], r))
+				.cloned()
+				.collect();
+
+			if !wanted.is_empty() {
+				(observer.callback)(&wanted[..]);
+			}
+		}
+	}
+
+	/// Run a sequence of mutations and deliver the records they produced
+	/// as one batch once 'f' returns
+	pub fn batch<F: FnOnce(&mut Document)>(&mut self, f: F) {
+		f(self);
+		self.deliver_mutations();
+	}
+
+	/// Records queued since the last delivery
+	pub fn pending_mutations(&self) -> &[MutationRecord] {
+		&self.pending_mutations[..]
+	}
+
+	pub fn element_at(&self, path: &[usize]) -> Option<&Element> {
+		let mut e = match self.element {
+			Some(ref e) => e,
+			None => return None,
+		};
+
+		for &i in path.iter() {
+			e = match e.children.get(i) {
+				Some(c) => c,
+				None => return None,
+			};
+		}
+
+		Some(e)
+	}
+
+	fn element_at_mut(&mut self, path: &[usize]) -> Option<&mut Element> {
+		let mut e = match self.element {
+			Some(ref mut e) => e,
+			None => return None,
+		};
+
+		for &i in path.iter() {
+			e = match e.children.get_mut(i) {
+				Some(c) => c,
+				None => return None,
+			};
+		}
+
+		Some(e)
+	}
+
+	fn queue_mutation(&mut self, target: &[usize], kind: MutationKind) {
+		if let MutationKind::ChildList { index, added, removed } = kind {
+			let pending = ::std::mem::replace(&mut self.pending_mutations, Vec::new());
+			self.pending_mutations = pending.into_iter().filter_map(|mut record| {
+				rebase(&record.target[..], target, index, added, removed).map(|path| {
+					record.target = path;
+					record
+				})
+			}).collect();
+		}
+
+		self.pending_mutations.push(MutationRecord {
+			target: target.to_vec(),
+			kind: kind,
+		});
+	}
+
+	/// Add 'child' as the last child of the element at 'parent'. Return
+	/// false if there is no such element
+	pub fn append_child(&mut self, parent: &[usize], child: Element) -> bool {
+		let len = match self.element_at(parent) {
+			Some(e) => e.children.len(),
+			None => return false,
+		};
+
+		self.insert_child(parent, len, child)
+	}
+
+	/// Insert 'child' before child 'index' of the element at 'parent'
+	pub fn insert_child(&mut self, parent: &[usize], index: usize, child: Element) -> bool {
+		match self.element_at_mut(parent) {
+			Some(ref mut e) if index <= e.children.len() => e.children.insert(index, child),
+			_ => return false,
+		}
+
+		self.queue_mutation(parent, MutationKind::ChildList { index: index, added: 1, removed: 0 });
+		true
+	}
+
+	/// Move the children of 'fragment' to the end of the element at
+	/// 'parent' as a single child list change. Loose text in the fragment
+	/// has nowhere to go and is dropped
+	pub fn append_fragment(&mut self, parent: &[usize], fragment: DocumentFragment) -> bool {
+		let added = fragment.children.len();
+		let index = match self.element_at_mut(parent) {
+			Some(e) => {
+				let index = e.children.len();
+				e.children.extend(fragment.children.into_iter());
+				index
+			}
+			None => return false,
+		};
+
+		if added > 0 {
+			self.queue_mutation(parent, MutationKind::ChildList { index: index, added: added, removed: 0 });
+		}
+		true
+	}
+
+	/// Remove and return child 'index' of the element at 'parent'
+	pub fn remove_child(&mut self, parent: &[usize], index: usize) -> Option<Element> {
+		let removed = match self.element_at_mut(parent) {
+			Some(ref mut e) if index < e.children.len() => e.children.remove(index),
+			_ => return None,
+		};
+
+		self.queue_mutation(parent, MutationKind::ChildList { index: index, added: 0, removed: 1 });
+		Some(removed)
+	}
+
+	pub fn set_attribute(&mut self, target: &[usize], name: &str, value: &str) -> bool {
+		let old_value = match self.element_at_mut(target) {
+			Some(e) => e.attributes.insert(name.to_string(), value.to_string()),
+			None => return false,
+		};
+
+		self.queue_mutation(target, MutationKind::Attributes {
+			name: name.to_string(),
+			old_value: old_value,
+		});
+		true
+	}
+
+	/// Remove attribute 'name'. Nothing is recorded if it was not set
+	pub fn remove_attribute(&mut self, target: &[usize], name: &str) -> bool {
+		let old_value = match self.element_at_mut(target) {
+			Some(e) => e.attributes.remove(name),
+			None => return false,
+		};
+
+		if old_value.is_some() {
+			self.queue_mutation(target, MutationKind::Attributes {
+				name: name.to_string(),
+				old_value: old_value,
+			});
+		}
+		true
+	}
+
+	/// Replace the dynamic state of the element at 'target', e.g. when the
+	/// pointer moves over it. State is not markup, so no record is queued
+	/// and the embedder decides what to restyle
+	pub fn set_element_state(&mut self, target: &[usize], state: ElementState) -> bool {
+		match self.element_at_mut(target) {
+			Some(e) => {
+				e.state = state;
+				true
+			}
+			None => false,
+		}
+	}
+
+	/// Replace the text of the element at 'target'
+	pub fn set_text(&mut self, target: &[usize], text: Option<String>) -> bool {
+		let old_value = match self.element_at_mut(target) {
+			Some(e) => ::std::mem::replace(&mut e.text, text),
+			None => return false,
+		};
+
+		self.queue_mutation(target, MutationKind::CharacterData { old_value: old_value });
+		true
+	}
+}
+
+/// Return where the node at 'path' is after 'removed' children were taken
+/// out of the element at 'parent' and 'added' were inserted, starting at
+/// child 'index'. None if the node was one of the removed children or
+/// below one
+fn rebase(path: &[usize], parent: &[usize], index: usize, added: usize, removed: usize) -> Option<NodePath> {
+	let depth = parent.len();
+	if path.len() <= depth || !path.starts_with(parent) {
+		return Some(path.to_vec());
+	}
+
+	let child = path[depth];
+	if child < index {
+		return Some(path.to_vec());
+	} else if child < index + removed {
+		return None;
+	}
+
+	let mut rebased = path.to_vec();
+	rebased[depth] = child - removed + added;
+	Some(rebased)
+}
+
+#[cfg(test)]
+mod test_mutation {
+	use std::rc::Rc;
+	use std::cell::RefCell;
+
+	use super::super::{Document, DocumentFragment, Doctype, Element, ElementType, new_element};
+	use super::{MutationKind, MutationRecord, ObserverOptions};
+
+	fn test_document() -> Document {
+		let mut document = Document::new(Doctype::Html);
+		document.element = Some(Element::new_root(ElementType::ClassE));
+		document.element.as_mut().unwrap().add_child(ElementType::Head, None);
+		document.element.as_mut().unwrap().add_child(ElementType::Body, None);
+		document
+	}
+
+	#[test]
+	fn mutations_are_batched() {
+		let mut document = test_document();
+		let seen: Rc<RefCell<Vec<Vec<MutationRecord>>>> = Rc::new(RefCell::new(Vec::new()));
+
+		let log = seen.clone();
+		document.observe(Vec::new(), ObserverOptions::all(), Box::new(move |records: &[MutationRecord]| {
+			log.borrow_mut().push(records.to_vec());
+		}));
+
+		document.batch(|doc| {
+			assert!(doc.append_child(&[1], new_element(ElementType::Title, None)));
+			assert!(doc.set_attribute(&[1, 0], "class", "x"));
+			assert!(doc.set_text(&[1, 0], Some("hi".to_string())));
+		});
+
+		assert_eq!(seen.borrow().len(), 1);
+
+		let batch = &seen.borrow()[0];
+		assert_eq!(batch.len(), 3);
+		assert_eq!(batch[0].target, vec![1]);
+		assert_eq!(batch[0].kind, MutationKind::ChildList { index: 0, added: 1, removed: 0 });
+		assert_eq!(batch[1].kind, MutationKind::Attributes { name: "class".to_string(), old_value: None });
+		assert_eq!(batch[2].kind, MutationKind::CharacterData { old_value: None });
+		assert!(document.pending_mutations().is_empty());
+	}
+
+	#[test]
+	fn observer_options_filter_records() {
+		let mut document = test_document();
+		let count = Rc::new(RefCell::new(0));
+
+		let c = count.clone();
+		let options = ObserverOptions { attributes: true, .. ObserverOptions::default() };
+		let id = document.observe(vec![1], options, Box::new(move |records: &[MutationRecord]| {
+			*c.borrow_mut() += records.len();
+		}));
+
+		// not the observed node, and subtree is off
+		document.set_attribute(&[0], "id", "a");
+		// wrong kind
+		document.append_child(&[1], new_element(ElementType::Title, None));
+		document.set_attribute(&[1], "id", "b");
+		document.deliver_mutations();
+		assert_eq!(*count.borrow(), 1);
+
+		document.disconnect(id);
+		document.set_attribute(&[1], "id", "c");
+		document.deliver_mutations();
+		assert_eq!(*count.borrow(), 1);
+	}
+
+	#[test]
+	fn invalid_paths_are_not_recorded() {
+		let mut document = test_document();
+
+		assert!(!document.append_child(&[5], new_element(ElementType::Title, None)));
+		assert!(document.remove_child(&[0], 0).is_none());
+		assert!(!document.set_text(&[0, 3], None));
+		assert!(document.pending_mutations().is_empty());
+
+		assert!(document.remove_child(&[], 0).is_some());
+		assert_eq!(document.pending_mutations().len(), 1);
+	}
+
+	#[test]
+	fn targets_follow_later_child_list_changes() {
+		let mut document = test_document();
+		assert!(document.append_child(&[1], new_element(ElementType::Title, None)));
+		assert!(document.append_child(&[1], new_element(ElementType::Title, None)));
+		document.deliver_mutations();
+
+		assert!(document.set_attribute(&[1, 1], "id", "a"));
+		assert!(document.set_text(&[1, 0], None));
+		assert!(document.insert_child(&[1], 0, new_element(ElementType::Title, None)));
+		assert!(document.remove_child(&[1], 1).is_some());
+
+		let records = document.pending_mutations();
+		assert_eq!(records.len(), 3);
+		assert_eq!(records[0].target, vec![1, 1]);
+		assert_eq!(records[0].kind, MutationKind::Attributes { name: "id".to_string(), old_value: None });
+		assert_eq!(document.element_at(&records[0].target[..]).unwrap().get_attribute("id"), Some("a"));
+		assert_eq!(records[1].target, vec![1]);
+		assert_eq!(records[2].target, vec![1]);
+	}
+
+	#[test]
+	fn fragment_children_are_appended_once() {
+		let mut document = test_document();
+		let mut fragment = DocumentFragment::new();
+		fragment.children.push(new_element(ElementType::Title, None));
+		fragment.children.push(new_element(ElementType::Title, None));
+
+		assert!(document.append_fragment(&[1], fragment.clone()));
+		assert!(document.append_fragment(&[1], fragment));
+
+		assert_eq!(document.element_at(&[1]).unwrap().children.len(), 4);
+		assert_eq!(document.pending_mutations().len(), 2);
+		assert_eq!(document.pending_mutations()[1].kind,
+				   MutationKind::ChildList { index: 2, added: 2, removed: 0 });
+	}
+}
@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+
+use dom_tree::Namespace;
+
+/// Tag names inside <svg> that are not all lowercase. The tokenizer lowers
+/// every name, so these are restored once we know we are in svg content
+/// html.spec.whatwg.org/multipage/parsing.html#parsing-main-inforeign
+static SVG_TAG_NAMES: [(&'static str, &'static str); 37] = [
+	("altglyph", "altGlyph"),
+	("altglyphdef", "altGlyphDef"),
+	("altglyphitem", "altGlyphItem"),
+	("animatecolor", "animateColor"),
+	("animatemotion", "animateMotion"),
+	("animatetransform", "animateTransform"),
+	("clippath", "clipPath"),
+	("feblend", "feBlend"),
+	("fecolormatrix", "feColorMatrix"),
+	("fecomponenttransfer", "feComponentTransfer"),
+	("fecomposite", "feComposite"),
+	("feconvolvematrix", "feConvolveMatrix"),
+	("fediffuselighting", "feDiffuseLighting"),
+	("fedisplacementmap", "feDisplacementMap"),
+	("fedistantlight", "feDistantLight"),
+	("fedropshadow", "feDropShadow"),
+	("feflood", "feFlood"),
+	("fefunca", "feFuncA"),
+	("fefuncb", "feFuncB"),
+	("fefuncg", "feFuncG"),
+	("fefuncr", "feFuncR"),
+	("fegaussianblur", "feGaussianBlur"),
+	("feimage", "feImage"),
+	("femerge", "feMerge"),
+	("femergenode", "feMergeNode"),
+	("femorphology", "feMorphology"),
+	("feoffset", "feOffset"),
+	("fepointlight", "fePointLight"),
+	("fespecularlighting", "feSpecularLighting"),
+	("fespotlight", "feSpotLight"),
+	("fetile", "feTile"),
+	("feturbulence", "feTurbulence"),
+	("foreignobject", "foreignObject"),
+	("glyphref", "glyphRef"),
+	("lineargradient", "linearGradient"),
+	("radialgradient", "radialGradient"),
+	("textpath", "textPath"),
+];
+
+/// Attribute names on svg elements that are not all lowercase
+static SVG_ATTRIBUTE_NAMES: [(&'static str, &'static str); 58] = [
+	("attributename", "attributeName"),
+	("attributetype", "attributeType"),
+	("basefrequency", "baseFrequency"),
+	("baseprofile", "baseProfile"),
+	("calcmode", "calcMode"),
+	("clippathunits", "clipPathUnits"),
+	("diffuseconstant", "diffuseConstant"),
+	("edgemode", "edgeMode"),
+	("filterunits", "filterUnits"),
+	("glyphref", "glyphRef"),
+	("gradienttransform", "gradientTransform"),
+	("gradientunits", "gradientUnits"),
+	("kernelmatrix", "kernelMatrix"),
+	("kernelunitlength", "kernelUnitLength"),
+	("keypoints", "keyPoints"),
+	("keysplines", "keySplines"),
+	("keytimes", "keyTimes"),
+	("lengthadjust", "lengthAdjust"),
+	("limitingconeangle", "limitingConeAngle"),
+	("markerheight", "markerHeight"),
+	("markerunits", "markerUnits"),
+	("markerwidth", "markerWidth"),
+	("maskcontentunits", "maskContentUnits"),
+	("maskunits", "maskUnits"),
+	("numoctaves", "numOctaves"),
+	("pathlength", "pathLength"),
+	("patterncontentunits", "patternContentUnits"),
+	("patterntransform", "patternTransform"),
+	("patternunits", "patternUnits"),
+	("pointsatx", "pointsAtX"),
+	("pointsaty", "pointsAtY"),
+	("pointsatz", "pointsAtZ"),
+	("preservealpha", "preserveAlpha"),
+	("preserveaspectratio", "preserveAspectRatio"),
+	("primitiveunits", "primitiveUnits"),
+	("refx", "refX"),
+	("refy", "refY"),
+	("repeatcount", "repeatCount"),
+	("repeatdur", "repeatDur"),
+	("requiredextensions", "requiredExtensions"),
+	("requiredfeatures", "requiredFeatures"),
+	("specularconstant", "specularConstant"),
+	("specularexponent", "specularExponent"),
+	("spreadmethod", "spreadMethod"),
+	("startoffset", "startOffset"),
+	("stddeviation", "stdDeviation"),
+	("stitchtiles", "stitchTiles"),
+	("surfacescale", "surfaceScale"),
+	("systemlanguage", "systemLanguage"),
+	("tablevalues", "tableValues"),
+	("targetx", "targetX"),
+	("targety", "targetY"),
+	("textlength", "textLength"),
+	("viewbox", "viewBox"),
+	("viewtarget", "viewTarget"),
+	("xchannelselector", "xChannelSelector"),
+	("ychannelselector", "yChannelSelector"),
+	("zoomandpan", "zoomAndPan"),
+];
+
+fn lookup(table: &[(&'static str, &'static str)], name: &str) -> Option<&'static str> {
+	table.iter().find(|&&(lower, _)| lower == name).map(|&(_, fixed)| fixed)
+}
+
+/// Return the namespace an element named 'name' is created in when its
+/// parent's children are parsed in 'parent'
+pub fn namespace_for_tag(parent: Namespace, name: &str) -> Namespace {
+	match (parent, name) {
+		(Namespace::Html, "svg") => Namespace::Svg,
+		(Namespace::Html, "math") => Namespace::MathMl,
+		(Namespace::MathMl, "svg") => Namespace::Svg,
+		(ns, _) => ns,
+	}
+}
+
+/// Restore the case of a lowercased tag name in 'namespace'
+pub fn adjust_tag_name(namespace: Namespace, name: &str) -> String {
+	match namespace {
+		Namespace::Svg => lookup(&SVG_TAG_NAMES, name).unwrap_or(name).to_string(),
+		_ => name.to_string(),
+	}
+}
+
+/// Restore the case of a lowercased attribute name on an element in
+/// 'namespace'
+pub fn adjust_attribute_name(namespace: Namespace, name: &str) -> String {
+	match namespace {
+		Namespace::Svg => lookup(&SVG_ATTRIBUTE_NAMES, name).unwrap_or(name).to_string(),
+		Namespace::MathMl if name == "definitionurl" => "definitionURL".to_string(),
+		_ => name.to_string(),
+	}
+}
+
+/// Html integration points are foreign elements whose children are parsed
+/// as html again, e.g. the body of an svg <foreignObject>. A mathml
+/// <annotation-xml> only is one when its encoding says it holds html
+/// html.spec.whatwg.org/multipage/parsing.html#html-integration-point
+pub fn is_html_integration_point(namespace: Namespace, name: &str,
+								 attributes: &HashMap<String, String>) -> bool {
+	match namespace {
+		Namespace::Svg => name == "foreignObject" || name == "desc" || name == "title",
+		Namespace::MathMl if name == "annotation-xml" => {
+			match attributes.get("encoding") {
+				Some(encoding) => {
+					let encoding = encoding.to_lowercase();
+					encoding == "text/html" || encoding == "application/xhtml+xml"
+				}
+				None => false,
+			}
+		}
+		_ => false,
+	}
+}
+
+/// Mathml text integration points parse their children as html, except
+/// for <mglyph> and <malignmark> which stay mathml
+/// html.spec.whatwg.org/multipage/parsing.html#mathml-text-integration-point
+pub fn is_mathml_text_integration_point(namespace: Namespace, name: &str) -> bool {
+	match (namespace, name) {
+		(Namespace::MathMl, "mi") | (Namespace::MathMl, "mo") | (Namespace::MathMl, "mn") |
+		(Namespace::MathMl, "ms") | (Namespace::MathMl, "mtext") => true,
+		_ => false,
+	}
+}
+
+/// Return true if a start tag named 'name' ends foreign content: the open
+/// svg and mathml elements are closed and the tag is parsed as html. <font>
+/// only breaks out when it has presentational attributes
+/// html.spec.whatwg.org/multipage/parsing.html#parsing-main-inforeign
+pub fn breaks_out_of_foreign_content(name: &str, attributes: &[(String, String)]) -> bool {
+	match name {
+		"b" | "big" | "blockquote" | "body" | "br" | "center" | "code" | "dd" |
+		"div" | "dl" | "dt" | "em" | "embed" | "h1" | "h2" | "h3" | "h4" | "h5" |
+		"h6" | "head" | "hr" | "i" | "img" | "li" | "listing" | "menu" | "meta" |
+		"nobr" | "ol" | "p" | "pre" | "ruby" | "s" | "small" | "span" | "strong" |
+		"strike" | "sub" | "sup" | "table" | "tt" | "u" | "ul" | "var" => true,
+		"font" => attributes.iter().any(|&(ref attr, _)| {
+			attr == "color" || attr == "face" || attr == "size"
+		}),
+		_ => false,
+	}
+}
@@ -1,8 +1,18 @@
+use std::collections::HashMap;
+
 use super::text_parser;
 use super::dom_tree;
+use super::dom_tree::Namespace;
+
+mod foreign;
 
 pub struct HtmlParser {
 	pub parse: text_parser::TextParser,	
+	/// Lowercased names of the elements being parsed, outermost first
+	open_elements: Vec<String>,
+	/// Set when parsing a fragment whose context is in the body, where the
+	/// document already has its <html>, <head> and <body>
+	in_body_fragment: bool,
 }
 
 impl HtmlParser {
@@ -10,6 +20,8 @@ impl HtmlParser {
 		let parser = text_parser::TextParser::new(input);
 		HtmlParser {
 			parse: parser,
+			open_elements: Vec::new(),
+			in_body_fragment: false,
 		}
 	}
 
@@ -51,79 +63,432 @@ impl HtmlParser {
 		result
 	}
 
-	fn parse_node(&mut self) -> String {
+	/// Parse a start tag from '<' to '>'. Tag and attribute names are
+	/// lowercased; it is up to the caller to case correct them for foreign
+	/// content
+	fn parse_node(&mut self) -> Tag {
 		self.parse.consume_while(|c| c != '<');
 		self.parse.consume_char();
 
-		// consume first word in tag; breaks at whitespace in case there is a class
-		// tag, or just goes to end of node
-		let ele_type = self.parse.consume_while(|c| !c.is_whitespace() && c != '>');
+		// consume first word in tag; breaks at whitespace in case there are
+		// attributes, or just goes to end of node
+		let name = self.parse.consume_while(|c| !c.is_whitespace() && c != '>' && c != '/');
+
+		let mut attributes = Vec::new();
+		let mut self_closing = false;
+
+		loop {
+			self.parse.consume_whitespace();
+
+			match self.parse.peek_char() {
+				None => break,
+				Some('>') => {
+					self.parse.consume_char();
+					break;
+				}
+				Some('/') => {
+					self.parse.consume_char();
+					self_closing = self.parse.peek_char() == Some('>');
+				}
+				Some(_) => {
+					let attr = self.parse_attribute();
+					attributes.push(attr);
+				}
+			}
+		}
+
+		Tag {
+			name: name.to_lowercase(),
+			attributes: attributes,
+			self_closing: self_closing,
+		}
+	}
+
+	/// Parse the start tag at the cursor without consuming it
+	fn peek_node(&mut self) -> Tag {
+		let mark = self.parse.mark();
+		let tag = self.parse_node();
+		self.parse.rewind(mark);
+
+		tag
+	}
 
-		// Consume to end of node in case there were class tags.
-		self.parse.consume_while(|c| c != '>');
+	/// Return the lowercased name of the end tag at the cursor without
+	/// consuming it
+	fn peek_end_tag_name(&mut self) -> String {
+		let mark = self.parse.mark();
+		self.parse.consume_char();
 		self.parse.consume_char();
+		let name = self.parse.consume_while(|c| !c.is_whitespace() && c != '>');
+		self.parse.rewind(mark);
+
+		name.to_lowercase()
+	}
+
+	/// Parse 'name', 'name=value', 'name="value"' or "name='value'"
+	fn parse_attribute(&mut self) -> (String, String) {
+		let name = self.parse.consume_while(|c| match c {
+			'=' | '>' | '/' => false,
+			c => !c.is_whitespace(),
+		});
+		self.parse.consume_whitespace();
+
+		let mut value = String::new();
+		if self.parse.consume_if_char_matches('=') {
+			self.parse.consume_whitespace();
+
+			match self.parse.peek_char() {
+				Some(q) if q == '"' || q == '\'' => {
+					self.parse.consume_char();
+					value = self.parse.consume_while(|c| c != q);
+					self.parse.consume_char();
+				}
+				_ => {
+					value = self.parse.consume_while(|c| !c.is_whitespace() && c != '>');
+				}
+			}
+		}
 
-		ele_type
+		(name.to_lowercase(), value)
 	}
 
 	fn parse_dom_text(&mut self) -> String {
 		self.parse.consume_while(|c| c != '<')
 	}
 
-	fn give_element_type(string :&str) -> Option<dom_tree::ElementType> {
-		match string {
-			"html" => Some(dom_tree::ElementType::ClassE),
-			"head" => Some(dom_tree::ElementType::Head), 
-			"title" => Some(dom_tree::ElementType::Title), 
-			"body" => Some(dom_tree::ElementType::Body),
-			_ => {println!("failed match");return None},
+	/// Read the contents of an element such as <script> or <title> as text
+	/// up to its end tag 'end_tag', which is consumed too. Without an end
+	/// tag, as for <plaintext> or a fragment parsed in such an element, the
+	/// rest of the input is text
+	/// html.spec.whatwg.org/multipage/parsing.html#rawtext-state
+	fn parse_raw_text(&mut self, end_tag: Option<&str>) -> Option<String> {
+		let mut text = String::new();
+
+		loop {
+			text.push_str(&self.parse_dom_text()[..]);
+
+			if self.parse.end_of_string() {
+				break;
+			}
+
+			let at_end_tag = match end_tag {
+				Some(name) => self.parse.peek_next_char() == Some('/') && self.peek_end_tag_name() == name,
+				None => false,
+			};
+			if at_end_tag {
+				self.parse.consume_while(|c| c != '>');
+				self.parse.consume_char();
+				break;
+			}
+
+			text.push(self.parse.consume_char().unwrap());
 		}
+
+		if text.is_empty() { None } else { Some(text) }
 	}
 
 	pub fn parse_element(&mut self) -> Option<dom_tree::Element> {
+		self.parse_element_in(Namespace::Html)
+	}
+
+	/// Parse an element whose parent's children are in namespace 'parent'.
+	/// Entering <svg> or <math> switches to foreign content, where names
+	/// are case corrected and '/>' closes an element
+	fn parse_element_in(&mut self, parent: Namespace) -> Option<dom_tree::Element> {
 		
 		// parse a node
 		// < to >
-		let ele_type = self.parse_node();
+		let tag = self.parse_node();
+
+		// markup declarations and comments are not elements
+		if tag.name.is_empty() || tag.name.starts_with('!') {
+			return None;
+		}
+
+		// a fragment's context already sits in the body; these start tags
+		// are dropped and their contents become siblings
+		let is_document_tag = match &tag.name[..] {
+			"html" | "head" | "body" => true,
+			_ => false,
+		};
+		if self.in_body_fragment && parent == Namespace::Html && is_document_tag {
+			return None;
+		}
+
+		let namespace = foreign::namespace_for_tag(parent, &tag.name[..]);
+		let name = foreign::adjust_tag_name(namespace, &tag.name[..]);
+
+		let mut attributes = HashMap::new();
+		for (attr_name, value) in tag.attributes.into_iter() {
+			attributes.insert(foreign::adjust_attribute_name(namespace, &attr_name[..]), value);
+		}
+
+		let has_contents = match namespace {
+			Namespace::Html => !is_void_element(&name[..]),
+			_ => !tag.self_closing,
+		};
+
+		if !has_contents {
+			return Some(dom_tree::new_named_element(namespace, &name[..], attributes, None));
+		}
+
+		if namespace == Namespace::Html && is_text_only_element(&name[..]) {
+			let end_tag = if name == "plaintext" { None } else { Some(&tag.name[..]) };
+			let text = self.parse_raw_text(end_tag);
+			return Some(dom_tree::new_named_element(namespace, &name[..], attributes, text));
+		}
 
 		// if the next thing is not '<', parse until '<'. that is your text
 		let dom_text = self.parse_dom_text();
 		let t = if dom_text == "" { None } else { Some(dom_text) };
 		
 		// create an element
-		let new_ele = HtmlParser::give_element_type(ele_type.as_slice());
+		let mut element = dom_tree::new_named_element(namespace, &name[..], attributes, t);
 
-		let mut element : Option<dom_tree::Element>;
-		match new_ele {
-			Some(s) => element = Some(dom_tree::new_element(s, t)),
-			None => element = None,
-		}
+		let text_integration_point = foreign::is_mathml_text_integration_point(namespace, &name[..]);
+		let children_ns = if text_integration_point ||
+			foreign::is_html_integration_point(namespace, &name[..], &element.attributes) {
+			Namespace::Html
+		} else {
+			namespace
+		};
 
-		if element.is_none() {
-			return None;
-		}
+		self.open_elements.push(tag.name.clone());
 
 		loop {
+			// text after a child is that child's tail
+			let dom_text = self.parse_dom_text();
+			match element.children.last_mut() {
+				Some(c) => append_text(&mut c.tail, dom_text),
+				None => append_text(&mut element.text, dom_text),
+			}
+
 			if self.parse.end_of_string() {
 				break;
 			}
 
 			// tag candidate is either closing a tag or not. 
-			// if its closing ('</ >'), then consume it and break
+			// if it closes this element, then consume it and break. if it
+			// closes an ancestor, leave it for that ancestor; end tags of
+			// elements that are not open are ignored
 			// if its not, it must be another node that needs to be
 			// added
-			if self.parse.peek_next_char().unwrap() == '/' {
+			if self.parse.peek_next_char() == Some('/') {
+				let end_name = self.peek_end_tag_name();
+				let closes_self = end_name == tag.name;
+				let closes_ancestor = !closes_self &&
+					self.open_elements.iter().rev().skip(1).any(|open| *open == end_name);
+
+				if !closes_ancestor {
+					self.parse.consume_while(|c| c != '>');
+					self.parse.consume_char();
+				}
+				if closes_self || closes_ancestor {
+					break;
+				}
+			} else {
+				let next_tag = self.peek_node();
+
+				// html markup inside svg or mathml closes the foreign
+				// elements; the tag is parsed again by the nearest html
+				// ancestor
+				if children_ns != Namespace::Html &&
+				   foreign::breaks_out_of_foreign_content(&next_tag.name[..], &next_tag.attributes[..]) {
+					break;
+				}
+
+				let next_ns = match &next_tag.name[..] {
+					"mglyph" | "malignmark" if text_integration_point => Namespace::MathMl,
+					_ => children_ns,
+				};
+
+				let next_node = self.parse_element_in(next_ns);
+				if next_node.is_some() {
+					element.add_child_element(next_node.unwrap());
+				}
+			}
+		}
+
+		self.open_elements.pop();
+
+		// the contents of a template are inert; they belong to the template
+		// rather than being its children
+		if element.is(Namespace::Html, "template") {
+			element.template_contents = Some(dom_tree::DocumentFragment {
+				text: element.text.take(),
+				children: ::std::mem::replace(&mut element.children, Vec::new()),
+			});
+		}
+
+		Some(element)
+	}
+
+	/// Parse text followed by a sequence of sibling elements up to the end
+	/// of the input. Stray closing tags are ignored
+	fn parse_nodes(&mut self, namespace: Namespace) -> dom_tree::DocumentFragment {
+		let mut fragment = dom_tree::DocumentFragment::new();
+
+		loop {
+			let dom_text = self.parse_dom_text();
+			match fragment.children.last_mut() {
+				Some(c) => append_text(&mut c.tail, dom_text),
+				None => append_text(&mut fragment.text, dom_text),
+			}
+
+			if self.parse.end_of_string() {
+				break;
+			}
+
+			if self.parse.peek_next_char() == Some('/') {
 				self.parse.consume_while(|c| c != '>');
 				self.parse.consume_char();
-				break;
 			} else {
-				let next_node = self.parse_element();
-				if next_node.is_some() {
-					element.as_mut().unwrap().add_child_element(next_node.unwrap());
+				match self.parse_element_in(namespace) {
+					Some(e) => fragment.children.push(e),
+					None => {}
 				}
 			}
 		}
 
-		element
+		fragment
+	}
+}
+
+/// Parse 'input' as the contents of 'context', following the html fragment
+/// parsing algorithm. The context decides how the markup is read: inside
+/// elements such as <title> or <style> everything is text, inside svg and
+/// mathml the nodes are foreign content
+/// html.spec.whatwg.org/multipage/parsing.html#parsing-html-fragments
+pub fn parse_fragment(context: &dom_tree::Element, input: &str) -> dom_tree::DocumentFragment {
+	let mut parser = HtmlParser::new(input.to_string());
+
+	// the context's end tag is not part of the input, so all of it is text
+	if context.namespace == Namespace::Html && is_text_only_element(&context.tag_name[..]) {
+		let mut fragment = dom_tree::DocumentFragment::new();
+		fragment.text = parser.parse_raw_text(None);
+		return fragment;
+	}
+
+	let namespace = if foreign::is_mathml_text_integration_point(context.namespace, &context.tag_name[..]) ||
+		foreign::is_html_integration_point(context.namespace, &context.tag_name[..], &context.attributes) {
+		Namespace::Html
+	} else {
+		context.namespace
+	};
+
+	parser.in_body_fragment = !context.is(Namespace::Html, "html");
+	parser.parse_nodes(namespace)
+}
+
+/// Add 'text' to the end of the text run in 'run'
+fn append_text(run: &mut Option<String>, text: String) {
+	if text.is_empty() {
+		return;
+	}
+
+	match *run {
+		Some(ref mut s) => s.push_str(&text[..]),
+		None => *run = Some(text),
+	}
+}
+
+/// Html elements whose contents are read as raw text (rcdata, rawtext and
+/// plaintext in the tokenizer) instead of markup
+fn is_text_only_element(name: &str) -> bool {
+	match name {
+		"title" | "textarea" | "style" | "script" | "xmp" | "iframe" |
+		"noembed" | "noframes" | "plaintext" => true,
+		_ => false,
+	}
+}
+
+/// A start tag as written in the markup
+struct Tag {
+	name: String,
+	attributes: Vec<(String, String)>,
+	self_closing: bool,
+}
+
+/// Html elements that never have contents and need no closing tag
+fn is_void_element(name: &str) -> bool {
+	match name {
+		"area" | "base" | "br" | "col" | "embed" | "hr" | "img" | "input" |
+		"link" | "meta" | "source" | "track" | "wbr" => true,
+		_ => false,
+	}
+}
+
+#[cfg(test)]
+mod test_html_parser {
+	use std::collections::HashMap;
+	use dom_tree;
+	use dom_tree::{Element, Namespace};
+
+	fn parse(html: &str) -> Element {
+		super::HtmlParser::new(html.to_string()).parse_element().unwrap()
+	}
+
+	#[test]
+	fn foreign_content_breakout() {
+		let body = parse("<body><svg><circle></circle><p>Html</p></svg></body>");
+		assert_eq!(body.children.len(), 2);
+		assert!(body.children[0].is(Namespace::Svg, "svg"));
+		assert!(body.children[0].children[0].is(Namespace::Svg, "circle"));
+		assert!(body.children[1].is(Namespace::Html, "p"));
+		assert_eq!(body.children[1].text, Some("Html".to_string()));
+
+		// <font> only breaks out with a presentational attribute
+		let body = parse("<body><svg><font>a</font></svg></body>");
+		assert_eq!(body.children.len(), 1);
+		assert!(body.children[0].children[0].is(Namespace::Svg, "font"));
+
+		let body = parse("<body><svg><font color=\"red\">a</font></svg></body>");
+		assert_eq!(body.children.len(), 2);
+		assert!(body.children[1].is(Namespace::Html, "font"));
+		assert_eq!(body.children[1].get_attribute("color"), Some("red"));
+	}
+
+	#[test]
+	fn svg_case_correction() {
+		let svg = parse("<svg viewbox=\"0 0 1 1\"><clippath clippathunits=\"userSpaceOnUse\"></clippath>\
+						 <foreignobject><title>T</title></foreignobject></svg>");
+		assert_eq!(svg.get_attribute("viewBox"), Some("0 0 1 1"));
+		assert!(svg.children[0].is(Namespace::Svg, "clipPath"));
+		assert_eq!(svg.children[0].get_attribute("clipPathUnits"), Some("userSpaceOnUse"));
+		assert!(svg.children[1].is(Namespace::Svg, "foreignObject"));
+		// html names are not case corrected
+		assert!(svg.children[1].children[0].is(Namespace::Html, "title"));
+	}
+
+	#[test]
+	fn self_closing_foreign_tags() {
+		let body = parse("<body><svg><circle r=\"1\"/><rect/></svg><math><mi/></math><p/>a</p></body>");
+		let svg = &body.children[0];
+		assert_eq!(svg.children.len(), 2);
+		assert!(svg.children[0].is(Namespace::Svg, "circle"));
+		assert_eq!(svg.children[0].get_attribute("r"), Some("1"));
+		assert!(svg.children[0].children.is_empty());
+		assert!(svg.children[1].is(Namespace::Svg, "rect"));
+		assert!(body.children[1].children[0].is(Namespace::MathMl, "mi"));
+
+		// '/>' does not close html elements
+		assert!(body.children[2].is(Namespace::Html, "p"));
+		assert_eq!(body.children[2].text, Some("a".to_string()));
+	}
+
+	#[test]
+	fn raw_text_elements() {
+		let body = parse("<body><script>a<b</script><title>x <i>y</i></TITLE><p>z</p></body>");
+		assert!(body.children[0].children.is_empty());
+		assert_eq!(body.children[1].text, Some("x <i>y</i>".to_string()));
+		assert!(body.children[2].is(Namespace::Html, "p"));
+
+		// a document and a fragment read the same raw text
+		let script = &body.children[0];
+		let context = dom_tree::new_named_element(Namespace::Html, "script", HashMap::new(), None);
+		let fragment = super::parse_fragment(&context, "a<b");
+		assert_eq!(script.text, Some("a<b".to_string()));
+		assert_eq!(fragment.text, script.text);
+		assert!(fragment.children.is_empty());
 	}
 }
@@ -1,20 +1,167 @@
 use super::style_tree;
 use super::css::box_model;
 use super::css::stylesheet;
+use super::css::stylesheet::BlockType;
+use super::css::selector::PseudoElement;
+#[cfg(feature = "serde")]
+use serde::{Serialize, Serializer};
+#[cfg(feature = "serde")]
+use serde::ser::SerializeStruct;
 
 pub struct LayoutNode<'a> {
 	layout_box : box_model::Box,
 	block : stylesheet::BlockType,
 	content : &'a style_tree::StyleNode<'a>,
+	pub text : Vec<TextFragment<'a>>,
 	pub children : Vec<LayoutNode<'a>>,
 }
 
+/// A run of a box's own text and the declarations it is styled with. The
+/// text of a block box is split so its ::first-letter and ::first-line
+/// get fragments of their own; 'pseudo' says which one a fragment is.
+/// Text inside child boxes, including a ::before, is not considered
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct TextFragment<'a> {
+	pub text: String,
+	pub pseudo: Option<PseudoElement>,
+	pub declarations: Option<&'a Vec<stylesheet::Declaration>>,
+}
+
+/// Serialized as '{"tag_name": String, "box": Box, "block": BlockType,
+/// "text": [{"text": String, "pseudo": PseudoElement or null,
+/// "declarations": [...] or null}, ...], "children": [LayoutNode, ...]}'.
+/// The style node a layout node was built from is identified by its
+/// element's tag name only
+#[cfg(feature = "serde")]
+impl<'a> Serialize for LayoutNode<'a> {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		let mut node = try!(serializer.serialize_struct("LayoutNode", 5));
+		try!(node.serialize_field("tag_name", &self.content.element().tag_name));
+		try!(node.serialize_field("box", &self.layout_box));
+		try!(node.serialize_field("block", &self.block));
+		try!(node.serialize_field("text", &self.text));
+		try!(node.serialize_field("children", &self.children));
+		node.end()
+	}
+}
+
 pub fn build_layout_tree<'b>(style: &'b style_tree::StyleNode<'b> ) -> LayoutNode<'b> {
-	
+	let block = style.get_block_type();
+
 	LayoutNode {
 		layout_box: style.create_layout_box(),
-		block: style.get_block_type(),
+		block: block,
 		content: &style,
+		text: text_fragments(style, block),
 		children: style.children.iter().map(|child| { build_layout_tree(child)}).collect(),
 	}
 }
+
+/// Split the text of 'style' into fragments. Only block boxes have a first
+/// line and first letter. Lines are not wrapped, so the first line ends at
+/// the first newline that 'white-space' preserves
+fn text_fragments<'b>(style: &'b style_tree::StyleNode<'b>, block: BlockType) -> Vec<TextFragment<'b>> {
+	let text = match style.text() {
+		Some(t) if !t.is_empty() => t,
+		_ => return Vec::new(),
+	};
+
+	let fragment = |text: &str, pseudo: Option<PseudoElement>, declarations| {
+		TextFragment {
+			text: text.to_string(),
+			pseudo: pseudo,
+			declarations: declarations,
+		}
+	};
+
+	if block != BlockType::Block || (style.first_line.is_none() && style.first_letter.is_none()) {
+		return vec![fragment(text, None, style.declarations.as_ref())];
+	}
+
+	let white_space = style.get_white_space();
+	let line_end = if white_space.preserves_newlines() {
+		text.find('\n').unwrap_or(text.len())
+	} else {
+		text.len()
+	};
+
+	let (letter_start, letter_end) = match style.first_letter {
+		Some(_) => first_letter(&text[..line_end]),
+		None => (0, 0),
+	};
+
+	let mut fragments = Vec::new();
+	let line_declarations = style.first_line.as_ref().or(style.declarations.as_ref());
+	let line_pseudo = style.first_line.as_ref().map(|_| PseudoElement::FirstLine);
+
+	if letter_start > 0 {
+		fragments.push(fragment(&text[..letter_start], line_pseudo, line_declarations));
+	}
+	if letter_end > letter_start {
+		fragments.push(fragment(&text[letter_start..letter_end], Some(PseudoElement::FirstLetter), style.first_letter.as_ref()));
+	}
+	if line_end > letter_end {
+		fragments.push(fragment(&text[letter_end..line_end], line_pseudo, line_declarations));
+	}
+	if text.len() > line_end {
+		fragments.push(fragment(&text[line_end..], None, style.declarations.as_ref()));
+	}
+
+	fragments
+}
+
+/// The byte range of the first letter of 'line': after any leading white
+/// space, punctuation followed by one letter or digit and the punctuation
+/// right after it. (0, 0) if the line has no letter or digit
+/// www.w3.org/TR/css-pseudo-4/#first-letter-pattern
+fn first_letter(line: &str) -> (usize, usize) {
+	let start = line.len() - line.trim_left().len();
+	let mut chars = line[start..].char_indices().map(|(i, c)| (start + i, c)).peekable();
+
+	// leading punctuation
+	while let Some(&(_, c)) = chars.peek() {
+		if !is_punctuation(c) {
+			break;
+		}
+		chars.next();
+	}
+
+	let mut end = match chars.next() {
+		Some((i, c)) if c.is_alphanumeric() => i + c.len_utf8(),
+		_ => return (0, 0),
+	};
+
+	// trailing punctuation
+	for (i, c) in chars {
+		if !is_punctuation(c) {
+			break;
+		}
+		end = i + c.len_utf8();
+	}
+
+	(start, end)
+}
+
+fn is_punctuation(c: char) -> bool {
+	match c {
+		'!' | '"' | '#' | '%' | '&' | '\'' | '(' | ')' | '*' | ',' | '.' | '/' | ':' | ';' |
+		'?' | '@' | '[' | '\\' | ']' | '_' | '{' | '}' | '-' |
+		'\u{AB}' | '\u{BB}' | '\u{A1}' | '\u{BF}' | '\u{2018}' | '\u{2019}' | '\u{201C}' | '\u{201D}' => true,
+		_ => false,
+	}
+}
+
+#[cfg(test)]
+mod test_layout_tree {
+	use super::first_letter;
+
+	#[test]
+	fn layout_first_letter() {
+		assert_eq!(first_letter("Hello"), (0, 1));
+		assert_eq!(first_letter("  \u{201C}Quoted,\u{201D}"), (2, 6));
+		assert_eq!(first_letter("(1) item"), (0, 3));
+		assert_eq!(first_letter("\u{C9}t\u{E9}"), (0, 2));
+		assert_eq!(first_letter("   "), (0, 0));
+		assert_eq!(first_letter("-- "), (0, 0));
+	}
+}
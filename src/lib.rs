@@ -1,5 +1,17 @@
 #![feature(core)]
 
+//! With the optional `serde` cargo feature the DOM (`dom_tree::Document`),
+//! stylesheets (`css::stylesheet::StyleSheet`), style trees
+//! (`style_tree::StyleNode`) and layout trees (`layout_tree::LayoutNode`)
+//! implement `Serialize`. Everything but the style and layout trees, which
+//! borrow from the DOM, also implements `Deserialize`. Each type documents
+//! the shape it serializes to; enums use serde's default externally tagged
+//! form, e.g. `"Html"` or `{"Size": [12.0, "Px"]}`.
+
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+
 pub mod dom_tree;
 pub mod text_parser;
 pub mod html_parser;
@@ -0,0 +1,587 @@
+use std::collections::HashMap;
+
+use super::super::css::stylesheet::{Declaration, Property, Value, Unit, BlockType, WhiteSpaceType,
+									LengthContext, ALL_PROPERTIES, string_to_declarations,
+									string_to_property_value};
+use super::super::css::cascade::CssWideKeyword;
+use super::super::css::custom;
+use super::super::css::custom::PropertyRegistry;
+use super::super::css::color::Color;
+use super::super::css::media::MediaEnvironment;
+#[cfg(feature = "serde")]
+use serde::{Serialize, Serializer};
+#[cfg(feature = "serde")]
+use serde::ser::SerializeMap;
+
+/// The font size of 'medium', the initial font size
+const MEDIUM_FONT_SIZE: f32 = 16.0;
+
+/// What Property::Custom computes to. Custom properties have no slot of
+/// their own, each is read by name through custom_property()
+static GUARANTEED_INVALID: Value = Value::Missing;
+
+/// The computed value of every property of a style node, indexed by
+/// property. Properties the cascade gives no value take their parent's if
+/// they are inherited and their initial value otherwise. Lengths are in px,
+/// with the font size resolved against the parent's and other 'em's
+/// against the node's own; percentages other than those of 'font-size' and
+/// 'line-height' are left for layout.
+/// 'custom' holds the custom properties that have a value, as the text of
+/// their tokens, with var() substituted
+/// www.w3.org/TR/css-cascade-5/#computed
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComputedStyle {
+	values: Vec<Value>,
+	custom: HashMap<String, String>,
+	root_font_size: f32,
+}
+
+impl ComputedStyle {
+	/// Compute the style of a node from the declarations the cascade picks
+	/// for it and the computed style of its parent, None for the root.
+	/// Custom properties are computed first, so var() can be substituted
+	/// in the other properties; those 'registry' registers have to match
+	/// their syntax. Viewport units are resolved against the viewport of
+	/// 'media'
+	pub fn compute(declarations: Option<&Vec<Declaration>>,
+				   parent: Option<&ComputedStyle>,
+				   registry: &PropertyRegistry,
+				   media: &MediaEnvironment) -> ComputedStyle {
+		let mut custom = compute_custom_properties(declarations, parent, registry);
+		let mut values = Vec::with_capacity(ALL_PROPERTIES.len());
+		let mut from_parent = Vec::with_capacity(ALL_PROPERTIES.len());
+
+		for property in ALL_PROPERTIES.iter() {
+			let declared = declarations.and_then(|d| d.iter().rev().find(|x| x.property_name == *property))
+				.map(|d| substitute_var(*property, &d.property_value, &custom));
+
+			let inherit = match declared {
+				Some(Value::CssWide(CssWideKeyword::Inherit)) => true,
+				Some(Value::CssWide(CssWideKeyword::Initial)) => false,
+				Some(Value::CssWide(_)) | Some(Value::Missing) | None => property.inherited(),
+				Some(value) => {
+					values.push(value);
+					from_parent.push(false);
+					continue;
+				}
+			};
+
+			match (inherit, parent) {
+				(true, Some(p)) => values.push(p.get(*property).clone()),
+				_ => values.push(property.initial_value()),
+			}
+			from_parent.push(inherit && parent.is_some());
+		}
+
+		let parent_context = LengthContext {
+			font_size: parent.map_or(MEDIUM_FONT_SIZE, |p| p.font_size()),
+			root_font_size: parent.map_or(MEDIUM_FONT_SIZE, |p| p.root_font_size),
+			viewport: media.viewport,
+		};
+
+		let size = Property::FontSize as usize;
+		let font_size = if from_parent[size] {
+			parent_context.font_size
+		} else {
+			compute_font_size(&values[size], &parent_context)
+		};
+		values[size] = Value::Size(font_size, Unit::Px);
+
+		let root_font_size = parent.map_or(font_size, |p| p.root_font_size);
+		let context = LengthContext {
+			font_size: font_size,
+			root_font_size: root_font_size,
+			viewport: media.viewport,
+		};
+
+		// registered custom properties hold lengths in px
+		for (name, value) in custom.iter_mut() {
+			match registry.compute(&name[..], &value[..], &context) {
+				Some(computed) => *value = computed,
+				None => {}
+			}
+		}
+
+		let mut style = ComputedStyle {
+			values: values,
+			custom: custom,
+			root_font_size: root_font_size,
+		};
+
+		// 'color' comes before the properties that refer to it
+		for (i, property) in ALL_PROPERTIES.iter().enumerate() {
+			if from_parent[i] || *property == Property::FontSize {
+				continue;
+			}
+
+			let value = style.compute_value(*property, &style.values[i], &context, parent);
+			style.values[i] = value;
+		}
+
+		style
+	}
+
+	/// The computed value of 'value' given for 'property'. Lengths of
+	/// properties that cannot be negative are clamped to 0
+	fn compute_value(&self, property: Property, value: &Value, context: &LengthContext,
+					 parent: Option<&ComputedStyle>) -> Value {
+		let computed = match (property, value) {
+			(Property::Color, &Value::CurrentColor) => match parent {
+				Some(p) => p.get(Property::Color).clone(),
+				None => Property::Color.initial_value(),
+			},
+			(_, &Value::CurrentColor) => self.get(Property::Color).clone(),
+			(Property::LineHeight, &Value::Percentage(p)) => Value::Size(context.font_size * p / 100.0, Unit::Px),
+			(Property::LineHeight, &Value::Calc(_)) => match value.to_number(context) {
+				Some(n) => Value::Number(n),
+				None => match value.resolve_length(context, context.font_size) {
+					Some(n) => Value::Size(n, Unit::Px),
+					None => value.clone(),
+				},
+			},
+			(Property::FontWeight, &Value::Keyword(ref k)) => {
+				let weight = parent.map_or(400.0, |p| p.number(Property::FontWeight));
+				Value::Number(relative_font_weight(&k[..], weight))
+			}
+			(Property::BorderTopWidth, _) | (Property::BorderBottomWidth, _) |
+			(Property::BorderLeftWidth, _) | (Property::BorderRightWidth, _) => {
+				match *self.get(border_style(property)) {
+					Value::Keyword(ref k) if k == "none" || k == "hidden" => Value::Size(0.0, Unit::Px),
+					_ => absolute_lengths(value, context),
+				}
+			}
+			_ => absolute_lengths(value, context),
+		};
+
+		match computed {
+			Value::Size(n, unit) if n < 0.0 && property.non_negative() => Value::Size(0.0, unit),
+			Value::Number(n) if n < 0.0 && property.non_negative() => Value::Number(0.0),
+			computed => computed,
+		}
+	}
+
+	/// The computed value of 'property'. Property::Custom stands for every
+	/// custom property, so it has the guaranteed-invalid value; use
+	/// custom_property() for those
+	pub fn get(&self, property: Property) -> &Value {
+		match property {
+			Property::Custom => &GUARANTEED_INVALID,
+			_ => &self.values[property as usize],
+		}
+	}
+
+	/// The font size in px
+	pub fn font_size(&self) -> f32 {
+		match *self.get(Property::FontSize) {
+			Value::Size(n, _) => n,
+			_ => MEDIUM_FONT_SIZE,
+		}
+	}
+
+	/// The computed value of the custom property 'name', None if it has the
+	/// guaranteed-invalid value, as custom properties never set do
+	pub fn custom_property(&self, name: &str) -> Option<&str> {
+		self.custom.get(name).map(|v| &v[..])
+	}
+
+	/// The font size of the root element in px, what 'rem' is relative to
+	pub fn root_font_size(&self) -> f32 {
+		self.root_font_size
+	}
+
+	pub fn color(&self) -> Color {
+		match *self.get(Property::Color) {
+			Value::ColorValue(c) => c,
+			_ => Color::rgb(0, 0, 0),
+		}
+	}
+
+	/// Whether the node is laid out as a block, inline or not at all.
+	/// inline-block, inline-table and the like sit in a line; the other
+	/// display types are taken to be blocks
+	pub fn block_type(&self) -> BlockType {
+		match *self.get(Property::Display) {
+			Value::Block(b) => b,
+			Value::Keyword(ref k) if k.starts_with("inline") || k.starts_with("ruby") => BlockType::Inline,
+			_ => BlockType::Block,
+		}
+	}
+
+	pub fn white_space(&self) -> WhiteSpaceType {
+		match *self.get(Property::WhiteSpace) {
+			Value::WhiteSpace(w) => w,
+			_ => WhiteSpaceType::Normal,
+		}
+	}
+
+	/// The length 'property' computes to in px, 0 if it is a percentage,
+	/// 'auto' or not a length
+	pub fn length(&self, property: Property) -> f32 {
+		match *self.get(property) {
+			Value::Size(n, Unit::Px) => n,
+			_ => 0.0,
+		}
+	}
+
+	/// The number 'property' computes to, 0 if it is not a number
+	pub fn number(&self, property: Property) -> f32 {
+		match *self.get(property) {
+			Value::Number(n) => n,
+			_ => 0.0,
+		}
+	}
+}
+
+/// Serialized as a map from each property to its computed value, then
+/// from the name of each custom property with a value to its text
+#[cfg(feature = "serde")]
+impl Serialize for ComputedStyle {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		let mut map = try!(serializer.serialize_map(Some(self.values.len() + self.custom.len())));
+		for (property, value) in ALL_PROPERTIES.iter().zip(self.values.iter()) {
+			try!(map.serialize_entry(property, value));
+		}
+		for (name, value) in self.custom.iter() {
+			try!(map.serialize_entry(name, value));
+		}
+		map.end()
+	}
+}
+
+/// The value 'value' declared for 'property' gives once the var() in it
+/// are replaced by the custom properties in 'custom'. A value that does
+/// not parse after substitution, or uses a custom property with no value
+/// and no fallback, is invalid at computed-value time and acts as 'unset'
+/// www.w3.org/TR/css-variables-1/#invalid-at-computed-value-time
+fn substitute_var(property: Property, value: &Value, custom: &HashMap<String, String>) -> Value {
+	let mut lookup = |name: &str| custom.get(name).cloned();
+
+	let substituted = match *value {
+		Value::Unsubstituted(ref text) => custom::substitute(&text[..], &mut lookup).map(|text| {
+			string_to_property_value(property, &text[..])
+		}),
+		Value::PendingSubstitution(ref shorthand, ref text) => custom::substitute(&text[..], &mut lookup).and_then(|text| {
+			string_to_declarations(&shorthand[..], &text[..])
+				.and_then(|d| d.into_iter().find(|x| x.property_name == property))
+				.map(|d| d.property_value)
+		}),
+		_ => return value.clone(),
+	};
+
+	match substituted {
+		Some(Value::Missing) | None => Value::CssWide(CssWideKeyword::Unset),
+		Some(value) => value,
+	}
+}
+
+/// The computed values of the custom properties of a node: those it
+/// declares, with var() substituted, and those it inherits from 'parent'
+/// or that 'registry' gives an initial value
+fn compute_custom_properties(declarations: Option<&Vec<Declaration>>,
+							 parent: Option<&ComputedStyle>,
+							 registry: &PropertyRegistry) -> HashMap<String, String> {
+	let mut resolver = CustomProperties {
+		declared: HashMap::new(),
+		parent: parent,
+		registry: registry,
+		computed: HashMap::new(),
+		resolving: Vec::new(),
+		cyclic: Vec::new(),
+	};
+
+	for declaration in declarations.into_iter().flat_map(|d| d.iter()) {
+		match declaration.property_value {
+			Value::Custom(ref name, _) | Value::CustomCssWide(ref name, _) => {
+				resolver.declared.insert(name.clone(), declaration.property_value.clone());
+			}
+			_ => {}
+		}
+	}
+
+	let mut names: Vec<String> = resolver.declared.keys().cloned().collect();
+	names.extend(parent.into_iter().flat_map(|p| p.custom.keys().cloned()));
+	names.extend(registry.rules().into_iter().map(|r| r.name.clone()));
+
+	let mut custom = HashMap::new();
+	for name in names.into_iter() {
+		match resolver.value(&name[..]) {
+			Some(value) => {
+				custom.insert(name, value);
+			}
+			None => {}
+		}
+	}
+	custom
+}
+
+/// Works out the custom properties of a node one at a time, following the
+/// var() in their values to the ones they depend on. 'resolving' holds the
+/// properties being worked out, so a var() back to one of them closes a
+/// cycle; the properties in a cycle go in 'cyclic'
+/// www.w3.org/TR/css-variables-1/#cycles
+struct CustomProperties<'a> {
+	declared: HashMap<String, Value>,
+	parent: Option<&'a ComputedStyle>,
+	registry: &'a PropertyRegistry,
+	computed: HashMap<String, Option<String>>,
+	resolving: Vec<String>,
+	cyclic: Vec<String>,
+}
+
+impl<'a> CustomProperties<'a> {
+	/// The computed value of the custom property 'name', None for the
+	/// guaranteed-invalid value
+	fn value(&mut self, name: &str) -> Option<String> {
+		match self.computed.get(name) {
+			Some(value) => return value.clone(),
+			None => {}
+		}
+
+		let declared = match self.declared.get(name) {
+			Some(value) => value.clone(),
+			None => return self.unset(name),
+		};
+
+		let value = match declared {
+			Value::CustomCssWide(_, CssWideKeyword::Inherit) => self.inherited(name),
+			Value::CustomCssWide(_, CssWideKeyword::Initial) => self.registry.initial_value(name),
+			Value::Custom(_, text) => {
+				if self.resolving.iter().any(|n| n == name) {
+					let start = self.resolving.iter().position(|n| n == name).unwrap();
+					let cycle = self.resolving[start..].to_vec();
+					self.cyclic.extend(cycle);
+					return None;
+				}
+
+				self.resolving.push(name.to_string());
+				let substituted = custom::substitute(&text[..], &mut |n: &str| self.value(n));
+				self.resolving.pop();
+
+				let valid = match (&substituted, self.registry.get(name)) {
+					(&Some(ref v), Some(rule)) => custom::matches_syntax(&rule.syntax[..], &v[..]),
+					(&Some(_), None) => true,
+					(&None, _) => false,
+				};
+
+				// invalid at computed-value time
+				if self.cyclic.iter().any(|n| n == name) || !valid {
+					match self.registry.get(name) {
+						Some(_) => self.unset(name),
+						None => None,
+					}
+				} else {
+					substituted
+				}
+			}
+			_ => self.unset(name),
+		};
+
+		self.computed.insert(name.to_string(), value.clone());
+		value
+	}
+
+	fn inherited(&self, name: &str) -> Option<String> {
+		match self.parent {
+			Some(p) => p.custom.get(name).cloned(),
+			None => self.registry.initial_value(name),
+		}
+	}
+
+	/// The value of 'name' when it is not declared: its parent's if it is
+	/// inherited, as unregistered custom properties are, its initial value
+	/// otherwise
+	fn unset(&self, name: &str) -> Option<String> {
+		if self.registry.inherits(name) {
+			self.inherited(name)
+		} else {
+			self.registry.initial_value(name)
+		}
+	}
+}
+
+/// The font size 'value' gives in px. Relative sizes, 'em's and
+/// percentages are of the parent's font size, which 'context' holds
+/// www.w3.org/TR/css-fonts-4/#font-size-prop
+fn compute_font_size(value: &Value, context: &LengthContext) -> f32 {
+	let parent = context.font_size;
+
+	match *value {
+		Value::Keyword(ref k) => match &k[..] {
+			"xx-small" => 9.0,
+			"x-small" => 10.0,
+			"small" => 13.0,
+			"large" => 18.0,
+			"x-large" => 24.0,
+			"xx-large" => 32.0,
+			"xxx-large" => 48.0,
+			"larger" => parent * 1.2,
+			"smaller" => parent / 1.2,
+			_ => MEDIUM_FONT_SIZE,
+		},
+		_ => value.resolve_length(context, parent).map_or(parent, |n| n.max(0.0)),
+	}
+}
+
+/// The weight 'bolder' or 'lighter' give when the parent's is 'parent'
+/// www.w3.org/TR/css-fonts-4/#relative-weights
+fn relative_font_weight(keyword: &str, parent: f32) -> f32 {
+	if keyword == "bolder" {
+		if parent < 350.0 {
+			400.0
+		} else if parent < 550.0 {
+			700.0
+		} else {
+			parent.max(900.0)
+		}
+	} else if parent < 100.0 {
+		parent
+	} else if parent < 550.0 {
+		100.0
+	} else if parent < 750.0 {
+		400.0
+	} else {
+		700.0
+	}
+}
+
+/// The border style property that goes with border width 'property'
+fn border_style(property: Property) -> Property {
+	match property {
+		Property::BorderTopWidth => Property::BorderTopStyle,
+		Property::BorderBottomWidth => Property::BorderBottomStyle,
+		Property::BorderLeftWidth => Property::BorderLeftStyle,
+		_ => Property::BorderRightStyle,
+	}
+}
+
+/// 'value' with its lengths in px. Calculations that can be resolved
+/// without a percentage basis become lengths or numbers
+fn absolute_lengths(value: &Value, context: &LengthContext) -> Value {
+	match *value {
+		Value::Size(n, unit) => Value::Size(n * unit.to_px(context), Unit::Px),
+		Value::Calc(_) => match (value.to_px(context), value.to_number(context)) {
+			(Some(n), _) => Value::Size(n, Unit::Px),
+			(_, Some(n)) => Value::Number(n),
+			_ => value.clone(),
+		},
+		Value::List(ref items) => Value::List(items.iter().map(|v| absolute_lengths(v, context)).collect()),
+		_ => value.clone(),
+	}
+}
+
+#[cfg(test)]
+mod test_computed {
+	use super::ComputedStyle;
+	use super::super::super::css::stylesheet::{string_to_declarations, Declaration, Property, Value, Unit};
+	use super::super::super::css::custom::PropertyRegistry;
+	use super::super::super::css::color::Color;
+	use super::super::super::css::media::MediaEnvironment;
+
+	fn declarations(list: &[(&str, &str)]) -> Vec<Declaration> {
+		list.iter().flat_map(|&(name, value)| string_to_declarations(name, value).unwrap().into_iter()).collect()
+	}
+
+	fn compute(list: &[(&str, &str)], parent: Option<&ComputedStyle>) -> ComputedStyle {
+		ComputedStyle::compute(Some(&declarations(list)), parent, &PropertyRegistry::new(), &MediaEnvironment::default())
+	}
+
+	#[test]
+	fn computed_initial_and_inherited() {
+		let root = ComputedStyle::compute(None, None, &PropertyRegistry::new(), &MediaEnvironment::default());
+		assert_eq!(root.font_size(), 16.0);
+		assert_eq!(root.color(), Color::rgb(0, 0, 0));
+		assert_eq!(root.get(Property::FontWeight), &Value::Number(400.0));
+		assert_eq!(root.get(Property::Custom), &Value::Missing);
+		assert_eq!(root.length(Property::Custom), 0.0);
+
+		let parent = compute(&[("color", "red"), ("margin-top", "4px"),
+							   ("font-weight", "bold")], Some(&root));
+		let child = compute(&[("padding-top", "inherit"), ("color", "initial")], Some(&parent));
+		let grandchild = compute(&[("margin-top", "inherit"), ("color", "unset")], Some(&child));
+
+		assert_eq!(child.color(), Color::rgb(0, 0, 0));
+		assert_eq!(child.length(Property::MarginTop), 0.0);
+		assert_eq!(child.number(Property::FontWeight), 700.0);
+		assert_eq!(grandchild.length(Property::MarginTop), 0.0);
+		assert_eq!(grandchild.color(), Color::rgb(0, 0, 0));
+
+		let child = compute(&[("border-top-color", "currentColor")], Some(&parent));
+		assert_eq!(child.get(Property::BorderTopColor), &Value::ColorValue(Color::rgb(255, 0, 0)));
+	}
+
+	#[test]
+	fn computed_font_sizes_and_lengths() {
+		let root = compute(&[("font-size", "20px")], None);
+		let parent = compute(&[("font-size", "2em")], Some(&root));
+		assert_eq!(parent.font_size(), 40.0);
+		assert_eq!(parent.root_font_size(), 20.0);
+
+		let child = compute(&[("font-size", "50%"), ("margin-top", "1em"),
+							  ("margin-bottom", "2rem"), ("line-height", "150%"),
+							  ("padding-top", "calc(1em + 2px)")], Some(&parent));
+		assert_eq!(child.font_size(), 20.0);
+		assert_eq!(child.length(Property::MarginTop), 20.0);
+		assert_eq!(child.length(Property::MarginBottom), 40.0);
+		assert_eq!(child.get(Property::LineHeight), &Value::Size(30.0, Unit::Px));
+		assert_eq!(child.length(Property::PaddingTop), 22.0);
+
+		// line-height numbers are inherited as numbers
+		let parent = compute(&[("line-height", "1.5")], Some(&root));
+		let child = compute(&[("font-size", "larger")], Some(&parent));
+		assert_eq!(child.get(Property::LineHeight), &Value::Number(1.5));
+		assert_eq!(child.font_size(), 24.0);
+		assert_eq!(compute(&[("font-size", "x-large")], Some(&parent)).font_size(), 24.0);
+
+		// math can go out of range; the lengths are clamped instead
+		let child = compute(&[("padding-top", "calc(-10px)"), ("border-left-style", "solid"),
+							  ("border-left-width", "calc(1px - 1em)"), ("margin-top", "calc(-10px)")], Some(&root));
+		assert_eq!(child.length(Property::PaddingTop), 0.0);
+		assert_eq!(child.length(Property::BorderLeftWidth), 0.0);
+		assert_eq!(child.length(Property::MarginTop), -10.0);
+		assert_eq!(compute(&[("font-size", "calc(2px - 1em)")], Some(&root)).font_size(), 0.0);
+	}
+
+	#[test]
+	fn computed_keywords() {
+		let root = compute(&[("font-weight", "300")], None);
+		assert_eq!(compute(&[("font-weight", "bolder")], Some(&root)).number(Property::FontWeight), 400.0);
+		assert_eq!(compute(&[("font-weight", "lighter")], Some(&root)).number(Property::FontWeight), 100.0);
+
+		// borders without a style have no width
+		let style = compute(&[("border-top-width", "thick"), ("border-left-width", "thick"),
+							  ("border-left-style", "solid")], Some(&root));
+		assert_eq!(style.length(Property::BorderTopWidth), 0.0);
+		assert_eq!(style.length(Property::BorderLeftWidth), 5.0);
+		assert_eq!(style.length(Property::BorderRightWidth), 0.0);
+	}
+
+	#[test]
+	fn computed_custom_properties() {
+		let root = compute(&[("--size", "2em"), ("--color", "red"), ("--Color", "blue"), ("--empty", "")], None);
+		assert_eq!(root.custom_property("--size"), Some("2em"));
+		assert_eq!(root.custom_property("--empty"), Some(""));
+		assert_eq!(root.custom_property("--missing"), None);
+
+		let child = compute(&[("font-size", "20px"), ("margin", "var(--size) 0 var(--gap, 3px)"),
+							  ("color", "var(--Color)"), ("--gap", "var(--size)"),
+							  ("padding-top", "var(--missing)"), ("border-top-color", "var(--color, blue)")], Some(&root));
+		assert_eq!(child.custom_property("--color"), Some("red"));
+		assert_eq!(child.custom_property("--gap"), Some("2em"));
+		assert_eq!(child.length(Property::MarginTop), 40.0);
+		assert_eq!(child.length(Property::MarginBottom), 40.0);
+		assert_eq!(child.color(), Color::rgb(0, 0, 255));
+		assert_eq!(child.get(Property::BorderTopColor), &Value::ColorValue(Color::rgb(255, 0, 0)));
+		// invalid at computed-value time, so unset
+		assert_eq!(child.length(Property::PaddingTop), 0.0);
+
+		// custom properties in a cycle have no value, those using them fall back
+		let cycle = compute(&[("--a", "var(--b)"), ("--b", "var(--a, 1px)"), ("--c", "var(--a, 2px)"),
+							  ("--color", "initial"), ("margin-top", "var(--c)"), ("color", "var(--a)")], Some(&root));
+		assert_eq!(cycle.custom_property("--a"), None);
+		assert_eq!(cycle.custom_property("--b"), None);
+		assert_eq!(cycle.custom_property("--c"), Some("2px"));
+		assert_eq!(cycle.custom_property("--color"), None);
+		assert_eq!(cycle.length(Property::MarginTop), 2.0);
+		assert_eq!(cycle.color(), Color::rgb(0, 0, 0));
+	}
+}
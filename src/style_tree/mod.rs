@@ -1,118 +1,581 @@
 use super::css::stylesheet;
-use super::css::stylesheet::{Property, BlockType};
+use super::css::stylesheet::{Property, BlockType, WhiteSpaceType, ContentItem};
 use super::dom_tree;
 use super::css::box_model;
+use super::css::selector;
+use super::css::selector::{MatchContext, PseudoElement};
+use super::css::media::MediaEnvironment;
+use super::css::user_agent;
+use self::computed::ComputedStyle;
+#[cfg(feature = "serde")]
+use serde::{Serialize, Serializer};
+#[cfg(feature = "serde")]
+use serde::ser::SerializeStruct;
+
+pub mod computed;
 
 /// A style node is used to create a parallel tree to the dom tree. Each
-/// node contains a list of css declaration that would apply to the node 
+/// node contains the css declarations the cascade picks for the node, one
+/// for each property a matching rule sets, and the computed value of every
+/// property, inherited from its parent node where nothing is declared.
+/// Elements with a ::before or ::after that has content get an extra node
+/// as their first or last child, whose 'element' is the originating
+/// element and whose 'content' is the generated text
 pub struct StyleNode<'a> {
 	element : &'a dom_tree::Element,
-	pub declarations: Option<&'a Vec<stylesheet::Declaration>>,
+	pub pseudo: Option<PseudoElement>,
+	pub content: Option<String>,
+	pub declarations: Option<Vec<stylesheet::Declaration>>,
+	/// Declarations for the first line and first letter of the element's
+	/// text, applied by layout
+	pub first_line: Option<Vec<stylesheet::Declaration>>,
+	pub first_letter: Option<Vec<stylesheet::Declaration>>,
+	pub computed: ComputedStyle,
 	pub children: Vec<StyleNode<'a>>,
 }
 
 impl<'a> StyleNode<'a> {
 
+	/// A style node for 'node' alone, styled by the default user agent
+	/// stylesheet and 'style' and computed as the root
 	pub fn new<'c>(node: &'c dom_tree::Element, style: &'c stylesheet::StyleSheet) -> StyleNode<'c> {
 		let children = Vec::new();
-		let decls = 
-			style.ruleset.rule_map.get(&stylesheet::Selector::SelectorType(node.e_type));
+		let context = MatchContext::root(node);
+		let media = MediaEnvironment::default();
+		let user_agent = user_agent::default_stylesheet();
+		let sheets = stylesheet::StyleSheetList::new(&[&*user_agent, style]);
+		let declarations = sheets.cascade(&context, &media, None);
+		let computed = ComputedStyle::compute(declarations.as_ref(), None, sheets.registry(), &media);
 
 		StyleNode {
 			element: &node,
-			declarations: decls,
+			pseudo: None,
+			content: None,
+			declarations: declarations,
+			first_line: sheets.cascade(&context, &media, Some(PseudoElement::FirstLine)),
+			first_letter: sheets.cascade(&context, &media, Some(PseudoElement::FirstLetter)),
+			computed: computed,
 			children: children,
 		}
 	}
 
+	/// return the text this node displays: the generated content of a
+	/// ::before or ::after node, the element's own text otherwise
+	pub fn text(& self) -> Option<&str> {
+		match self.pseudo {
+			Some(_) => self.content.as_ref().map(|c| &c[..]),
+			None => self.element.text.as_ref().map(|t| &t[..]),
+		}
+	}
+
 	/// return a copy of the dom element contained by this style node
 	pub fn get_element(& self) -> dom_tree::Element {
 		(*self.element).clone()
 	}
 
-	/// return a box_model::Box based on the stylesheet declarations of the
-	/// StyleNode
+	/// return the dom element contained by this style node without copying
+	pub fn element(& self) -> &'a dom_tree::Element {
+		self.element
+	}
+
+	/// return a box_model::Box based on the computed style of the
+	/// StyleNode. Percentages are left as 0
 	pub fn create_layout_box(& self) -> box_model::Box {
-		let decls = self.declarations;
-		let mut retval : box_model::Box;
-
-		match decls {
-			Some(d) => {
-				let pt = stylesheet::box_value_from_declaration(d, Property::PaddingTop);
-				let pb = stylesheet::box_value_from_declaration(d, Property::PaddingBottom);
-				let pl = stylesheet::box_value_from_declaration(d, Property::PaddingLeft);
-				let pr = stylesheet::box_value_from_declaration(d, Property::PaddingRight);
-
-				let bt = stylesheet::box_value_from_declaration(d, Property::BorderTopHeight);
-				let bb = stylesheet::box_value_from_declaration(d, Property::BorderBottomHeight);
-				let bl = stylesheet::box_value_from_declaration(d, Property::BorderLeftHeight);
-				let br = stylesheet::box_value_from_declaration(d, Property::BorderRightHeight);
-
-				let mt = stylesheet::box_value_from_declaration(d, Property::MarginTop);
-				let mb = stylesheet::box_value_from_declaration(d, Property::MarginBottom);
-				let ml = stylesheet::box_value_from_declaration(d, Property::MarginLeft);
-				let mr = stylesheet::box_value_from_declaration(d, Property::MarginRight);
-				
-				retval = box_model::Box::default();
-				retval.padding.top = pt;
-				retval.padding.bottom = pb;
-				retval.padding.left = pl;
-				retval.padding.right = pr;
-
-				retval.border.top = bt;
-				retval.border.bottom = bb;
-				retval.border.left = bl;
-				retval.border.right = br;
-
-				retval.margin.top = mt;
-				retval.margin.bottom = mb;
-				retval.margin.left = ml;
-				retval.margin.right = mr;
-			}
-			None => {retval = box_model::Box::default();}
-		}
+		let computed = &self.computed;
+		let mut retval = box_model::Box::default();
+
+		retval.padding.top = computed.length(Property::PaddingTop);
+		retval.padding.bottom = computed.length(Property::PaddingBottom);
+		retval.padding.left = computed.length(Property::PaddingLeft);
+		retval.padding.right = computed.length(Property::PaddingRight);
+
+		retval.border.top = computed.length(Property::BorderTopWidth);
+		retval.border.bottom = computed.length(Property::BorderBottomWidth);
+		retval.border.left = computed.length(Property::BorderLeftWidth);
+		retval.border.right = computed.length(Property::BorderRightWidth);
+
+		retval.margin.top = computed.length(Property::MarginTop);
+		retval.margin.bottom = computed.length(Property::MarginBottom);
+		retval.margin.left = computed.length(Property::MarginLeft);
+		retval.margin.right = computed.length(Property::MarginRight);
 
 		retval
 	}
 
-	/// return a BlockType based on the stylesheet declaration of the
-	/// StyleNode
+	/// return a BlockType based on the computed 'display' of the StyleNode
 	pub fn get_block_type(& self) -> BlockType {
-		let decls = self.declarations;
-		let mut retval : BlockType;
-
-		match decls {
-			Some(d) => {
-				let display_val = d.iter().find(|x| x.property_name == Property::Display);
-
-				match display_val {
-					Some(d) => {
-						match d.property_value {
-							stylesheet::Value::Block(BlockType::Inline) => {retval = BlockType::Inline;}
-							_ => {retval = BlockType::Block;}
+		self.computed.block_type()
+	}
+
+	/// return the computed white-space value of this StyleNode
+	pub fn get_white_space(& self) -> WhiteSpaceType {
+		self.computed.white_space()
+	}
+
+	/// return the text of this node as it would be rendered: elements with
+	/// 'display: none' are left out, block boxes start on their own line,
+	/// or after a blank line when they have vertical margins, and white
+	/// space is collapsed according to 'white-space'. Like browsers,
+	/// generated content is not included
+	/// html.spec.whatwg.org/multipage/dom.html#the-innertext-idl-attribute
+	pub fn inner_text(& self) -> String {
+		let mut items = Vec::new();
+
+		self.collect_text(&mut items);
+		join_text_items(items)
+	}
+
+	fn collect_text(& self, items: &mut Vec<TextItem>) {
+		let block = self.get_block_type();
+		if block == BlockType::None || self.pseudo.is_some() {
+			return;
+		}
+
+		let white_space = self.get_white_space();
+		let computed = &self.computed;
+		let has_margins = computed.length(Property::MarginTop) > 0.0 ||
+						  computed.length(Property::MarginBottom) > 0.0;
+		let breaks = match block {
+			BlockType::Block if has_margins => 2,
+			BlockType::Block => 1,
+			_ => 0,
+		};
+
+		items.push(TextItem::Break(breaks));
+
+		if self.element.is(dom_tree::Namespace::Html, "br") {
+			items.push(TextItem::Text("\n".to_string(), false));
+		}
+
+		match self.element.text {
+			Some(ref t) => items.push(process_white_space(&t[..], white_space)),
+			None => {}
+		}
+
+		// a child's tail is this node's text, so it is shown even when the
+		// child is not. ::before and ::after share the element and its tail
+		for c in self.children.iter() {
+			c.collect_text(items);
+
+			match c.element.tail {
+				Some(ref t) if c.pseudo.is_none() => items.push(process_white_space(&t[..], white_space)),
+				_ => {}
+			}
+		}
+
+		items.push(TextItem::Break(breaks));
+	}
+}
+
+/// Pieces of rendered text. Text is marked true if its spaces are
+/// collapsible; Break is the number of line breaks required at a block
+/// boundary
+enum TextItem {
+	Text(String, bool),
+	Break(usize),
+}
+
+fn process_white_space(text: &str, white_space: WhiteSpaceType) -> TextItem {
+	if !white_space.collapses_spaces() {
+		return TextItem::Text(text.to_string(), false);
+	}
+
+	let keep_newlines = white_space.preserves_newlines();
+	let mut result = String::new();
+	let mut in_space = false;
+
+	for c in text.chars() {
+		if c == '\n' && keep_newlines {
+			// spaces around a preserved newline are removed
+			let trimmed = result.trim_right_matches(' ').len();
+			result.truncate(trimmed);
+			result.push('\n');
+			in_space = true;
+		} else if c.is_whitespace() {
+			if !in_space {
+				result.push(' ');
+			}
+			in_space = true;
+		} else {
+			result.push(c);
+			in_space = false;
+		}
+	}
+
+	TextItem::Text(result, true)
+}
+
+/// Join text items, collapsing spaces across item boundaries and turning
+/// runs of breaks into the largest number of newlines asked for. Breaks at
+/// the very start or end produce nothing
+fn join_text_items(items: Vec<TextItem>) -> String {
+	let mut result = String::new();
+	let mut pending_breaks = 0;
+	let mut collapsible_end = false;
+
+	for item in items.into_iter() {
+		match item {
+			TextItem::Break(n) => {
+				if !result.is_empty() && n > pending_breaks {
+					pending_breaks = n;
+				}
+			}
+			TextItem::Text(text, collapsible) => {
+				let at_line_start = result.is_empty() || pending_breaks > 0 ||
+									result.ends_with('\n');
+				let text = if collapsible && (at_line_start || collapsible_end) {
+					text.trim_left_matches(' ').to_string()
+				} else {
+					text
+				};
+
+				if text.is_empty() {
+					continue;
+				}
+
+				if pending_breaks > 0 {
+					if collapsible_end {
+						let trimmed = result.trim_right_matches(' ').len();
+						result.truncate(trimmed);
+					}
+					for _ in 0..pending_breaks {
+						result.push('\n');
+					}
+					pending_breaks = 0;
+				}
+
+				result.push_str(&text[..]);
+				collapsible_end = collapsible && text.ends_with(' ');
+			}
+		}
+	}
+
+	if collapsible_end {
+		let trimmed = result.trim_right_matches(' ').len();
+		result.truncate(trimmed);
+	}
+
+	result
+}
+
+/// Serialized as '{"tag_name": String, "namespace": Namespace,
+/// "pseudo": PseudoElement or null, "content": String or null,
+/// "declarations": [Declaration, ...] or null, "first_line": [...] or null,
+/// "first_letter": [...] or null, "computed": {Property: Value, ...},
+/// "children": [StyleNode, ...]}'.
+/// Only the element's name is written, its own children are already
+/// mirrored by 'children'
+#[cfg(feature = "serde")]
+impl<'a> Serialize for StyleNode<'a> {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		let mut node = try!(serializer.serialize_struct("StyleNode", 9));
+		try!(node.serialize_field("tag_name", &self.element.tag_name));
+		try!(node.serialize_field("namespace", &self.element.namespace));
+		try!(node.serialize_field("pseudo", &self.pseudo));
+		try!(node.serialize_field("content", &self.content));
+		try!(node.serialize_field("declarations", &self.declarations));
+		try!(node.serialize_field("first_line", &self.first_line));
+		try!(node.serialize_field("first_letter", &self.first_letter));
+		try!(node.serialize_field("computed", &self.computed));
+		try!(node.serialize_field("children", &self.children));
+		node.end()
+	}
+}
+
+/// A counter instance. 'depth' is the depth in the tree of the element
+/// that created it; it stays in scope for that element's descendants and
+/// following siblings
+struct Counter {
+	name: String,
+	value: i32,
+	depth: usize,
+}
+
+/// The counters and quote nesting in effect while the style tree is built
+/// in document order
+/// www.w3.org/TR/css-lists-3/#creating-a-counter
+struct GeneratedContent {
+	counters: Vec<Counter>,
+	quote_depth: usize,
+}
+
+impl GeneratedContent {
+	fn new() -> GeneratedContent {
+		GeneratedContent {
+			counters: Vec::new(),
+			quote_depth: 0,
+		}
+	}
+
+	/// Apply the counter-reset, counter-set and counter-increment
+	/// declarations of an element at 'depth', in that order
+	fn update(&mut self, decls: Option<&Vec<stylesheet::Declaration>>, depth: usize) {
+		let decls = match decls {
+			Some(d) => d,
+			None => return,
+		};
+
+		for &property in [Property::CounterReset, Property::CounterSet, Property::CounterIncrement].iter() {
+			let counters = decls.iter().rev().filter_map(|x| {
+				match (x.property_name, &x.property_value) {
+					(p, &stylesheet::Value::Counters(ref c)) if p == property => Some(c),
+					_ => None,
+				}
+			}).next();
+
+			for &(ref name, value) in counters.into_iter().flat_map(|c| c.iter()) {
+				match property {
+					// a reset replaces the counter a previous sibling created
+					Property::CounterReset => {
+						match self.counters.iter().rposition(|c| c.name == *name) {
+							Some(i) if self.counters[i].depth == depth => self.counters[i].value = value,
+							_ => self.counters.push(Counter { name: name.clone(), value: value, depth: depth }),
+						}
+					}
+					_ => {
+						let i = match self.counters.iter().rposition(|c| c.name == *name) {
+							Some(i) => i,
+							None => {
+								self.counters.push(Counter { name: name.clone(), value: 0, depth: depth });
+								self.counters.len() - 1
+							}
+						};
+
+						if property == Property::CounterSet {
+							self.counters[i].value = value;
+						} else {
+							self.counters[i].value += value;
 						}
 					}
-					None => {retval = BlockType::Block;}
 				}
 			}
-			None => {retval = BlockType::Block;}
 		}
+	}
 
-		retval
+	/// Drop the counters created below 'depth', once an element at 'depth'
+	/// and all of its descendants are done
+	fn leave(&mut self, depth: usize) {
+		self.counters.retain(|c| c.depth <= depth);
+	}
+
+	/// Build the text of a 'content' value for 'element'
+	fn text(&mut self, items: &[ContentItem], element: &dom_tree::Element) -> String {
+		let mut result = String::new();
+
+		for item in items.iter() {
+			match *item {
+				ContentItem::Text(ref t) => result.push_str(&t[..]),
+				ContentItem::Attr(ref name) => result.push_str(element.get_attribute(&name[..]).unwrap_or("")),
+				ContentItem::Counter(ref name, style) => {
+					let value = self.counters.iter().rev().find(|c| c.name == *name).map_or(0, |c| c.value);
+					result.push_str(&style.format(value)[..]);
+				}
+				ContentItem::Counters(ref name, ref separator, style) => {
+					let mut values = self.counters.iter().filter(|c| c.name == *name).peekable();
+
+					if values.peek().is_none() {
+						result.push_str(&style.format(0)[..]);
+					}
+
+					while let Some(c) = values.next() {
+						result.push_str(&style.format(c.value)[..]);
+						if values.peek().is_some() {
+							result.push_str(&separator[..]);
+						}
+					}
+				}
+				ContentItem::OpenQuote => {
+					result.push_str(if self.quote_depth % 2 == 0 { "\u{201C}" } else { "\u{2018}" });
+					self.quote_depth += 1;
+				}
+				ContentItem::CloseQuote => {
+					if self.quote_depth > 0 {
+						self.quote_depth -= 1;
+						result.push_str(if self.quote_depth % 2 == 0 { "\u{201D}" } else { "\u{2019}" });
+					}
+				}
+				ContentItem::NoOpenQuote => self.quote_depth += 1,
+				ContentItem::NoCloseQuote => {
+					if self.quote_depth > 0 {
+						self.quote_depth -= 1;
+					}
+				}
+			}
+		}
+
+		result
 	}
 }
 
+/// Build the style tree with the default user agent stylesheet under the
+/// author stylesheet 'style'
 pub fn build_style_tree<'c>(root: &'c dom_tree::Element, 
 							style: &'c stylesheet::StyleSheet) -> StyleNode<'c> {
 
+	build_style_tree_for(root, style, &MediaEnvironment::default())
+}
+
+/// Build the style tree with the @media and @import rules of 'style'
+/// evaluated against 'media'. Build it again when the viewport changes
+pub fn build_style_tree_for<'c>(root: &'c dom_tree::Element,
+								style: &'c stylesheet::StyleSheet,
+								media: &MediaEnvironment) -> StyleNode<'c> {
+
+	build_style_tree_with(root, &*user_agent::default_stylesheet(), style, media)
+}
+
+/// Build the style tree with 'user_agent' in place of the default user
+/// agent stylesheet, for embedders that bring their own. Its declarations
+/// lose to the author's unless it has the user agent origin; see
+/// user_agent::stylesheet_from_css
+pub fn build_style_tree_with<'c>(root: &'c dom_tree::Element,
+								 user_agent: &stylesheet::StyleSheet,
+								 style: &stylesheet::StyleSheet,
+								 media: &MediaEnvironment) -> StyleNode<'c> {
+
+	build_style_tree_from_sheets(root, &[user_agent, style], media)
+}
+
+/// Build the style tree with every rule of 'sheets' that matches an
+/// element, whatever its origin: the user agent stylesheet, user
+/// stylesheets and each author stylesheet, such as those of the document's
+/// <style> elements, in document order
+pub fn build_style_tree_from_sheets<'c>(root: &'c dom_tree::Element,
+										sheets: &[&stylesheet::StyleSheet],
+										media: &MediaEnvironment) -> StyleNode<'c> {
+
+	let sheets = stylesheet::StyleSheetList::new(sheets);
+	build_style_node(root, &MatchContext::root(root), &sheets, media, None, &mut GeneratedContent::new(), 0)
+}
+
+/// 'context' locates 'element' in the DOM so rules with combinators can be
+/// matched against its ancestors and siblings. 'sheets' are cascaded
+/// together, in order, and the result computed against 'parent', the
+/// computed style of the parent node. Counters are updated in document
+/// order: the element, its ::before, its children, its ::after
+fn build_style_node<'c>(element: &'c dom_tree::Element,
+						context: &MatchContext,
+						sheets: &stylesheet::StyleSheetList,
+						media: &MediaEnvironment,
+						parent: Option<&ComputedStyle>,
+						generated: &mut GeneratedContent,
+						depth: usize) -> StyleNode<'c> {
+	let declarations = sheets.cascade(context, media, None);
+	let computed = ComputedStyle::compute(declarations.as_ref(), parent, sheets.registry(), media);
+	generated.update(declarations.as_ref(), depth);
+
+	// ::before and ::after inherit from the element
+	let pseudo_style = |pseudo| sheets.cascade(context, media, Some(pseudo)).map(|d| {
+		let style = ComputedStyle::compute(Some(&d), Some(&computed), sheets.registry(), media);
+		(d, style)
+	});
+
+	let mut children = Vec::new();
+
+	match build_pseudo_node(element, pseudo_style(PseudoElement::Before), PseudoElement::Before, generated, depth + 1) {
+		Some(n) => children.push(n),
+		None => {}
+	}
+
+	for (i, child) in element.children.iter().enumerate() {
+		children.push(build_style_node(child, &context.child(i), sheets, media, Some(&computed), generated, depth + 1));
+	}
+
+	match build_pseudo_node(element, pseudo_style(PseudoElement::After), PseudoElement::After, generated, depth + 1) {
+		Some(n) => children.push(n),
+		None => {}
+	}
+
+	generated.leave(depth);
+
 	StyleNode {
-		element: &root,
-		declarations: style.
-					  ruleset.
-					  rule_map.
-					  get(&stylesheet::Selector::SelectorType(root.e_type)),
-		children: root.children.iter().map(|child| build_style_tree(child, style)).collect(),
+		element: element,
+		pseudo: None,
+		content: None,
+		declarations: declarations,
+		first_line: sheets.cascade(context, media, Some(PseudoElement::FirstLine)),
+		first_letter: sheets.cascade(context, media, Some(PseudoElement::FirstLetter)),
+		computed: computed,
+		children: children,
+	}
+}
+
+/// Build the node for the ::before or ::after of 'element' from the
+/// declarations cascaded for it and its computed style, if any rule styles
+/// it. There is none unless its content computes to something other than
+/// 'none' or 'normal', or if it has 'display: none'
+fn build_pseudo_node<'c>(element: &'c dom_tree::Element,
+						 style: Option<(Vec<stylesheet::Declaration>, ComputedStyle)>,
+						 pseudo: PseudoElement,
+						 generated: &mut GeneratedContent,
+						 depth: usize) -> Option<StyleNode<'c>> {
+	let (declarations, computed) = match style {
+		Some(s) => s,
+		None => return None,
+	};
+
+	let items = match *computed.get(Property::Content) {
+		stylesheet::Value::Content(ref items) if !items.is_empty() => items.clone(),
+		_ => return None,
+	};
+
+	let node = StyleNode {
+		element: element,
+		pseudo: Some(pseudo),
+		content: None,
+		declarations: Some(declarations),
+		first_line: None,
+		first_letter: None,
+		computed: computed,
+		children: Vec::new(),
+	};
+
+	if node.get_block_type() == BlockType::None {
+		return None;
 	}
 
+	generated.update(node.declarations.as_ref(), depth);
+	let content = generated.text(&items[..], element);
+
+	Some(StyleNode { content: Some(content), .. node })
 }
 
+/// Return the subtrees whose style has to be recomputed after the mutations
+/// in 'records', for elements styled by 'sheets'. Changing an element's
+/// children or attributes restyles it and everything below it. When a rule
+/// uses a sibling combinator, an attribute change can also affect the
+/// element's later siblings, so it restyles its parent's subtree. Text
+/// changes only the element holding it. Paths already covered by another
+/// returned path are left out.
+/// With :has() any change can also restyle an ancestor, so the subtree of
+/// the farthest ancestor a :has() argument reaches is restyled, one level
+/// up for each of its combinators. That makes ':has(> img)' cheap, but a
+/// :has() argument with a descendant combinator, as in ':has(img)',
+/// restyles the whole document on every change
+pub fn restyle_roots(records: &[dom_tree::MutationRecord],
+					 sheets: &[&stylesheet::StyleSheet]) -> Vec<dom_tree::NodePath> {
+	let mut roots: Vec<dom_tree::NodePath> = Vec::new();
+	let siblings = if sheets.iter().any(|s| s.uses_sibling_combinators()) { 1 } else { 0 };
+	let has_reach = sheets.iter().fold(Some(0), |reach, s| selector::max_reach(reach, s.has_reach()));
+
+	for record in records.iter() {
+		// how many levels above the target the restyled subtree starts
+		let levels = match (has_reach, &record.kind) {
+			(None, _) => record.target.len(),
+			(Some(n), _) if n > 0 => n + siblings,
+			(_, &dom_tree::MutationKind::Attributes { .. }) => siblings,
+			_ => 0,
+		};
+		let depth = record.target.len().saturating_sub(levels);
+		let target = record.target[..depth].to_vec();
+
+		if roots.iter().any(|r| target.starts_with(&r[..])) {
+			continue;
+		}
+
+		roots.retain(|r| !r.starts_with(&target[..]));
+		roots.push(target);
+	}
+
+	roots
+}
@@ -66,6 +66,17 @@ impl TextParser {
 		result
 	}
 
+	/// Return the cursor position, to be handed back to rewind() when a
+	/// caller needs to look further ahead than peek_next_char()
+	pub fn mark(&self) -> usize {
+		self.cursor
+	}
+
+	/// Move the cursor back to a position returned by mark()
+	pub fn rewind(&mut self, mark: usize) {
+		self.cursor = mark;
+	}
+
 	/// Consume whitespace characters until non-whitespace char is hit
 	pub fn consume_whitespace(&mut self) {
 		self.consume_while(|c| c.is_whitespace());
@@ -182,4 +193,17 @@ mod test_parser {
 			None => println!("Done"),
 		}
 	}
-}
\ No newline at end of file
+
+	#[test]
+	fn parser_mark_and_rewind() {
+		let test_string = "<svg><p>";
+		let mut p = super::TextParser::new(test_string.to_string());
+
+		let mark = p.mark();
+		assert_eq!(p.consume_while(|c| c != '>'), "<svg");
+
+		p.rewind(mark);
+		assert_eq!(p.peek_char(), Some('<'));
+		assert_eq!(p.consume_while(|c| c != 'v'), "<s");
+	}
+}
@@ -1,4 +1,6 @@
 extern crate ac_browser;
+#[cfg(feature = "serde")]
+extern crate serde_json;
 
 use ac_browser::dom_tree;
 use ac_browser::text_parser;
@@ -16,6 +18,23 @@ pub fn test_parse_doctype(doctype: &str, is_proper: bool) {
 	}
 }
 
+/// The value the cascade gives 'property' of 'node', if any
+fn declared<'a>(node: &'a style_tree::StyleNode, property: css::stylesheet::Property) -> Option<&'a css::stylesheet::Value> {
+	match node.declarations {
+		Some(ref d) => d.iter().find(|x| x.property_name == property).map(|x| &x.property_value),
+		None => None,
+	}
+}
+
+/// The length the cascade gives the line-height of 'node', 0 if none. The
+/// style tests use line-height to tell which rule matched
+fn line_height(node: &style_tree::StyleNode) -> u32 {
+	match declared(node, css::stylesheet::Property::LineHeight) {
+		Some(&css::stylesheet::Value::Size(n, _)) => n as u32,
+		_ => 0,
+	}
+}
+
 #[test]
 fn html_parse_doctype() {
 	let proper_doctype = "<!DOCTYPE html>";
@@ -92,7 +111,7 @@ fn build_style_node() {
 	let mut document = dom_tree::Document::new(dom_tree::Doctype::Html);
 	document.element = html.parse_element();
 
-	let css_text = "h1 {
+	let css_text = "head {
 					font-size: 12px;
 					line-height: 32px;
 					color: red
@@ -112,10 +131,11 @@ fn build_style_node() {
 
 	let head_decs = &head_style.declarations;
 
+	// the author's declarations come after the user agent's display: none
 	assert!(head_decs.is_some());
-	assert!(head_decs.unwrap()[0].property_name == css::stylesheet::Property::FontSize);
-	assert!(head_decs.unwrap()[1].property_name == css::stylesheet::Property::LineHeight);
-	assert!(head_decs.unwrap()[2].property_name == css::stylesheet::Property::Color);
+	let head_properties: Vec<_> = head_decs.as_ref().unwrap().iter().map(|d| d.property_name).collect();
+	assert_eq!(head_properties, vec![css::stylesheet::Property::Display, css::stylesheet::Property::FontSize,
+									 css::stylesheet::Property::LineHeight, css::stylesheet::Property::Color]);
 
 	assert!(head_style.get_element().e_type == dom_tree::ElementType::Head);
 
@@ -126,9 +146,9 @@ fn build_style_node() {
 	let body_decs = &body_style.declarations;
 
 	assert!(body_decs.is_some());
-	assert!(body_decs.unwrap()[0].property_name == css::stylesheet::Property::Color);
-	assert!(body_decs.unwrap()[1].property_name == css::stylesheet::Property::FontSize);
-	assert!(body_decs.unwrap()[2].property_name == css::stylesheet::Property::LineHeight);
+	let body_properties: Vec<_> = body_decs.as_ref().unwrap().iter().map(|d| d.property_name).collect();
+	assert!(body_properties.ends_with(&[css::stylesheet::Property::Color, css::stylesheet::Property::FontSize,
+										css::stylesheet::Property::LineHeight]));
 
 	assert!(body_style.get_element().e_type == dom_tree::ElementType::Body);
 }
@@ -149,7 +169,7 @@ fn build_style_layout_tree() {
 	let mut document = dom_tree::Document::new(dom_tree::Doctype::Html);
 	document.element = html.parse_element();
 
-	let css_text = "h1 {
+	let css_text = "head {
 					font-size: 12px;
 					line-height: 32px;
 					color: red
@@ -183,16 +203,16 @@ fn build_style_layout_tree() {
 	let head_decs = &ch[0].declarations;
 
 	assert!(head_decs.is_some());
-	assert!(head_decs.unwrap()[0].property_name == css::stylesheet::Property::FontSize);
-	assert!(head_decs.unwrap()[1].property_name == css::stylesheet::Property::LineHeight);
-	assert!(head_decs.unwrap()[2].property_name == css::stylesheet::Property::Color);
+	let head_properties: Vec<_> = head_decs.as_ref().unwrap().iter().map(|d| d.property_name).collect();
+	assert!(head_properties.ends_with(&[css::stylesheet::Property::FontSize, css::stylesheet::Property::LineHeight,
+										css::stylesheet::Property::Color]));
 
 	let body_decs = &ch[1].declarations;	
 
 	assert!(body_decs.is_some());
-	assert!(body_decs.unwrap()[0].property_name == css::stylesheet::Property::Color);
-	assert!(body_decs.unwrap()[1].property_name == css::stylesheet::Property::FontSize);
-	assert!(body_decs.unwrap()[2].property_name == css::stylesheet::Property::LineHeight);
+	let body_properties: Vec<_> = body_decs.as_ref().unwrap().iter().map(|d| d.property_name).collect();
+	assert!(body_properties.ends_with(&[css::stylesheet::Property::Color, css::stylesheet::Property::FontSize,
+										css::stylesheet::Property::LineHeight]));
 
 
 	// Build Layout Tree
@@ -220,7 +240,672 @@ fn parse_title_selector() {
 	css_parse_selector("h3", true);
 	css_parse_selector("h4", true);
 
-	css_parse_selector("none", false);
-
 	css_parse_selector("title {", true);
+	css_parse_selector("*", true);
+	css_parse_selector("p.note#main[lang|=en]", true);
+
+	css_parse_selector("#", false);
+	css_parse_selector("p.", false);
+	css_parse_selector("[lang", false);
+}
+
+#[test]
+fn html_parse_foreign_content() {
+	let test_string = 
+	"<html>\
+		<body>\
+			<svg viewbox=\"0 0 10 10\">\
+				<lineargradient id=\"g\"></lineargradient>\
+				<circle r=\"5\"/>\
+				<foreignobject><title>Html again</title></foreignobject>\
+			</svg>\
+			<math definitionurl=\"x\"><mi>x</mi></math>\
+		</body>\
+	</html>";
+	let mut html = html_parser::HtmlParser::new(test_string.to_string());
+	let root = html.parse_element().unwrap();
+	let body = &root.children[0];
+
+	assert_eq!(body.children.len(), 2);
+
+	let svg = &body.children[0];
+	assert!(svg.is(dom_tree::Namespace::Svg, "svg"));
+	assert_eq!(svg.get_attribute("viewBox"), Some("0 0 10 10"));
+	assert_eq!(svg.children.len(), 3);
+
+	assert!(svg.children[0].is(dom_tree::Namespace::Svg, "linearGradient"));
+	assert!(svg.children[1].is(dom_tree::Namespace::Svg, "circle"));
+	assert!(svg.children[1].children.is_empty());
+	assert!(svg.children[2].is(dom_tree::Namespace::Svg, "foreignObject"));
+
+	let title = &svg.children[2].children[0];
+	assert_eq!(title.namespace, dom_tree::Namespace::Html);
+	assert_eq!(title.e_type, dom_tree::ElementType::Title);
+
+	let math = &body.children[1];
+	assert!(math.is(dom_tree::Namespace::MathMl, "math"));
+	assert_eq!(math.get_attribute("definitionURL"), Some("x"));
+	assert!(math.children[0].is(dom_tree::Namespace::MathMl, "mi"));
+}
+
+#[test]
+fn html_parse_foreign_content_breakout() {
+	let test_string = 
+	"<html>\
+		<body>\
+			<svg><circle/><p>Html</p></svg>\
+			<math>\
+				<annotation-xml encoding=\"Text/Html\"><b>Html</b></annotation-xml>\
+				<annotation-xml><b>Breaks out</b></annotation-xml>\
+			</math>\
+			<math><mi><mglyph/><i>x</i></mi></math>\
+		</body>\
+	</html>";
+	let mut html = html_parser::HtmlParser::new(test_string.to_string());
+	let root = html.parse_element().unwrap();
+	let body = &root.children[0];
+
+	assert_eq!(body.children.len(), 5);
+
+	let svg = &body.children[0];
+	assert!(svg.is(dom_tree::Namespace::Svg, "svg"));
+	assert_eq!(svg.children.len(), 1);
+	assert!(body.children[1].is(dom_tree::Namespace::Html, "p"));
+
+	let math = &body.children[2];
+	assert_eq!(math.children.len(), 2);
+	assert!(math.children[0].children[0].is(dom_tree::Namespace::Html, "b"));
+	assert!(math.children[1].children.is_empty());
+	assert!(body.children[3].is(dom_tree::Namespace::Html, "b"));
+
+	let mi = &body.children[4].children[0];
+	assert!(mi.children[0].is(dom_tree::Namespace::MathMl, "mglyph"));
+	assert!(mi.children[1].is(dom_tree::Namespace::Html, "i"));
+}
+
+#[test]
+fn style_selectors_respect_namespaces() {
+	let html_string = "<html><body><svg><title>Not html</title></svg><title>Html</title></body></html>";
+	let mut html = html_parser::HtmlParser::new(html_string.to_string());
+	let root = html.parse_element().unwrap();
+
+	let css_text = "@namespace url(http://www.w3.org/1999/xhtml);
+					@namespace svg url(http://www.w3.org/2000/svg);
+					title { line-height: 2px }
+					svg|title { line-height: 4px }";
+	let mut css = css::parser::CssParser::new(css_text.to_string());
+	let stylesheet = css.parse_css();
+
+	let style_tree = style_tree::build_style_tree(&root, &stylesheet);
+	let body = &style_tree.children[0];
+	let svg_title = &body.children[0].children[0];
+	let html_title = &body.children[1];
+
+	assert!(svg_title.declarations.as_ref().unwrap()[0].property_value == css::stylesheet::Value::Size(4.0, css::stylesheet::Unit::Px));
+	assert!(declared(html_title, css::stylesheet::Property::LineHeight) ==
+			Some(&css::stylesheet::Value::Size(2.0, css::stylesheet::Unit::Px)));
+
+	// the user agent stylesheet hides html titles only
+	assert!(svg_title.get_block_type() != css::stylesheet::BlockType::None);
+	assert!(html_title.get_block_type() == css::stylesheet::BlockType::None);
+}
+
+#[test]
+fn restyle_from_mutation_records() {
+	let html_string = "<html><head><title>T</title></head><body></body></html>";
+	let mut html = html_parser::HtmlParser::new(html_string.to_string());
+	let mut document = dom_tree::Document::new(dom_tree::Doctype::Html);
+	document.element = html.parse_element();
+
+	let stylesheet = css::parser::CssParser::new("body.dark { line-height: 2px }".to_string()).parse_css();
+	let dirty = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+	let d = dirty.clone();
+	document.observe(Vec::new(), dom_tree::ObserverOptions::all(),
+					 Box::new(move |records: &[dom_tree::MutationRecord]| {
+		*d.borrow_mut() = style_tree::restyle_roots(records, &[&stylesheet]);
+	}));
+
+	document.batch(|doc| {
+		doc.set_text(&[0, 0], Some("New title".to_string()));
+		doc.set_attribute(&[1], "class", "dark");
+		doc.append_child(&[1], dom_tree::new_element(dom_tree::ElementType::Title, None));
+		doc.set_attribute(&[0], "lang", "en");
+	});
+
+	assert_eq!(*dirty.borrow(), vec![vec![1], vec![0]]);
+
+	// with a sibling combinator an attribute change can affect the later
+	// siblings, so the class on <body> restyles everything under the
+	// document element
+	let plain = css::parser::CssParser::new("body.dark { line-height: 2px }".to_string()).parse_css();
+	let siblings = css::parser::CssParser::new("head + body.dark { line-height: 2px }".to_string()).parse_css();
+	document.set_attribute(&[1], "class", "light");
+	assert_eq!(style_tree::restyle_roots(document.pending_mutations(), &[&plain]), vec![vec![1]]);
+	assert_eq!(style_tree::restyle_roots(document.pending_mutations(), &[&plain, &siblings]),
+			   vec![Vec::<usize>::new()]);
+}
+
+#[test]
+fn restyle_with_has_selectors() {
+	let html_string = "<html><body><ul><li>1</li></ul></body></html>";
+	let mut html = html_parser::HtmlParser::new(html_string.to_string());
+	let mut document = dom_tree::Document::new(dom_tree::Doctype::Html);
+	document.element = html.parse_element();
+
+	document.set_text(&[0, 0, 0], None);
+	let plain = css::parser::CssParser::new("li { line-height: 1px }".to_string()).parse_css();
+	let has = css::parser::CssParser::new("ul:has(li:empty) { line-height: 2px }".to_string()).parse_css();
+	let has_child = css::parser::CssParser::new("ul:has(> li:empty) { line-height: 2px }".to_string()).parse_css();
+	let siblings = css::parser::CssParser::new("li + li { line-height: 3px }".to_string()).parse_css();
+
+	assert_eq!(style_tree::restyle_roots(document.pending_mutations(), &[&plain]), vec![vec![0, 0, 0]]);
+	// the <ul> now matches, so restyling the <li> alone is not enough. A
+	// descendant combinator in :has() can reach any ancestor
+	assert_eq!(style_tree::restyle_roots(document.pending_mutations(), &[&plain, &has]),
+			   vec![Vec::<usize>::new()]);
+	assert_eq!(style_tree::restyle_roots(document.pending_mutations(), &[&plain, &has_child]), vec![vec![0, 0]]);
+	// with sibling combinators the <ul> can also restyle its later siblings
+	assert_eq!(style_tree::restyle_roots(document.pending_mutations(), &[&has_child, &siblings]), vec![vec![0]]);
+	assert!(style_tree::restyle_roots(&[], &[&has]).is_empty());
+}
+
+#[test]
+fn html_parse_template_contents() {
+	let test_string = "<html><body><template><title>Row</title><body></body></template></body></html>";
+	let mut html = html_parser::HtmlParser::new(test_string.to_string());
+	let root = html.parse_element().unwrap();
+	let template = &root.children[0].children[0];
+
+	assert!(template.is(dom_tree::Namespace::Html, "template"));
+	assert!(template.children.is_empty());
+
+	let contents = template.template_contents.as_ref().unwrap();
+	assert_eq!(contents.children.len(), 2);
+	assert_eq!(contents.children[0].e_type, dom_tree::ElementType::Title);
+
+	// template contents are never styled
+	let css = css::parser::CssParser::new("title { line-height: 2px }".to_string()).parse_css();
+	let style = style_tree::build_style_tree(&root, &css);
+	assert!(style.children[0].children[0].children.is_empty());
+}
+
+#[test]
+fn html_parse_fragment_in_context() {
+	let body = dom_tree::new_element(dom_tree::ElementType::Body, None);
+	let fragment = html_parser::parse_fragment(&body, "Intro<title>A</title></p><head></head><body><p>B</p></body>");
+
+	assert_eq!(fragment.text, Some("Intro".to_string()));
+	assert_eq!(fragment.children.len(), 2);
+	assert_eq!(fragment.children[0].e_type, dom_tree::ElementType::Title);
+	assert!(fragment.children[1].is(dom_tree::Namespace::Html, "p"));
+
+	let title = dom_tree::new_element(dom_tree::ElementType::Title, None);
+	let fragment = html_parser::parse_fragment(&title, "<b>not markup</b>");
+	assert_eq!(fragment.text, Some("<b>not markup</b>".to_string()));
+	assert!(fragment.children.is_empty());
+
+	let svg = dom_tree::new_named_element(dom_tree::Namespace::Svg, "svg",
+										  std::collections::HashMap::new(), None);
+	let fragment = html_parser::parse_fragment(&svg, "<clippath/><rect/>");
+	assert_eq!(fragment.children.len(), 2);
+	assert!(fragment.children[0].is(dom_tree::Namespace::Svg, "clipPath"));
+	assert!(fragment.children[1].is(dom_tree::Namespace::Svg, "rect"));
+}
+
+#[test]
+fn style_inner_text() {
+	let html_string = "<html>\
+		<body>  Some   text\
+			<title>Hidden</title>\
+			<p>Para</p>\
+			<p>  keep   this \n Last</p>\
+		</body>\
+	</html>";
+	let mut html = html_parser::HtmlParser::new(html_string.to_string());
+	let root = html.parse_element().unwrap();
+
+	assert_eq!(root.text_content(), "  Some   textHiddenPara  keep   this \n Last");
+
+	let css_text = "title { display: none }";
+	let stylesheet = css::parser::CssParser::new(css_text.to_string()).parse_css();
+	let style = style_tree::build_style_tree(&root, &stylesheet);
+
+	assert_eq!(style.inner_text(), "Some text\n\nPara\n\nkeep this Last");
+
+	// white-space is inherited by the paragraphs
+	let css_text = "title { display: none }
+					body { white-space: pre-line }";
+	let stylesheet = css::parser::CssParser::new(css_text.to_string()).parse_css();
+	let style = style_tree::build_style_tree(&root, &stylesheet);
+
+	assert_eq!(style.inner_text(), "Some text\n\nPara\n\nkeep this\nLast");
+
+	// text after an inline child stays in place; a block without vertical
+	// margins only starts a new line
+	let html_string = "<html><body><p>a<b>b</b>c <i>d</i></p><p class=\"flush\">e</p>f</body></html>";
+	let mut html = html_parser::HtmlParser::new(html_string.to_string());
+	let root = html.parse_element().unwrap();
+
+	assert_eq!(root.text_content(), "abc def");
+	assert_eq!(root.children[0].children[0].children[0].tail, Some("c ".to_string()));
+
+	let css_text = ".flush { margin: 0 }";
+	let stylesheet = css::parser::CssParser::new(css_text.to_string()).parse_css();
+	let style = style_tree::build_style_tree(&root, &stylesheet);
+
+	assert_eq!(style.inner_text(), "abc d\n\ne\nf");
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serialize_trees_to_json() {
+	let html_string = "<html><head><title>T</title></head><body b=\"2\" a=\"1\"></body></html>";
+	let mut html = html_parser::HtmlParser::new(html_string.to_string());
+	let mut document = dom_tree::Document::new(dom_tree::Doctype::Html);
+	document.element = html.parse_element();
+
+	let json = serde_json::to_string(&document).unwrap();
+	assert!(json.starts_with("{\"d_type\":\"Html\",\"element\":{\"e_type\":\"ClassE\""));
+	assert!(json.contains("\"attributes\":{\"a\":\"1\",\"b\":\"2\"}"));
+
+	let copy: dom_tree::Document = serde_json::from_str(&json).unwrap();
+	assert_eq!(copy.element.unwrap().children[0].children[0].text, Some("T".to_string()));
+
+	let css_text = "body { line-height: 2px } title { display: inline }";
+	let stylesheet = css::parser::CssParser::new(css_text.to_string()).parse_css();
+
+	let json = serde_json::to_string(&stylesheet).unwrap();
+	assert_eq!(json, "{\"rules\":[\
+		{\"selectors\":[{\"compounds\":[{\"simple\":[{\"Type\":[\"Any\",\"body\"]}]}],\"combinators\":[]}],\
+		\"declarations\":[{\"property_name\":\"LineHeight\",\"property_value\":{\"Size\":[2.0,\"Px\"]},\"important\":false}],\"source_index\":0},\
+		{\"selectors\":[{\"compounds\":[{\"simple\":[{\"Type\":[\"Any\",\"title\"]}]}],\"combinators\":[]}],\
+		\"declarations\":[{\"property_name\":\"Display\",\"property_value\":{\"Block\":\"Inline\"},\"important\":false}],\"source_index\":1}]}");
+
+	let copy: css::stylesheet::StyleSheet = serde_json::from_str(&json).unwrap();
+	assert_eq!(copy.rules().len(), 2);
+	// the index is rebuilt, so the copy matches like the original
+	let copy_style = style_tree::build_style_tree(document.element.as_ref().unwrap(), &copy);
+	assert!(copy_style.children[1].declarations.is_some());
+
+	let root = document.element.as_ref().unwrap();
+	let style = style_tree::build_style_tree(root, &stylesheet);
+	let json = serde_json::to_value(&style).unwrap();
+	assert_eq!(json["children"][1]["tag_name"], "body");
+	assert_eq!(json["children"][1]["declarations"].as_array().unwrap().last().unwrap()["property_name"], "LineHeight");
+	assert_eq!(json["children"][0]["declarations"][0]["property_value"]["Block"], "None");
+
+	let layout = layout_tree::build_layout_tree(&style);
+	let json = serde_json::to_value(&layout).unwrap();
+	assert_eq!(json["children"][0]["children"][0]["block"], "Inline");
+	assert_eq!(json["children"][0]["box"]["margin"]["top"], 0.0);
+}
+
+#[test]
+fn style_compound_selectors() {
+	let html_string = "<html><body><p class=\"note\" lang=\"en\">A</p><p class=\"note\">B</p></body></html>";
+	let mut html = html_parser::HtmlParser::new(html_string.to_string());
+	let root = html.parse_element().unwrap();
+
+	let css_text = "p.note[lang] { line-height: 3px }";
+	let stylesheet = css::parser::CssParser::new(css_text.to_string()).parse_css();
+	let style = style_tree::build_style_tree(&root, &stylesheet);
+	let body = &style.children[0];
+
+	assert!(declared(body, css::stylesheet::Property::LineHeight).is_none());
+	assert!(declared(&body.children[0], css::stylesheet::Property::LineHeight).is_some());
+	assert!(declared(&body.children[1], css::stylesheet::Property::LineHeight).is_none());
+}
+
+#[test]
+fn style_combinator_selectors() {
+	let html_string = "<html><body>\
+		<nav><ul><li><a>1</a></li><li><a>2</a></li></ul></nav>\
+		<h1>T</h1><p>A</p><p>B</p>\
+	</body></html>";
+	let mut html = html_parser::HtmlParser::new(html_string.to_string());
+	let root = html.parse_element().unwrap();
+
+	let css_text = "nav a { line-height: 1px }
+					h1 + p { line-height: 2px }
+					h1 ~ p ~ p { line-height: 3px }";
+	let stylesheet = css::parser::CssParser::new(css_text.to_string()).parse_css();
+	let style = style_tree::build_style_tree(&root, &stylesheet);
+	let body = &style.children[0];
+
+	let ul = &body.children[0].children[0];
+	assert_eq!(line_height(&ul.children[0].children[0]), 1);
+	assert_eq!(line_height(&ul.children[1].children[0]), 1);
+	assert_eq!(line_height(&ul.children[1]), 0);
+	assert_eq!(line_height(&body.children[1]), 0);
+	assert_eq!(line_height(&body.children[2]), 2);
+	assert_eq!(line_height(&body.children[3]), 3);
+}
+
+#[test]
+fn style_most_specific_rule_wins() {
+	let html_string = "<html><body>\
+		<p>A</p><p class=\"note\">B</p><p id=\"main\" class=\"note\">C</p><h2>D</h2>\
+	</body></html>";
+	let mut html = html_parser::HtmlParser::new(html_string.to_string());
+	let root = html.parse_element().unwrap();
+
+	let css_text = "#main { line-height: 3px }
+					body p { line-height: 1px }
+					.note, h2 { line-height: 2px }";
+	let stylesheet = css::parser::CssParser::new(css_text.to_string()).parse_css();
+	let style = style_tree::build_style_tree(&root, &stylesheet);
+	let body = &style.children[0];
+
+	assert_eq!(line_height(&body.children[0]), 1);
+	assert_eq!(line_height(&body.children[1]), 2);
+	assert_eq!(line_height(&body.children[2]), 3);
+	assert_eq!(line_height(&body.children[3]), 2);
+}
+
+#[test]
+fn style_pseudo_class_selectors() {
+	let html_string = "<html><body>\
+		<ul><li>1</li><li>2</li><li>3</li></ul>\
+		<a href=\"/\">home</a>\
+	</body></html>";
+	let mut html = html_parser::HtmlParser::new(html_string.to_string());
+	let mut document = dom_tree::Document::new(dom_tree::Doctype::Html);
+	document.element = html.parse_element();
+
+	let css_text = "li:nth-child(odd):not(:last-child) { line-height: 1px }
+					ul:has(> li:empty), li:only-child { line-height: 9px }
+					a:link:hover { line-height: 2px }";
+	let stylesheet = css::parser::CssParser::new(css_text.to_string()).parse_css();
+
+	{
+		let style = style_tree::build_style_tree(document.element.as_ref().unwrap(), &stylesheet);
+		let body = &style.children[0];
+
+		assert_eq!(line_height(&body.children[0]), 0);
+		assert_eq!(line_height(&body.children[0].children[0]), 1);
+		assert_eq!(line_height(&body.children[0].children[1]), 0);
+		assert_eq!(line_height(&body.children[0].children[2]), 0);
+		assert_eq!(line_height(&body.children[1]), 0);
+	}
+
+	let hovered = dom_tree::ElementState { hover: true, .. dom_tree::ElementState::default() };
+	assert!(document.set_element_state(&[0, 1], hovered));
+
+	let style = style_tree::build_style_tree(document.element.as_ref().unwrap(), &stylesheet);
+	assert_eq!(line_height(&style.children[0].children[1]), 2);
+}
+
+#[test]
+fn style_generated_content() {
+	let html_string = "<html><body>\
+		<h2 title=\"Intro\">A</h2><p>Some text</p>\
+		<h2 title=\"Usage\">B</h2><ol><li>x</li><li>y</li></ol>\
+	</body></html>";
+	let mut html = html_parser::HtmlParser::new(html_string.to_string());
+	let root = html.parse_element().unwrap();
+
+	let css_text = "body { counter-reset: chapter }
+					h2 { counter-increment: chapter }
+					h2::before { content: counter(chapter, upper-roman) '. ' attr(title) }
+					ol { counter-reset: item }
+					li { counter-increment: item }
+					li::after { content: ' (' counters(item, '.') ')' }
+					p::before { content: none }
+					p::first-letter { line-height: 2px }";
+	let stylesheet = css::parser::CssParser::new(css_text.to_string()).parse_css();
+	let style = style_tree::build_style_tree(&root, &stylesheet);
+	let body = &style.children[0];
+
+	let h2 = &body.children[0];
+	assert_eq!(h2.children.len(), 1);
+	assert_eq!(h2.children[0].pseudo, Some(css::selector::PseudoElement::Before));
+	assert_eq!(h2.children[0].content, Some("I. Intro".to_string()));
+	assert!(h2.children[0].get_block_type() == css::stylesheet::BlockType::Inline);
+	assert_eq!(body.children[2].children[0].content, Some("II. Usage".to_string()));
+
+	let ol = &body.children[3];
+	assert_eq!(ol.children[1].children[0].pseudo, Some(css::selector::PseudoElement::After));
+	assert_eq!(ol.children[1].children[0].content, Some(" (2)".to_string()));
+
+	// generated content is not part of the element's text
+	assert_eq!(body.inner_text(), "A\n\nSome text\n\nB\n\nx\ny");
+
+	let p = &body.children[1];
+	assert!(p.children.is_empty());
+	assert!(p.first_letter.is_some());
+
+	let layout = layout_tree::build_layout_tree(&style);
+	let text = &layout.children[0].children[1].text;
+	assert_eq!(text.len(), 2);
+	assert_eq!(text[0].text, "S");
+	assert_eq!(text[0].pseudo, Some(css::selector::PseudoElement::FirstLetter));
+	assert_eq!(text[1].text, "ome text");
+	assert_eq!(text[1].pseudo, None);
+}
+
+#[test]
+fn style_conditional_rules() {
+	let html_string = "<html><body><h1>T</h1><p>A</p></body></html>";
+	let mut html = html_parser::HtmlParser::new(html_string.to_string());
+	let root = html.parse_element().unwrap();
+
+	let css_text = "h1 { line-height: 1px }
+					@media print { h1 { line-height: 2px } }
+					@media screen { @supports (line-height: 3px) { p { line-height: 3px } } }
+					@supports (not (line-height: 3px)) { p { line-height: 4px } }";
+	let stylesheet = css::parser::CssParser::new(css_text.to_string()).parse_css();
+	let style = style_tree::build_style_tree(&root, &stylesheet);
+	let body = &style.children[0];
+
+	let line_height = |node: &style_tree::StyleNode| {
+		match declared(node, css::stylesheet::Property::LineHeight) {
+			Some(&css::stylesheet::Value::Size(n, _)) => n as u32,
+			_ => 0,
+		}
+	};
+
+	assert_eq!(line_height(&body.children[0]), 1);
+	assert_eq!(line_height(&body.children[1]), 3);
+}
+
+#[test]
+fn style_media_breakpoints() {
+	let html_string = "<html><body><h1>T</h1><p>A</p></body></html>";
+	let mut html = html_parser::HtmlParser::new(html_string.to_string());
+	let root = html.parse_element().unwrap();
+
+	let css_text = "h1, p { line-height: 1px }
+					@media (max-width: 600px) { h1 { line-height: 2px } }
+					@media screen and (min-width: 601px) and (orientation: landscape) { p { line-height: 3px } }";
+	let stylesheet = css::parser::CssParser::new(css_text.to_string()).parse_css();
+
+	let line_height = |node: &style_tree::StyleNode| {
+		match declared(node, css::stylesheet::Property::LineHeight) {
+			Some(&css::stylesheet::Value::Size(n, _)) => n as u32,
+			_ => 0,
+		}
+	};
+
+	let narrow = css::media::MediaEnvironment::screen(400.0, 800.0);
+	let style = style_tree::build_style_tree_for(&root, &stylesheet, &narrow);
+	let body = &style.children[0];
+	assert_eq!(line_height(&body.children[0]), 2);
+	assert_eq!(line_height(&body.children[1]), 1);
+
+	let wide = css::media::MediaEnvironment::screen(1024.0, 768.0);
+	let style = style_tree::build_style_tree_for(&root, &stylesheet, &wide);
+	let body = &style.children[0];
+	assert_eq!(line_height(&body.children[0]), 1);
+	assert_eq!(line_height(&body.children[1]), 3);
+}
+
+#[test]
+fn style_cascade_per_property() {
+	let html_string = "<html><body><p id=\"intro\">A</p></body></html>";
+	let mut html = html_parser::HtmlParser::new(html_string.to_string());
+	let root = html.parse_element().unwrap();
+
+	let css_text = "@layer base { p { display: inline !important; padding-top: 1px } }
+					#intro { padding-top: 4px; margin-top: 2px; display: block }
+					p { margin-top: 3px !important }";
+	let stylesheet = css::parser::CssParser::new(css_text.to_string()).parse_css();
+
+	let style = style_tree::build_style_tree(&root, &stylesheet);
+	let p = &style.children[0].children[0];
+
+	assert!(p.get_block_type() == css::stylesheet::BlockType::Inline);
+	let layout_box = p.create_layout_box();
+	assert_eq!(layout_box.padding.top, 4.0);
+	assert_eq!(layout_box.margin.top, 3.0);
+	// from the user agent stylesheet
+	assert_eq!(layout_box.margin.bottom, 16.0);
+}
+
+#[test]
+fn style_user_agent_stylesheet() {
+	let html_string = "<html><head><script>x</script></head>\
+		<body><h1>T</h1><p>A <b>bold</b></p><ul><li>1</li></ul><table><tr><td>c</td></tr></table></body></html>";
+	let mut html = html_parser::HtmlParser::new(html_string.to_string());
+	let root = html.parse_element().unwrap();
+	let no_author = css::stylesheet::StyleSheet::new();
+
+	let style = style_tree::build_style_tree(&root, &no_author);
+	let body = &style.children[1];
+	assert!(style.children[0].get_block_type() == css::stylesheet::BlockType::None);
+	assert_eq!(body.create_layout_box().margin.left, 8.0);
+	assert!(declared(&body.children[0], css::stylesheet::Property::FontWeight) ==
+			Some(&css::stylesheet::Value::Number(700.0)));
+	assert!(declared(&body.children[1].children[0], css::stylesheet::Property::FontWeight) ==
+			Some(&css::stylesheet::Value::Keyword("bolder".to_string())));
+	assert!(declared(&body.children[2], css::stylesheet::Property::ListStyleType) ==
+			Some(&css::stylesheet::Value::Keyword("disc".to_string())));
+	assert_eq!(body.children[2].create_layout_box().padding.left, 40.0);
+	assert!(declared(&body.children[3].children[0].children[0], css::stylesheet::Property::Display) ==
+			Some(&css::stylesheet::Value::Keyword("table-cell".to_string())));
+	assert_eq!(style.inner_text(), "T\n\nA bold\n\n1\n\nc");
+
+	// author rules win over the user agent's, unless they are important
+	let author = css::parser::CssParser::new("body { margin: 0 } h1 { display: inline }".to_string()).parse_css();
+	let user_agent = css::user_agent::stylesheet_from_css("h1 { display: none !important } body { margin: 2px }");
+	let media = css::media::MediaEnvironment::default();
+	let style = style_tree::build_style_tree_with(&root, &user_agent, &author, &media);
+	let body = &style.children[1];
+	assert_eq!(body.create_layout_box().margin.left, 0.0);
+	assert!(body.children[0].get_block_type() == css::stylesheet::BlockType::None);
+	assert!(body.children[1].declarations.is_none());
+}
+
+#[test]
+fn style_computed_values() {
+	let html_string = "<html><body><h1>T<span>s</span></h1><p>A</p></body></html>";
+	let mut html = html_parser::HtmlParser::new(html_string.to_string());
+	let root = html.parse_element().unwrap();
+
+	let css_text = "body { color: #008000; font-size: 20px; white-space: pre }
+					p { margin-top: inherit; color: initial; font-size: 1.5em }
+					span { padding-left: 1em; border-left: 2px solid currentColor }";
+	let stylesheet = css::parser::CssParser::new(css_text.to_string()).parse_css();
+
+	let style = style_tree::build_style_tree(&root, &stylesheet);
+	let body = &style.children[0];
+	let (h1, p) = (&body.children[0], &body.children[1]);
+	let span = &h1.children[0];
+
+	assert_eq!(h1.computed.color(), css::color::Color::rgb(0, 128, 0));
+	assert_eq!(h1.computed.font_size(), 40.0);
+	assert_eq!(h1.create_layout_box().margin.top, 0.67 * 40.0);
+	assert!(span.get_white_space() == css::stylesheet::WhiteSpaceType::Pre);
+	assert_eq!(span.create_layout_box().padding.left, 40.0);
+	assert_eq!(span.create_layout_box().border.left, 2.0);
+	assert!(span.computed.get(css::stylesheet::Property::BorderLeftColor) ==
+			&css::stylesheet::Value::ColorValue(css::color::Color::rgb(0, 128, 0)));
+
+	assert_eq!(p.computed.font_size(), 30.0);
+	assert_eq!(p.computed.color(), css::color::Color::rgb(0, 0, 0));
+	assert_eq!(p.create_layout_box().margin.top, 8.0);
+}
+
+#[test]
+fn style_multiple_stylesheets() {
+	let html_string = "<html><body><p id=\"intro\" class=\"lead\">A</p><p class=\"lead\">B</p></body></html>";
+	let mut html = html_parser::HtmlParser::new(html_string.to_string());
+	let root = html.parse_element().unwrap();
+
+	let user_agent = css::user_agent::default_stylesheet();
+	let base = css::parser::CssParser::new("p { margin-top: 1px; padding-top: 1px; padding-left: 1px }
+											.lead { padding-top: 2px }".to_string()).parse_css();
+	let page = css::parser::CssParser::new("#intro { padding-left: 3px } p { padding-top: 4px }
+											.lead { margin-bottom: 5px }".to_string()).parse_css();
+	let media = css::media::MediaEnvironment::default();
+
+	let style = style_tree::build_style_tree_from_sheets(&root, &[&*user_agent, &base, &page], &media);
+	let body = &style.children[0];
+	let (intro, other) = (body.children[0].create_layout_box(), body.children[1].create_layout_box());
+
+	// every matching rule of every sheet applies, property by property
+	assert_eq!(intro.margin.top, 1.0);
+	assert_eq!(intro.padding.top, 2.0);
+	assert_eq!(intro.padding.left, 3.0);
+	assert_eq!(intro.margin.bottom, 5.0);
+	assert_eq!(other.padding.left, 1.0);
+	assert_eq!(other.padding.top, 2.0);
+	assert!(body.children[0].get_block_type() == css::stylesheet::BlockType::Block);
+}
+
+#[test]
+fn style_custom_properties() {
+	let html_string = "<html><body><div><p>A</p></div></body></html>";
+	let mut html = html_parser::HtmlParser::new(html_string.to_string());
+	let root = html.parse_element().unwrap();
+
+	let css_text = "@property --gap { syntax: '<length>'; inherits: false; initial-value: 1px }
+					body { --brand: #00f; --gap: 1em; --label: 'Note: '; font-size: 20px }
+					div { padding-left: var(--gap); color: var(--brand) }
+					p { margin-top: var(--gap); border-left: var(--gap) solid var(--missing) }
+					p::before { content: var(--label) }";
+	let stylesheet = css::parser::CssParser::new(css_text.to_string()).parse_css();
+
+	let style = style_tree::build_style_tree(&root, &stylesheet);
+	let div = &style.children[0].children[0];
+	let p = &div.children[0];
+
+	// registered lengths compute to px and, here, are not inherited
+	assert_eq!(style.children[0].computed.custom_property("--gap"), Some("20px"));
+	assert_eq!(div.create_layout_box().padding.left, 1.0);
+	assert_eq!(p.create_layout_box().margin.top, 1.0);
+	// unregistered custom properties are inherited
+	assert_eq!(p.computed.custom_property("--brand"), Some("#00f"));
+	assert_eq!(p.computed.color(), css::color::Color::rgb(0, 0, 255));
+	assert_eq!(p.create_layout_box().border.left, 0.0);
+	assert_eq!(p.children[0].content, Some("Note: ".to_string()));
+}
+
+#[test]
+fn css_serialize_and_edit_stylesheet() {
+	let html_string = "<html><body><p>A</p></body></html>";
+	let mut html = html_parser::HtmlParser::new(html_string.to_string());
+	let root = html.parse_element().unwrap();
+
+	let css_text = "@media screen { p { margin : 0 2px ; color:#f00 } }
+					@layer base { body { font-size: 20px } }";
+	let mut stylesheet = css::parser::CssParser::new(css_text.to_string()).parse_css();
+	let text = css::serializer::serialize_stylesheet(&stylesheet).unwrap();
+
+	assert_eq!(text, "@media screen {\n  p { margin: 0px 2px; color: rgb(255, 0, 0); }\n}\n\
+					  @layer base {\n  body { font-size: 20px; }\n}");
+	assert_eq!(css::serializer::serialize_stylesheet(&css::parser::CssParser::new(text.clone()).parse_css()), Some(text));
+
+	// edited rules and declarations take effect in the style tree
+	assert!(stylesheet.set_property(&[0, 0], "margin-top", "5px", false));
+	assert!(stylesheet.insert_rule(&[], 2, "p { padding-left: 3px }"));
+	assert!(stylesheet.delete_rule(&[], 1).is_some());
+
+	let style = style_tree::build_style_tree(&root, &stylesheet);
+	let p = &style.children[0].children[0];
+	let layout = p.create_layout_box();
+	assert_eq!(layout.margin.top, 5.0);
+	assert_eq!(layout.margin.right, 2.0);
+	assert_eq!(layout.padding.left, 3.0);
+	assert_eq!(style.children[0].computed.font_size(), 16.0);
+	assert_eq!(stylesheet.property_value(&[0, 0], "margin"), Some("5px 2px 0px".to_string()));
 }